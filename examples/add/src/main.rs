@@ -7,7 +7,6 @@ use omniglot::markers::{AccessScope, AllocScope};
 #[allow(non_snake_case)]
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
-#[allow(improper_ctypes)] // TODO: fix this by wrapping functions with u128s
 pub mod libadd {
     include!(concat!(env!("OUT_DIR"), "/libogadd_bindings.rs"));
 }
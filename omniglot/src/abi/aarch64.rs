@@ -0,0 +1,33 @@
+// -*- fill-column: 80; -*-
+
+// ABI
+//
+// The AArch64 Procedure Call Standard (AAPCS64), as used on Linux and
+// bare-metal Cortex-A targets. Integer and pointer arguments are passed in
+// x0-x7, followed by 8-byte stack slots.
+
+pub enum AArch64ABI {}
+impl super::OGABI for AArch64ABI {}
+
+macro_rules! aarch64_areg_impl {
+    ($reg:ident, $name:expr) => {
+        impl super::calling_convention::ArgumentSlot
+            for super::calling_convention::$reg<AArch64ABI>
+        {
+            const IS_REG: bool = true;
+            const IS_STACKED: bool = false;
+            const IS_INVALID: bool = false;
+            const REG_NAME: &'static str = $name;
+            const STACK_OFFSET_WORDS: usize = usize::MAX;
+        }
+    };
+}
+
+aarch64_areg_impl!(AREG0, "x0");
+aarch64_areg_impl!(AREG1, "x1");
+aarch64_areg_impl!(AREG2, "x2");
+aarch64_areg_impl!(AREG3, "x3");
+aarch64_areg_impl!(AREG4, "x4");
+aarch64_areg_impl!(AREG5, "x5");
+aarch64_areg_impl!(AREG6, "x6");
+aarch64_areg_impl!(AREG7, "x7");
@@ -4,13 +4,26 @@ use core::convert::Infallible;
 use core::marker::PhantomData;
 
 pub trait ArgumentSlot {
+    /// Whether this slot is an integer argument register.
     const IS_REG: bool;
+    /// Whether this slot is a floating-point argument register, used for `f32`
+    /// and `f64` arguments by ABIs which pass those separately.
+    const IS_FP_REG: bool = false;
     const IS_STACKED: bool;
     const IS_INVALID: bool;
     const REG_NAME: &'static str;
     const STACK_OFFSET_WORDS: usize;
 }
 
+/// Whether an argument is passed to one of the named parameters of a function,
+/// or through its variadic (`...`) parameter list. Some ABIs place variadic
+/// arguments differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgumentKind {
+    Fixed,
+    Variadic,
+}
+
 pub enum Invalid {}
 impl ArgumentSlot for Invalid {
     const IS_REG: bool = false;
@@ -31,6 +44,47 @@ impl<const OFFSET: usize, ABI: super::OGABI> ArgumentSlot for Stacked<OFFSET, AB
     const STACK_OFFSET_WORDS: usize = OFFSET;
 }
 
+/// Whether two strings are equal. Unlike `==`, this can be used in `const`
+/// contexts.
+pub const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+/// Index of the register referred to by the argument slot `S` in `regs`, or
+/// `usize::MAX` if `S` does not refer to any of these registers.
+///
+/// ABIs list their integer and floating-point argument registers separately,
+/// in order of assignment, such that the index of an argument register is its
+/// position within its class.
+pub const fn arg_reg_index<S: ArgumentSlot>(regs: &[&str]) -> usize {
+    if !S::IS_REG && !S::IS_FP_REG {
+        return usize::MAX;
+    }
+
+    let mut i = 0;
+    while i < regs.len() {
+        if str_eq(regs[i], S::REG_NAME) {
+            return i;
+        }
+        i += 1;
+    }
+
+    usize::MAX
+}
+
 // ---------- Register types ---------------------------------------------------
 
 macro_rules! register_type_def {
@@ -53,3 +107,28 @@ register_type_def![
     AREG16, AREG17, AREG18, AREG19, AREG20, AREG21, AREG22, AREG23,
     AREG24, AREG25, AREG26, AREG27, AREG28, AREG29, AREG30, AREG31,
 ];
+
+#[rustfmt::skip]
+register_type_def![
+    FPREG0, FPREG1, FPREG2, FPREG3, FPREG4, FPREG5, FPREG6, FPREG7,
+    FPREG8, FPREG9, FPREG10, FPREG11, FPREG12, FPREG13, FPREG14, FPREG15,
+    FPREG16, FPREG17, FPREG18, FPREG19, FPREG20, FPREG21, FPREG22, FPREG23,
+    FPREG24, FPREG25, FPREG26, FPREG27, FPREG28, FPREG29, FPREG30, FPREG31,
+];
+
+#[test]
+fn test_arg_reg_index() {
+    use super::sysv_amd64::SysVAMD64ABI;
+
+    const REGS: [&str; 3] = ["rdi", "rsi", "rdx"];
+
+    assert!(str_eq("rsi", "rsi"));
+    assert!(!str_eq("rsi", "rs"));
+    assert!(!str_eq("xmm0", "xmm1"));
+
+    assert_eq!(arg_reg_index::<AREG2<SysVAMD64ABI>>(&REGS), 2);
+    assert_eq!(arg_reg_index::<AREG3<SysVAMD64ABI>>(&REGS), usize::MAX);
+    assert_eq!(arg_reg_index::<FPREG0<SysVAMD64ABI>>(&["xmm0"]), 0);
+    assert_eq!(arg_reg_index::<Stacked<0, SysVAMD64ABI>>(&["stacked"]), usize::MAX);
+    assert_eq!(arg_reg_index::<Invalid>(&["invalid"]), usize::MAX);
+}
@@ -2,9 +2,16 @@
 
 pub trait OGABI {}
 
+pub mod aarch64;
 pub mod calling_convention;
+pub mod riscv;
+pub mod rv32e_c;
+pub mod rv32g_c;
 pub mod rv32i_c;
+pub mod rv64g_c;
+pub mod rv64i_c;
 pub mod sysv_amd64;
+pub mod wasm32;
 
 // For Mock implementations, that don't have any ABI constraints
 pub enum GenericABI {}
@@ -0,0 +1,320 @@
+// -*- fill-column: 80; -*-
+
+// Argument placement shared by the RISC-V ABIs (`Rv32iCABI`, `Rv32gCABI`,
+// `Rv32eCABI`, `Rv64iCABI` and `Rv64gCABI`), following the integer and
+// hard-float calling conventions of the RISC-V psABI for scalar arguments.
+
+use super::calling_convention::ArgumentKind;
+
+/// Class of a scalar argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RiscVScalarClass {
+    /// Integers and pointers.
+    Integer,
+    /// `f32` and `f64`.
+    Float,
+}
+
+/// A scalar argument of `size` bytes. Scalars are naturally aligned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RiscVScalar {
+    pub size: usize,
+    pub class: RiscVScalarClass,
+}
+
+/// `i128`, `u128` and `__int128`.
+///
+/// On RV64, these are passed in a pair of integer registers (aligned to an even
+/// register when variadic), or 16-byte aligned on the stack. On RV32, they are
+/// passed and returned by reference.
+pub const RISCV_INT128: RiscVScalar = RiscVScalar {
+    size: 16,
+    class: RiscVScalarClass::Integer,
+};
+
+/// Location of an argument. Argument registers are identified by their index,
+/// i.e., `aN` and `faN`.
+///
+/// Scalars larger than two words are passed by reference: their location then
+/// holds a pointer to a copy of the argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RiscVArgLocation {
+    /// Passed in `count` consecutive integer argument registers, starting at
+    /// `first`, with the least significant word first.
+    Registers { first: usize, count: usize },
+    /// Passed in a floating-point argument register.
+    FpRegister(usize),
+    /// Passed with its least significant word in the last integer argument
+    /// register, and its most significant word on the stack.
+    Split { reg: usize, offset_words: usize },
+    /// Passed on the stack, occupying `words` words starting at `offset_words`
+    /// above the stack pointer on entry to the callee, i.e., the `OFFSET` of
+    /// its `Stacked` slots.
+    Stacked { offset_words: usize, words: usize },
+}
+
+/// Assigns argument locations, in order, to all arguments of a function.
+///
+/// Fixed floating-point arguments are passed in floating-point registers (when
+/// the ABI has them and they fit). Variadic floating-point arguments are always
+/// passed according to the integer calling convention instead, and variadic
+/// arguments spanning two words start at an even integer register.
+///
+/// After all arguments have been allocated, [`stack_words`] is the
+/// `STACK_SPILL` to use for invoking the function.
+///
+/// [`stack_words`]: RiscVArgAllocator::stack_words
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RiscVArgAllocator {
+    // Width of integer registers and stack words, and of floating-point
+    // registers, in bytes (0 for soft-float ABIs):
+    xlen: usize,
+    flen: usize,
+    aregs: usize,
+    stack_align: usize,
+    next_areg: usize,
+    next_fpreg: usize,
+    stack_words: usize,
+}
+
+impl RiscVArgAllocator {
+    const fn new(xlen: usize, flen: usize, aregs: usize, stack_align: usize) -> Self {
+        RiscVArgAllocator {
+            xlen,
+            flen,
+            aregs,
+            stack_align,
+            next_areg: 0,
+            next_fpreg: 0,
+            stack_words: 0,
+        }
+    }
+
+    /// The ILP32 ABI, for [`Rv32iCABI`](super::rv32i_c::Rv32iCABI).
+    pub const fn ilp32() -> Self {
+        Self::new(4, 0, 8, 16)
+    }
+
+    /// The ILP32D ABI, for [`Rv32gCABI`](super::rv32g_c::Rv32gCABI).
+    pub const fn ilp32d() -> Self {
+        Self::new(4, 8, 8, 16)
+    }
+
+    /// The ILP32E ABI, for [`Rv32eCABI`](super::rv32e_c::Rv32eCABI). Its stack
+    /// is only word-aligned, and hence two-word arguments are never aligned to
+    /// even registers or stack words.
+    pub const fn ilp32e() -> Self {
+        Self::new(4, 0, 6, 4)
+    }
+
+    /// The LP64 ABI, for [`Rv64iCABI`](super::rv64i_c::Rv64iCABI).
+    pub const fn lp64() -> Self {
+        Self::new(8, 0, 8, 16)
+    }
+
+    /// The LP64D ABI, for [`Rv64gCABI`](super::rv64g_c::Rv64gCABI).
+    pub const fn lp64d() -> Self {
+        Self::new(8, 8, 8, 16)
+    }
+
+    /// Determine whether a function returning `ret` returns it through a
+    /// hidden pointer to caller-provided memory (the `sret` pointer), which is
+    /// the case for scalars larger than two words. This pointer is passed in
+    /// `a0`, so this must be called before allocating any arguments.
+    pub const fn allocate_return(&mut self, ret: RiscVScalar) -> bool {
+        let sret = ret.size > 2 * self.xlen;
+        if sret {
+            self.next_areg += 1;
+        }
+        sret
+    }
+
+    /// Assign the location of the next argument.
+    pub const fn allocate(&mut self, arg: RiscVScalar, kind: ArgumentKind) -> RiscVArgLocation {
+        if matches!(arg.class, RiscVScalarClass::Float)
+            && matches!(kind, ArgumentKind::Fixed)
+            && arg.size <= self.flen
+            && self.next_fpreg < 8
+        {
+            self.next_fpreg += 1;
+            return RiscVArgLocation::FpRegister(self.next_fpreg - 1);
+        }
+
+        let words = if arg.size > 2 * self.xlen {
+            1
+        } else {
+            arg.size.div_ceil(self.xlen)
+        };
+        let aligned_pair = words == 2 && 2 * self.xlen <= self.stack_align;
+
+        if aligned_pair && matches!(kind, ArgumentKind::Variadic) {
+            self.next_areg = self.next_areg.next_multiple_of(2);
+        }
+
+        if self.next_areg + words <= self.aregs {
+            self.next_areg += words;
+            RiscVArgLocation::Registers {
+                first: self.next_areg - words,
+                count: words,
+            }
+        } else if words == 2 && self.next_areg + 1 == self.aregs {
+            self.next_areg = self.aregs;
+            self.stack_words += 1;
+            RiscVArgLocation::Split {
+                reg: self.aregs - 1,
+                offset_words: self.stack_words - 1,
+            }
+        } else {
+            self.next_areg = self.aregs;
+            let offset_words = if aligned_pair {
+                self.stack_words.next_multiple_of(2)
+            } else {
+                self.stack_words
+            };
+            self.stack_words = offset_words + words;
+            RiscVArgLocation::Stacked {
+                offset_words,
+                words,
+            }
+        }
+    }
+
+    /// Number of words of arguments allocated on the stack so far.
+    pub const fn stack_words(&self) -> usize {
+        self.stack_words
+    }
+}
+
+#[test]
+fn test_riscv_variadic_arguments() {
+    use ArgumentKind::*;
+    use RiscVArgLocation::*;
+
+    let int = RiscVScalar {
+        size: 4,
+        class: RiscVScalarClass::Integer,
+    };
+    let double = RiscVScalar {
+        size: 8,
+        class: RiscVScalarClass::Float,
+    };
+
+    // int snprintf(char *buf, size_t len, const char *fmt, ...), called with
+    // a double and an int as variadic arguments. Fixed doubles are passed in
+    // floating-point registers:
+    let mut fixed = RiscVArgAllocator::ilp32d();
+    for _ in 0..3 {
+        fixed.allocate(int, Fixed);
+    }
+    assert_eq!(fixed.allocate(double, Fixed), FpRegister(0));
+
+    // ... whereas variadic ones use an aligned pair of integer registers:
+    let mut variadic = RiscVArgAllocator::ilp32d();
+    for _ in 0..3 {
+        variadic.allocate(int, Fixed);
+    }
+    assert_eq!(
+        variadic.allocate(double, Variadic),
+        Registers { first: 4, count: 2 }
+    );
+    assert_eq!(
+        variadic.allocate(int, Variadic),
+        Registers { first: 6, count: 1 }
+    );
+    assert_eq!(
+        variadic.allocate(double, Variadic),
+        Stacked {
+            offset_words: 0,
+            words: 2
+        }
+    );
+    assert_eq!(variadic.stack_words(), 2);
+
+    // ILP32E neither aligns register pairs, nor stack words:
+    let mut ilp32e = RiscVArgAllocator::ilp32e();
+    assert_eq!(
+        ilp32e.allocate(int, Fixed),
+        Registers { first: 0, count: 1 }
+    );
+    assert_eq!(
+        ilp32e.allocate(double, Variadic),
+        Registers { first: 1, count: 2 }
+    );
+    for _ in 0..2 {
+        ilp32e.allocate(int, Variadic);
+    }
+    assert_eq!(
+        ilp32e.allocate(double, Variadic),
+        Split {
+            reg: 5,
+            offset_words: 0
+        }
+    );
+    assert_eq!(
+        ilp32e.allocate(int, Variadic),
+        Stacked {
+            offset_words: 1,
+            words: 1
+        }
+    );
+
+    // On RV64, doubles occupy a single integer register:
+    let mut lp64d = RiscVArgAllocator::lp64d();
+    lp64d.allocate(int, Fixed);
+    assert_eq!(
+        lp64d.allocate(double, Variadic),
+        Registers { first: 1, count: 1 }
+    );
+}
+
+#[test]
+fn test_riscv_int128_arguments() {
+    use ArgumentKind::*;
+    use RiscVArgLocation::*;
+
+    let int = RiscVScalar {
+        size: 4,
+        class: RiscVScalarClass::Integer,
+    };
+
+    // On RV32, 128-bit integers are passed and returned by reference:
+    let mut ilp32 = RiscVArgAllocator::ilp32();
+    assert!(ilp32.allocate_return(RISCV_INT128));
+    assert_eq!(
+        ilp32.allocate(RISCV_INT128, Fixed),
+        Registers { first: 1, count: 1 }
+    );
+
+    // On RV64, they occupy a register pair, which is aligned when variadic:
+    let mut lp64 = RiscVArgAllocator::lp64();
+    assert!(!lp64.allocate_return(RISCV_INT128));
+    lp64.allocate(int, Fixed);
+    assert_eq!(
+        lp64.allocate(RISCV_INT128, Fixed),
+        Registers { first: 1, count: 2 }
+    );
+    assert_eq!(
+        lp64.allocate(RISCV_INT128, Variadic),
+        Registers { first: 4, count: 2 }
+    );
+    assert_eq!(
+        lp64.allocate(RISCV_INT128, Fixed),
+        Registers { first: 6, count: 2 }
+    );
+
+    // ... and 16-byte aligned on the stack:
+    assert_eq!(
+        lp64.allocate(int, Fixed),
+        Stacked {
+            offset_words: 0,
+            words: 1
+        }
+    );
+    assert_eq!(
+        lp64.allocate(RISCV_INT128, Fixed),
+        Stacked {
+            offset_words: 2,
+            words: 2
+        }
+    );
+}
@@ -0,0 +1,31 @@
+// -*- fill-column: 80; -*-
+
+// ABI
+//
+// The RISC-V ILP32E calling convention, for RV32E cores with only 16 integer
+// registers. Arguments are passed in a0-a5 (there are no a6 and a7), followed
+// by 4-byte stack slots.
+
+pub enum Rv32eCABI {}
+impl super::OGABI for Rv32eCABI {}
+
+macro_rules! rv32e_c_areg_impl {
+    ($reg:ident, $name:expr) => {
+        impl super::calling_convention::ArgumentSlot
+            for super::calling_convention::$reg<Rv32eCABI>
+        {
+            const IS_REG: bool = true;
+            const IS_STACKED: bool = false;
+            const IS_INVALID: bool = false;
+            const REG_NAME: &'static str = $name;
+            const STACK_OFFSET_WORDS: usize = usize::MAX;
+        }
+    };
+}
+
+rv32e_c_areg_impl!(AREG0, "a0");
+rv32e_c_areg_impl!(AREG1, "a1");
+rv32e_c_areg_impl!(AREG2, "a2");
+rv32e_c_areg_impl!(AREG3, "a3");
+rv32e_c_areg_impl!(AREG4, "a4");
+rv32e_c_areg_impl!(AREG5, "a5");
@@ -0,0 +1,58 @@
+// -*- fill-column: 80; -*-
+
+// ABI
+//
+// The RISC-V ILP32D hard-float calling convention, as used on RV32GC targets
+// (RV32IMAFDC). Integer and pointer arguments are passed in a0-a7, as in the
+// soft-float `Rv32iCABI`, whereas `f32` and `f64` arguments are passed in
+// fa0-fa7 (`f32` values NaN-boxed).
+
+pub enum Rv32gCABI {}
+impl super::OGABI for Rv32gCABI {}
+
+macro_rules! rv32g_c_areg_impl {
+    ($reg:ident, $name:expr) => {
+        impl super::calling_convention::ArgumentSlot
+            for super::calling_convention::$reg<Rv32gCABI>
+        {
+            const IS_REG: bool = true;
+            const IS_STACKED: bool = false;
+            const IS_INVALID: bool = false;
+            const REG_NAME: &'static str = $name;
+            const STACK_OFFSET_WORDS: usize = usize::MAX;
+        }
+    };
+}
+
+rv32g_c_areg_impl!(AREG0, "a0");
+rv32g_c_areg_impl!(AREG1, "a1");
+rv32g_c_areg_impl!(AREG2, "a2");
+rv32g_c_areg_impl!(AREG3, "a3");
+rv32g_c_areg_impl!(AREG4, "a4");
+rv32g_c_areg_impl!(AREG5, "a5");
+rv32g_c_areg_impl!(AREG6, "a6");
+rv32g_c_areg_impl!(AREG7, "a7");
+
+macro_rules! rv32g_c_fpreg_impl {
+    ($reg:ident, $name:expr) => {
+        impl super::calling_convention::ArgumentSlot
+            for super::calling_convention::$reg<Rv32gCABI>
+        {
+            const IS_REG: bool = false;
+            const IS_FP_REG: bool = true;
+            const IS_STACKED: bool = false;
+            const IS_INVALID: bool = false;
+            const REG_NAME: &'static str = $name;
+            const STACK_OFFSET_WORDS: usize = usize::MAX;
+        }
+    };
+}
+
+rv32g_c_fpreg_impl!(FPREG0, "fa0");
+rv32g_c_fpreg_impl!(FPREG1, "fa1");
+rv32g_c_fpreg_impl!(FPREG2, "fa2");
+rv32g_c_fpreg_impl!(FPREG3, "fa3");
+rv32g_c_fpreg_impl!(FPREG4, "fa4");
+rv32g_c_fpreg_impl!(FPREG5, "fa5");
+rv32g_c_fpreg_impl!(FPREG6, "fa6");
+rv32g_c_fpreg_impl!(FPREG7, "fa7");
@@ -0,0 +1,33 @@
+// -*- fill-column: 80; -*-
+
+// ABI
+//
+// The RISC-V LP64 soft-float calling convention, for RV64I targets. Integer,
+// pointer and floating-point arguments are passed in a0-a7, followed by 8-byte
+// stack slots.
+
+pub enum Rv64iCABI {}
+impl super::OGABI for Rv64iCABI {}
+
+macro_rules! rv64i_c_areg_impl {
+    ($reg:ident, $name:expr) => {
+        impl super::calling_convention::ArgumentSlot
+            for super::calling_convention::$reg<Rv64iCABI>
+        {
+            const IS_REG: bool = true;
+            const IS_STACKED: bool = false;
+            const IS_INVALID: bool = false;
+            const REG_NAME: &'static str = $name;
+            const STACK_OFFSET_WORDS: usize = usize::MAX;
+        }
+    };
+}
+
+rv64i_c_areg_impl!(AREG0, "a0");
+rv64i_c_areg_impl!(AREG1, "a1");
+rv64i_c_areg_impl!(AREG2, "a2");
+rv64i_c_areg_impl!(AREG3, "a3");
+rv64i_c_areg_impl!(AREG4, "a4");
+rv64i_c_areg_impl!(AREG5, "a5");
+rv64i_c_areg_impl!(AREG6, "a6");
+rv64i_c_areg_impl!(AREG7, "a7");
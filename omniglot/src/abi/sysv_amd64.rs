@@ -25,3 +25,638 @@ sysv_amd64_areg_impl!(AREG2, "rdx");
 sysv_amd64_areg_impl!(AREG3, "rcx");
 sysv_amd64_areg_impl!(AREG4, "r8");
 sysv_amd64_areg_impl!(AREG5, "r9");
+
+macro_rules! sysv_amd64_fpreg_impl {
+    ($reg:ident, $name:expr) => {
+        impl super::calling_convention::ArgumentSlot
+            for super::calling_convention::$reg<SysVAMD64ABI>
+        {
+            const IS_REG: bool = false;
+            const IS_FP_REG: bool = true;
+            const IS_STACKED: bool = false;
+            const IS_INVALID: bool = false;
+            const REG_NAME: &'static str = $name;
+            const STACK_OFFSET_WORDS: usize = usize::MAX;
+        }
+    };
+}
+
+sysv_amd64_fpreg_impl!(FPREG0, "xmm0");
+sysv_amd64_fpreg_impl!(FPREG1, "xmm1");
+sysv_amd64_fpreg_impl!(FPREG2, "xmm2");
+sysv_amd64_fpreg_impl!(FPREG3, "xmm3");
+sysv_amd64_fpreg_impl!(FPREG4, "xmm4");
+sysv_amd64_fpreg_impl!(FPREG5, "xmm5");
+sysv_amd64_fpreg_impl!(FPREG6, "xmm6");
+sysv_amd64_fpreg_impl!(FPREG7, "xmm7");
+
+// ---------- Aggregate classification -----------------------------------------
+
+// Argument registers available for INTEGER and SSE eightbytes:
+const SYSV_AMD64_INT_AREGS: usize = 6;
+const SYSV_AMD64_SSE_AREGS: usize = 8;
+
+/// Class of a scalar member of a type passed by value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysVScalarClass {
+    /// Integers, pointers, `bool` and `char`.
+    Integer,
+    /// `f32` and `f64`.
+    Sse,
+}
+
+/// A scalar member of a type, at `offset` bytes from its start.
+///
+/// Scalars are naturally aligned, so `size` must be a power of two. Members
+/// which are not aligned to their size (e.g., in packed structs) force the
+/// enclosing type to be passed in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SysVScalar {
+    pub offset: usize,
+    pub size: usize,
+    pub class: SysVScalarClass,
+}
+
+/// Layout of a type passed or returned by value.
+///
+/// Nested structs, unions and arrays are described by flattening them into
+/// their scalar members. A plain scalar type is described by a single member
+/// at offset `0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SysVLayout<'a> {
+    pub size: usize,
+    pub align: usize,
+    pub fields: &'a [SysVScalar],
+}
+
+/// Layout of `i128`, `u128` and `__int128`.
+///
+/// These are classified as two INTEGER eightbytes, and hence passed in two
+/// consecutive argument registers and returned in `rax` and `rdx`. On the
+/// stack, they are aligned to 16 bytes.
+pub const SYSV_AMD64_INT128: SysVLayout<'static> = SysVLayout {
+    size: 16,
+    align: 16,
+    fields: &[SysVScalar {
+        offset: 0,
+        size: 16,
+        class: SysVScalarClass::Integer,
+    }],
+};
+
+/// Class of an eightbyte of a type, as per Section 3.2.3 of the System V AMD64
+/// psABI. We do not support the `X87`, `X87UP`, `SSEUP` and `COMPLEX_X87`
+/// classes, and hence `long double` and vector types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysVClass {
+    /// Padding only, which does not occupy any register.
+    NoClass,
+    Integer,
+    Sse,
+    Memory,
+}
+
+/// Classify both eightbytes of a type. Types larger than two eightbytes, or
+/// with any unaligned member, are classified as `Memory` entirely.
+///
+/// # Panics
+///
+/// Panics (or fails to compile, when evaluated in a const context) if an `Sse`
+/// field spans more than one eightbyte. Such fields (e.g., `__m128` or
+/// `_Float128`) require the unsupported `SSEUP` class.
+pub const fn sysv_amd64_classify(layout: &SysVLayout<'_>) -> [SysVClass; 2] {
+    const MEMORY: [SysVClass; 2] = [SysVClass::Memory; 2];
+
+    if layout.size > 16 {
+        return MEMORY;
+    }
+
+    let mut classes = [SysVClass::NoClass; 2];
+    let mut i = 0;
+    while i < layout.fields.len() {
+        let field = layout.fields[i];
+        i += 1;
+
+        if field.size == 0 {
+            continue;
+        }
+        if !field.size.is_power_of_two()
+            || !field.offset.is_multiple_of(field.size)
+            || field.offset + field.size > 16
+        {
+            return MEMORY;
+        }
+        if field.size > 8 && matches!(field.class, SysVScalarClass::Sse) {
+            panic!("SSE fields larger than an eightbyte (SSEUP class) are unsupported");
+        }
+
+        // Merge the field's class into every eightbyte it occupies. INTEGER
+        // takes precedence over SSE:
+        let mut eightbyte = field.offset / 8;
+        while eightbyte <= (field.offset + field.size - 1) / 8 {
+            classes[eightbyte] = match (classes[eightbyte], field.class) {
+                (SysVClass::Integer, _) | (_, SysVScalarClass::Integer) => SysVClass::Integer,
+                _ => SysVClass::Sse,
+            };
+            eightbyte += 1;
+        }
+    }
+
+    classes
+}
+
+/// A register occupied by an eightbyte, identified by its index within the
+/// INTEGER or SSE argument (or return) registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysVReg {
+    Integer(usize),
+    Sse(usize),
+}
+
+impl SysVReg {
+    /// Name of this register when used to pass an argument, matching the
+    /// `REG_NAME` of its `ArgumentSlot`.
+    pub const fn argument_register_name(self) -> &'static str {
+        const INT: [&str; SYSV_AMD64_INT_AREGS] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+        const SSE: [&str; SYSV_AMD64_SSE_AREGS] = [
+            "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7",
+        ];
+
+        match self {
+            SysVReg::Integer(i) => INT[i],
+            SysVReg::Sse(i) => SSE[i],
+        }
+    }
+
+    /// Name of this register when used to return a value.
+    pub const fn return_register_name(self) -> &'static str {
+        match self {
+            SysVReg::Integer(i) => ["rax", "rdx"][i],
+            SysVReg::Sse(i) => ["xmm0", "xmm1"][i],
+        }
+    }
+}
+
+// Assign registers to the INTEGER and SSE eightbytes of `classes`, following
+// `int` and `sse` already used registers. Returns the assigned registers, and
+// the number of used registers afterwards:
+const fn sysv_amd64_assign_registers(
+    classes: [SysVClass; 2],
+    mut int: usize,
+    mut sse: usize,
+) -> ([Option<SysVReg>; 2], usize, usize) {
+    let mut regs = [None; 2];
+    let mut i = 0;
+    while i < classes.len() {
+        regs[i] = match classes[i] {
+            SysVClass::Integer => {
+                int += 1;
+                Some(SysVReg::Integer(int - 1))
+            }
+            SysVClass::Sse => {
+                sse += 1;
+                Some(SysVReg::Sse(sse - 1))
+            }
+            SysVClass::NoClass | SysVClass::Memory => None,
+        };
+        i += 1;
+    }
+
+    (regs, int, sse)
+}
+
+/// Location of a return value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysVReturnLocation {
+    /// Returned in up to two registers, one per (non-padding) eightbyte.
+    Registers([Option<SysVReg>; 2]),
+    /// Returned in memory provided by the caller, whose address is passed as a
+    /// hidden first argument in `rdi` (the `sret` pointer) and returned in
+    /// `rax`.
+    Sret,
+}
+
+/// Determine how a value of the given layout is returned.
+pub const fn sysv_amd64_return_location(layout: &SysVLayout<'_>) -> SysVReturnLocation {
+    let classes = sysv_amd64_classify(layout);
+    if matches!(classes[0], SysVClass::Memory) {
+        return SysVReturnLocation::Sret;
+    }
+
+    let (regs, _, _) = sysv_amd64_assign_registers(classes, 0, 0);
+    SysVReturnLocation::Registers(regs)
+}
+
+/// Location of an argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysVArgLocation {
+    /// Passed in up to two registers, one per (non-padding) eightbyte.
+    Registers([Option<SysVReg>; 2]),
+    /// Passed on the stack, occupying `words` 8-byte words starting at
+    /// `offset_words` above the stack pointer on entry to the callee (excluding
+    /// the return address), i.e., the `OFFSET` of its `Stacked` slots.
+    Stacked { offset_words: usize, words: usize },
+}
+
+/// Assigns argument locations, in order, to all arguments of a function.
+///
+/// After all arguments have been allocated, [`stack_words`] is the
+/// `STACK_SPILL` to use for invoking the function.
+///
+/// [`stack_words`]: SysVArgAllocator::stack_words
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SysVArgAllocator {
+    int_regs: usize,
+    sse_regs: usize,
+    stack_words: usize,
+}
+
+impl SysVArgAllocator {
+    /// Create an allocator for a function returning a value at `ret`. An `sret`
+    /// pointer occupies the first INTEGER argument register.
+    pub const fn new(ret: SysVReturnLocation) -> Self {
+        SysVArgAllocator {
+            int_regs: matches!(ret, SysVReturnLocation::Sret) as usize,
+            sse_regs: 0,
+            stack_words: 0,
+        }
+    }
+
+    /// Assign the location of the next argument.
+    ///
+    /// Arguments are passed in registers only when all of their eightbytes fit
+    /// into the remaining registers of their respective classes. Otherwise,
+    /// they are passed on the stack and the registers remain available to
+    /// subsequent arguments. Variadic arguments are placed just like fixed
+    /// ones, but see [`vector_registers`](Self::vector_registers).
+    pub const fn allocate(&mut self, layout: &SysVLayout<'_>) -> SysVArgLocation {
+        let classes = sysv_amd64_classify(layout);
+
+        if !matches!(classes[0], SysVClass::Memory) {
+            let (regs, int, sse) =
+                sysv_amd64_assign_registers(classes, self.int_regs, self.sse_regs);
+
+            if int <= SYSV_AMD64_INT_AREGS && sse <= SYSV_AMD64_SSE_AREGS {
+                self.int_regs = int;
+                self.sse_regs = sse;
+                return SysVArgLocation::Registers(regs);
+            }
+        }
+
+        // Stacked arguments occupy whole words, and retain alignments larger
+        // than a word:
+        let align_words = if layout.align > 8 {
+            layout.align / 8
+        } else {
+            1
+        };
+        let offset_words = self.stack_words.next_multiple_of(align_words);
+        let words = layout.size.div_ceil(8);
+        self.stack_words = offset_words + words;
+
+        SysVArgLocation::Stacked {
+            offset_words,
+            words,
+        }
+    }
+
+    /// Number of SSE registers used by the arguments allocated so far.
+    ///
+    /// Calls to variadic functions must pass (an upper bound of) this number
+    /// in `al`.
+    pub const fn vector_registers(&self) -> u8 {
+        self.sse_regs as u8
+    }
+
+    /// Number of words of arguments allocated on the stack so far.
+    pub const fn stack_words(&self) -> usize {
+        self.stack_words
+    }
+}
+
+#[test]
+fn test_sysv_amd64_classify() {
+    use SysVClass::*;
+    use SysVScalarClass as S;
+
+    // struct { int32_t a; float b; }: both share an INTEGER eightbyte:
+    let int_float = [
+        SysVScalar {
+            offset: 0,
+            size: 4,
+            class: S::Integer,
+        },
+        SysVScalar {
+            offset: 4,
+            size: 4,
+            class: S::Sse,
+        },
+    ];
+    assert_eq!(
+        sysv_amd64_classify(&SysVLayout {
+            size: 8,
+            align: 4,
+            fields: &int_float
+        }),
+        [Integer, NoClass]
+    );
+
+    // struct { float a; float b; int64_t c; }:
+    let floats_long = [
+        SysVScalar {
+            offset: 0,
+            size: 4,
+            class: S::Sse,
+        },
+        SysVScalar {
+            offset: 4,
+            size: 4,
+            class: S::Sse,
+        },
+        SysVScalar {
+            offset: 8,
+            size: 8,
+            class: S::Integer,
+        },
+    ];
+    assert_eq!(
+        sysv_amd64_classify(&SysVLayout {
+            size: 16,
+            align: 8,
+            fields: &floats_long
+        }),
+        [Sse, Integer]
+    );
+
+    // struct { double a; double b; double c; } is too large:
+    let three_doubles = [
+        SysVScalar {
+            offset: 0,
+            size: 8,
+            class: S::Sse,
+        },
+        SysVScalar {
+            offset: 8,
+            size: 8,
+            class: S::Sse,
+        },
+        SysVScalar {
+            offset: 16,
+            size: 8,
+            class: S::Sse,
+        },
+    ];
+    assert_eq!(
+        sysv_amd64_classify(&SysVLayout {
+            size: 24,
+            align: 8,
+            fields: &three_doubles
+        }),
+        [Memory, Memory]
+    );
+
+    // struct __attribute__((packed)) { char a; int32_t b; }:
+    let packed = [
+        SysVScalar {
+            offset: 0,
+            size: 1,
+            class: S::Integer,
+        },
+        SysVScalar {
+            offset: 1,
+            size: 4,
+            class: S::Integer,
+        },
+    ];
+    assert_eq!(
+        sysv_amd64_classify(&SysVLayout {
+            size: 5,
+            align: 1,
+            fields: &packed
+        }),
+        [Memory, Memory]
+    );
+}
+
+#[test]
+fn test_sysv_amd64_locations() {
+    use SysVScalarClass as S;
+
+    let long = [SysVScalar {
+        offset: 0,
+        size: 8,
+        class: S::Integer,
+    }];
+    let long = SysVLayout {
+        size: 8,
+        align: 8,
+        fields: &long,
+    };
+    let double_long = [
+        SysVScalar {
+            offset: 0,
+            size: 8,
+            class: S::Sse,
+        },
+        SysVScalar {
+            offset: 8,
+            size: 8,
+            class: S::Integer,
+        },
+    ];
+    let double_long = SysVLayout {
+        size: 16,
+        align: 8,
+        fields: &double_long,
+    };
+    let large = [
+        SysVScalar {
+            offset: 0,
+            size: 8,
+            class: S::Integer,
+        },
+        SysVScalar {
+            offset: 8,
+            size: 8,
+            class: S::Integer,
+        },
+        SysVScalar {
+            offset: 16,
+            size: 8,
+            class: S::Integer,
+        },
+    ];
+    let large = SysVLayout {
+        size: 24,
+        align: 8,
+        fields: &large,
+    };
+    let aligned = [
+        SysVScalar {
+            offset: 0,
+            size: 8,
+            class: S::Integer,
+        },
+        SysVScalar {
+            offset: 16,
+            size: 8,
+            class: S::Integer,
+        },
+    ];
+    let aligned = SysVLayout {
+        size: 32,
+        align: 16,
+        fields: &aligned,
+    };
+
+    // Small aggregates are returned in registers, large ones through an `sret`
+    // pointer in `rdi`:
+    assert_eq!(
+        sysv_amd64_return_location(&double_long),
+        SysVReturnLocation::Registers([Some(SysVReg::Sse(0)), Some(SysVReg::Integer(0))])
+    );
+    assert_eq!(SysVReg::Integer(0).return_register_name(), "rax");
+    let ret = sysv_amd64_return_location(&large);
+    assert_eq!(ret, SysVReturnLocation::Sret);
+
+    let mut args = SysVArgAllocator::new(ret);
+    assert_eq!(
+        args.allocate(&double_long),
+        SysVArgLocation::Registers([Some(SysVReg::Sse(0)), Some(SysVReg::Integer(1))])
+    );
+    assert_eq!(SysVReg::Integer(1).argument_register_name(), "rsi");
+
+    // Memory-class aggregates are stacked, in order:
+    assert_eq!(
+        args.allocate(&large),
+        SysVArgLocation::Stacked {
+            offset_words: 0,
+            words: 3
+        }
+    );
+    assert_eq!(
+        args.allocate(&aligned),
+        SysVArgLocation::Stacked {
+            offset_words: 4,
+            words: 4
+        }
+    );
+
+    // Aggregates which do not fit into the remaining registers are stacked
+    // entirely, leaving the registers to subsequent arguments:
+    for reg in 2..6 {
+        assert_eq!(
+            args.allocate(&long),
+            SysVArgLocation::Registers([Some(SysVReg::Integer(reg)), None])
+        );
+    }
+    assert_eq!(
+        args.allocate(&double_long),
+        SysVArgLocation::Stacked {
+            offset_words: 8,
+            words: 2
+        }
+    );
+    assert_eq!(args.stack_words(), 10);
+
+    // The classification can be evaluated at compile time:
+    const STACK_SPILL: usize = {
+        let large = [
+            SysVScalar {
+                offset: 0,
+                size: 8,
+                class: SysVScalarClass::Integer,
+            },
+            SysVScalar {
+                offset: 8,
+                size: 8,
+                class: SysVScalarClass::Sse,
+            },
+            SysVScalar {
+                offset: 16,
+                size: 4,
+                class: SysVScalarClass::Sse,
+            },
+        ];
+        let large = SysVLayout {
+            size: 24,
+            align: 8,
+            fields: &large,
+        };
+        let mut args = SysVArgAllocator::new(sysv_amd64_return_location(&large));
+        args.allocate(&large);
+        args.stack_words()
+    };
+    assert_eq!(STACK_SPILL, 3);
+}
+
+#[test]
+#[should_panic(expected = "SSEUP")]
+fn test_sysv_amd64_classify_sseup() {
+    // __m128 would occupy an SSE and an SSEUP eightbyte:
+    sysv_amd64_classify(&SysVLayout {
+        size: 16,
+        align: 16,
+        fields: &[SysVScalar {
+            offset: 0,
+            size: 16,
+            class: SysVScalarClass::Sse,
+        }],
+    });
+}
+
+#[test]
+fn test_sysv_amd64_int128() {
+    use SysVReg::*;
+
+    assert_eq!(
+        sysv_amd64_return_location(&SYSV_AMD64_INT128),
+        SysVReturnLocation::Registers([Some(Integer(0)), Some(Integer(1))])
+    );
+
+    // 128-bit integers occupy a pair of registers, which need not start at an
+    // even register:
+    let long = [SysVScalar {
+        offset: 0,
+        size: 8,
+        class: SysVScalarClass::Integer,
+    }];
+    let long = SysVLayout {
+        size: 8,
+        align: 8,
+        fields: &long,
+    };
+    let mut args = SysVArgAllocator::new(sysv_amd64_return_location(&long));
+    args.allocate(&long);
+    assert_eq!(
+        args.allocate(&SYSV_AMD64_INT128),
+        SysVArgLocation::Registers([Some(Integer(1)), Some(Integer(2))])
+    );
+
+    // ... and are never split between registers and the stack. On the stack,
+    // they are 16-byte aligned:
+    args.allocate(&long);
+    args.allocate(&long);
+    assert_eq!(
+        args.allocate(&SYSV_AMD64_INT128),
+        SysVArgLocation::Stacked {
+            offset_words: 0,
+            words: 2
+        }
+    );
+    args.allocate(&long);
+    assert_eq!(
+        args.allocate(&long),
+        SysVArgLocation::Stacked {
+            offset_words: 2,
+            words: 1
+        }
+    );
+    assert_eq!(
+        args.allocate(&SYSV_AMD64_INT128),
+        SysVArgLocation::Stacked {
+            offset_words: 4,
+            words: 2
+        }
+    );
+}
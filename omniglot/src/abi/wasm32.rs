@@ -0,0 +1,11 @@
+// -*- fill-column: 80; -*-
+
+// ABI
+//
+// The WebAssembly C ABI (as implemented by clang for `wasm32`) does not pass
+// arguments in registers, or on a machine stack: all arguments are typed
+// parameters of the callee. Hence, this ABI does not define any argument
+// slots.
+
+pub enum Wasm32ABI {}
+impl super::OGABI for Wasm32ABI {}
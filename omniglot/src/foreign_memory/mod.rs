@@ -59,6 +59,8 @@ fn check_access_scope_imprint<ID: crate::id::OGID>(
 ) {
     if ref_imprint != access_scope.id_imprint() {
         check_scopes_imprint_panic::<ID>(ref_imprint, access_scope.id_imprint());
+    } else if access_scope.is_poisoned() {
+        check_access_scope_poisoned_panic();
     }
 }
 
@@ -74,6 +76,8 @@ fn check_scopes_imprint<ID: crate::id::OGID, R: AllocTracker>(
         check_scopes_imprint_panic::<ID>(ref_imprint, alloc_scope.id_imprint());
     } else if ref_imprint != access_scope.id_imprint() {
         check_scopes_imprint_panic::<ID>(ref_imprint, access_scope.id_imprint());
+    } else if access_scope.is_poisoned() {
+        check_access_scope_poisoned_panic();
     }
 }
 
@@ -85,6 +89,12 @@ pub(self) fn check_scopes_imprint_panic<ID: crate::id::OGID>(
     panic!("ID mismatch: {:?} vs. {:?}!", imprint_a, imprint_b,);
 }
 
+// Panic function for accesses against the `AccessScope` of a poisoned foreign
+// domain:
+fn check_access_scope_poisoned_panic() {
+    panic!("Access to the memory of a poisoned foreign domain!");
+}
+
 const fn sub_ref_check<T, U>(byte_offset: usize) -> bool {
     use core::mem::{align_of, size_of};
 
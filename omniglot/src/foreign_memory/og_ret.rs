@@ -30,6 +30,44 @@ impl<T> OGRet<T> {
     pub fn from_og_copy(og_copy: OGCopy<T>) -> OGRet<T> {
         OGRet::Initialized(og_copy.inner)
     }
+
+    /// Create an `OGRet` from a value returned in a pair of registers (e.g.,
+    /// `rax` and `rdx`), with the register holding the least significant bytes
+    /// first. This covers values up to twice the register size, such as `u128`
+    /// on 64-bit targets. Values narrower than the register pair are taken
+    /// from its least significant bytes.
+    ///
+    /// Returns `None` if `T` is larger than both registers combined.
+    pub fn from_register_pair<R: zerocopy::IntoBytes + zerocopy::Immutable>(
+        regs: [R; 2],
+    ) -> Option<OGRet<T>> {
+        // Registers of up to 128 bits:
+        let mut bytes = [0_u8; 32];
+
+        let reg_size = core::mem::size_of::<R>();
+        let size = core::mem::size_of::<T>();
+        if size > 2 * reg_size || 2 * reg_size > bytes.len() {
+            return None;
+        }
+
+        // Assemble the little-endian representation of the register pair,
+        // least significant byte first:
+        for (reg, dst) in regs.iter().zip(bytes.chunks_exact_mut(reg_size)) {
+            dst.copy_from_slice(reg.as_bytes());
+            if cfg!(target_endian = "big") {
+                dst.reverse();
+            }
+        }
+
+        // ... of which `T` occupies the least significant bytes, in native
+        // byte order:
+        let value = &mut bytes[..size];
+        if cfg!(target_endian = "big") {
+            value.reverse();
+        }
+
+        Some(OGRet::Initialized(MaybeValid::from_bytes(value)))
+    }
 }
 
 impl<T: Copy> Clone for OGRet<T> {
@@ -114,3 +152,41 @@ impl<T> OGRet<*mut T> {
         }
     }
 }
+
+#[test]
+fn test_og_ret_from_register_pair() {
+    let lo = 0x0123_4567_89ab_cdef_u64;
+    let hi = 0xfedc_ba98_7654_3210_u64;
+
+    assert_eq!(
+        OGRet::<u128>::from_register_pair([lo, hi]).unwrap().valid(),
+        (hi as u128) << 64 | lo as u128
+    );
+    assert_eq!(
+        OGRet::<i128>::from_register_pair([u64::MAX, u64::MAX])
+            .unwrap()
+            .valid(),
+        -1
+    );
+    assert_eq!(
+        OGRet::<u32>::from_register_pair([lo, hi]).unwrap().valid(),
+        0x89ab_cdef
+    );
+    assert_eq!(
+        OGRet::<u16>::from_register_pair([lo, hi]).unwrap().valid(),
+        0xcdef
+    );
+    assert_eq!(
+        OGRet::<u64>::from_register_pair([lo as u32, hi as u32])
+            .unwrap()
+            .valid(),
+        (hi as u32 as u64) << 32 | lo as u32 as u64
+    );
+    assert_eq!(
+        OGRet::<i8>::from_register_pair([-2_i32 as u32, 0])
+            .unwrap()
+            .valid(),
+        -2
+    );
+    assert!(OGRet::<u128>::from_register_pair([lo as u32, hi as u32]).is_none());
+}
@@ -52,6 +52,10 @@ pub enum OGError {
     /// as a zero-length allocation).
     AllocInvalidLayout,
 
+    /// Foreign code wrote past the bounds of an allocation with the contained
+    /// layout, as detected by red zones placed around it.
+    AllocRedZoneOverrun { layout: core::alloc::Layout },
+
     /// The Omniglot runtime could not allocate the callback due to an
     /// insufficient number of callback slots.
     SetupCallbackInsufficientSlots,
@@ -73,6 +77,60 @@ pub enum OGError {
     /// The runtime failed to find a symbol to be exposed by the
     /// foreign library.
     SymbolNotFound,
+
+    /// The runtime failed to load the foreign library.
+    LibraryLoadFailed,
+
+    /// The foreign library depends on imports which the runtime cannot provide
+    /// (such as a WebAssembly module importing WASI or other host functions).
+    LibraryUnsupportedImport,
+
+    /// The foreign domain has terminated (e.g., because the foreign library
+    /// crashed) and cannot execute any further foreign code.
+    ForeignDomainTerminated,
+
+    /// The foreign code raised a trap (e.g., due to an out-of-bounds memory
+    /// access or an `unreachable` instruction), and its execution was aborted.
+    ForeignTrap,
+
+    /// The foreign code did not complete before its deadline, and its execution
+    /// was aborted.
+    ///
+    /// The foreign domain is poisoned afterwards (see
+    /// [`AccessScope::poison`](markers::AccessScope::poison)).
+    Timeout,
+
+    /// A deadline was requested for foreign code, but the runtime cannot
+    /// preempt it (see
+    /// [`OGRuntime::SUPPORTS_DEADLINE`](rt::OGRuntime::SUPPORTS_DEADLINE)).
+    /// The foreign code was not executed.
+    DeadlineUnsupported,
+
+    /// The foreign code raised a fault (such as a segmentation fault, bus error
+    /// or arithmetic exception), and its execution was aborted.
+    ///
+    /// Contains the number of the raised signal, the faulting address, and the
+    /// address of the foreign symbol being executed. The foreign domain is
+    /// poisoned afterwards (see
+    /// [`AccessScope::poison`](markers::AccessScope::poison)).
+    ForeignFault {
+        signal: i32,
+        address: usize,
+        symbol: usize,
+    },
+
+    /// Foreign code modified a region of host memory which was shared with it
+    /// as immutable.
+    ///
+    /// Reported by runtimes which cannot prevent such writes, but detect them
+    /// after the fact (such as the `MockRt` with immutable region checks
+    /// enabled). Contains the address and length of the modified region, and
+    /// the address of the foreign symbol being executed.
+    ForeignWriteToImmutable {
+        region_start: usize,
+        region_len: usize,
+        symbol: usize,
+    },
 }
 
 pub type OGResult<T> = Result<T, OGError>;
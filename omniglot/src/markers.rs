@@ -35,14 +35,42 @@ impl<'a, T: AllocTracker, ID: OGID> AllocScope<'a, T, ID> {
 
 pub struct AccessScope<ID: OGID> {
     id_imprint: ID::Imprint,
+    poisoned: bool,
 }
 
 impl<ID: OGID> AccessScope<ID> {
     pub unsafe fn new(id_imprint: ID::Imprint) -> Self {
-        AccessScope { id_imprint }
+        AccessScope {
+            id_imprint,
+            poisoned: false,
+        }
     }
 
     pub fn id_imprint(&self) -> ID::Imprint {
         self.id_imprint
     }
+
+    /// Mark the foreign domain of this scope as poisoned.
+    ///
+    /// Runtimes poison a domain when foreign code has been aborted at an
+    /// arbitrary point (e.g., when its deadline expired), potentially leaving
+    /// its memory in an inconsistent state. Dereferencing any reference into
+    /// foreign memory against a poisoned scope panics.
+    pub fn poison(&mut self) {
+        self.poisoned = true;
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Clear this scope's poisoned state.
+    ///
+    /// # Safety
+    ///
+    /// The foreign domain must have been reset to a consistent state, such
+    /// that all of its memory may be accessed again.
+    pub unsafe fn unpoison(&mut self) {
+        self.poisoned = false;
+    }
 }
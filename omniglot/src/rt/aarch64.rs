@@ -0,0 +1,73 @@
+// -*- fill-column: 80; -*-
+
+use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
+use crate::foreign_memory::og_ret::OGRet;
+use crate::rt::OGRuntime;
+
+/// Retrieves the result of a function called through [`AArch64Rt::invoke`].
+///
+/// # Safety
+///
+/// Implementations must only return `OGRet`s containing the values returned by
+/// the last invoked foreign function.
+pub unsafe trait AArch64InvokeRes<RT: AArch64BaseRt, T: Sized> {
+    fn new() -> Self;
+
+    fn into_result_registers(self, rt: &RT) -> OGResult<OGRet<T>>;
+
+    /// # Safety
+    ///
+    /// `stacked_res` must point to a stacked allocation of `T` that the last
+    /// invoked foreign function returned its result in (passed in `x8`).
+    unsafe fn into_result_stacked(self, rt: &RT, stacked_res: *mut T) -> OGResult<OGRet<T>>;
+}
+
+pub trait AArch64BaseRt: OGRuntime<ABI = crate::abi::aarch64::AArch64ABI> + Sized {
+    type InvokeRes<T>: AArch64InvokeRes<Self, T>;
+}
+
+pub trait AArch64Rt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
+    AArch64BaseRt
+{
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the AAPCS64 calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
+    unsafe extern "C" fn invoke();
+}
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn aarch64_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"])
+}
+
+#[test]
+fn test_aarch64_argument_slots() {
+    use crate::abi::aarch64::AArch64ABI;
+    use crate::abi::calling_convention::{
+        AREG0, AREG1, AREG2, AREG3, AREG4, AREG5, AREG6, AREG7, Invalid, Stacked,
+    };
+
+    const {
+        assert!(aarch64_areg_index::<AREG0<AArch64ABI>>() == 0);
+        assert!(aarch64_areg_index::<AREG1<AArch64ABI>>() == 1);
+        assert!(aarch64_areg_index::<AREG2<AArch64ABI>>() == 2);
+        assert!(aarch64_areg_index::<AREG3<AArch64ABI>>() == 3);
+        assert!(aarch64_areg_index::<AREG4<AArch64ABI>>() == 4);
+        assert!(aarch64_areg_index::<AREG5<AArch64ABI>>() == 5);
+        assert!(aarch64_areg_index::<AREG6<AArch64ABI>>() == 6);
+        assert!(aarch64_areg_index::<AREG7<AArch64ABI>>() == 7);
+        assert!(aarch64_areg_index::<Stacked<0, AArch64ABI>>() == usize::MAX);
+        assert!(aarch64_areg_index::<Invalid>() == usize::MAX);
+
+        assert!(<Stacked<2, AArch64ABI> as ArgumentSlot>::STACK_OFFSET_WORDS == 2);
+        assert!(<AREG7<AArch64ABI> as ArgumentSlot>::STACK_OFFSET_WORDS == usize::MAX);
+    }
+}
@@ -0,0 +1,126 @@
+// -*- fill-column: 80; -*-
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+
+/// A [`MockRtAllocator`](super::MockRtAllocator) which allocates from a
+/// caller-provided memory arena.
+///
+/// Stacked allocations are strictly nested, so the arena is managed as a
+/// simple bump allocator: each allocation is placed above the current top of
+/// the arena, which is restored once the allocation's scope ends. This
+/// allocator neither requires `std`, nor any architecture-specific assembly.
+pub struct ArenaAllocator<'a> {
+    start: *mut u8,
+    len: usize,
+    // Offset of the first free byte in the arena:
+    top: Cell<usize>,
+    _arena: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> ArenaAllocator<'a> {
+    pub fn new(arena: &'a mut [MaybeUninit<u8>]) -> Self {
+        ArenaAllocator {
+            start: arena.as_mut_ptr() as *mut u8,
+            len: arena.len(),
+            top: Cell::new(0),
+            _arena: PhantomData,
+        }
+    }
+}
+
+impl super::MockRtAllocator for ArenaAllocator<'_> {
+    unsafe fn with_alloc<R, F: FnOnce(*mut ()) -> R>(
+        &self,
+        layout: core::alloc::Layout,
+        f: F,
+    ) -> Result<R, super::MockRtAllocError> {
+        let top = self.top.get();
+
+        // Align the allocation upward from the current top of the arena:
+        let offset = (self.start as usize)
+            .checked_add(top)
+            .and_then(|addr| addr.checked_next_multiple_of(layout.align()))
+            .map(|addr| addr - self.start as usize)
+            .ok_or(super::MockRtAllocError::NoMem)?;
+        let new_top = offset
+            .checked_add(layout.size())
+            .filter(|new_top| *new_top <= self.len)
+            .ok_or(super::MockRtAllocError::NoMem)?;
+
+        // Restore the previous top of the arena when this allocation's scope
+        // ends, even if `f` panics:
+        struct RestoreTop<'t>(&'t Cell<usize>, usize);
+
+        impl Drop for RestoreTop<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.1);
+            }
+        }
+
+        self.top.set(new_top);
+        let _restore_top = RestoreTop(&self.top, top);
+
+        Ok(f(unsafe { self.start.add(offset) } as *mut ()))
+    }
+
+    fn with_saved_state<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let _restore_top = super::MockRtRestoreCell(&self.top, self.top.get());
+        f()
+    }
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_arena_allocator() {
+    use crate::OGError;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+
+    let mut arena = [MaybeUninit::<u8>::uninit(); 256];
+    let arena_range = arena.as_ptr_range();
+    let (rt, mut alloc_scope, _access_scope) = unsafe {
+        super::MockRt::new(
+            false,
+            false,
+            ArenaAllocator::new(&mut arena),
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let byte = core::alloc::Layout::new::<u8>();
+    let over_aligned = core::alloc::Layout::from_size_align(64, 64).unwrap();
+
+    let first = rt
+        .allocate_stacked_mut(byte, &mut alloc_scope, |outer, alloc_scope| {
+            assert!(arena_range.contains(&(outer as *const MaybeUninit<u8>)));
+
+            // Nested allocations are placed above the outer one, respecting
+            // their alignment:
+            rt.allocate_stacked_mut(over_aligned, alloc_scope, |inner, alloc_scope| {
+                assert!(inner as usize > outer as usize);
+                assert_eq!(inner as usize % 64, 0);
+
+                // The arena is exhausted:
+                assert_eq!(
+                    rt.allocate_stacked_mut(
+                        core::alloc::Layout::new::<[u8; 256]>(),
+                        alloc_scope,
+                        |_, _| ()
+                    ),
+                    Err(OGError::AllocNoMem)
+                );
+            })
+            .unwrap();
+
+            outer
+        })
+        .unwrap();
+
+    // Memory is reused once the allocations' scopes end:
+    let second = rt
+        .allocate_stacked_mut(byte, &mut alloc_scope, |ptr, _| ptr)
+        .unwrap();
+    assert_eq!(first, second);
+}
@@ -0,0 +1,336 @@
+// -*- fill-column: 80; -*-
+
+//! A dedicated, guard-paged stack for foreign code executed by the
+//! [`MockRt`](super::MockRt).
+//!
+//! The [`ForeignStackAllocator`] switches to a separately allocated stack for
+//! the duration of `execute`, and places all stacked allocations on it. The
+//! lowest page of this stack is inaccessible. When foreign code (or host code
+//! invoked by it through callbacks) overflows the stack, we catch the resulting
+//! fault, abandon the foreign stack and return
+//! [`OGError::StackOverflow`](crate::OGError::StackOverflow) from `execute`.
+//!
+//! Recovering from an overflow discards all frames on the foreign stack without
+//! running their destructors. This includes frames of host callbacks.
+
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::ffi::c_void;
+use core::ops::Range;
+
+use super::recovery::with_recovery;
+use super::stack_alloc::{StackAllocator, StackFrameAllocAMD64};
+use super::{MockRtAllocError, MockRtAllocator};
+
+/// A [`MockRtAllocator`] which runs foreign code on a dedicated stack,
+/// protected by a guard page.
+///
+/// Allocations made by the host before executing foreign code are placed at the
+/// top of the foreign stack, and foreign code runs on the remainder below
+/// them. Allocations made while on the foreign stack (e.g., within callbacks)
+/// are placed below the current stack pointer.
+///
+/// Only the guard page protects against overflows. Foreign functions with
+/// stack frames larger than a page may skip over it, unless they probe their
+/// stack.
+pub struct ForeignStackAllocator {
+    mapping: *mut c_void,
+    mapping_len: usize,
+    // Usable stack, excluding the guard page:
+    stack: Range<usize>,
+    // Lowest address used by allocations made from the host stack:
+    top: Cell<usize>,
+    on_stack: StackAllocator<StackFrameAllocAMD64>,
+}
+
+impl ForeignStackAllocator {
+    /// Allocate a foreign stack of at least `stack_size` bytes, plus a guard
+    /// page below it.
+    pub fn new(stack_size: usize) -> Result<Self, MockRtAllocError> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mapping_len = stack_size
+            .checked_next_multiple_of(page_size)
+            .and_then(|size| size.checked_add(page_size))
+            .ok_or(MockRtAllocError::InvalidLayout)?;
+
+        let mapping = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                mapping_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_STACK,
+                -1,
+                0,
+            )
+        };
+        if mapping == libc::MAP_FAILED {
+            return Err(MockRtAllocError::NoMem);
+        }
+
+        let allocator = ForeignStackAllocator {
+            mapping,
+            mapping_len,
+            stack: mapping as usize + page_size..mapping as usize + mapping_len,
+            top: Cell::new(mapping as usize + mapping_len),
+            on_stack: StackAllocator::new(),
+        };
+
+        if unsafe { libc::mprotect(mapping, page_size, libc::PROT_NONE) } != 0 {
+            return Err(MockRtAllocError::NoMem);
+        }
+
+        Ok(allocator)
+    }
+
+    /// The usable range of the foreign stack, excluding its guard page.
+    pub fn stack_range(&self) -> Range<*mut u8> {
+        self.stack.start as *mut u8..self.stack.end as *mut u8
+    }
+
+    /// Discard the contents of the foreign stack, returning it to its initial,
+    /// zero-filled state.
+    ///
+    /// Returns `false` and leaves the stack untouched while it is in use, i.e.,
+    /// when there are live allocations on it or foreign code is executing.
+    pub fn reset(&self) -> bool {
+        if self.top.get() != self.stack.end || self.stack.contains(&stack_pointer()) {
+            return false;
+        }
+
+        let res = unsafe {
+            libc::madvise(
+                self.stack.start as *mut c_void,
+                self.stack.end - self.stack.start,
+                libc::MADV_DONTNEED,
+            )
+        };
+        res == 0
+    }
+}
+
+impl Drop for ForeignStackAllocator {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.mapping, self.mapping_len) };
+    }
+}
+
+impl MockRtAllocator for ForeignStackAllocator {
+    unsafe fn with_alloc<R, F: FnOnce(*mut ()) -> R>(
+        &self,
+        layout: Layout,
+        f: F,
+    ) -> Result<R, MockRtAllocError> {
+        let sp = stack_pointer();
+        let padded_size = layout
+            .size()
+            .checked_add(core::cmp::max(16, layout.align()) - 1)
+            .ok_or(MockRtAllocError::InvalidLayout)?;
+
+        if self.stack.contains(&sp) {
+            // We're executing on the foreign stack, so allocate by moving the
+            // stack pointer. Refuse allocations that would reach the guard page
+            // (or skip over it):
+            if sp - self.stack.start < padded_size {
+                return Err(MockRtAllocError::StackOverflow);
+            }
+
+            return unsafe { self.on_stack.with_alloc(layout, f) };
+        }
+
+        // Otherwise, place the allocation below the host's prior allocations:
+        let top = self.top.get();
+        let ptr = top
+            .checked_sub(layout.size())
+            .map(|ptr| ptr & !(layout.align() - 1))
+            .filter(|ptr| *ptr >= self.stack.start)
+            .ok_or(MockRtAllocError::StackOverflow)?;
+
+        // Restore the previous top when this allocation's scope ends, even if
+        // `f` panics:
+        struct RestoreTop<'t>(&'t Cell<usize>, usize);
+
+        impl Drop for RestoreTop<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.1);
+            }
+        }
+
+        self.top.set(ptr);
+        let _restore_top = RestoreTop(&self.top, top);
+
+        Ok(f(ptr as *mut ()))
+    }
+
+    fn places_all_allocations(&self) -> bool {
+        true
+    }
+
+    fn with_saved_state<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        self.on_stack.with_saved_state(|| {
+            let _restore_top = super::MockRtRestoreCell(&self.top, self.top.get());
+            f()
+        })
+    }
+
+    unsafe fn with_execution_stack<R, F: FnOnce() -> R>(
+        &self,
+        f: F,
+    ) -> Result<R, MockRtAllocError> {
+        // Nested executions (from within callbacks) continue on the foreign
+        // stack that we're already on:
+        if self.stack.contains(&stack_pointer()) {
+            return Ok(f());
+        }
+
+        match unsafe {
+            with_recovery(
+                Some(self.top.get() & !0xf),
+                self.mapping as usize..self.stack.start,
+                false,
+                None,
+                f,
+            )
+        } {
+            Ok(ret) => Ok(ret),
+            // Without a timeout or catching faults, `f` can only be aborted by a
+            // stack overflow:
+            Err(_) => Err(MockRtAllocError::StackOverflow),
+        }
+    }
+}
+
+fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) sp, options(nomem, nostack)) };
+    sp
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_foreign_stack_overflow() {
+    use crate::OGError;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+
+    fn recurse(depth: usize) -> usize {
+        let frame = core::hint::black_box([depth as u8; 256]);
+        if depth == 0 {
+            0
+        } else {
+            recurse(depth - 1) + frame[0] as usize
+        }
+    }
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        super::MockRt::new(
+            false,
+            false,
+            ForeignStackAllocator::new(64 * 1024).unwrap(),
+            OGRuntimeBranding::new(),
+        )
+    };
+    let stack = rt.allocator().stack_range();
+
+    rt.allocate_stacked_mut(
+        Layout::new::<u64>(),
+        &mut alloc_scope,
+        |ptr, alloc_scope| {
+            // Allocations are visible to foreign code on its stack, which runs
+            // below them:
+            assert!(stack.contains(&(ptr as *mut u8)));
+            let sp = rt
+                .execute(
+                    core::ptr::null(),
+                    alloc_scope,
+                    &mut access_scope,
+                    stack_pointer,
+                )
+                .unwrap();
+            assert!(stack.contains(&(sp as *mut u8)) && sp < ptr as usize);
+
+            // Nested allocations on the foreign stack are placed below its stack
+            // pointer, and must not exceed it:
+            let allocator = rt.allocator();
+            let (inner, sp) = unsafe {
+                allocator.with_execution_stack(|| {
+                    let sp = stack_pointer();
+                    let inner = allocator.with_alloc(Layout::new::<u64>(), |inner| inner);
+                    let too_large = allocator.with_alloc(Layout::new::<[u8; 128 * 1024]>(), |_| ());
+                    assert!(matches!(too_large, Err(MockRtAllocError::StackOverflow)));
+                    (inner.unwrap(), sp)
+                })
+            }
+            .unwrap();
+            assert!(stack.contains(&(inner as *mut u8)) && (inner as usize) < sp);
+
+            // Overflowing the foreign stack is reported as an error:
+            assert_eq!(
+                rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                    recurse(1 << 20)
+                }),
+                Err(OGError::StackOverflow)
+            );
+            assert!(!rt.allocator().reset());
+
+            // ... and does not affect subsequent executions:
+            assert_eq!(
+                rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                    recurse(16)
+                }),
+                Ok((1..=16).sum()),
+            );
+        },
+    )
+    .unwrap();
+
+    assert!(rt.allocator().reset());
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_foreign_stack_zero_copy_immutable() {
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        super::MockRt::new(
+            true,
+            false,
+            ForeignStackAllocator::new(64 * 1024).unwrap(),
+            OGRuntimeBranding::new(),
+        )
+    };
+    let stack = rt.allocator().stack_range();
+
+    // Immutable values are copied onto the foreign stack, instead of sharing
+    // host memory with foreign code:
+    rt.write_stacked_t(
+        42_u64,
+        &mut alloc_scope,
+        &mut access_scope,
+        |val, alloc_scope, access_scope| {
+            assert!(stack.contains(&(val.as_ptr() as *mut u8)));
+            assert_eq!(*val.validate(access_scope).unwrap(), 42);
+
+            let host = [1_u32, 2, 3];
+            rt.write_stacked_ref_t(&host, alloc_scope, access_scope, |arr, _, access_scope| {
+                assert!(stack.contains(&(arr.as_ptr() as *mut u8)));
+                assert_ne!(arr.as_ptr(), &host as *const _);
+                assert_eq!(*arr.validate(access_scope).unwrap(), host);
+            })
+            .unwrap();
+
+            rt.write_stacked_slice(
+                &host,
+                alloc_scope,
+                access_scope,
+                |slice, _, access_scope| {
+                    assert!(stack.contains(&(slice.as_ptr() as *mut u8)));
+                    assert_eq!(&*slice.validate(access_scope).unwrap(), &host);
+                },
+            )
+            .unwrap();
+        },
+    )
+    .unwrap();
+}
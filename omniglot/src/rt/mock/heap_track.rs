@@ -0,0 +1,306 @@
+// -*- fill-column: 80; -*-
+
+//! Tracking of a foreign library's heap allocations, for use with the
+//! [`MockRt`](super::MockRt).
+//!
+//! We interpose the library's calls to `malloc`, `calloc`, `realloc` and
+//! `free`, and record all live heap blocks. Upgrades of pointers returned by
+//! foreign code can then be validated against these blocks, catching bindings
+//! that upgrade dangling or out-of-bounds pointers.
+//!
+//! The set of live blocks is shared among all tracking runtimes in this
+//! process. Allocations made before interposition (e.g., by the library's
+//! initializers), or made on the library's behalf by other libraries (such as
+//! `strdup` or `fopen` in the host's C library), are not tracked.
+
+use core::cell::UnsafeCell;
+use core::ffi::{CStr, c_void};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+use crate::util::dl::DlLibrary;
+use crate::{OGError, OGResult};
+
+// Live heap blocks, mapping their start address to their size:
+static MOCK_RT_HEAP_BLOCKS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+type HeapBlocksGuard = MutexGuard<'static, BTreeMap<usize, usize>>;
+
+std::thread_local! {
+    // The guard of `MOCK_RT_HEAP_BLOCKS` while this thread holds it. Our hooks
+    // run as part of foreign code, which may be abandoned without running any
+    // destructors. Keeping the guard here allows the MockRt to release it
+    // afterwards, through `release_abandoned_lock`:
+    static MOCK_RT_HEAP_BLOCKS_GUARD: UnsafeCell<Option<HeapBlocksGuard>> =
+        const { UnsafeCell::new(None) };
+}
+
+fn with_heap_blocks<R>(f: impl FnOnce(&mut BTreeMap<usize, usize>) -> R) -> R {
+    // Releases the lock again, even if `f` panics:
+    struct ReleaseGuard(*mut Option<HeapBlocksGuard>);
+
+    impl Drop for ReleaseGuard {
+        fn drop(&mut self) {
+            unsafe { *self.0 = None };
+        }
+    }
+
+    // A panic while holding this lock cannot leave the map in an inconsistent
+    // state, so ignore any poisoning:
+    let blocks = MOCK_RT_HEAP_BLOCKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let slot = MOCK_RT_HEAP_BLOCKS_GUARD.with(UnsafeCell::get);
+    unsafe { *slot = Some(blocks) };
+    let release_guard = ReleaseGuard(slot);
+
+    f(unsafe { (*release_guard.0).as_mut() }.unwrap())
+}
+
+/// Release the lock on the set of live heap blocks, if it is held by frames of
+/// this thread that have been abandoned.
+///
+/// # Safety
+///
+/// Must not be called while any frame of this thread could still access the
+/// set of live heap blocks, i.e., only after abandoning foreign code.
+pub(super) unsafe fn release_abandoned_lock() {
+    let _ = MOCK_RT_HEAP_BLOCKS_GUARD.try_with(|slot| unsafe { *slot.get() = None });
+}
+
+// Our own allocations (such as nodes of the `BTreeMap` above) are served by
+// the host's allocator directly, so these never recurse:
+unsafe extern "C" fn mock_rt_heap_malloc(size: usize) -> *mut c_void {
+    let ptr = unsafe { libc::malloc(size) };
+    if !ptr.is_null() {
+        with_heap_blocks(|blocks| blocks.insert(ptr as usize, size));
+    }
+    ptr
+}
+
+unsafe extern "C" fn mock_rt_heap_calloc(nmemb: usize, size: usize) -> *mut c_void {
+    let ptr = unsafe { libc::calloc(nmemb, size) };
+    if !ptr.is_null() {
+        // calloc fails when this multiplication overflows:
+        with_heap_blocks(|blocks| blocks.insert(ptr as usize, nmemb * size));
+    }
+    ptr
+}
+
+unsafe extern "C" fn mock_rt_heap_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    // Hold the lock across the reallocation, such that no other thread can
+    // observe the old block as live after it has been moved:
+    with_heap_blocks(|blocks| {
+        let new_ptr = unsafe { libc::realloc(ptr, size) };
+        if !new_ptr.is_null() {
+            blocks.remove(&(ptr as usize));
+            blocks.insert(new_ptr as usize, size);
+        } else if size == 0 {
+            // The block has been freed:
+            blocks.remove(&(ptr as usize));
+        }
+        new_ptr
+    })
+}
+
+unsafe extern "C" fn mock_rt_heap_free(ptr: *mut c_void) {
+    with_heap_blocks(|blocks| {
+        blocks.remove(&(ptr as usize));
+        unsafe { libc::free(ptr) };
+    })
+}
+
+/// Interpose the heap allocation functions used by `library`.
+///
+/// Libraries need not import all of these functions. However, interposition
+/// fails if the library imports none of them (e.g., because it uses a
+/// statically linked allocator), as none of its allocations could be tracked
+/// then. On failure, all GOT slots rewritten so far are restored, and the
+/// library is left untouched.
+///
+/// # Safety
+///
+/// No thread may concurrently call into this library.
+pub(super) unsafe fn interpose_heap(library: &DlLibrary) -> OGResult<()> {
+    let functions: [(&CStr, *const ()); 4] = [
+        (c"malloc", mock_rt_heap_malloc as *const ()),
+        (c"calloc", mock_rt_heap_calloc as *const ()),
+        (c"realloc", mock_rt_heap_realloc as *const ()),
+        (c"free", mock_rt_heap_free as *const ()),
+    ];
+
+    let mut interpositions = Vec::with_capacity(functions.len());
+    for (symbol, replacement) in functions {
+        match unsafe { library.interpose(symbol, replacement) } {
+            Some(interposition) => interpositions.push(interposition),
+            None => break,
+        }
+    }
+
+    if interpositions.len() == functions.len()
+        && interpositions
+            .iter()
+            .any(|interposition| interposition.slots() != 0)
+    {
+        return Ok(());
+    }
+
+    for interposition in interpositions.into_iter().rev() {
+        unsafe { interposition.revert(library) };
+    }
+    Err(OGError::LibraryLoadFailed)
+}
+
+/// Check whether `[ptr, ptr + len)` lies within a single live heap block.
+pub(super) fn heap_block_contains(ptr: *mut (), len: usize) -> bool {
+    let start = ptr as usize;
+    let Some(end) = start.checked_add(len) else {
+        return false;
+    };
+
+    with_heap_blocks(|blocks| {
+        blocks
+            .range(..=start)
+            .next_back()
+            .is_some_and(|(block_start, block_size)| end <= block_start + block_size)
+    })
+}
+
+#[test]
+fn test_heap_block_tracking() {
+    unsafe {
+        let ptr = mock_rt_heap_malloc(16) as *mut ();
+        assert!(heap_block_contains(ptr, 16));
+        assert!(heap_block_contains(ptr.byte_add(8), 8));
+        assert!(!heap_block_contains(ptr.byte_add(8), 9));
+
+        let ptr = mock_rt_heap_realloc(ptr as *mut c_void, 4096) as *mut ();
+        assert!(heap_block_contains(ptr, 4096));
+
+        mock_rt_heap_free(ptr as *mut c_void);
+        assert!(!heap_block_contains(ptr, 1));
+
+        let ptr = mock_rt_heap_calloc(4, 8) as *mut ();
+        assert!(heap_block_contains(ptr, 32));
+        mock_rt_heap_free(ptr as *mut c_void);
+    }
+}
+
+#[cfg(all(feature = "runtime_id", target_arch = "x86_64"))]
+#[test]
+fn test_abandoned_heap_lock_released() {
+    use crate::OGError;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+    use crate::rt::mock::heap_alloc::HeapAllocator;
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        unsafe { super::MockRt::new(false, false, HeapAllocator, OGRuntimeBranding::new()) };
+
+    // Foreign code whose deadline expires while one of our hooks holds the
+    // lock on the set of live heap blocks:
+    assert_eq!(
+        rt.execute_with_deadline(
+            core::ptr::null(),
+            &mut alloc_scope,
+            &mut access_scope,
+            core::time::Duration::from_millis(10),
+            || with_heap_blocks(|_| {
+                loop {
+                    core::hint::black_box(());
+                }
+            }),
+        ),
+        Err(OGError::Timeout)
+    );
+
+    // The lock has been released again:
+    let ptr = unsafe { mock_rt_heap_malloc(16) } as *mut ();
+    assert!(heap_block_contains(ptr, 16));
+    unsafe { mock_rt_heap_free(ptr as *mut c_void) };
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_library_heap_tracking() {
+    use crate::alloc_tracker::AllocTracker;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+    use crate::rt::mock::MockRt;
+    use crate::rt::mock::heap_alloc::HeapAllocator;
+
+    let path = crate::util::test_lib::compile(
+        "mock_rt_heap_track",
+        "
+        #include <stdlib.h>
+
+        void *alloc(size_t size) {
+            return malloc(size);
+        }
+
+        void release(void *ptr) {
+            free(ptr);
+        }
+        ",
+    );
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::<_, _>::new_with_library_heap_tracking(
+            &path,
+            false,
+            HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    }
+    .unwrap();
+
+    let symtab = rt.resolve_symbols(&[c"alloc", c"release"], &[]).unwrap();
+    let alloc: unsafe extern "C" fn(usize) -> *mut () =
+        unsafe { core::mem::transmute(rt.lookup_symbol(0, usize::MAX, &symtab).unwrap()) };
+    let release: unsafe extern "C" fn(*mut ()) =
+        unsafe { core::mem::transmute(rt.lookup_symbol(1, usize::MAX, &symtab).unwrap()) };
+
+    // Blocks allocated by the library are valid for upgrades until freed:
+    let ptr = rt
+        .execute(
+            core::ptr::null(),
+            &mut alloc_scope,
+            &mut access_scope,
+            || unsafe { alloc(24) },
+        )
+        .unwrap();
+    assert!(alloc_scope.tracker().is_valid_mut(ptr, 24));
+    assert!(!alloc_scope.tracker().is_valid_mut(ptr, 25));
+
+    rt.execute(
+        core::ptr::null(),
+        &mut alloc_scope,
+        &mut access_scope,
+        || unsafe { release(ptr) },
+    )
+    .unwrap();
+    assert!(!alloc_scope.tracker().is_valid_mut(ptr, 1));
+
+    // Libraries that don't use the host's allocator cannot be tracked:
+    let path = crate::util::test_lib::compile(
+        "mock_rt_heap_track_none",
+        "
+        unsigned long identity(unsigned long x) {
+            return x;
+        }
+        ",
+    );
+    assert!(matches!(
+        unsafe {
+            MockRt::<_, _>::new_with_library_heap_tracking(
+                &path,
+                false,
+                HeapAllocator,
+                OGRuntimeBranding::new(),
+            )
+        },
+        Err(OGError::LibraryLoadFailed)
+    ));
+}
@@ -11,12 +11,49 @@ use crate::foreign_memory::{og_mut_ref::OGMutRef, og_ref::OGRef, og_slice::OGSli
 use crate::id::OGID;
 use crate::markers::{AccessScope, AllocScope};
 use crate::rt::{CallbackContext, CallbackReturn, OGRuntime};
+#[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+use crate::util::dl::DlLibrary;
 use crate::{OGError, OGResult};
 
+pub mod arena_alloc;
+
+#[cfg_attr(
+    feature = "nightly",
+    doc(cfg(all(
+        feature = "std",
+        target_os = "linux",
+        target_env = "gnu",
+        target_arch = "x86_64"
+    )))
+)]
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+pub mod foreign_stack;
+
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+mod recovery;
+
 #[cfg_attr(feature = "nightly", doc(cfg(feature = "std")))]
 #[cfg(any(feature = "std", doc))]
 pub mod heap_alloc;
 
+#[cfg_attr(
+    feature = "nightly",
+    doc(cfg(all(feature = "std", target_os = "linux", target_env = "gnu")))
+)]
+#[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+pub mod heap_track;
+
+pub mod red_zone;
 pub mod stack_alloc;
 
 // Use 6 arguments, as that's how many are passed in registers on x86.
@@ -29,9 +66,27 @@ pub struct CallbackTrampolineFnReturn {
 type CallbackTrampolineFn =
     unsafe extern "C" fn(usize, usize, usize, usize, usize, usize) -> CallbackTrampolineFnReturn;
 
+// Whether the callback trampolines capture the floating-point argument and
+// return registers on this target:
+const MOCK_RT_CAPTURES_FP_REGS: bool = cfg!(any(
+    target_arch = "x86_64",
+    all(
+        any(target_arch = "riscv32", target_arch = "riscv64"),
+        target_feature = "f"
+    )
+));
+
 #[derive(Debug, Clone)]
 pub struct MockRtCallbackContext {
     pub arg_regs: [usize; 6],
+    // Raw bit patterns of the floating-point argument registers (xmm0-xmm7 on
+    // x86_64, fa0-fa7 on RISC-V). A `float` occupies the lower 32 bits of its
+    // register.
+    pub fp_arg_regs: [u64; 8],
+    // Points to the first stack-spilled argument, i.e., the caller's stack
+    // pointer at the time of the call. Captured by the assembly trampolines on
+    // x86_64, AArch64 and RISC-V, and null on all other architectures.
+    stack_pointer: *mut c_void,
 }
 
 impl CallbackContext for MockRtCallbackContext {
@@ -39,14 +94,25 @@ impl CallbackContext for MockRtCallbackContext {
         self.arg_regs.get(reg).copied()
     }
 
+    fn get_fp_argument_register(&self, reg: usize) -> Option<u64> {
+        // Floating-point registers are only captured by some of the assembly
+        // trampolines:
+        if MOCK_RT_CAPTURES_FP_REGS {
+            self.fp_arg_regs.get(reg).copied()
+        } else {
+            None
+        }
+    }
+
     fn get_stack_pointer(&self) -> *mut c_void {
-        unimplemented!()
+        self.stack_pointer
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct MockRtCallbackReturn {
     pub return_regs: [usize; 2],
+    pub fp_return_regs: [u64; 2],
 }
 
 impl CallbackReturn for MockRtCallbackReturn {
@@ -58,12 +124,412 @@ impl CallbackReturn for MockRtCallbackReturn {
             false
         }
     }
+
+    fn set_fp_return_register(&mut self, reg: usize, value: u64) -> bool {
+        if !MOCK_RT_CAPTURES_FP_REGS {
+            return false;
+        }
+
+        if let Some(r) = self.fp_return_regs.get_mut(reg) {
+            *r = value;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// The allocation chain that callbacks are dispatched against, along with the
+// imprint of the runtime that published it.
+//
+// Trampolines are shared between all runtimes with the same `ID` type, so they
+// cannot identify the runtime they belong to. However, foreign code can only
+// invoke a callback while the host is executing foreign code of that runtime,
+// on the same thread. We thus keep a per-thread stack of these frames, where
+// each runtime pushes a frame for its own allocation chain when executing
+// foreign code or setting up a callback, and pops it again afterwards. Frames
+// are linked through `prev`, innermost first, and live on the stack of the
+// function that pushed them.
+//
+// A trampoline is dispatched against the innermost frame pushed by a runtime
+// with its `ID` type. `ID` need not be `'static`, so we identify it through
+// the `TypeId` of its `ID::Imprint`, and its type name. Distinct `ID` types
+// sharing an imprint type are told apart by their names. Only a frame matching
+// both may have its `id_imprint` cast to `ID::Imprint`.
+//
+// Runtimes of the same `ID` type share all trampolines. A trampoline invoked
+// while they are nested on one thread is thus dispatched to the innermost of
+// them, whose foreign code is executing.
+struct MockRtDispatchFrame {
+    alloc_chain: *const MockRtAllocChain<'static>,
+    imprint_type: core::any::TypeId,
+    id_type_name: &'static str,
+    id_imprint: *const (),
+    prev: *const MockRtDispatchFrame,
 }
 
-// TODO: this should be a hashmap which takes a runtime ID derived from the OGID
-// as key, to work with multiple mock runtimes in parallel:
-static mut ACTIVE_ALLOC_CHAIN_HEAD_ROG: Option<(*const MockRtAllocChain<'static>, *const ())> =
-    None;
+#[cfg(feature = "std")]
+std::thread_local! {
+    static MOCK_RT_DISPATCH_FRAME: core::cell::Cell<*const MockRtDispatchFrame> =
+        const { core::cell::Cell::new(core::ptr::null()) };
+}
+
+// Without `std`, we have no thread-local storage. We instead assume that there
+// is only a single thread of execution, as is the case on most embedded
+// platforms:
+#[cfg(not(feature = "std"))]
+struct MockRtDispatchFrameCell(core::cell::Cell<*const MockRtDispatchFrame>);
+
+#[cfg(not(feature = "std"))]
+unsafe impl Sync for MockRtDispatchFrameCell {}
+
+#[cfg(not(feature = "std"))]
+static MOCK_RT_DISPATCH_FRAME: MockRtDispatchFrameCell =
+    MockRtDispatchFrameCell(core::cell::Cell::new(core::ptr::null()));
+
+fn mock_rt_dispatch_frame_replace(
+    frame: *const MockRtDispatchFrame,
+) -> *const MockRtDispatchFrame {
+    #[cfg(feature = "std")]
+    {
+        MOCK_RT_DISPATCH_FRAME.with(|cell| cell.replace(frame))
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        MOCK_RT_DISPATCH_FRAME.0.replace(frame)
+    }
+}
+
+fn mock_rt_dispatch_frame_get() -> *const MockRtDispatchFrame {
+    #[cfg(feature = "std")]
+    {
+        MOCK_RT_DISPATCH_FRAME.with(|cell| cell.get())
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        MOCK_RT_DISPATCH_FRAME.0.get()
+    }
+}
+
+// Find the innermost frame pushed by a runtime with the `ID` type of a
+// trampoline, along with its imprint (see `MockRtDispatchFrame`):
+fn mock_rt_dispatch_frame_find<ID: OGID>()
+-> Option<(&'static MockRtDispatchFrame, &'static ID::Imprint)> {
+    let imprint_type = core::any::TypeId::of::<ID::Imprint>();
+    let id_type_name = core::any::type_name::<ID>();
+
+    let mut frame_ptr = mock_rt_dispatch_frame_get();
+    // All frames remain live while they are linked into this list:
+    while let Some(frame) = unsafe { frame_ptr.as_ref() } {
+        if frame.imprint_type == imprint_type && frame.id_type_name == id_type_name {
+            return Some((frame, unsafe {
+                &*(frame.id_imprint as *const ID::Imprint)
+            }));
+        }
+        frame_ptr = frame.prev;
+    }
+
+    None
+}
+
+// A panic raised by a host callback. Panics must never unwind through foreign
+// code, so `callback_wrapper` catches them and stores them here. They are
+// resumed once control returns to the host (in `mock_rt_with_dispatch_frame`).
+//
+// Without `std` we cannot catch panics. There, the `extern "C"` boundary of the
+// trampolines turns any unwinding panic into an abort.
+#[cfg(feature = "std")]
+std::thread_local! {
+    static MOCK_RT_CALLBACK_PANIC: core::cell::Cell<
+        Option<std::boxed::Box<dyn core::any::Any + Send + 'static>>,
+    > = const { core::cell::Cell::new(None) };
+}
+
+// Run a host callback, catching any panic it raises:
+fn mock_rt_catch_callback_panic(f: impl FnOnce()) {
+    #[cfg(feature = "std")]
+    {
+        if let Err(payload) = std::panic::catch_unwind(core::panic::AssertUnwindSafe(f)) {
+            // Foreign code may invoke further callbacks before returning to
+            // the host. Only the first panic is resumed:
+            MOCK_RT_CALLBACK_PANIC.with(|cell| {
+                let pending = cell.take();
+                cell.set(Some(pending.unwrap_or(payload)));
+            });
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        f()
+    }
+}
+
+// Run `f`, aborting it with `OGError::Timeout` once `timeout` expires, or (with
+// `catch_faults`) with `OGError::ForeignFault` when it raises a fault. Where we
+// cannot recover from faults or preempt foreign code, `f` always runs to
+// completion:
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+fn mock_rt_with_recovery<R>(
+    target_symbol: *const (),
+    catch_faults: bool,
+    timeout: Option<core::time::Duration>,
+    f: impl FnOnce() -> R,
+) -> OGResult<R> {
+    if !catch_faults && timeout.is_none() {
+        return Ok(f());
+    }
+
+    unsafe { recovery::with_recovery(None, 0..0, catch_faults, timeout, f) }.map_err(
+        |aborted| match aborted {
+            recovery::Aborted::Fault { signal, address } => OGError::ForeignFault {
+                signal,
+                address,
+                symbol: target_symbol as usize,
+            },
+            recovery::Aborted::StackOverflow => OGError::StackOverflow,
+            recovery::Aborted::Timeout => OGError::Timeout,
+        },
+    )
+}
+
+#[cfg(not(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+)))]
+fn mock_rt_with_recovery<R>(
+    _target_symbol: *const (),
+    _catch_faults: bool,
+    _timeout: Option<core::time::Duration>,
+    f: impl FnOnce() -> R,
+) -> OGResult<R> {
+    Ok(f())
+}
+
+// Run host code invoked by foreign code through a callback. Faults it raises
+// are a bug in the host, and must not be caught as faults of foreign code:
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+fn mock_rt_with_host_code<R>(f: impl FnOnce() -> R) -> R {
+    recovery::with_host_code(f)
+}
+
+#[cfg(not(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+)))]
+fn mock_rt_with_host_code<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+/// Hooks of a runtime built on the MockRt that revokes the host's access to its
+/// own memory while foreign code executes (see
+/// [`MprotectRt`](crate::rt::mprotect::MprotectRt)).
+///
+/// Host code running in the midst of foreign code (callbacks, and our signal
+/// handler) first calls `restore`, which returns whether access had been
+/// revoked. If so, it calls `revoke` before returning to foreign code. Both
+/// are invoked with `data`, and must only access memory that remains
+/// accessible to foreign code.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+#[derive(Clone, Copy)]
+pub(crate) struct MockRtHostAccessHooks {
+    pub data: *const (),
+    pub restore: unsafe extern "C" fn(*const ()) -> bool,
+    pub revoke: unsafe extern "C" fn(*const ()),
+}
+
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+std::thread_local! {
+    // Only ever accessed as a plain `Cell`, which is safe to do from within a
+    // signal handler, and while the host's heap is inaccessible:
+    static MOCK_RT_HOST_ACCESS_HOOKS: core::cell::Cell<Option<MockRtHostAccessHooks>> =
+        const { core::cell::Cell::new(None) };
+}
+
+/// Run `f` with `hooks` installed for this thread, restoring the previously
+/// installed hooks afterwards.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+pub(crate) fn mock_rt_with_host_access_hooks<R>(
+    hooks: MockRtHostAccessHooks,
+    f: impl FnOnce() -> R,
+) -> R {
+    let prev = MOCK_RT_HOST_ACCESS_HOOKS.with(|cell| cell.replace(Some(hooks)));
+    let _restore_hooks = MockRtRestoreHooks(prev);
+    f()
+}
+
+// Restores the outer host access hooks, even when `f` unwinds or is abandoned
+// (in which case the MockRt's `execute` drops its frame):
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+struct MockRtRestoreHooks(Option<MockRtHostAccessHooks>);
+
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+impl Drop for MockRtRestoreHooks {
+    fn drop(&mut self) {
+        MOCK_RT_HOST_ACCESS_HOOKS.with(|cell| cell.set(self.0));
+    }
+}
+
+/// Restore the host's access to its memory, if revoked by the hooks installed
+/// for this thread. Returns these hooks if so, which must then be used to
+/// revoke access again before returning to foreign code.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+pub(crate) fn mock_rt_restore_host_access() -> Option<MockRtHostAccessHooks> {
+    // During thread exit, no foreign code can be executing:
+    let hooks = MOCK_RT_HOST_ACCESS_HOOKS
+        .try_with(|cell| cell.get())
+        .ok()??;
+    unsafe { (hooks.restore)(hooks.data) }.then_some(hooks)
+}
+
+// Run host code in the midst of foreign code, with the host's access to its
+// memory restored for the duration of `f`:
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+fn mock_rt_with_host_access<R>(f: impl FnOnce() -> R) -> R {
+    let restored = mock_rt_restore_host_access();
+    let res = f();
+    if let Some(hooks) = restored {
+        unsafe { (hooks.revoke)(hooks.data) };
+    }
+    res
+}
+
+#[cfg(not(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+)))]
+fn mock_rt_with_host_access<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+/// The lowest stack pointer of this thread's host stack, recorded when foreign
+/// code was entered from it with its stack within `stack`.
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+pub(crate) fn mock_rt_host_stack_pointer(stack: core::ops::Range<usize>) -> Option<usize> {
+    recovery::lowest_host_stack_pointer(stack)
+}
+
+// Restores a `Cell` of an allocator's bookkeeping to its saved value once
+// dropped, see `MockRtAllocator::with_saved_state`:
+struct MockRtRestoreCell<'c, T: Copy>(&'c core::cell::Cell<T>, T);
+
+impl<T: Copy> Drop for MockRtRestoreCell<'_, T> {
+    fn drop(&mut self) {
+        self.0.set(self.1);
+    }
+}
+
+// Releases host locks held by abandoned frames of foreign code or callbacks,
+// once dropped. Other per-thread state (such as dispatch frames) is restored by
+// the drop guards of the frames enclosing the foreign code:
+struct MockRtReleaseAbandonedLocks;
+
+impl Drop for MockRtReleaseAbandonedLocks {
+    fn drop(&mut self) {
+        // All frames that could hold these locks have returned or have been
+        // abandoned by now:
+        #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+        unsafe {
+            heap_track::release_abandoned_lock()
+        };
+    }
+}
+
+// Push a dispatch frame for `alloc_chain` for the duration of `f`:
+fn mock_rt_with_dispatch_frame<ID: OGID, R>(
+    alloc_chain: &MockRtAllocChain<'_>,
+    id_imprint: &ID::Imprint,
+    f: impl FnOnce() -> R,
+) -> R {
+    // Restores the outer frame, even when `f` unwinds. All the references of
+    // the inner frame are local to our caller's stack, so there's nothing we'd
+    // need to deallocate:
+    struct RestoreFrame(*const MockRtDispatchFrame);
+
+    impl Drop for RestoreFrame {
+        fn drop(&mut self) {
+            mock_rt_dispatch_frame_replace(self.0);
+        }
+    }
+
+    let frame = MockRtDispatchFrame {
+        alloc_chain: (alloc_chain as *const MockRtAllocChain<'_>).cast(),
+        imprint_type: core::any::TypeId::of::<ID::Imprint>(),
+        id_type_name: core::any::type_name::<ID>(),
+        id_imprint: id_imprint as *const ID::Imprint as *const (),
+        prev: mock_rt_dispatch_frame_get(),
+    };
+    let _restore_frame = RestoreFrame(mock_rt_dispatch_frame_replace(&frame));
+
+    let res = f();
+
+    // Re-raise any panic of a callback invoked within `f`, now that we're
+    // back on the host side:
+    #[cfg(feature = "std")]
+    if let Some(payload) = MOCK_RT_CALLBACK_PANIC.with(|cell| cell.take()) {
+        std::panic::resume_unwind(payload);
+    }
+
+    res
+}
 
 #[inline(never)]
 extern "C" fn mock_rt_callback_dispatch<ID: OGID>(
@@ -71,12 +537,10 @@ extern "C" fn mock_rt_callback_dispatch<ID: OGID>(
     callback_ctx: &MockRtCallbackContext,
     callback_ret: &mut MockRtCallbackReturn,
 ) {
-    let alloc_chain_head_ref_opt: &Option<(*const MockRtAllocChain<'_>, *const ())> =
-        unsafe { &*core::ptr::addr_of!(ACTIVE_ALLOC_CHAIN_HEAD_ROG) };
+    let (frame, id_imprint) = mock_rt_dispatch_frame_find::<ID>()
+        .expect("No active MockRt with this callback's ID type on this thread!");
 
-    let (alloc_chain_head_ref_ptr, id_imprint_ptr) = alloc_chain_head_ref_opt.unwrap();
-    let alloc_chain_head_ref: &MockRtAllocChain<'static> = unsafe { &*alloc_chain_head_ref_ptr };
-    let id_imprint: &ID::Imprint = unsafe { &*(id_imprint_ptr as *const ID::Imprint) };
+    let alloc_chain_head_ref: &MockRtAllocChain<'static> = unsafe { &*frame.alloc_chain };
 
     let callback_desc = alloc_chain_head_ref
         .find_callback_descriptor(callback_id)
@@ -85,7 +549,7 @@ extern "C" fn mock_rt_callback_dispatch<ID: OGID>(
     let mut inner_alloc_scope: AllocScope<'_, MockRtAllocChain<'_>, ID> =
         unsafe { AllocScope::new(MockRtAllocChain::Cons(alloc_chain_head_ref), *id_imprint) };
 
-    unsafe {
+    mock_rt_with_host_code(|| unsafe {
         callback_desc.invoke(
             callback_ctx,
             callback_ret,
@@ -95,11 +559,268 @@ extern "C" fn mock_rt_callback_dispatch<ID: OGID>(
             // trampoline.
             &mut AccessScope::<ID>::new(*id_imprint) as *mut _ as *mut (),
         )
-    };
+    });
+}
+
+// Register state saved by the callback trampolines. The floating-point return
+// registers are loaded from this frame after the callback returns.
+#[repr(C)]
+struct MockRtCallbackTrampolineFrame {
+    arg_regs: [usize; 6],
+    fp_arg_regs: [u64; 8],
+    fp_return_regs: [u64; 2],
 }
 
 // TODO: reason about aliasing of the MockRtAllocChain
-extern "C" fn mock_rt_callback_trampoline<const CALLBACK_ID: usize, ID: OGID>(
+extern "C" fn mock_rt_callback_trampoline_inner<ID: OGID>(
+    callback_id: usize,
+    frame: &mut MockRtCallbackTrampolineFrame,
+    stack_pointer: *mut c_void,
+) -> CallbackTrampolineFnReturn {
+    let mut callback_ret = MockRtCallbackReturn {
+        return_regs: [0; 2],
+        fp_return_regs: [0; 2],
+    };
+
+    // The callback runs as host code, which may need access to host memory
+    // that has been revoked for foreign code. Callback panics are caught within
+    // the dispatcher, so access is revoked again before returning:
+    mock_rt_with_host_access(|| {
+        mock_rt_callback_dispatch::<ID>(
+            callback_id,
+            &MockRtCallbackContext {
+                arg_regs: frame.arg_regs,
+                fp_arg_regs: frame.fp_arg_regs,
+                stack_pointer,
+            },
+            &mut callback_ret,
+        )
+    });
+
+    frame.fp_return_regs = callback_ret.fp_return_regs;
+
+    CallbackTrampolineFnReturn {
+        reg0: callback_ret.return_regs[0],
+        reg1: callback_ret.return_regs[1],
+    }
+}
+
+// On x86_64, the trampoline additionally captures the caller's stack pointer
+// and the floating-point argument registers, such that callbacks can access
+// stack-spilled and floating-point arguments:
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn mock_rt_callback_trampoline<const CALLBACK_ID: usize, ID: OGID>(
+    _a0: usize,
+    _a1: usize,
+    _a2: usize,
+    _a3: usize,
+    _a4: usize,
+    _a5: usize,
+) -> CallbackTrampolineFnReturn {
+    core::arch::naked_asm!(
+        "
+        // Set up a stack frame, and save all argument registers in a
+        // `MockRtCallbackTrampolineFrame` on the stack. Its size is a multiple
+        // of 16 bytes, leaving the stack pointer 16-byte aligned:
+        push rbp
+        mov rbp, rsp
+        sub rsp, {frame_size}
+        mov qword ptr [rsp + 0], rdi
+        mov qword ptr [rsp + 8], rsi
+        mov qword ptr [rsp + 16], rdx
+        mov qword ptr [rsp + 24], rcx
+        mov qword ptr [rsp + 32], r8
+        mov qword ptr [rsp + 40], r9
+        movsd qword ptr [rsp + 48], xmm0
+        movsd qword ptr [rsp + 56], xmm1
+        movsd qword ptr [rsp + 64], xmm2
+        movsd qword ptr [rsp + 72], xmm3
+        movsd qword ptr [rsp + 80], xmm4
+        movsd qword ptr [rsp + 88], xmm5
+        movsd qword ptr [rsp + 96], xmm6
+        movsd qword ptr [rsp + 104], xmm7
+
+        // The stack-spilled arguments start right above our return address.
+        // The integer return value is passed through in rax and rdx:
+        mov rdi, {callback_id}
+        mov rsi, rsp
+        lea rdx, [rbp + 16]
+        call {inner}
+
+        movsd xmm0, qword ptr [rsp + 112]
+        movsd xmm1, qword ptr [rsp + 120]
+
+        mov rsp, rbp
+        pop rbp
+        ret
+        ",
+        frame_size = const core::mem::size_of::<MockRtCallbackTrampolineFrame>(),
+        callback_id = const CALLBACK_ID,
+        inner = sym mock_rt_callback_trampoline_inner::<ID>,
+    );
+}
+
+// On AArch64, the trampoline captures the caller's stack pointer:
+#[cfg(target_arch = "aarch64")]
+#[unsafe(naked)]
+unsafe extern "C" fn mock_rt_callback_trampoline<const CALLBACK_ID: usize, ID: OGID>(
+    _a0: usize,
+    _a1: usize,
+    _a2: usize,
+    _a3: usize,
+    _a4: usize,
+    _a5: usize,
+) -> CallbackTrampolineFnReturn {
+    core::arch::naked_asm!(
+        "
+        // Set up a frame record, and save all argument registers in a
+        // `MockRtCallbackTrampolineFrame` on the stack. Its size is a multiple
+        // of 16 bytes, leaving the stack pointer 16-byte aligned:
+        stp x29, x30, [sp, #-16]!
+        mov x29, sp
+        sub sp, sp, #{frame_size}
+        stp x0, x1, [sp, #0]
+        stp x2, x3, [sp, #16]
+        stp x4, x5, [sp, #32]
+
+        // The stack-spilled arguments start at the caller's stack pointer,
+        // right above our frame record. The integer return value is passed
+        // through in x0 and x1:
+        mov x0, #{callback_id}
+        mov x1, sp
+        add x2, x29, #16
+        bl {inner}
+
+        mov sp, x29
+        ldp x29, x30, [sp], #16
+        ret
+        ",
+        frame_size = const core::mem::size_of::<MockRtCallbackTrampolineFrame>(),
+        callback_id = const CALLBACK_ID,
+        inner = sym mock_rt_callback_trampoline_inner::<ID>,
+    );
+}
+
+// Width of the RISC-V floating-point registers in bytes, or 0 without the F
+// extension:
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+const MOCK_RT_RISCV_FLEN: usize = if cfg!(target_feature = "d") {
+    8
+} else if cfg!(target_feature = "f") {
+    4
+} else {
+    0
+};
+
+// On RISC-V, the trampoline captures the caller's stack pointer, and the
+// floating-point argument registers when the F or D extension is available:
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[unsafe(naked)]
+unsafe extern "C" fn mock_rt_callback_trampoline<const CALLBACK_ID: usize, ID: OGID>(
+    _a0: usize,
+    _a1: usize,
+    _a2: usize,
+    _a3: usize,
+    _a4: usize,
+    _a5: usize,
+) -> CallbackTrampolineFnReturn {
+    core::arch::naked_asm!(
+        "
+        // Save the return address and frame pointer, and point the frame
+        // pointer to the caller's stack pointer:
+        addi sp, sp, -16
+        .if {xlen} == 8
+        sd ra, 8(sp)
+        sd s0, 0(sp)
+        .else
+        sw ra, 12(sp)
+        sw s0, 8(sp)
+        .endif
+        addi s0, sp, 16
+
+        // Save all argument registers in a `MockRtCallbackTrampolineFrame` on
+        // the stack, keeping the stack pointer 16-byte aligned:
+        addi sp, sp, -{frame_size}
+        .if {xlen} == 8
+        sd a0, 0(sp)
+        sd a1, 8(sp)
+        sd a2, 16(sp)
+        sd a3, 24(sp)
+        sd a4, 32(sp)
+        sd a5, 40(sp)
+        .else
+        sw a0, 0(sp)
+        sw a1, 4(sp)
+        sw a2, 8(sp)
+        sw a3, 12(sp)
+        sw a4, 16(sp)
+        sw a5, 20(sp)
+        .endif
+        .if {flen} == 8
+        fsd fa0, {fp_args} + 0(sp)
+        fsd fa1, {fp_args} + 8(sp)
+        fsd fa2, {fp_args} + 16(sp)
+        fsd fa3, {fp_args} + 24(sp)
+        fsd fa4, {fp_args} + 32(sp)
+        fsd fa5, {fp_args} + 40(sp)
+        fsd fa6, {fp_args} + 48(sp)
+        fsd fa7, {fp_args} + 56(sp)
+        .elseif {flen} == 4
+        fsw fa0, {fp_args} + 0(sp)
+        fsw fa1, {fp_args} + 8(sp)
+        fsw fa2, {fp_args} + 16(sp)
+        fsw fa3, {fp_args} + 24(sp)
+        fsw fa4, {fp_args} + 32(sp)
+        fsw fa5, {fp_args} + 40(sp)
+        fsw fa6, {fp_args} + 48(sp)
+        fsw fa7, {fp_args} + 56(sp)
+        .endif
+
+        // The stack-spilled arguments start at the caller's stack pointer. The
+        // integer return value is passed through in a0 and a1:
+        li a0, {callback_id}
+        mv a1, sp
+        mv a2, s0
+        call {inner}
+
+        .if {flen} == 8
+        fld fa0, {fp_ret} + 0(sp)
+        fld fa1, {fp_ret} + 8(sp)
+        .elseif {flen} == 4
+        flw fa0, {fp_ret} + 0(sp)
+        flw fa1, {fp_ret} + 8(sp)
+        .endif
+
+        addi sp, s0, -16
+        .if {xlen} == 8
+        ld ra, 8(sp)
+        ld s0, 0(sp)
+        .else
+        lw ra, 12(sp)
+        lw s0, 8(sp)
+        .endif
+        addi sp, sp, 16
+        ret
+        ",
+        xlen = const core::mem::size_of::<usize>(),
+        flen = const MOCK_RT_RISCV_FLEN,
+        fp_args = const core::mem::offset_of!(MockRtCallbackTrampolineFrame, fp_arg_regs),
+        fp_ret = const core::mem::offset_of!(MockRtCallbackTrampolineFrame, fp_return_regs),
+        frame_size = const core::mem::size_of::<MockRtCallbackTrampolineFrame>()
+            .next_multiple_of(16),
+        callback_id = const CALLBACK_ID,
+        inner = sym mock_rt_callback_trampoline_inner::<ID>,
+    );
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64"
+)))]
+unsafe extern "C" fn mock_rt_callback_trampoline<const CALLBACK_ID: usize, ID: OGID>(
     a0: usize,
     a1: usize,
     a2: usize,
@@ -107,36 +828,69 @@ extern "C" fn mock_rt_callback_trampoline<const CALLBACK_ID: usize, ID: OGID>(
     a4: usize,
     a5: usize,
 ) -> CallbackTrampolineFnReturn {
-    let mut callback_ret = MockRtCallbackReturn {
-        return_regs: [0; 2],
-    };
-
-    mock_rt_callback_dispatch::<ID>(
+    mock_rt_callback_trampoline_inner::<ID>(
         CALLBACK_ID,
-        &MockRtCallbackContext {
+        &mut MockRtCallbackTrampolineFrame {
             arg_regs: [a0, a1, a2, a3, a4, a5],
+            fp_arg_regs: [0; 8],
+            fp_return_regs: [0; 2],
         },
-        &mut callback_ret,
-    );
+        core::ptr::null_mut(),
+    )
+}
 
-    CallbackTrampolineFnReturn {
-        reg0: callback_ret.return_regs[0],
-        reg1: callback_ret.return_regs[1],
-    }
+/// Upper bound for the callback trampoline pool size of a [`MockRt`].
+pub const MOCK_RT_MAX_CALLBACK_POOL_SIZE: usize = 4096;
+
+// Only evaluated at compile time, such that just the trampolines of a pool end
+// up in the final binary:
+const fn mock_rt_callback_trampoline_lookup<ID: OGID>(callback_id: usize) -> CallbackTrampolineFn {
+    seq_macro::seq!(N in 0..4096 {
+        match callback_id {
+            #( N => mock_rt_callback_trampoline::<N, ID>, )*
+            _ => panic!("Callback ID exceeds MOCK_RT_MAX_CALLBACK_POOL_SIZE"),
+        }
+    })
 }
 
-pub enum MockRtCallbackTrampolinePool<ID: OGID> {
+pub enum MockRtCallbackTrampolinePool<ID: OGID, const SIZE: usize> {
     _Dummy(PhantomData<ID>, core::convert::Infallible),
 }
 
-impl<ID: OGID> MockRtCallbackTrampolinePool<ID> {
-    const CALLBACKS: [CallbackTrampolineFn; 512] = seq_macro::seq!(N in 0..512 { [
-        #( mock_rt_callback_trampoline::<N, ID>, )*
-    ] });
+impl<ID: OGID, const SIZE: usize> MockRtCallbackTrampolinePool<ID, SIZE> {
+    const CALLBACKS: [CallbackTrampolineFn; SIZE] = {
+        assert!(
+            SIZE <= MOCK_RT_MAX_CALLBACK_POOL_SIZE,
+            "MockRt callback pool size exceeds MOCK_RT_MAX_CALLBACK_POOL_SIZE"
+        );
+
+        let mut callbacks = [mock_rt_callback_trampoline::<0, ID> as CallbackTrampolineFn; SIZE];
+        let mut i = 0;
+        while i < SIZE {
+            callbacks[i] = mock_rt_callback_trampoline_lookup::<ID>(i);
+            i += 1;
+        }
+        callbacks
+    };
 }
 
+#[derive(Debug)]
 pub enum MockRtAllocError {
     InvalidLayout,
+    NoMem,
+    StackOverflow,
+    RedZoneOverrun(core::alloc::Layout),
+}
+
+impl From<MockRtAllocError> for OGError {
+    fn from(err: MockRtAllocError) -> Self {
+        match err {
+            MockRtAllocError::InvalidLayout => OGError::AllocInvalidLayout,
+            MockRtAllocError::NoMem => OGError::AllocNoMem,
+            MockRtAllocError::StackOverflow => OGError::StackOverflow,
+            MockRtAllocError::RedZoneOverrun(layout) => OGError::AllocRedZoneOverrun { layout },
+        }
+    }
 }
 
 pub trait MockRtAllocator {
@@ -145,12 +899,68 @@ pub trait MockRtAllocator {
         layout: core::alloc::Layout,
         f: F,
     ) -> Result<R, MockRtAllocError>;
+
+    /// Check the integrity of all live allocations. Called after executing
+    /// foreign code.
+    fn check_allocations(&self) -> Result<(), MockRtAllocError> {
+        Ok(())
+    }
+
+    /// Whether the MockRt must place all stacked allocations through this
+    /// allocator, instead of keeping some on the host stack. This is the case
+    /// when allocations are surrounded by red zones, or must reside on a
+    /// dedicated foreign stack.
+    fn places_all_allocations(&self) -> bool {
+        false
+    }
+
+    /// Run `f`, restoring this allocator's bookkeeping of live allocations to
+    /// its current state once `f` returns.
+    ///
+    /// The MockRt may abandon foreign code (e.g., after a fault or timeout),
+    /// discarding its frames without running their destructors. This includes
+    /// the scopes of allocations made by host callbacks, which thus never end.
+    /// The MockRt executes foreign code within this method. As allocations are
+    /// strictly nested, restoring the state has no effect when `f` has not
+    /// been abandoned.
+    fn with_saved_state<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        f()
+    }
+
+    /// Run `f`, which executes foreign code, on the stack that foreign code is
+    /// to use. By default, this is the current (host) stack.
+    ///
+    /// # Safety
+    ///
+    /// `f` may only be invoked by the MockRt's `execute` method.
+    unsafe fn with_execution_stack<R, F: FnOnce() -> R>(
+        &self,
+        f: F,
+    ) -> Result<R, MockRtAllocError> {
+        Ok(f())
+    }
 }
 
-pub struct MockRt<ID: OGID, A: MockRtAllocator> {
+/// A runtime for foreign code that runs in the host's domain.
+///
+/// Callbacks are set up from a pool of `CALLBACK_POOL_SIZE` trampolines, at
+/// most [`MOCK_RT_MAX_CALLBACK_POOL_SIZE`]. Attempting to set up more callbacks
+/// at once fails with [`OGError::SetupCallbackInsufficientSlots`].
+pub struct MockRt<ID: OGID, A: MockRtAllocator, const CALLBACK_POOL_SIZE: usize = 512> {
     zero_copy_immutable: bool,
+    check_immutable_regions: bool,
+    catch_faults: bool,
     allocator: A,
     id_imprint: ID::Imprint,
+    // When set, symbols are resolved from this library. Otherwise, the MockRt
+    // expects the bindings to be statically linked to the foreign library:
+    #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+    library: Option<DlLibrary>,
+}
+
+pub struct MockRtSymbolTableState<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> {
+    symbols: [Option<*const ()>; SYMTAB_SIZE],
+    fixed_offset_symbols: [Option<*const ()>; FIXED_OFFSET_SYMTAB_SIZE],
 }
 
 impl<ID: OGID, A: MockRtAllocator> MockRt<ID, A> {
@@ -163,12 +973,46 @@ impl<ID: OGID, A: MockRtAllocator> MockRt<ID, A> {
         Self,
         AllocScope<'static, MockRtAllocChain<'static>, ID>,
         AccessScope<ID>,
+    ) {
+        unsafe {
+            Self::new_with_callback_pool(
+                zero_copy_immutable,
+                all_upgrades_valid,
+                allocator,
+                branding,
+            )
+        }
+    }
+}
+
+impl<ID: OGID, A: MockRtAllocator, const CALLBACK_POOL_SIZE: usize>
+    MockRt<ID, A, CALLBACK_POOL_SIZE>
+{
+    /// Create a MockRt with a callback trampoline pool of `CALLBACK_POOL_SIZE`
+    /// slots.
+    ///
+    /// # Safety
+    ///
+    /// See [`MockRt::new`].
+    pub unsafe fn new_with_callback_pool(
+        zero_copy_immutable: bool,
+        all_upgrades_valid: bool,
+        allocator: A,
+        branding: ID,
+    ) -> (
+        Self,
+        AllocScope<'static, MockRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
     ) {
         (
             MockRt {
                 zero_copy_immutable,
+                check_immutable_regions: false,
+                catch_faults: false,
                 allocator,
                 id_imprint: branding.get_imprint(),
+                #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+                library: None,
             },
             unsafe {
                 AllocScope::new(
@@ -180,6 +1024,156 @@ impl<ID: OGID, A: MockRtAllocator> MockRt<ID, A> {
         )
     }
 
+    /// Create a MockRt which resolves symbols from the shared object at
+    /// `library_path`, instead of relying on the bindings to be statically
+    /// linked against the foreign library.
+    ///
+    /// Symbols missing from the library are reported by
+    /// [`OGRuntime::resolve_symbols`].
+    ///
+    /// # Safety
+    ///
+    /// In addition to the requirements of [`MockRt::new`], loading the library
+    /// runs its initializers without any isolation.
+    #[cfg_attr(
+        feature = "nightly",
+        doc(cfg(all(feature = "std", target_os = "linux", target_env = "gnu")))
+    )]
+    #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+    pub unsafe fn new_with_library(
+        library_path: &CStr,
+        zero_copy_immutable: bool,
+        all_upgrades_valid: bool,
+        allocator: A,
+        branding: ID,
+    ) -> OGResult<(
+        Self,
+        AllocScope<'static, MockRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
+    )> {
+        let library = unsafe { DlLibrary::open(library_path) }.ok_or(OGError::LibraryLoadFailed)?;
+
+        let (mut rt, alloc_scope, access_scope) = unsafe {
+            Self::new_with_callback_pool(
+                zero_copy_immutable,
+                all_upgrades_valid,
+                allocator,
+                branding,
+            )
+        };
+        rt.library = Some(library);
+
+        Ok((rt, alloc_scope, access_scope))
+    }
+
+    /// Create a MockRt which resolves symbols from the shared object at
+    /// `library_path`, and tracks the library's heap allocations.
+    ///
+    /// The library's calls to `malloc`, `calloc`, `realloc` and `free` are
+    /// interposed, and pointers returned by foreign code can be upgraded
+    /// exactly when they lie within a live heap block (or a stacked
+    /// allocation). See [`heap_track`] for its limitations.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as for [`MockRt::new_with_library`] apply.
+    #[cfg_attr(
+        feature = "nightly",
+        doc(cfg(all(feature = "std", target_os = "linux", target_env = "gnu")))
+    )]
+    #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+    pub unsafe fn new_with_library_heap_tracking(
+        library_path: &CStr,
+        zero_copy_immutable: bool,
+        allocator: A,
+        branding: ID,
+    ) -> OGResult<(
+        Self,
+        AllocScope<'static, MockRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
+    )> {
+        let library = unsafe { DlLibrary::open(library_path) }.ok_or(OGError::LibraryLoadFailed)?;
+
+        // The library has only just been loaded, no other thread can be
+        // calling into it yet:
+        unsafe { heap_track::interpose_heap(&library) }?;
+
+        Ok((
+            MockRt {
+                zero_copy_immutable,
+                check_immutable_regions: false,
+                catch_faults: false,
+                allocator,
+                id_imprint: branding.get_imprint(),
+                library: Some(library),
+            },
+            unsafe { AllocScope::new(MockRtAllocChain::TrackedHeap, branding.get_imprint()) },
+            unsafe { AccessScope::new(branding.get_imprint()) },
+        ))
+    }
+
+    /// Check that foreign code does not write to host memory shared with it
+    /// as immutable.
+    ///
+    /// With `zero_copy_immutable`, `write_stacked_t`, `write_stacked_ref_t`
+    /// and `write_stacked_slice` share host memory with foreign code without
+    /// copying it (unless the allocator places all allocations, see
+    /// [`MockRtAllocator::places_all_allocations`]), and nothing prevents
+    /// foreign code from writing to it. When enabled, `execute` fingerprints
+    /// all such regions before running foreign code and verifies them
+    /// afterwards, returning [`OGError::ForeignWriteToImmutable`] if any region
+    /// was modified.
+    pub fn set_check_immutable_regions(&mut self, check: bool) {
+        self.check_immutable_regions = check;
+    }
+
+    /// Recover from faults raised by foreign code.
+    ///
+    /// When enabled, `execute` returns [`OGError::ForeignFault`] and poisons
+    /// the `AccessScope` when foreign code raises a `SIGSEGV`, `SIGBUS` or
+    /// `SIGFPE`. Enabling this installs process-wide handlers for these
+    /// signals. They defer to the previous handlers for all faults raised
+    /// outside of foreign code, including faults raised by host callbacks.
+    #[cfg_attr(
+        feature = "nightly",
+        doc(cfg(all(
+            feature = "std",
+            target_os = "linux",
+            target_env = "gnu",
+            target_arch = "x86_64"
+        )))
+    )]
+    #[cfg(all(
+        feature = "std",
+        target_os = "linux",
+        target_env = "gnu",
+        target_arch = "x86_64"
+    ))]
+    pub fn set_catch_faults(&mut self, catch: bool) {
+        if catch {
+            recovery::install_signal_handler();
+        }
+        self.catch_faults = catch;
+    }
+
+    /// Access this runtime's stacked allocator, e.g., to inspect the stack
+    /// used by foreign code.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    // Resolve a single symbol. Returns `Ok(None)` when this runtime has no
+    // library to resolve symbols from:
+    fn resolve_symbol<'a>(&self, name: &'a CStr) -> Result<Option<*const ()>, Option<&'a CStr>> {
+        #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+        if let Some(library) = &self.library {
+            return library.symbol(name).map(Some).ok_or(Some(name));
+        }
+
+        let _ = name;
+        Ok(None)
+    }
+
     fn setup_callback_int<'a, C, F, R>(
         &self,
         callback: &'a mut C,
@@ -221,8 +1215,10 @@ impl<ID: OGID, A: MockRtAllocator> MockRt<ID, A> {
             let ctx: &mut Context<'a, ClosureTy> =
                 unsafe { &mut *(ctx_ptr as *mut Context<'a, ClosureTy>) };
 
-            // For now, we assume that the functoin doesn't unwind:
-            (ctx.closure)(callback_ctx, callback_ret, alloc_scope, access_scope)
+            // This is called from foreign code, so we must not unwind:
+            mock_rt_catch_callback_panic(|| {
+                (ctx.closure)(callback_ctx, callback_ret, alloc_scope, access_scope)
+            })
         }
 
         // Ensure that the context pointer is compatible in size and
@@ -255,36 +1251,89 @@ impl<ID: OGID, A: MockRtAllocator> MockRt<ID, A> {
             )
         };
 
-        let alloc_chain_head_ref_opt: &mut Option<(*const MockRtAllocChain<'_>, *const ())> =
-            unsafe { &mut *core::ptr::addr_of_mut!(ACTIVE_ALLOC_CHAIN_HEAD_ROG) };
-
-        // Implement a "stack" of ACTIVE_ALLOC_CHAIN_HEAD_ROGs, keeping the
-        // previous value in a local variable. We'll put it back after running
-        // our closure:
-        let outer_alloc_chain_head_ref = alloc_chain_head_ref_opt.clone();
+        // Callback IDs are allocated in LIFO order, so the slots of callbacks
+        // whose scopes have exited are reused:
+        let callback_trampoline =
+            *MockRtCallbackTrampolinePool::<ID, CALLBACK_POOL_SIZE>::CALLBACKS
+                .get(callback_id)
+                .ok_or(OGError::SetupCallbackInsufficientSlots)?;
 
-        // "Push" a new stack top, consisting of the new reference to the
-        // inner_alloc_scope's tracker and an ID imprint value:
-        let tracker = inner_alloc_scope.tracker() as *const _;
+        // Publish the new allocation chain, such that the callback can be
+        // dispatched even when it is invoked outside of `execute`:
+        let tracker = inner_alloc_scope.tracker() as *const MockRtAllocChain<'_>;
         let id_imprint: ID::Imprint = alloc_scope.id_imprint();
-        *alloc_chain_head_ref_opt = Some((
-            tracker as *const MockRtAllocChain<'static>,
-            &id_imprint as *const ID::Imprint as *const (),
-        ));
+        let res = mock_rt_with_dispatch_frame::<ID, _>(unsafe { &*tracker }, &id_imprint, || {
+            fun(
+                callback_trampoline as *const CallbackTrampolineFn,
+                &mut inner_alloc_scope,
+            )
+        });
 
-        let callback_trampoline = MockRtCallbackTrampolinePool::<ID>::CALLBACKS[callback_id];
+        Ok(res)
+    }
 
-        let res = fun(
-            callback_trampoline as *const CallbackTrampolineFn,
-            &mut inner_alloc_scope,
-        );
+    fn execute_int<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, ID>,
+        access_scope: &mut AccessScope<ID>,
+        timeout: Option<core::time::Duration>,
+        f: F,
+    ) -> OGResult<R> {
+        self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
+
+        // Foreign code runs on the stack provided by our allocator, and is
+        // aborted when it faults or once its deadline (if any) expires. This
+        // discards its frames, including those of host callbacks, so restore
+        // any host state that they would otherwise restore when returning:
+        let f = || {
+            self.allocator.with_saved_state(|| {
+                let _release_locks = MockRtReleaseAbandonedLocks;
+                mock_rt_with_recovery(target_symbol, self.catch_faults, timeout, || unsafe {
+                    self.allocator.with_execution_stack(f)
+                })
+            })
+        };
+
+        let res = if !self.check_immutable_regions {
+            // Foreign code may invoke any callbacks in this allocation chain:
+            mock_rt_with_dispatch_frame::<ID, _>(alloc_scope.tracker(), &self.id_imprint, f)
+        } else {
+            let mut f = Some(f);
+            let mut res = None;
+            let modified =
+                mock_rt_verify_immutable_allocations(alloc_scope.tracker().iter(), &mut || {
+                    res = Some(mock_rt_with_dispatch_frame::<ID, _>(
+                        alloc_scope.tracker(),
+                        &self.id_imprint,
+                        f.take().unwrap(),
+                    ));
+                });
+
+            if let Some(allocation) = modified {
+                return Err(OGError::ForeignWriteToImmutable {
+                    region_start: allocation.ptr as usize,
+                    region_len: allocation.len,
+                    symbol: target_symbol as usize,
+                });
+            }
+
+            res.unwrap()
+        };
+
+        let res = match res {
+            Ok(res) => res?,
+            Err(err) => {
+                // Foreign code was aborted at an arbitrary point, and may have
+                // left its memory in an inconsistent state:
+                access_scope.poison();
+                return Err(err);
+            }
+        };
 
-        // Reset the alloc_chain_head_ref_opt to the previous value.
-        let _inner_alloc_chain_head_ref =
-            unsafe { core::ptr::replace(alloc_chain_head_ref_opt, outer_alloc_chain_head_ref) };
+        // Foreign code may have overrun any of the live stacked allocations:
+        self.allocator.check_allocations()?;
 
-        // All the references of `_inner_alloc_chain_head_ref` are local to our
-        // stack, so there's nothing we'd need to deallocate:
         Ok(res)
     }
 
@@ -314,6 +1363,10 @@ pub struct MockRtAllocation {
 }
 
 impl MockRtAllocation {
+    pub fn new(ptr: *mut (), len: usize, mutable: bool) -> Self {
+        MockRtAllocation { ptr, len, mutable }
+    }
+
     fn matches(&self, ptr: *mut (), len: usize, mutable: bool) -> bool {
         (ptr as usize) >= (self.ptr as usize)
             && ((ptr as usize)
@@ -322,6 +1375,47 @@ impl MockRtAllocation {
                 .unwrap_or(false))
             && (!mutable || self.mutable)
     }
+
+    // A 64-bit FNV-1a hash of this allocation's contents:
+    fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for offset in 0..self.len {
+            // Foreign code may write to this memory, so don't let the compiler
+            // assume it is unchanged:
+            let byte = unsafe { core::ptr::read_volatile((self.ptr as *const u8).add(offset)) };
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+// Fingerprint all immutable allocations in `chain`, invoke `f`, and then verify
+// that none of these allocations were modified. Returns the most recent
+// modified allocation, if any.
+//
+// We keep the fingerprints on the stack, by recursing once per immutable
+// allocation, as we cannot rely on a heap allocator:
+fn mock_rt_verify_immutable_allocations<'a>(
+    mut chain: MockRtAllocChainIter<'a>,
+    f: &mut dyn FnMut(),
+) -> Option<&'a MockRtAllocation> {
+    let allocation = chain.find_map(|elem| match elem {
+        MockRtAllocChain::Allocation(allocation, _) if !allocation.mutable => Some(allocation),
+        _ => None,
+    });
+
+    if let Some(allocation) = allocation {
+        let fingerprint = allocation.fingerprint();
+        let modified = mock_rt_verify_immutable_allocations(chain, f);
+        if allocation.fingerprint() != fingerprint {
+            Some(allocation)
+        } else {
+            modified
+        }
+    } else {
+        f();
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -363,6 +1457,14 @@ pub enum MockRtAllocChain<'a> {
     // where the foreign library allocates, we allow disabling upgrade
     // checks. Otherwise, only stacked allocations can be upgraded.
     Base(bool),
+    // Runtimes which know the regions of memory belonging to the foreign
+    // library can instead validate upgrades against those. The list is shared
+    // with the runtime, and may outlive it:
+    #[cfg(feature = "std")]
+    Regions(std::rc::Rc<[MockRtAllocation]>),
+    // Validate upgrades against the live heap blocks of foreign libraries
+    // whose allocation functions have been interposed (see `heap_track`):
+    TrackedHeap,
     Allocation(MockRtAllocation, &'a MockRtAllocChain<'a>),
     Callback(
         usize,
@@ -381,6 +1483,9 @@ impl<'a> Iterator for MockRtAllocChainIter<'a> {
         if let Some(cur) = self.0 {
             self.0 = match cur {
                 MockRtAllocChain::Base(_) => None,
+                #[cfg(feature = "std")]
+                MockRtAllocChain::Regions(_) => None,
+                MockRtAllocChain::TrackedHeap => None,
                 MockRtAllocChain::Allocation(_, pred) => Some(pred),
                 MockRtAllocChain::Callback(_, _, pred) => Some(pred),
                 MockRtAllocChain::Cons(pred) => Some(pred),
@@ -401,6 +1506,21 @@ impl<'a> MockRtAllocChain<'a> {
     fn is_valid_int(&self, ptr: *mut (), len: usize, mutable: bool) -> bool {
         self.iter().any(|elem| match elem {
             MockRtAllocChain::Base(all_upgrades_valid) => *all_upgrades_valid,
+            #[cfg(feature = "std")]
+            MockRtAllocChain::Regions(regions) => regions
+                .iter()
+                .any(|region| region.matches(ptr, len, mutable)),
+            MockRtAllocChain::TrackedHeap => {
+                #[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+                {
+                    heap_track::heap_block_contains(ptr, len)
+                }
+
+                #[cfg(not(all(feature = "std", target_os = "linux", target_env = "gnu")))]
+                {
+                    false
+                }
+            }
             MockRtAllocChain::Allocation(alloc, _) => alloc.matches(ptr, len, mutable),
             MockRtAllocChain::Callback(_, _, _) => false,
             MockRtAllocChain::Cons(_) => false,
@@ -411,6 +1531,9 @@ impl<'a> MockRtAllocChain<'a> {
         self.iter()
             .find_map(|elem| match elem {
                 MockRtAllocChain::Base(_) => None,
+                #[cfg(feature = "std")]
+                MockRtAllocChain::Regions(_) => None,
+                MockRtAllocChain::TrackedHeap => None,
                 MockRtAllocChain::Allocation(_, _) => None,
                 MockRtAllocChain::Callback(id, _, _) => Some(id + 1),
                 MockRtAllocChain::Cons(_) => None,
@@ -421,6 +1544,9 @@ impl<'a> MockRtAllocChain<'a> {
     fn find_callback_descriptor(&self, id: usize) -> Option<&MockRtCallbackDescriptor<'_>> {
         self.iter().find_map(|elem| match elem {
             MockRtAllocChain::Base(_) => None,
+            #[cfg(feature = "std")]
+            MockRtAllocChain::Regions(_) => None,
+            MockRtAllocChain::TrackedHeap => None,
             MockRtAllocChain::Allocation(_, _) => None,
             MockRtAllocChain::Callback(desc_id, desc, _) => {
                 if id == *desc_id {
@@ -444,7 +1570,9 @@ unsafe impl AllocTracker for MockRtAllocChain<'_> {
     }
 }
 
-unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
+unsafe impl<ID: OGID, A: MockRtAllocator, const CALLBACK_POOL_SIZE: usize> OGRuntime
+    for MockRt<ID, A, CALLBACK_POOL_SIZE>
+{
     type ID = ID;
     type AllocTracker<'a> = MockRtAllocChain<'a>;
     type ABI = GenericABI;
@@ -452,26 +1580,56 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
     type CallbackContext = MockRtCallbackContext;
     type CallbackReturn = MockRtCallbackReturn;
 
-    type SymbolTableState<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> = ();
+    type SymbolTableState<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> =
+        MockRtSymbolTableState<SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>;
 
     fn resolve_symbols<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
         &self,
-        _symbol_table: &'a [&'a CStr; SYMTAB_SIZE],
-        _fixed_offset_symbol_table: &'a [Option<&'a CStr>; FIXED_OFFSET_SYMTAB_SIZE],
+        symbol_table: &'a [&'a CStr; SYMTAB_SIZE],
+        fixed_offset_symbol_table: &'a [Option<&'a CStr>; FIXED_OFFSET_SYMTAB_SIZE],
     ) -> Result<
         Self::SymbolTableState<'a, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
         Option<&'a core::ffi::CStr>,
     > {
-        Ok(())
+        let mut symbols = [None; SYMTAB_SIZE];
+        for (dst, name) in symbols.iter_mut().zip(symbol_table.iter()) {
+            *dst = self.resolve_symbol(name)?;
+        }
+
+        let mut fixed_offset_symbols = [None; FIXED_OFFSET_SYMTAB_SIZE];
+        for (dst, name_opt) in fixed_offset_symbols
+            .iter_mut()
+            .zip(fixed_offset_symbol_table.iter())
+        {
+            if let Some(name) = name_opt {
+                *dst = self.resolve_symbol(name)?;
+            }
+        }
+
+        Ok(MockRtSymbolTableState {
+            symbols,
+            fixed_offset_symbols,
+        })
     }
 
     fn lookup_symbol<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
         &self,
-        _compact_symtab_index: usize,
-        _fixed_offset_symtab_index: usize,
-        _symtabstate: &Self::SymbolTableState<'_, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+        compact_symtab_index: usize,
+        fixed_offset_symtab_index: usize,
+        symtabstate: &Self::SymbolTableState<'_, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
     ) -> Option<*const ()> {
-        None
+        symtabstate
+            .symbols
+            .get(compact_symtab_index)
+            .copied()
+            .flatten()
+            .or_else(|| {
+                symtabstate
+                    .fixed_offset_symbols
+                    .get(fixed_offset_symtab_index)
+                    .copied()
+                    .flatten()
+            })
     }
 
     fn setup_callback<'a, C, F, R>(
@@ -517,14 +1675,33 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
 
     fn execute<R, F: FnOnce() -> R>(
         &self,
-        _target_symbol: *const (),
+        target_symbol: *const (),
         alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
         access_scope: &mut AccessScope<Self::ID>,
         f: F,
     ) -> OGResult<R> {
-        self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
+        self.execute_int(target_symbol, alloc_scope, access_scope, None, f)
+    }
 
-        Ok(f())
+    const SUPPORTS_DEADLINE: bool = cfg!(all(
+        feature = "std",
+        target_os = "linux",
+        target_env = "gnu",
+        target_arch = "x86_64"
+    ));
+
+    fn execute_with_deadline<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        timeout: core::time::Duration,
+        f: F,
+    ) -> OGResult<R> {
+        if !Self::SUPPORTS_DEADLINE {
+            return Err(OGError::DeadlineUnsupported);
+        }
+        self.execute_int(target_symbol, alloc_scope, access_scope, Some(timeout), f)
     }
 
     fn allocate_stacked_untracked_mut<F, R>(
@@ -536,9 +1713,7 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
         F: FnOnce(*mut ()) -> R,
     {
         // Simply proxy this to our underlying allocator:
-        (unsafe { self.allocator.with_alloc(layout, fun) }).map_err(|e| match e {
-            MockRtAllocError::InvalidLayout => OGError::AllocInvalidLayout,
-        })
+        Ok(unsafe { self.allocator.with_alloc(layout, fun) }?)
     }
 
     fn allocate_stacked_mut<F, R>(
@@ -594,6 +1769,36 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
     {
         self.id_imprint_check(Some(alloc_scope), None)?;
 
+        // Place the allocation through our allocator (e.g., between red zones,
+        // or on the foreign stack), instead of on our stack:
+        if self.allocator.places_all_allocations() && core::mem::size_of::<T>() != 0 {
+            return self.allocate_stacked_untracked_mut(core::alloc::Layout::new::<T>(), |ptr| {
+                let mut inner_alloc_scope = unsafe {
+                    AllocScope::new(
+                        MockRtAllocChain::Allocation(
+                            MockRtAllocation {
+                                ptr,
+                                len: core::mem::size_of::<T>(),
+                                mutable: true,
+                            },
+                            alloc_scope.tracker(),
+                        ),
+                        alloc_scope.id_imprint(),
+                    )
+                };
+
+                fun(
+                    unsafe {
+                        OGMutRef::upgrade_from_ptr_unchecked(
+                            ptr as *mut T,
+                            alloc_scope.id_imprint(),
+                        )
+                    },
+                    &mut inner_alloc_scope,
+                )
+            });
+        }
+
         let t = UnsafeCell::new(MaybeUninit::<T>::uninit());
 
         // Create a new AllocScope instance that wraps a new allocation
@@ -647,7 +1852,10 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
     {
         self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
 
-        if self.zero_copy_immutable {
+        // Allocators which place all allocations (e.g., on the foreign stack)
+        // must receive a copy of immutable values as well, so we never share
+        // host memory with foreign code:
+        if self.zero_copy_immutable && !self.allocator.places_all_allocations() {
             // We can't wrap `write_stacked_ref_t` here, as our `T: ?Copy`.
 
             // While there are no guarantees that foreign code will uphold to
@@ -723,7 +1931,7 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
     {
         self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
 
-        if self.zero_copy_immutable {
+        if self.zero_copy_immutable && !self.allocator.places_all_allocations() {
             // For safety considerations, see `write_stacked_t`.
 
             // Create a new AllocScope instance that wraps a new allocation
@@ -789,7 +1997,7 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
     {
         self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
 
-        if self.zero_copy_immutable {
+        if self.zero_copy_immutable && !self.allocator.places_all_allocations() {
             // For safety considerations, see `write_stacked_t`.
 
             // Create a new AllocScope instance that wraps a new allocation
@@ -833,3 +2041,772 @@ unsafe impl<ID: OGID, A: MockRtAllocator> OGRuntime for MockRt<ID, A> {
         }
     }
 }
+
+#[cfg(all(test, feature = "std", feature = "runtime_id"))]
+fn test_invoke_trampoline(trampoline: *const CallbackTrampolineFn, arg: usize) -> usize {
+    let trampoline: CallbackTrampolineFn =
+        unsafe { core::mem::transmute::<*const (), _>(trampoline as *const ()) };
+    unsafe { trampoline(arg, 0, 0, 0, 0, 0) }.reg0
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_concurrent_callback_dispatch() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let threads: std::vec::Vec<_> = (0..8)
+        .map(|thread_idx| {
+            std::thread::spawn(move || {
+                let (rt, mut alloc_scope, mut access_scope) = unsafe {
+                    MockRt::new(
+                        false,
+                        false,
+                        heap_alloc::HeapAllocator,
+                        OGRuntimeBranding::new(),
+                    )
+                };
+
+                for i in 0..1000 {
+                    let mut callback =
+                        |ctx: &MockRtCallbackContext,
+                         ret: &mut MockRtCallbackReturn,
+                         _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                         _: &mut AccessScope<OGRuntimeBranding>| {
+                            ret.set_return_register(
+                                0,
+                                ctx.get_argument_register(0).unwrap() * 8 + thread_idx,
+                            );
+                        };
+
+                    let res = rt
+                        .setup_callback(
+                            &mut callback,
+                            &mut alloc_scope,
+                            |trampoline, alloc_scope| {
+                                rt.execute(
+                                    core::ptr::null(),
+                                    alloc_scope,
+                                    &mut access_scope,
+                                    || test_invoke_trampoline(trampoline, i),
+                                )
+                                .unwrap()
+                            },
+                        )
+                        .unwrap();
+
+                    assert_eq!(res, i * 8 + thread_idx);
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_nested_runtime_callback_dispatch() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt_a, mut alloc_scope_a, mut access_scope_a) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+    let (rt_b, mut alloc_scope_b, mut access_scope_b) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let mut callback_b = |_: &MockRtCallbackContext,
+                          ret: &mut MockRtCallbackReturn,
+                          _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                          _: &mut AccessScope<OGRuntimeBranding>| {
+        ret.set_return_register(0, 2);
+    };
+
+    let mut callback_a = |_: &MockRtCallbackContext,
+                          ret: &mut MockRtCallbackReturn,
+                          _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                          _: &mut AccessScope<OGRuntimeBranding>| {
+        // Both runtimes share the same `ID` type and callback ID, and thus
+        // the same trampoline. It must still be dispatched to runtime B here:
+        let res_b = rt_b
+            .setup_callback(
+                &mut callback_b,
+                &mut alloc_scope_b,
+                |trampoline, alloc_scope| {
+                    rt_b.execute(core::ptr::null(), alloc_scope, &mut access_scope_b, || {
+                        test_invoke_trampoline(trampoline, 0)
+                    })
+                    .unwrap()
+                },
+            )
+            .unwrap();
+
+        ret.set_return_register(0, 10 + res_b);
+    };
+
+    rt_a.setup_callback(
+        &mut callback_a,
+        &mut alloc_scope_a,
+        |trampoline, alloc_scope| {
+            rt_a.execute(core::ptr::null(), alloc_scope, &mut access_scope_a, || {
+                assert_eq!(test_invoke_trampoline(trampoline, 0), 12);
+
+                // Runtime B's dispatch state has been popped again:
+                assert_eq!(test_invoke_trampoline(trampoline, 0), 12);
+            })
+            .unwrap()
+        },
+    )
+    .unwrap();
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_callback_dispatch_skips_other_id_types() {
+    use crate::id::lifetime::OGLifetimeBranding;
+    use crate::id::runtime::OGRuntimeBranding;
+
+    OGLifetimeBranding::new(|brand| {
+        let (rt_a, mut alloc_scope_a, mut access_scope_a) =
+            unsafe { MockRt::new(false, false, heap_alloc::HeapAllocator, brand) };
+        let (rt_b, mut alloc_scope_b, mut access_scope_b) = unsafe {
+            MockRt::new(
+                false,
+                false,
+                heap_alloc::HeapAllocator,
+                OGRuntimeBranding::new(),
+            )
+        };
+
+        let mut callback_b = |_: &MockRtCallbackContext,
+                              ret: &mut MockRtCallbackReturn,
+                              _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                              _: &mut AccessScope<OGRuntimeBranding>| {
+            ret.set_return_register(0, 42);
+        };
+
+        rt_b.setup_callback(
+            &mut callback_b,
+            &mut alloc_scope_b,
+            |trampoline, alloc_scope| {
+                rt_b.execute(core::ptr::null(), alloc_scope, &mut access_scope_b, || {
+                    // Runtime A is now the innermost runtime on this thread.
+                    // Its `ID` type differs, so it must not be picked for
+                    // runtime B's trampoline:
+                    rt_a.execute(
+                        core::ptr::null(),
+                        &mut alloc_scope_a,
+                        &mut access_scope_a,
+                        || assert_eq!(test_invoke_trampoline(trampoline, 0), 42),
+                    )
+                    .unwrap()
+                })
+                .unwrap()
+            },
+        )
+        .unwrap();
+    })
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_callback_dispatch_skips_shared_imprint_types() {
+    use crate::id::OGID;
+    use crate::id::runtime::OGRuntimeBranding;
+
+    // A distinct `ID` type which shares the imprint type of `OGRuntimeBranding`:
+    #[derive(Debug)]
+    struct SharedImprintBranding(OGRuntimeBranding);
+
+    unsafe impl OGID for SharedImprintBranding {
+        type Imprint = <OGRuntimeBranding as OGID>::Imprint;
+
+        fn get_imprint(&self) -> Self::Imprint {
+            self.0.get_imprint()
+        }
+    }
+
+    let (rt_a, mut alloc_scope_a, mut access_scope_a) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            SharedImprintBranding(OGRuntimeBranding::new()),
+        )
+    };
+    let (rt_b, mut alloc_scope_b, mut access_scope_b) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let mut callback_b = |_: &MockRtCallbackContext,
+                          ret: &mut MockRtCallbackReturn,
+                          _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                          _: &mut AccessScope<OGRuntimeBranding>| {
+        ret.set_return_register(0, 42);
+    };
+
+    rt_b.setup_callback(
+        &mut callback_b,
+        &mut alloc_scope_b,
+        |trampoline, alloc_scope| {
+            rt_b.execute(core::ptr::null(), alloc_scope, &mut access_scope_b, || {
+                // Runtime A is now the innermost runtime on this thread, with
+                // the same imprint type. Its `ID` type differs, so it must not
+                // be picked for runtime B's trampoline:
+                rt_a.execute(
+                    core::ptr::null(),
+                    &mut alloc_scope_a,
+                    &mut access_scope_a,
+                    || assert_eq!(test_invoke_trampoline(trampoline, 0), 42),
+                )
+                .unwrap()
+            })
+            .unwrap()
+        },
+    )
+    .unwrap();
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_callback_pool_exhaustion_and_reuse() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, _access_scope) = unsafe {
+        MockRt::<_, _, 2>::new_with_callback_pool(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let mut callback = |_: &MockRtCallbackContext,
+                        _: &mut MockRtCallbackReturn,
+                        _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                        _: &mut AccessScope<OGRuntimeBranding>| {};
+    let mut callback_b = callback;
+    let mut callback_c = callback;
+
+    // Sequentially set up callbacks reuse the slot freed by their predecessor:
+    let first = rt
+        .setup_callback(&mut callback, &mut alloc_scope, |trampoline, _| trampoline)
+        .unwrap();
+    for _ in 0..8 {
+        let trampoline = rt
+            .setup_callback(&mut callback, &mut alloc_scope, |trampoline, _| trampoline)
+            .unwrap();
+        assert_eq!(trampoline, first);
+    }
+
+    // Nesting more callbacks than the pool has slots fails:
+    let res = rt.setup_callback(&mut callback, &mut alloc_scope, |t0, alloc_scope| {
+        rt.setup_callback(&mut callback_b, alloc_scope, |t1, alloc_scope| {
+            assert_ne!(t0, t1);
+            rt.setup_callback(&mut callback_c, alloc_scope, |_, _| ())
+        })
+    });
+    assert!(matches!(
+        res,
+        Ok(Ok(Err(OGError::SetupCallbackInsufficientSlots)))
+    ));
+}
+
+#[cfg(all(
+    feature = "std",
+    feature = "runtime_id",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv32",
+        target_arch = "riscv64"
+    )
+))]
+#[test]
+fn test_callback_stack_spilled_arguments() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let mut callback = |ctx: &MockRtCallbackContext,
+                        ret: &mut MockRtCallbackReturn,
+                        _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                        _: &mut AccessScope<OGRuntimeBranding>| {
+        let stack_args = ctx.get_stack_pointer() as *const usize;
+        let mut sum = 0;
+        for reg in 0..6 {
+            sum += ctx.get_argument_register(reg).unwrap();
+        }
+        for i in 0..3 {
+            sum += unsafe { stack_args.add(i).read() } * 1000;
+        }
+        ret.set_return_register(0, sum);
+    };
+
+    let res = rt
+        .setup_callback(&mut callback, &mut alloc_scope, |trampoline, alloc_scope| {
+            rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                #[allow(clippy::type_complexity)]
+                let trampoline: unsafe extern "C" fn(
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                    usize,
+                ) -> CallbackTrampolineFnReturn =
+                    unsafe { core::mem::transmute::<*const (), _>(trampoline as *const ()) };
+                unsafe { trampoline(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11) }.reg0
+            })
+            .unwrap()
+        })
+        .unwrap();
+
+    // Arguments are spilled onto the stack once all 6 (x86_64) or 8 (AArch64,
+    // RISC-V) integer argument registers are taken:
+    if cfg!(target_arch = "x86_64") {
+        assert_eq!(res, 21 + (7 + 8 + 9) * 1000);
+    } else {
+        assert_eq!(res, 21 + (9 + 10 + 11) * 1000);
+    }
+}
+
+#[cfg(all(
+    feature = "std",
+    feature = "runtime_id",
+    any(
+        target_arch = "x86_64",
+        all(
+            any(target_arch = "riscv32", target_arch = "riscv64"),
+            target_feature = "d"
+        )
+    )
+))]
+#[test]
+fn test_callback_fp_arguments_and_return() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let mut callback = |ctx: &MockRtCallbackContext,
+                        ret: &mut MockRtCallbackReturn,
+                        _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                        _: &mut AccessScope<OGRuntimeBranding>| {
+        let a = f64::from_bits(ctx.get_fp_argument_register(0).unwrap());
+        let b = f32::from_bits(ctx.get_fp_argument_register(1).unwrap() as u32);
+        let scale = ctx.get_argument_register(0).unwrap() as f64;
+        assert!(ret.set_fp_return_register(0, ((a + b as f64) * scale).to_bits()));
+        assert!(!ret.set_fp_return_register(2, 0));
+    };
+
+    let res = rt
+        .setup_callback(
+            &mut callback,
+            &mut alloc_scope,
+            |trampoline, alloc_scope| {
+                rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                    let trampoline: unsafe extern "C" fn(f64, usize, f32) -> f64 =
+                        unsafe { core::mem::transmute::<*const (), _>(trampoline as *const ()) };
+                    unsafe { trampoline(1.25, 4, 0.5) }
+                })
+                .unwrap()
+            },
+        )
+        .unwrap();
+
+    assert_eq!(res, 7.0);
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_callback_panic_resumed_on_host() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::new(
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    let mut callback = |ctx: &MockRtCallbackContext,
+                        ret: &mut MockRtCallbackReturn,
+                        _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                        _: &mut AccessScope<OGRuntimeBranding>| {
+        let arg = ctx.get_argument_register(0).unwrap();
+        if arg == 0 {
+            panic!("callback panic");
+        }
+        ret.set_return_register(0, arg + 1);
+    };
+
+    rt.setup_callback(
+        &mut callback,
+        &mut alloc_scope,
+        |trampoline, alloc_scope| {
+            let panic = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+                rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                    // Foreign code continues running after the callback panicked:
+                    assert_eq!(test_invoke_trampoline(trampoline, 0), 0);
+                    test_invoke_trampoline(trampoline, 1)
+                })
+            }))
+            .unwrap_err();
+            assert_eq!(panic.downcast_ref::<&str>(), Some(&"callback panic"));
+
+            // The runtime remains usable:
+            let res = rt
+                .execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                    test_invoke_trampoline(trampoline, 41)
+                })
+                .unwrap();
+            assert_eq!(res, 42);
+        },
+    )
+    .unwrap();
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let allocator = stack_alloc::StackAllocator::<stack_alloc::StackFrameAllocAMD64>::new();
+        let Err(panic) = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| unsafe {
+            allocator.with_alloc(core::alloc::Layout::new::<u64>(), |_| {
+                panic!("stacked panic");
+            })
+        })) else {
+            panic!("with_alloc did not resume the panic");
+        };
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"stacked panic"));
+    }
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_immutable_region_write_detection() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (mut rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::new(
+            true,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    };
+    rt.set_check_immutable_regions(true);
+
+    let symbol = 0x1000 as *const ();
+    // Foreign code writes to this memory, which is shared without copying it.
+    // Derive all pointers to it from a mutable heap allocation, and not from an
+    // immutable binding:
+    let mut values = std::vec![1u32, 2, 3, 4];
+    let ptr = values.as_mut_ptr();
+    rt.write_stacked_slice(
+        unsafe { core::slice::from_raw_parts(ptr, 4) },
+        &mut alloc_scope,
+        &mut access_scope,
+        |slice, alloc_scope, access_scope| {
+            assert_eq!(slice.as_ptr(), ptr as *const u32);
+
+            // Reading the region is fine:
+            let res = rt.execute(symbol, alloc_scope, access_scope, || unsafe {
+                ptr.add(1).read_volatile()
+            });
+            assert_eq!(res, Ok(2));
+
+            // Writing through the `const` pointer is caught:
+            let res = rt.execute(symbol, alloc_scope, access_scope, || unsafe {
+                ptr.add(2).write_volatile(42);
+            });
+            assert_eq!(
+                res,
+                Err(OGError::ForeignWriteToImmutable {
+                    region_start: ptr as usize,
+                    region_len: 16,
+                    symbol: symbol as usize,
+                })
+            );
+        },
+    )
+    .unwrap();
+}
+
+#[cfg(all(feature = "std", feature = "runtime_id"))]
+#[test]
+fn test_red_zone_overrun_detection() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    fn check<A: MockRtAllocator>(allocator: A) {
+        let (rt, mut alloc_scope, mut access_scope) =
+            unsafe { MockRt::new(false, false, allocator, OGRuntimeBranding::new()) };
+        let layout = core::alloc::Layout::from_size_align(13, 4).unwrap();
+
+        // Overruns are detected after `execute`, and again when the
+        // allocation's scope ends:
+        let mut execute_res = None;
+        let res = rt.allocate_stacked_mut(layout, &mut alloc_scope, |ptr, alloc_scope| {
+            assert_eq!(ptr as usize % 4, 0);
+            rt.execute(
+                core::ptr::null(),
+                alloc_scope,
+                &mut access_scope,
+                || unsafe {
+                    (ptr as *mut u8).add(12).write_volatile(0);
+                },
+            )
+            .unwrap();
+            execute_res = Some(rt.execute(
+                core::ptr::null(),
+                alloc_scope,
+                &mut access_scope,
+                || unsafe {
+                    (ptr as *mut u8).add(13).write_volatile(0);
+                },
+            ));
+        });
+        assert_eq!(
+            execute_res,
+            Some(Err(OGError::AllocRedZoneOverrun { layout }))
+        );
+        assert_eq!(res, Err(OGError::AllocRedZoneOverrun { layout }));
+
+        // Allocations of a `T` are placed between red zones as well:
+        let res = rt.allocate_stacked_t_mut::<u64, _, _>(&mut alloc_scope, |ogref, _| unsafe {
+            (ogref.as_ptr() as *mut u8).sub(1).write_volatile(0);
+        });
+        assert_eq!(
+            res,
+            Err(OGError::AllocRedZoneOverrun {
+                layout: core::alloc::Layout::new::<u64>()
+            })
+        );
+    }
+
+    check(red_zone::RedZoneAllocator::new(
+        heap_alloc::HeapAllocator,
+        16,
+    ));
+    #[cfg(target_arch = "x86_64")]
+    check(red_zone::RedZoneAllocator::new(
+        stack_alloc::StackAllocator::<stack_alloc::StackFrameAllocAMD64>::new(),
+        8,
+    ));
+}
+
+#[cfg(all(
+    feature = "std",
+    feature = "runtime_id",
+    target_os = "linux",
+    target_env = "gnu"
+))]
+#[test]
+fn test_new_with_library() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let library = crate::util::test_lib::compile(
+        "mock_rt_library",
+        "
+        unsigned long scale = 3;
+
+        unsigned long scaled_add(unsigned long a, unsigned long b) {
+            return (a + b) * scale;
+        }
+        ",
+    );
+
+    assert!(matches!(
+        unsafe {
+            MockRt::<_, _>::new_with_library(
+                c"/nonexistent/libmissing.so",
+                false,
+                false,
+                heap_alloc::HeapAllocator,
+                OGRuntimeBranding::new(),
+            )
+        },
+        Err(OGError::LibraryLoadFailed)
+    ));
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::<_, _>::new_with_library(
+            &library,
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    }
+    .unwrap();
+
+    // Symbols missing from the library are reported by name:
+    assert!(matches!(
+        rt.resolve_symbols(&[c"scaled_add", c"missing"], &[]),
+        Err(Some(name)) if name == c"missing"
+    ));
+
+    let symtab = rt.resolve_symbols(&[c"scaled_add"], &[]).unwrap();
+    let scaled_add = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let scaled_add: unsafe extern "C" fn(usize, usize) -> usize =
+        unsafe { core::mem::transmute(scaled_add) };
+
+    let res = rt.execute(
+        scaled_add as *const (),
+        &mut alloc_scope,
+        &mut access_scope,
+        || unsafe { scaled_add(2, 5) },
+    );
+    assert_eq!(res, Ok(21));
+}
+
+#[cfg(all(
+    feature = "std",
+    feature = "runtime_id",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+#[test]
+fn test_variadic_foreign_call() {
+    use crate::abi::sysv_amd64::{
+        SysVArgAllocator, SysVArgLocation, SysVLayout, SysVReg, SysVScalar, SysVScalarClass,
+        sysv_amd64_return_location,
+    };
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let library = crate::util::test_lib::compile(
+        "mock_rt_variadic",
+        "
+        #include <stdarg.h>
+        #include <stdio.h>
+
+        int format(char *buf, unsigned long len, const char *fmt, ...) {
+            va_list args;
+            va_start(args, fmt);
+            int res = vsnprintf(buf, len, fmt, args);
+            va_end(args);
+            return res;
+        }
+        ",
+    );
+
+    const fn scalar(class: SysVScalarClass) -> [SysVScalar; 1] {
+        [SysVScalar {
+            offset: 0,
+            size: 8,
+            class,
+        }]
+    }
+    const INT: [SysVScalar; 1] = scalar(SysVScalarClass::Integer);
+    const DOUBLE: [SysVScalar; 1] = scalar(SysVScalarClass::Sse);
+    let layout = |fields| SysVLayout {
+        size: 8,
+        align: 8,
+        fields,
+    };
+
+    // format(buf, len, fmt, ...), called with a string, an int and two doubles
+    // as variadic arguments:
+    let mut args = SysVArgAllocator::new(sysv_amd64_return_location(&layout(&INT)));
+    let locations =
+        [&INT, &INT, &INT, &INT, &INT, &DOUBLE, &DOUBLE].map(|arg| args.allocate(&layout(arg)));
+    let reg = |reg| SysVArgLocation::Registers([Some(reg), None]);
+    assert_eq!(
+        locations,
+        [
+            reg(SysVReg::Integer(0)),
+            reg(SysVReg::Integer(1)),
+            reg(SysVReg::Integer(2)),
+            reg(SysVReg::Integer(3)),
+            reg(SysVReg::Integer(4)),
+            reg(SysVReg::Sse(0)),
+            reg(SysVReg::Sse(1)),
+        ]
+    );
+    assert_eq!(args.stack_words(), 0);
+    assert_eq!(args.vector_registers(), 2);
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        MockRt::<_, _>::new_with_library(
+            &library,
+            false,
+            false,
+            heap_alloc::HeapAllocator,
+            OGRuntimeBranding::new(),
+        )
+    }
+    .unwrap();
+    let symtab = rt.resolve_symbols(&[c"format"], &[]).unwrap();
+    let symbol = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+    rt.allocate_stacked_mut(
+        core::alloc::Layout::new::<[u8; 64]>(),
+        &mut alloc_scope,
+        |buf, alloc_scope| {
+            let res = rt.execute(symbol, alloc_scope, &mut access_scope, || {
+                let res: usize;
+                unsafe {
+                    core::arch::asm!(
+                        "call {symbol}",
+                        symbol = in(reg) symbol,
+                        in("rdi") buf,
+                        in("rsi") 64,
+                        in("rdx") c"%s: %d, %.2f, %.3f".as_ptr(),
+                        in("rcx") c"answer".as_ptr(),
+                        in("r8") 42,
+                        in("xmm0") 1.5_f64,
+                        in("xmm1") -0.125_f64,
+                        // The number of vector registers used, for variadic
+                        // functions. The callee only saves this many vector
+                        // registers for `va_arg`:
+                        inout("rax") args.vector_registers() as usize => res,
+                        clobber_abi("C"),
+                    )
+                };
+                res as i32
+            });
+
+            // Both vector registers reached `vsnprintf`:
+            let expected = c"answer: 42, 1.50, -0.125";
+            assert_eq!(res, Ok(expected.count_bytes() as i32));
+            assert_eq!(unsafe { CStr::from_ptr(buf as *const _) }, expected);
+        },
+    )
+    .unwrap();
+}
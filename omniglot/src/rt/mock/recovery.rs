@@ -0,0 +1,913 @@
+// -*- fill-column: 80; -*-
+
+//! Recovery from foreign code that faults, overflows its stack or exceeds its
+//! deadline, for use by the [`MockRt`](super::MockRt).
+//!
+//! Host code establishes a recovery point through [`with_recovery`], which
+//! saves the host's callee-saved registers and stack pointer, and then runs a
+//! closure (optionally on a different stack). When this closure raises a
+//! `SIGSEGV`, `SIGBUS` or `SIGFPE` (including faults on a stack guard page), or
+//! its deadline timer fires, our signal handler rewrites the interrupted
+//! context to resume at the recovery point instead.
+//!
+//! Resuming at a recovery point discards all frames below it without running
+//! their destructors. This includes frames of host code invoked by foreign code
+//! through callbacks. The MockRt restores the state these frames would restore
+//! when returning (its allocator's bookkeeping, dispatch frames and the locks
+//! of our heap tracking hooks) in drop guards enclosing the recovery point.
+//! Any other host state modified by abandoned callbacks may be inconsistent.
+//!
+//! Faults raised by host code invoked through callbacks are not caught, as they
+//! indicate a bug in the host rather than in foreign code. Callbacks run within
+//! [`with_host_code`], which hides all enclosing recovery points that catch
+//! faults. Stack overflows and deadlines still abort such callbacks.
+
+use core::cell::Cell;
+use core::ffi::{c_int, c_void};
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering, compiler_fence};
+use core::time::Duration;
+use std::sync::{Once, OnceLock};
+
+/// Reason for aborting the closure passed to [`with_recovery`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Aborted {
+    StackOverflow,
+    Timeout,
+    Fault { signal: c_int, address: usize },
+}
+
+// Values returned by `mock_rt_recovery_enter`:
+const ENTER_RETURNED: usize = 0;
+const ENTER_STACK_OVERFLOW: usize = 1;
+const ENTER_TIMEOUT: usize = 2;
+const ENTER_FAULT: usize = 3;
+
+// Signals raised by faulting instructions:
+const FAULT_SIGNALS: [c_int; 3] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGFPE];
+
+// A recovery point. These are linked into a per-thread list, innermost first.
+#[repr(C)]
+struct RecoveryPoint {
+    // Host stack pointer to resume at, written by `mock_rt_recovery_enter` (and
+    // hence at offset 0):
+    host_sp: usize,
+    guard: Range<usize>,
+    catch_faults: bool,
+    // Pushed by `with_host_code`, this is not a recovery point but hides all
+    // enclosing ones from faults:
+    host_code: bool,
+    // Identifies signals of this recovery point's deadline timer, if any. Set
+    // once `f` has completed, after which its timer no longer aborts it:
+    timer_id: usize,
+    completed: Cell<bool>,
+    // Signal and faulting address, set by our signal handler:
+    fault: Cell<(c_int, usize)>,
+    prev: *const RecoveryPoint,
+}
+
+std::thread_local! {
+    // Only ever accessed as a plain `Cell`, which is safe to do from within our
+    // signal handler:
+    static MOCK_RT_RECOVERY_POINT: Cell<*const RecoveryPoint> =
+        const { Cell::new(core::ptr::null()) };
+}
+
+// Dispositions of the signals we handle prior to installing our handler:
+static MOCK_RT_PREV_SIGACTIONS: OnceLock<[(c_int, libc::sigaction); 4]> = OnceLock::new();
+
+// Deadline timers deliver this signal to the thread that armed them:
+fn timer_signal() -> c_int {
+    libc::SIGRTMIN()
+}
+
+// Signals of a deleted timer may still be pending. We identify timers by a
+// unique ID instead of their recovery point's address (which may be reused), so
+// that such signals are never attributed to a later recovery point:
+static MOCK_RT_NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Run `f`, returning early when it overflows its stack into `guard`, when it
+/// exceeds `timeout`, or (with `catch_faults`) when it raises any other fault.
+///
+/// When `stack_pointer` is set, `f` runs on the stack ending at this address.
+/// Otherwise, it runs on the current stack.
+///
+/// # Safety
+///
+/// `stack_pointer` must be the 16-byte aligned end of a stack sufficiently
+/// large for `f`. Aborting `f` discards its frames without running their
+/// destructors.
+pub(super) unsafe fn with_recovery<R, F: FnOnce() -> R>(
+    stack_pointer: Option<usize>,
+    guard: Range<usize>,
+    catch_faults: bool,
+    timeout: Option<Duration>,
+    f: F,
+) -> Result<R, Aborted> {
+    install_signal_handler();
+    ensure_signal_stack();
+
+    struct Data<RP, FP> {
+        closure: Option<FP>,
+        // Armed once we're running on the far side of the recovery point:
+        timer: Option<(libc::timer_t, Duration)>,
+        completed: *const Cell<bool>,
+        ret: Option<std::thread::Result<RP>>,
+    }
+
+    unsafe extern "C" fn invoke<RP, FP: FnOnce() -> RP>(data: *mut ()) {
+        let data: &mut Data<RP, FP> = unsafe { &mut *(data as *mut Data<RP, FP>) };
+        let closure = data.closure.take().unwrap();
+
+        if let Some((timer, timeout)) = data.timer {
+            // A zero `it_value` would disarm the timer instead:
+            set_timer(timer, core::cmp::max(timeout, Duration::from_nanos(1)));
+        }
+
+        // We must not unwind through `mock_rt_recovery_enter`, so catch any
+        // panic and resume it on the host side:
+        data.ret = Some(std::panic::catch_unwind(core::panic::AssertUnwindSafe(
+            closure,
+        )));
+
+        // From here on, the closure's result must not be discarded. Our signal
+        // handler ignores timer signals of completed recovery points, so only
+        // then disarm the timer:
+        compiler_fence(Ordering::SeqCst);
+        unsafe { &*data.completed }.set(true);
+        compiler_fence(Ordering::SeqCst);
+        if let Some((timer, _)) = data.timer {
+            set_timer(timer, Duration::ZERO);
+        }
+    }
+
+    let mut recovery_point = RecoveryPoint {
+        host_sp: 0,
+        guard,
+        catch_faults,
+        host_code: false,
+        timer_id: match timeout {
+            Some(_) => MOCK_RT_NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        },
+        completed: Cell::new(false),
+        fault: Cell::new((0, 0)),
+        prev: MOCK_RT_RECOVERY_POINT.with(Cell::get),
+    };
+
+    let mut data = Data {
+        closure: Some(f),
+        timer: timeout.map(|timeout| (create_timer(recovery_point.timer_id), timeout)),
+        completed: &recovery_point.completed,
+        ret: None,
+    };
+
+    MOCK_RT_RECOVERY_POINT.with(|active| active.set(&recovery_point));
+    let aborted = unsafe {
+        mock_rt_recovery_enter(
+            stack_pointer.unwrap_or(0),
+            invoke::<R, F>,
+            &mut data as *mut Data<R, F> as *mut (),
+            &mut recovery_point,
+        )
+    };
+    MOCK_RT_RECOVERY_POINT.with(|active| active.set(recovery_point.prev));
+
+    if let Some((timer, _)) = data.timer {
+        unsafe { libc::timer_delete(timer) };
+    }
+
+    match aborted {
+        ENTER_RETURNED => match data.ret.unwrap() {
+            Ok(ret) => Ok(ret),
+            Err(payload) => std::panic::resume_unwind(payload),
+        },
+        ENTER_STACK_OVERFLOW => Err(Aborted::StackOverflow),
+        ENTER_TIMEOUT => Err(Aborted::Timeout),
+        _ => {
+            let (signal, address) = recovery_point.fault.get();
+            Err(Aborted::Fault { signal, address })
+        }
+    }
+}
+
+/// Run host code invoked by foreign code, such that faults it raises are not
+/// attributed to the foreign code (and caught) by any enclosing recovery point.
+///
+/// Recovery points established within `f` are unaffected.
+pub(super) fn with_host_code<R>(f: impl FnOnce() -> R) -> R {
+    // Pops the barrier again, even when `f` unwinds:
+    struct PopBarrier(*const RecoveryPoint);
+
+    impl Drop for PopBarrier {
+        fn drop(&mut self) {
+            MOCK_RT_RECOVERY_POINT.with(|active| active.set(self.0));
+        }
+    }
+
+    let barrier = RecoveryPoint {
+        host_sp: 0,
+        guard: 0..0,
+        catch_faults: false,
+        host_code: true,
+        timer_id: 0,
+        completed: Cell::new(false),
+        fault: Cell::new((0, 0)),
+        prev: MOCK_RT_RECOVERY_POINT.with(Cell::get),
+    };
+
+    MOCK_RT_RECOVERY_POINT.with(|active| active.set(&barrier));
+    let _pop_barrier = PopBarrier(barrier.prev);
+
+    f()
+}
+
+// Create a (disarmed) timer which signals this thread, identifying the
+// recovery point with `timer_id` to resume at:
+fn create_timer(timer_id: usize) -> libc::timer_t {
+    let mut event: libc::sigevent = unsafe { core::mem::zeroed() };
+    event.sigev_notify = libc::SIGEV_THREAD_ID;
+    event.sigev_signo = timer_signal();
+    event.sigev_notify_thread_id = unsafe { libc::gettid() };
+    event.sigev_value = libc::sigval {
+        sival_ptr: timer_id as *mut c_void,
+    };
+
+    let mut timer: libc::timer_t = core::ptr::null_mut();
+    let res = unsafe { libc::timer_create(libc::CLOCK_MONOTONIC, &mut event, &mut timer) };
+    assert!(res == 0, "failed to create a deadline timer");
+    timer
+}
+
+// Arm `timer` to expire once after `timeout`, or disarm it for a zero
+// `timeout`:
+fn set_timer(timer: libc::timer_t, timeout: Duration) {
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        },
+    };
+    unsafe { libc::timer_settime(timer, 0, &spec, core::ptr::null_mut()) };
+}
+
+/// Install our signal handler for faults and deadline timers, if not done
+/// already. The handler applies process-wide, but defers to the previous
+/// disposition of a signal unless a recovery point on the signalled thread
+/// catches it.
+pub(super) fn install_signal_handler() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let prev = [libc::SIGSEGV, libc::SIGBUS, libc::SIGFPE, timer_signal()].map(|signal| {
+            let mut prev: libc::sigaction = unsafe { core::mem::zeroed() };
+            unsafe { libc::sigaction(signal, core::ptr::null(), &mut prev) };
+            (signal, prev)
+        });
+        MOCK_RT_PREV_SIGACTIONS.set(prev).unwrap();
+
+        let mut action: libc::sigaction = unsafe { core::mem::zeroed() };
+        action.sa_sigaction = mock_rt_recovery_signal_handler as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        unsafe { libc::sigemptyset(&mut action.sa_mask) };
+
+        for (signal, _) in prev {
+            unsafe { libc::sigaction(signal, &action, core::ptr::null_mut()) };
+        }
+    });
+}
+
+// An alternate signal stack allocated by `ensure_signal_stack`. Uninstalled and
+// freed once its thread exits:
+struct SignalStack {
+    mapping: *mut c_void,
+    size: usize,
+}
+
+impl SignalStack {
+    // Allocate and install an alternate signal stack for this thread, unless it
+    // already has one. Threads spawned by `std` usually do:
+    fn install() -> Option<SignalStack> {
+        let mut current: libc::stack_t = unsafe { core::mem::zeroed() };
+        unsafe { libc::sigaltstack(core::ptr::null(), &mut current) };
+        if current.ss_flags & libc::SS_DISABLE == 0 {
+            return None;
+        }
+
+        let size = core::cmp::max(libc::SIGSTKSZ, 64 * 1024);
+        let mapping = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert!(
+            mapping != libc::MAP_FAILED,
+            "failed to allocate a signal stack"
+        );
+
+        let stack = libc::stack_t {
+            ss_sp: mapping,
+            ss_flags: 0,
+            ss_size: size,
+        };
+        unsafe { libc::sigaltstack(&stack, core::ptr::null_mut()) };
+
+        Some(SignalStack { mapping, size })
+    }
+}
+
+impl Drop for SignalStack {
+    fn drop(&mut self) {
+        // Signals delivered after this point must not use the freed stack. Leave
+        // any signal stack installed by someone else in place:
+        let mut current: libc::stack_t = unsafe { core::mem::zeroed() };
+        unsafe { libc::sigaltstack(core::ptr::null(), &mut current) };
+        if current.ss_sp == self.mapping {
+            let disable = libc::stack_t {
+                ss_sp: core::ptr::null_mut(),
+                ss_flags: libc::SS_DISABLE,
+                ss_size: 0,
+            };
+            unsafe { libc::sigaltstack(&disable, core::ptr::null_mut()) };
+        }
+
+        unsafe { libc::munmap(self.mapping, self.size) };
+    }
+}
+
+std::thread_local! {
+    static MOCK_RT_SIGNAL_STACK: core::cell::OnceCell<Option<SignalStack>> =
+        const { core::cell::OnceCell::new() };
+}
+
+// Our signal handler cannot run on an overflowed stack, so ensure that this
+// thread has an alternate signal stack. This is checked only once per thread:
+fn ensure_signal_stack() {
+    // During thread exit, the signal stack may already be gone. We then run
+    // without one:
+    let _ = MOCK_RT_SIGNAL_STACK.try_with(|stack| {
+        stack.get_or_init(SignalStack::install);
+    });
+}
+
+// Find the innermost active recovery point of this thread matching `pred`:
+fn find_recovery_point(pred: impl Fn(&RecoveryPoint) -> bool) -> Option<&'static RecoveryPoint> {
+    let mut recovery_point = MOCK_RT_RECOVERY_POINT.with(Cell::get);
+    while let Some(point) = unsafe { recovery_point.as_ref() } {
+        if pred(point) {
+            return Some(point);
+        }
+        recovery_point = point.prev;
+    }
+    None
+}
+
+/// The lowest host stack pointer recorded by any active recovery point of this
+/// thread that lies within `stack`.
+pub(super) fn lowest_host_stack_pointer(stack: Range<usize>) -> Option<usize> {
+    let mut lowest = None;
+    let mut recovery_point = MOCK_RT_RECOVERY_POINT.with(Cell::get);
+    while let Some(point) = unsafe { recovery_point.as_ref() } {
+        if stack.contains(&point.host_sp) {
+            lowest = Some(core::cmp::min(point.host_sp, lowest.unwrap_or(usize::MAX)));
+        }
+        recovery_point = point.prev;
+    }
+    lowest
+}
+
+// Find the innermost active recovery point of this thread catching faults,
+// unless host code has been entered through `with_host_code` since:
+fn find_fault_recovery_point() -> Option<&'static RecoveryPoint> {
+    let mut recovery_point = MOCK_RT_RECOVERY_POINT.with(Cell::get);
+    while let Some(point) = unsafe { recovery_point.as_ref() } {
+        if point.host_code {
+            return None;
+        }
+        if point.catch_faults {
+            return Some(point);
+        }
+        recovery_point = point.prev;
+    }
+    None
+}
+
+unsafe extern "C" fn mock_rt_recovery_signal_handler(
+    signal: c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut c_void,
+) {
+    // Our recovery points live on the host stack, which foreign code may not
+    // have access to. Resuming at a recovery point returns to the host with
+    // access restored. On all other paths, we revoke it again before returning
+    // to the interrupted code:
+    let restored = super::mock_rt_restore_host_access();
+
+    let si_code = unsafe { (*info).si_code };
+    // Faults are raised by the kernel, not sent by another process:
+    let is_fault = FAULT_SIGNALS.contains(&signal) && si_code > 0;
+
+    let target = if signal == timer_signal() && si_code == libc::SI_TIMER {
+        let timer_id = unsafe { (*info).si_value().sival_ptr } as usize;
+        match find_recovery_point(|point| point.timer_id == timer_id && timer_id != 0) {
+            Some(point) if !point.completed.get() => Some((point, ENTER_TIMEOUT)),
+            // The timer of a recovery point that has completed, or that is no
+            // longer active:
+            _ => None,
+        }
+    } else if is_fault {
+        let fault_addr = unsafe { (*info).si_addr() } as usize;
+        // Overflows abandon the entire stack, even when a nested recovery
+        // point would catch other faults:
+        find_recovery_point(|point| point.guard.contains(&fault_addr))
+            .map(|point| (point, ENTER_STACK_OVERFLOW))
+            .or_else(|| {
+                let point = find_fault_recovery_point()?;
+                point.fault.set((signal, fault_addr));
+                Some((point, ENTER_FAULT))
+            })
+    } else {
+        None
+    };
+
+    // Resume at the recovery point, as if `mock_rt_recovery_enter` returned the
+    // reason for aborting:
+    if let Some((point, aborted)) = target {
+        let gregs = unsafe { &mut (*(context as *mut libc::ucontext_t)).uc_mcontext.gregs };
+        gregs[libc::REG_RSP as usize] = point.host_sp as i64;
+        gregs[libc::REG_RIP as usize] = mock_rt_recovery_resume as *const () as usize as i64;
+        gregs[libc::REG_RAX as usize] = aborted as i64;
+        return;
+    }
+
+    // Otherwise, defer to the previous disposition of this signal. Timer
+    // signals of recovery points that are no longer active are ignored:
+    if !(signal == timer_signal() && si_code == libc::SI_TIMER) {
+        unsafe { defer_to_prev_sigaction(signal, info, context, is_fault) };
+    }
+
+    if let Some(hooks) = restored {
+        unsafe { (hooks.revoke)(hooks.data) };
+    }
+}
+
+unsafe fn defer_to_prev_sigaction(
+    signal: c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut c_void,
+    is_fault: bool,
+) {
+    let Some(prev) = MOCK_RT_PREV_SIGACTIONS
+        .get()
+        .and_then(|prev| prev.iter().find(|(s, _)| *s == signal))
+        .map(|(_, prev)| prev)
+    else {
+        return;
+    };
+
+    match prev.sa_sigaction {
+        libc::SIG_IGN => (),
+        libc::SIG_DFL => {
+            // Restore the default disposition and raise this signal again. For
+            // faults, returning re-executes the faulting instruction:
+            let mut action: libc::sigaction = unsafe { core::mem::zeroed() };
+            action.sa_sigaction = libc::SIG_DFL;
+            unsafe { libc::sigaction(signal, &action, core::ptr::null_mut()) };
+            if !is_fault {
+                unsafe { libc::raise(signal) };
+            }
+        }
+        handler if prev.sa_flags & libc::SA_SIGINFO != 0 => {
+            let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                unsafe { core::mem::transmute(handler) };
+            handler(signal, info, context);
+        }
+        handler => {
+            let handler: extern "C" fn(c_int) = unsafe { core::mem::transmute(handler) };
+            handler(signal);
+        }
+    }
+}
+
+// Invoke `cb(data)`, on the stack ending at `stack_pointer` if it is non-zero.
+// Returns 0 when `cb` returns, or the reason for aborting it when resumed
+// through `mock_rt_recovery_resume`.
+//
+// Before invoking `cb`, this saves all callee-saved registers on the host stack
+// and records the resulting host stack pointer in `recovery_point`. The call
+// frame information allows the unwinder to walk from a different stack back
+// onto the host stack.
+#[unsafe(naked)]
+unsafe extern "C" fn mock_rt_recovery_enter(
+    _stack_pointer: usize,
+    _cb: unsafe extern "C" fn(*mut ()),
+    _data: *mut (),
+    _recovery_point: *mut RecoveryPoint,
+) -> usize {
+    core::arch::naked_asm!(
+        "
+        .cfi_startproc
+
+        push rbp
+        .cfi_def_cfa_offset 16
+        .cfi_offset rbp, -16
+        push rbx
+        .cfi_def_cfa_offset 24
+        .cfi_offset rbx, -24
+        push r12
+        .cfi_def_cfa_offset 32
+        .cfi_offset r12, -32
+        push r13
+        .cfi_def_cfa_offset 40
+        .cfi_offset r13, -40
+        push r14
+        .cfi_def_cfa_offset 48
+        .cfi_offset r14, -48
+        push r15
+        .cfi_def_cfa_offset 56
+        .cfi_offset r15, -56
+
+        // Keep the host stack 16-byte aligned:
+        sub rsp, 8
+        .cfi_def_cfa_offset 64
+
+        // Record the host stack pointer for recovery, and keep it in rbx:
+        mov [rcx], rsp
+        mov rbx, rsp
+        .cfi_def_cfa_register rbx
+
+        // Switch stacks if requested, and invoke the callback:
+        test rdi, rdi
+        jz 2f
+        mov rsp, rdi
+    2:
+        mov rdi, rdx
+        call rsi
+
+        mov rsp, rbx
+        .cfi_def_cfa_register rsp
+        xor eax, eax
+
+        add rsp, 8
+        .cfi_def_cfa_offset 56
+        pop r15
+        .cfi_def_cfa_offset 48
+        .cfi_restore r15
+        pop r14
+        .cfi_def_cfa_offset 40
+        .cfi_restore r14
+        pop r13
+        .cfi_def_cfa_offset 32
+        .cfi_restore r13
+        pop r12
+        .cfi_def_cfa_offset 24
+        .cfi_restore r12
+        pop rbx
+        .cfi_def_cfa_offset 16
+        .cfi_restore rbx
+        pop rbp
+        .cfi_def_cfa_offset 8
+        .cfi_restore rbp
+        ret
+
+        .cfi_endproc
+        ",
+    );
+}
+
+// Resumed by our signal handler, with the stack pointer set to the host stack
+// pointer recorded by `mock_rt_recovery_enter`, and the reason for aborting in
+// rax. Restores its callee-saved registers and returns to its caller.
+#[unsafe(naked)]
+unsafe extern "C" fn mock_rt_recovery_resume() {
+    core::arch::naked_asm!(
+        "
+        add rsp, 8
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbx
+        pop rbp
+        ret
+        ",
+    );
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_execute_with_deadline() {
+    use crate::OGError;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+    use crate::rt::mock::foreign_stack::ForeignStackAllocator;
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        super::MockRt::new(
+            false,
+            false,
+            ForeignStackAllocator::new(64 * 1024).unwrap(),
+            OGRuntimeBranding::new(),
+        )
+    };
+    const {
+        assert!(
+            <super::MockRt<OGRuntimeBranding, ForeignStackAllocator> as OGRuntime>::SUPPORTS_DEADLINE
+        )
+    };
+
+    let timeout = Duration::from_millis(10);
+    rt.write_stacked_t(
+        42_u32,
+        &mut alloc_scope,
+        &mut access_scope,
+        |val, alloc_scope, access_scope| {
+            // Calls completing before their deadline are unaffected:
+            assert_eq!(
+                rt.execute_with_deadline(
+                    core::ptr::null(),
+                    alloc_scope,
+                    access_scope,
+                    timeout,
+                    || 1
+                ),
+                Ok(1)
+            );
+            assert!(!access_scope.is_poisoned());
+
+            // Calls spinning forever are aborted, and poison the domain:
+            assert_eq!(
+                rt.execute_with_deadline(
+                    core::ptr::null(),
+                    alloc_scope,
+                    access_scope,
+                    timeout,
+                    || {
+                        loop {
+                            core::hint::black_box(());
+                        }
+                    }
+                ),
+                Err(OGError::Timeout)
+            );
+            assert!(access_scope.is_poisoned());
+            assert!(
+                std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+                    val.copy(access_scope);
+                }))
+                .is_err()
+            );
+
+            unsafe { access_scope.unpoison() };
+            assert_eq!(*val.copy(access_scope).valid_ref(), 42);
+        },
+    )
+    .unwrap();
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_execute_foreign_fault() {
+    use crate::OGError;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::OGRuntime;
+    use crate::rt::mock::foreign_stack::ForeignStackAllocator;
+
+    fn divide(dividend: u64, divisor: u64) -> u64 {
+        let quotient: u64;
+        unsafe {
+            core::arch::asm!(
+                "div {divisor}",
+                divisor = in(reg) divisor,
+                inout("rax") dividend => quotient,
+                inout("rdx") 0_u64 => _,
+                options(nomem, nostack),
+            )
+        };
+        quotient
+    }
+
+    let (mut rt, mut alloc_scope, mut access_scope) = unsafe {
+        super::MockRt::new(
+            false,
+            false,
+            ForeignStackAllocator::new(64 * 1024).unwrap(),
+            OGRuntimeBranding::new(),
+        )
+    };
+    rt.set_catch_faults(true);
+    let symbol = divide as *const ();
+
+    // Invalid memory accesses are reported with their faulting address, and
+    // poison the domain:
+    assert_eq!(
+        rt.execute(symbol, &mut alloc_scope, &mut access_scope, || unsafe {
+            core::ptr::read_volatile(0x10 as *const u64)
+        }),
+        Err(OGError::ForeignFault {
+            signal: libc::SIGSEGV,
+            address: 0x10,
+            symbol: symbol as usize,
+        })
+    );
+    assert!(access_scope.is_poisoned());
+    unsafe { access_scope.unpoison() };
+
+    // ... as are arithmetic exceptions:
+    assert!(matches!(
+        rt.execute(symbol, &mut alloc_scope, &mut access_scope, || divide(1, 0)),
+        Err(OGError::ForeignFault {
+            signal: libc::SIGFPE,
+            ..
+        })
+    ));
+    assert!(access_scope.is_poisoned());
+    unsafe { access_scope.unpoison() };
+
+    // Subsequent executions are unaffected:
+    assert_eq!(
+        rt.execute(symbol, &mut alloc_scope, &mut access_scope, || divide(
+            42, 2
+        )),
+        Ok(21)
+    );
+    assert!(!access_scope.is_poisoned());
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_host_callback_fault_not_caught() {
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::markers::{AccessScope, AllocScope};
+    use crate::rt::OGRuntime;
+    use crate::rt::mock::heap_alloc::HeapAllocator;
+    use crate::rt::mock::{MockRtAllocChain, MockRtCallbackContext, MockRtCallbackReturn};
+
+    // A fault within a host callback is not recoverable, so observe it from a
+    // child process:
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+
+    if pid == 0 {
+        let res = std::panic::catch_unwind(|| {
+            let (mut rt, mut alloc_scope, mut access_scope) = unsafe {
+                super::MockRt::new(false, false, HeapAllocator, OGRuntimeBranding::new())
+            };
+            rt.set_catch_faults(true);
+
+            let mut callback =
+                |_: &MockRtCallbackContext,
+                 _: &mut MockRtCallbackReturn,
+                 _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                 _: &mut AccessScope<OGRuntimeBranding>| {
+                    unsafe { core::ptr::read_volatile(0x10 as *const u64) };
+                };
+
+            rt.setup_callback(
+                &mut callback,
+                &mut alloc_scope,
+                |trampoline, alloc_scope| {
+                    rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                        super::test_invoke_trampoline(trampoline, 0)
+                    })
+                },
+            )
+        });
+
+        // The fault must have terminated the child before returning here:
+        unsafe { libc::_exit(if res.is_ok() { 0 } else { 1 }) };
+    }
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFSIGNALED(status));
+    assert_eq!(libc::WTERMSIG(status), libc::SIGSEGV);
+}
+
+#[test]
+fn test_signal_stack_per_thread() {
+    fn current_signal_stack() -> libc::stack_t {
+        let mut current: libc::stack_t = unsafe { core::mem::zeroed() };
+        unsafe { libc::sigaltstack(core::ptr::null(), &mut current) };
+        current
+    }
+
+    std::thread::spawn(|| {
+        // Start out without an alternate signal stack:
+        let disable = libc::stack_t {
+            ss_sp: core::ptr::null_mut(),
+            ss_flags: libc::SS_DISABLE,
+            ss_size: 0,
+        };
+        unsafe { libc::sigaltstack(&disable, core::ptr::null_mut()) };
+
+        assert_eq!(
+            unsafe { with_recovery(None, 0..0, false, None, || 1) },
+            Ok(1)
+        );
+        let installed = current_signal_stack();
+        assert_eq!(installed.ss_flags & libc::SS_DISABLE, 0);
+
+        // Subsequent recovery points reuse this thread's signal stack:
+        assert_eq!(
+            unsafe { with_recovery(None, 0..0, false, None, || 2) },
+            Ok(2)
+        );
+        assert_eq!(current_signal_stack().ss_sp, installed.ss_sp);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_abandoned_callback_restores_allocator_state() {
+    use crate::OGError;
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::markers::{AccessScope, AllocScope};
+    use crate::rt::OGRuntime;
+    use crate::rt::mock::foreign_stack::ForeignStackAllocator;
+    use crate::rt::mock::red_zone::RedZoneAllocator;
+    use crate::rt::mock::{
+        MockRtAllocChain, MockRtAllocator, MockRtCallbackContext, MockRtCallbackReturn,
+    };
+    use core::alloc::Layout;
+
+    let (rt, mut alloc_scope, mut access_scope) = unsafe {
+        super::MockRt::new(
+            false,
+            false,
+            RedZoneAllocator::new(ForeignStackAllocator::new(64 * 1024).unwrap(), 16),
+            OGRuntimeBranding::new(),
+        )
+    };
+
+    // The callback's allocation is live when its deadline expires, and its
+    // scope never ends:
+    let mut callback =
+        |_: &MockRtCallbackContext,
+         _: &mut MockRtCallbackReturn,
+         alloc_scope: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+         _: &mut AccessScope<OGRuntimeBranding>| {
+            rt.allocate_stacked_mut(Layout::new::<[u8; 4096]>(), alloc_scope, |_, _| {
+                loop {
+                    core::hint::black_box(());
+                }
+            })
+            .unwrap();
+        };
+
+    rt.setup_callback(
+        &mut callback,
+        &mut alloc_scope,
+        |trampoline, alloc_scope| {
+            assert_eq!(
+                rt.execute_with_deadline(
+                    core::ptr::null(),
+                    alloc_scope,
+                    &mut access_scope,
+                    Duration::from_millis(10),
+                    || super::test_invoke_trampoline(trampoline, 0),
+                ),
+                Err(OGError::Timeout)
+            );
+        },
+    )
+    .unwrap();
+    unsafe { access_scope.unpoison() };
+
+    // The abandoned allocation is no longer tracked, and no longer occupies
+    // the foreign stack:
+    assert!(rt.allocator().check_allocations().is_ok());
+    assert!(rt.allocator().allocator().reset());
+
+    // The foreign stack's budget is still available to nested allocations:
+    for _ in 0..64 {
+        rt.setup_callback(
+            &mut |_: &MockRtCallbackContext,
+                  _: &mut MockRtCallbackReturn,
+                  alloc_scope: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                  _: &mut AccessScope<OGRuntimeBranding>| {
+                rt.allocate_stacked_mut(Layout::new::<[u8; 4096]>(), alloc_scope, |_, _| ())
+                    .unwrap();
+            },
+            &mut alloc_scope,
+            |trampoline, alloc_scope| {
+                rt.execute(core::ptr::null(), alloc_scope, &mut access_scope, || {
+                    super::test_invoke_trampoline(trampoline, 0)
+                })
+                .unwrap();
+            },
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+// -*- fill-column: 80; -*-
+
+use core::alloc::Layout;
+use core::cell::Cell;
+
+use super::{MockRtAllocError, MockRtAllocator};
+
+// Byte pattern written to red zones:
+const RED_ZONE_CANARY: u8 = 0xa5;
+
+// A live allocation, linked into a list of all live allocations of a
+// `RedZoneAllocator`. Allocations are strictly nested, so these frames can
+// live on the host stack.
+struct RedZoneFrame {
+    base: *mut u8,
+    padded_size: usize,
+    offset: usize,
+    layout: Layout,
+    prev: *const RedZoneFrame,
+}
+
+impl RedZoneFrame {
+    fn red_zones(&self) -> [(*mut u8, usize); 2] {
+        let end = self.offset + self.layout.size();
+        [
+            (self.base, self.offset),
+            (unsafe { self.base.add(end) }, self.padded_size - end),
+        ]
+    }
+
+    fn fill(&self) {
+        for (ptr, len) in self.red_zones() {
+            unsafe { core::ptr::write_bytes(ptr, RED_ZONE_CANARY, len) };
+        }
+    }
+
+    fn intact(&self) -> bool {
+        self.red_zones().iter().all(|(ptr, len)| {
+            (0..*len).all(|i| unsafe { core::ptr::read_volatile(ptr.add(i)) } == RED_ZONE_CANARY)
+        })
+    }
+}
+
+/// A [`MockRtAllocator`] which surrounds every allocation of an underlying
+/// allocator with canary red zones.
+///
+/// The canaries are checked when an allocation's scope ends, and for all live
+/// allocations after foreign code has been executed. Overruns are reported as
+/// [`OGError::AllocRedZoneOverrun`](crate::OGError::AllocRedZoneOverrun),
+/// containing the layout of the affected allocation.
+pub struct RedZoneAllocator<A: MockRtAllocator> {
+    allocator: A,
+    red_zone_size: usize,
+    live: Cell<*const RedZoneFrame>,
+}
+
+impl<A: MockRtAllocator> RedZoneAllocator<A> {
+    /// Wrap `allocator`, placing at least `red_zone_size` bytes of canaries
+    /// before and after each allocation.
+    pub fn new(allocator: A, red_zone_size: usize) -> Self {
+        RedZoneAllocator {
+            allocator,
+            red_zone_size,
+            live: Cell::new(core::ptr::null()),
+        }
+    }
+
+    /// Access the underlying allocator.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+}
+
+impl<A: MockRtAllocator> MockRtAllocator for RedZoneAllocator<A> {
+    unsafe fn with_alloc<R, F: FnOnce(*mut ()) -> R>(
+        &self,
+        layout: Layout,
+        f: F,
+    ) -> Result<R, MockRtAllocError> {
+        // Align the leading red zone, such that the allocation itself retains
+        // its alignment:
+        let offset = self
+            .red_zone_size
+            .checked_next_multiple_of(layout.align())
+            .ok_or(MockRtAllocError::InvalidLayout)?;
+        let padded_size = offset
+            .checked_add(layout.size())
+            .and_then(|size| size.checked_add(self.red_zone_size))
+            .ok_or(MockRtAllocError::InvalidLayout)?;
+        let padded_layout = Layout::from_size_align(padded_size, layout.align())
+            .map_err(|_| MockRtAllocError::InvalidLayout)?;
+
+        // Pops our frame off the list of live allocations, even if `f` panics:
+        struct PopFrame<'a>(&'a Cell<*const RedZoneFrame>, *const RedZoneFrame);
+
+        impl Drop for PopFrame<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.1);
+            }
+        }
+
+        let mut overrun = false;
+        let ret = unsafe {
+            self.allocator.with_alloc(padded_layout, |base| {
+                let frame = RedZoneFrame {
+                    base: base as *mut u8,
+                    padded_size,
+                    offset,
+                    layout,
+                    prev: self.live.get(),
+                };
+                frame.fill();
+
+                self.live.set(&frame);
+                let pop_frame = PopFrame(&self.live, frame.prev);
+
+                let ret = f(base.byte_add(offset));
+
+                core::mem::drop(pop_frame);
+                overrun = !frame.intact();
+                ret
+            })
+        }?;
+
+        if overrun {
+            Err(MockRtAllocError::RedZoneOverrun(layout))
+        } else {
+            Ok(ret)
+        }
+    }
+
+    fn check_allocations(&self) -> Result<(), MockRtAllocError> {
+        self.allocator.check_allocations()?;
+
+        let mut frame_ptr = self.live.get();
+        while let Some(frame) = unsafe { frame_ptr.as_ref() } {
+            if !frame.intact() {
+                return Err(MockRtAllocError::RedZoneOverrun(frame.layout));
+            }
+            frame_ptr = frame.prev;
+        }
+
+        Ok(())
+    }
+
+    fn places_all_allocations(&self) -> bool {
+        true
+    }
+
+    fn with_saved_state<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        self.allocator.with_saved_state(|| {
+            // Frames of abandoned allocations are gone, and must not be checked
+            // any longer:
+            let _restore_live = super::MockRtRestoreCell(&self.live, self.live.get());
+            f()
+        })
+    }
+
+    unsafe fn with_execution_stack<R, F: FnOnce() -> R>(
+        &self,
+        f: F,
+    ) -> Result<R, MockRtAllocError> {
+        unsafe { self.allocator.with_execution_stack(f) }
+    }
+}
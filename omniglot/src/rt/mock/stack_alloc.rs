@@ -1,5 +1,6 @@
 // -*- fill-column: 80; -*-
 
+use core::cell::Cell;
 use core::marker::PhantomData;
 
 mod private {
@@ -8,6 +9,9 @@ mod private {
     #[cfg(any(target_arch = "x86_64", doc))]
     impl StackFrameAllocSeal for super::StackFrameAllocAMD64 {}
 
+    #[cfg(any(target_arch = "aarch64", doc))]
+    impl StackFrameAllocSeal for super::StackFrameAllocAArch64 {}
+
     #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", doc))]
     impl StackFrameAllocSeal for super::StackFrameAllocRiscv {}
 }
@@ -21,11 +25,33 @@ pub trait StackFrameAlloc: private::StackFrameAllocSeal {
     );
 }
 
-pub struct StackAllocator<I: StackFrameAlloc>(PhantomData<I>);
+pub struct StackAllocator<I: StackFrameAlloc> {
+    // Upper bound on the stack space used by all live allocations combined, and
+    // the stack space they may currently use:
+    budget: usize,
+    used: Cell<usize>,
+    _impl: PhantomData<I>,
+}
 
 impl<I: StackFrameAlloc> StackAllocator<I> {
     pub fn new() -> Self {
-        StackAllocator(PhantomData)
+        Self::with_budget(usize::MAX)
+    }
+
+    /// Create a stack allocator which refuses allocations with
+    /// [`OGError::StackOverflow`](crate::OGError::StackOverflow) once the
+    /// allocations live at any one time would use more than `budget` bytes of
+    /// stack space (including padding required for their alignment).
+    ///
+    /// This prevents allocations of an arbitrary size (e.g., with a length
+    /// taken from foreign memory) from moving the stack pointer past the
+    /// thread's stack.
+    pub fn with_budget(budget: usize) -> Self {
+        StackAllocator {
+            budget,
+            used: Cell::new(0),
+            _impl: PhantomData,
+        }
     }
 }
 
@@ -37,6 +63,8 @@ impl<I: StackFrameAlloc> super::MockRtAllocator for StackAllocator<I> {
     ) -> Result<R, super::MockRtAllocError> {
         enum Ret<RP> {
             Returned(RP),
+            #[cfg(feature = "std")]
+            Panicked(std::boxed::Box<dyn core::any::Any + Send + 'static>),
             Unwinded,
         }
 
@@ -53,11 +81,37 @@ impl<I: StackFrameAlloc> super::MockRtAllocator for StackAllocator<I> {
         ) {
             let data: &mut Data<RP, FP> = unsafe { &mut *(data as *mut Data<RP, FP>) };
 
-            // Assume that function doesn't unwind:
-            let ret = (data.closure.take().unwrap())(ptr);
-            data.ret = Ret::Returned(ret);
+            let closure = data.closure.take().unwrap();
+
+            // We must not unwind through the stack allocation assembly. With
+            // `std`, we catch the panic and resume it once that has returned.
+            // Otherwise, this `extern "C"` function aborts when unwinding:
+            #[cfg(feature = "std")]
+            {
+                data.ret = match std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+                    closure(ptr)
+                })) {
+                    Ok(ret) => Ret::Returned(ret),
+                    Err(payload) => Ret::Panicked(payload),
+                };
+            }
+
+            #[cfg(not(feature = "std"))]
+            {
+                data.ret = Ret::Returned(closure(ptr));
+            }
         }
 
+        // Each allocation uses at most its size plus the padding required to
+        // align it (our `StackFrameAlloc`s align to at least 16 bytes):
+        let used = self.used.get();
+        let new_used = layout
+            .size()
+            .checked_add(core::cmp::max(16, layout.align()) - 1)
+            .and_then(|size| size.checked_add(used))
+            .filter(|new_used| *new_used <= self.budget)
+            .ok_or(super::MockRtAllocError::StackOverflow)?;
+
         // Stack-allocate the context for the closure:
         let mut data = Data {
             // The callback will take() this closure ...
@@ -69,6 +123,7 @@ impl<I: StackFrameAlloc> super::MockRtAllocator for StackAllocator<I> {
         // Now, run the closure, using a monomorphized version of our C-style
         // callback that knows the type of the closure and its return value (and
         // hence Data<R, F>, passing in the stacked context:
+        self.used.set(new_used);
         unsafe {
             I::stack_alloc(
                 layout.size(),
@@ -77,6 +132,7 @@ impl<I: StackFrameAlloc> super::MockRtAllocator for StackAllocator<I> {
                 &mut data as *mut Data<R, F> as *mut (),
             )
         };
+        self.used.set(used);
 
         // Make sure that the closure has actually run:
         assert!(data.closure.is_none());
@@ -87,10 +143,20 @@ impl<I: StackFrameAlloc> super::MockRtAllocator for StackAllocator<I> {
             // The function returned normally:
             Ret::Returned(ret) => return Ok(ret),
 
+            // The function panicked, resume unwinding on this side of the
+            // stack allocation:
+            #[cfg(feature = "std")]
+            Ret::Panicked(payload) => std::panic::resume_unwind(payload),
+
             // The function paniced, panic ourselves!
             Ret::Unwinded => panic!("with_stacked_alloc closure unwinded"),
         }
     }
+
+    fn with_saved_state<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let _restore_used = super::MockRtRestoreCell(&self.used, self.used.get());
+        f()
+    }
 }
 
 #[cfg_attr(feature = "nightly", doc(cfg(target_arch = "x86_64")))]
@@ -117,53 +183,140 @@ impl StackFrameAlloc for StackFrameAllocAMD64 {
         let align_bitmask = !align.wrapping_sub(1);
 
         // Magic:
-        unsafe {
-            core::arch::asm!(
-                    "
-                // Save the original stack pointer in a callee-saved register, as we
-                // don't know ahead of time by how much we'll be moving it downward,
-                // and need to restore it:
-                mov r12, rsp
-
-                // Move the stack pointer downward by `size`:
-                sub rsp, rsi
+        unsafe { stack_frame_alloc_amd64(align_bitmask, size, data, cb) }
+    }
+}
 
-                // We have allcated `size` bytes on the stack, but they may not be
-                // properly aligned yet. We are given align_bitmask, which we can AND
-                // with the stack pointer to align it downward efficiently.
-                //
-                // This is guaranteed to align our stack to a 16-byte boundary, as is
-                // required for invoking our extern C function:
-                and rsp, {align_bitmask_reg}
+// Implemented as a naked function with its own call frame information, such
+// that the unwinder (e.g., when capturing a backtrace for a panic in `cb`) can
+// walk through our dynamically sized stack frame:
+#[cfg(any(target_arch = "x86_64", doc))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_frame_alloc_amd64(
+    _align_bitmask: usize,
+    _size: usize,
+    _data: *mut (),
+    _cb: unsafe extern "C" fn(*mut (), usize, *mut ()),
+) {
+    core::arch::naked_asm!(
+        "
+        .cfi_startproc
+
+        // Save the original stack pointer in rbp, as we don't know ahead of
+        // time by how much we'll be moving it downward, and need to restore it:
+        push rbp
+        .cfi_def_cfa_offset 16
+        .cfi_offset rbp, -16
+        mov rbp, rsp
+        .cfi_def_cfa_register rbp
+
+        // Move the stack pointer downward by `size`:
+        sub rsp, rsi
+
+        // We have allcated `size` bytes on the stack, but they may not be
+        // properly aligned yet. We are given align_bitmask, which we can AND
+        // with the stack pointer to align it downward efficiently.
+        //
+        // This is guaranteed to align our stack to a 16-byte boundary, as is
+        // required for invoking our extern C function:
+        and rsp, rdi
+
+        // Now, call the function, with the allocated pointer (equal to rsp)
+        // loaded in the first argument register. The second and third argument
+        // are passed in the correct registers already:
+        mov rdi, rsp
+        call rcx
+
+        // Finally, restore our old stack pointer:
+        mov rsp, rbp
+        .cfi_def_cfa_register rsp
+        pop rbp
+        .cfi_def_cfa_offset 8
+        .cfi_restore rbp
+        ret
+
+        .cfi_endproc
+        ",
+    );
+}
 
-                // Now, call the function, with the allocated pointer (equal to rsp)
-                // loaded in the first argument register:
-                mov rdi, rsp
-                call {cb_reg}
+#[cfg_attr(feature = "nightly", doc(cfg(target_arch = "aarch64")))]
+#[cfg(any(target_arch = "aarch64", doc))]
+pub enum StackFrameAllocAArch64 {}
 
-                // Finally, restore our old stack pointer:
-                mov rsp, r12
-                ",
-                    // Pass the second and third argument to our callback in the correct
-                    // registers already:
-                    in("si") size,
-                    in("dx") data,
+#[cfg(any(target_arch = "aarch64", doc))]
+impl StackFrameAlloc for StackFrameAllocAArch64 {
+    unsafe fn stack_alloc(
+        size: usize,
+        align: usize,
+        cb: unsafe extern "C" fn(*mut (), usize, *mut ()),
+        data: *mut (),
+    ) {
+        // We only support power-of-two align, and align must be a positive value.
+        assert!(align.is_power_of_two() && align >= 1);
 
-                    // Other values we need:
-                    cb_reg = in(reg) cb,
-                    align_bitmask_reg = in(reg) align_bitmask,
+        // AArch64 requires the stack pointer to always be 16-byte aligned:
+        let align = core::cmp::max(16, align);
 
-                    // We additionally clobber r12 as a callee-saved register to store our
-                    // original stack pointer:
-                    out("r12") _,
+        // Calculate a bitmask that we can AND with the stack pointer to align it
+        // downward:
+        let align_bitmask = !align.wrapping_sub(1);
 
-                    // Clobber all registers not preserved by a function call:
-                    clobber_abi("system"),
-            );
-        }
+        // Magic:
+        unsafe { stack_frame_alloc_aarch64(align_bitmask, size, data, cb) }
     }
 }
 
+// Like `stack_frame_alloc_amd64`, this carries its own call frame information:
+#[cfg(any(target_arch = "aarch64", doc))]
+#[unsafe(naked)]
+unsafe extern "C" fn stack_frame_alloc_aarch64(
+    _align_bitmask: usize,
+    _size: usize,
+    _data: *mut (),
+    _cb: unsafe extern "C" fn(*mut (), usize, *mut ()),
+) {
+    core::arch::naked_asm!(
+        "
+        .cfi_startproc
+
+        // Set up a frame record, and save the original stack pointer in the
+        // frame pointer, as we don't know ahead of time by how much we'll be
+        // moving it downward, and need to restore it:
+        stp x29, x30, [sp, #-16]!
+        .cfi_def_cfa_offset 16
+        .cfi_offset x29, -16
+        .cfi_offset x30, -8
+        mov x29, sp
+        .cfi_def_cfa x29, 16
+
+        // Move the stack pointer downward by `size`, and align it downward
+        // using the align_bitmask. The logical instructions cannot operate on
+        // sp directly, so go through a temporary register:
+        sub x9, sp, x1
+        and x9, x9, x0
+        mov sp, x9
+
+        // Now, call the function, with the allocated pointer (equal to sp)
+        // loaded in the first argument register. The second and third argument
+        // are passed in the correct registers already:
+        mov x0, sp
+        blr x3
+
+        // Finally, restore our old stack pointer and frame record:
+        mov sp, x29
+        .cfi_def_cfa sp, 16
+        ldp x29, x30, [sp], #16
+        .cfi_def_cfa_offset 0
+        .cfi_restore x29
+        .cfi_restore x30
+        ret
+
+        .cfi_endproc
+        ",
+    );
+}
+
 #[cfg_attr(
     feature = "nightly",
     doc(cfg(any(target_arch = "riscv32", target_arch = "riscv64")))
@@ -240,3 +393,96 @@ impl StackFrameAlloc for StackFrameAllocRiscv {
         }
     }
 }
+
+#[cfg(test)]
+fn test_stack_frame_alloc<I: StackFrameAlloc>() {
+    use super::MockRtAllocator;
+    use core::alloc::Layout;
+
+    let allocator = StackAllocator::<I>::new();
+    let local = 0u8;
+
+    // Over-aligned allocations:
+    for align in [1, 16, 64, 4096] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        let ptr = unsafe {
+            allocator.with_alloc(layout, |ptr| {
+                // The allocation must be usable:
+                (ptr as *mut u8).write_bytes(0xff, layout.size());
+                ptr
+            })
+        }
+        .unwrap();
+        assert_eq!(ptr as usize % align, 0);
+        assert!((ptr as usize) < &local as *const u8 as usize);
+    }
+
+    // Nested allocations are placed below each other, and don't overlap:
+    let res = unsafe {
+        allocator.with_alloc(Layout::new::<[u64; 4]>(), |outer| {
+            let outer = outer as *mut [u64; 4];
+            outer.write([1, 2, 3, 4]);
+
+            allocator
+                .with_alloc(Layout::from_size_align(128, 128).unwrap(), |inner| {
+                    assert_eq!(inner as usize % 128, 0);
+                    assert!(inner as usize + 128 <= outer as usize);
+                    (inner as *mut u8).write_bytes(0, 128);
+                    outer.read()
+                })
+                .unwrap()
+        })
+    }
+    .unwrap();
+    assert_eq!(res, [1, 2, 3, 4]);
+}
+
+#[cfg(test)]
+fn test_stack_budget<I: StackFrameAlloc>() {
+    use super::{MockRtAllocError, MockRtAllocator};
+    use core::alloc::Layout;
+
+    let allocator = StackAllocator::<I>::with_budget(1024);
+    let half = Layout::from_size_align(512, 8).unwrap();
+
+    unsafe {
+        allocator
+            .with_alloc(half, |_| {
+                // Nested allocations count towards the same budget:
+                assert!(matches!(
+                    allocator.with_alloc(half, |_| ()),
+                    Err(MockRtAllocError::StackOverflow)
+                ));
+                allocator
+                    .with_alloc(Layout::new::<[u8; 256]>(), |_| ())
+                    .unwrap();
+            })
+            .unwrap();
+
+        // The budget is returned once an allocation's scope ends:
+        allocator.with_alloc(half, |_| ()).unwrap();
+
+        // Sizes which would wrap around are refused as well:
+        assert!(matches!(
+            allocator.with_alloc(
+                Layout::from_size_align(isize::MAX as usize, 1).unwrap(),
+                |_| ()
+            ),
+            Err(MockRtAllocError::StackOverflow)
+        ));
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_stack_frame_alloc_amd64() {
+    test_stack_frame_alloc::<StackFrameAllocAMD64>();
+    test_stack_budget::<StackFrameAllocAMD64>();
+}
+
+#[cfg(target_arch = "aarch64")]
+#[test]
+fn test_stack_frame_alloc_aarch64() {
+    test_stack_frame_alloc::<StackFrameAllocAArch64>();
+    test_stack_budget::<StackFrameAllocAArch64>();
+}
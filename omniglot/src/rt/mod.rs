@@ -1,9 +1,57 @@
 // -*- fill-column: 80; -*-
 
 // TODO: why do we need these?
+pub mod aarch64;
 pub mod mock;
+pub mod rv32e_c;
+pub mod rv32g_c;
 pub mod rv32i_c;
+pub mod rv64g_c;
+pub mod rv64i_c;
 pub mod sysv_amd64;
+pub mod wasm32;
+
+#[cfg_attr(
+    feature = "nightly",
+    doc(cfg(all(
+        feature = "std",
+        target_os = "linux",
+        target_env = "gnu",
+        target_arch = "x86_64"
+    )))
+)]
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+pub mod mprotect;
+
+#[cfg_attr(
+    feature = "nightly",
+    doc(cfg(all(
+        feature = "std",
+        target_os = "linux",
+        target_env = "gnu",
+        target_arch = "x86_64"
+    )))
+)]
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    target_env = "gnu",
+    target_arch = "x86_64"
+))]
+pub mod subprocess;
+
+#[cfg_attr(feature = "nightly", doc(cfg(feature = "std")))]
+#[cfg(feature = "std")]
+pub mod rv32i_emu;
+
+#[cfg_attr(feature = "nightly", doc(cfg(feature = "wasm")))]
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 use crate::abi::OGABI;
 use crate::alloc_tracker::AllocTracker;
@@ -16,11 +64,28 @@ use crate::{OGError, OGResult};
 
 pub trait CallbackContext {
     fn get_argument_register(&self, reg: usize) -> Option<usize>;
+
+    /// Retrieve the raw bit pattern of a floating-point argument register.
+    ///
+    /// A `float` argument occupies the lower 32 bits. Returns `None` when the
+    /// register does not exist or is not captured by this runtime.
+    fn get_fp_argument_register(&self, _reg: usize) -> Option<u64> {
+        None
+    }
+
     fn get_stack_pointer(&self) -> *mut core::ffi::c_void;
 }
 
 pub trait CallbackReturn {
     fn set_return_register(&mut self, reg: usize, value: usize) -> bool;
+
+    /// Set a floating-point return register to the raw bit pattern `value`.
+    ///
+    /// A `float` return value occupies the lower 32 bits. Returns `false` when
+    /// the register does not exist or is not supported by this runtime.
+    fn set_fp_return_register(&mut self, _reg: usize, _value: u64) -> bool {
+        false
+    }
 }
 
 pub unsafe trait OGRuntime {
@@ -77,6 +142,28 @@ pub unsafe trait OGRuntime {
         f: F,
     ) -> OGResult<R>;
 
+    /// Whether this runtime can preempt foreign code, and thus enforces the
+    /// timeout of [`OGRuntime::execute_with_deadline`].
+    const SUPPORTS_DEADLINE: bool = false;
+
+    /// Like [`OGRuntime::execute`], but abort the foreign call once `timeout`
+    /// has elapsed, returning [`OGError::Timeout`] and poisoning the
+    /// `access_scope` (see [`AccessScope::poison`]).
+    ///
+    /// Runtimes that do not support deadlines (see
+    /// [`OGRuntime::SUPPORTS_DEADLINE`]) return
+    /// [`OGError::DeadlineUnsupported`] without executing the foreign call.
+    fn execute_with_deadline<R, F: FnOnce() -> R>(
+        &self,
+        _target_symbol: *const (),
+        _alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        _access_scope: &mut AccessScope<Self::ID>,
+        _timeout: core::time::Duration,
+        _f: F,
+    ) -> OGResult<R> {
+        Err(OGError::DeadlineUnsupported)
+    }
+
     fn allocate_stacked_untracked_mut<F, R>(
         &self,
         layout: core::alloc::Layout,
@@ -0,0 +1,1714 @@
+// -*- fill-column: 80; -*-
+
+//! A Linux userspace runtime revoking foreign code's access to select regions
+//! of host memory through page permissions.
+//!
+//! [`MprotectRt`] loads a foreign library as a shared object into the current
+//! process. **It does not isolate foreign code from the host.** Unlike runtimes
+//! based on memory protection keys, it cannot cheaply revoke foreign code's
+//! access to all of the host's memory. Instead, for the duration of every
+//! foreign function call, it revokes access to the following regions only:
+//!
+//! - the host's part of the current thread's stack. Foreign code runs on a
+//!   separate, guard-paged stack (see [`ForeignStackAllocator`]), which also
+//!   holds all stacked allocations.
+//! - unless disabled, the host's main heap (the program break, from which the
+//!   C library's allocator serves the main thread). The library's calls to
+//!   `malloc`, `calloc`, `realloc` and `free` are served from a sandbox heap
+//!   of this runtime instead.
+//! - any further regions registered through
+//!   [`MprotectRt::protect_host_region`].
+//!
+//! Access is restored whenever foreign code calls back into the host, faults
+//! or exceeds its deadline. Upgrades of foreign pointers are validated against
+//! the memory segments mapped for the foreign library, its sandbox heap and any
+//! stacked allocations. Each runtime loads a library of its own, and never
+//! accepts pointers into another runtime's library or sandbox heap.
+//!
+//! All other host memory remains accessible to foreign code, with the host's
+//! own permissions. This includes the host's static data, its other threads'
+//! stacks, and heap blocks served from other arenas or mappings. The pages holding the
+//! current thread's thread-local storage must remain accessible to foreign code
+//! as well, including any frames at the top of its stack sharing them. As the
+//! host's heap is process-wide, protecting it faults all other threads using
+//! it while foreign code executes. Foreign code cannot use functions of the C
+//! library which access the host's heap (such as `printf`, or `free` of blocks
+//! allocated before the library was loaded), nor the environment and program
+//! arguments at the top of the main thread's stack.
+//!
+//! # Invocation protocol
+//!
+//! This runtime's [`SysVAMD64Rt::invoke`] trampoline is to be called in place
+//! of the foreign function, with all arguments set up according to the System V
+//! AMD64 calling convention, and `STACK_SPILL` words of arguments passed on the
+//! stack. The `RTLOC` argument slot must hold a pointer to the runtime. The
+//! trampoline then revokes access to host memory, calls the `target_symbol`
+//! passed to the enclosing [`OGRuntime::execute`], and restores access. It
+//! stores the function's `rax` and `rdx` return registers in the runtime, for
+//! retrieval through [`SysVAMD64InvokeRes::into_result_registers`].
+
+use core::cell::Cell;
+use core::ffi::{CStr, c_void};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use std::rc::Rc;
+use std::vec::Vec;
+
+use crate::abi::calling_convention::ArgumentSlot;
+use crate::abi::sysv_amd64::SysVAMD64ABI;
+use crate::foreign_memory::og_ret::OGRet;
+use crate::id::OGID;
+use crate::markers::{AccessScope, AllocScope};
+use crate::maybe_valid::MaybeValid;
+use crate::rt::OGRuntime;
+use crate::rt::mock::foreign_stack::ForeignStackAllocator;
+use crate::rt::mock::{
+    MockRt, MockRtAllocChain, MockRtAllocation, MockRtCallbackContext, MockRtCallbackReturn,
+    MockRtHostAccessHooks, mock_rt_host_stack_pointer, mock_rt_with_host_access_hooks,
+};
+use crate::rt::sysv_amd64::{
+    SysVAMD64BaseRt, SysVAMD64InvokeRes, SysVAMD64Rt, sysv_amd64_areg_index,
+};
+use crate::util::dl::DlLibrary;
+use crate::{OGError, OGResult};
+
+/// Maximum number of regions registered through
+/// [`MprotectRt::protect_host_region`].
+pub const MPROTECT_RT_MAX_HOST_REGIONS: usize = 32;
+
+/// Maximum number of [`MprotectRt`] instances that can exist at once. Each one
+/// serves its library's heap allocations from a sandbox heap of its own.
+pub const MPROTECT_RT_MAX_INSTANCES: usize = 16;
+
+// Size of the address space reserved for each sandbox heap:
+const MPROTECT_RT_SANDBOX_HEAP_SIZE: usize = 1 << 30;
+
+// State shared with the `invoke` trampoline, and with host code running in the
+// midst of foreign code. It is placed on a page of its own, as it must remain
+// accessible while the host's memory is not. The runtime's first member points
+// to it, such that the trampoline can access it through the runtime pointer
+// passed in the `RTLOC` argument slot.
+//
+// All ranges are page-aligned `(start, end)` pairs:
+#[repr(C)]
+struct MprotectRtControl {
+    target: Cell<*const ()>,
+    ret: Cell<[usize; 2]>,
+    // Whether access to the ranges below is revoked, and whether revoking it
+    // failed for any foreign call since last checked:
+    revoked: Cell<bool>,
+    failed: Cell<bool>,
+    // The host's part of the current thread's stack, and the thread's static
+    // TLS block and control block, which is excluded from all other ranges:
+    stack: Cell<(usize, usize)>,
+    tls: Cell<(usize, usize)>,
+    // Start of the host's main heap, or zero if it is not protected. Its end is
+    // determined when revoking access:
+    heap_start: usize,
+    heap_end: Cell<usize>,
+    host_regions: [Cell<(usize, usize)>; MPROTECT_RT_MAX_HOST_REGIONS],
+    host_regions_len: Cell<usize>,
+}
+
+const _: () = assert!(core::mem::size_of::<MprotectRtControl>() <= 4096);
+
+impl MprotectRtControl {
+    // Invoke `f` with all ranges revoked from foreign code, until it returns
+    // `false`. These cover only parts of the host's memory (see the module
+    // documentation):
+    fn for_each_range(&self, mut f: impl FnMut(usize, usize) -> bool) -> bool {
+        let (tls_start, tls_end) = self.tls.get();
+
+        let mut protect = |(start, end): (usize, usize)| {
+            // Split off the thread's TLS block:
+            [(start, end.min(tls_start)), (start.max(tls_end), end)]
+                .into_iter()
+                .all(|(start, end)| start >= end || f(start, end))
+        };
+
+        protect(self.stack.get())
+            && (self.heap_start == 0 || protect((self.heap_start, self.heap_end.get())))
+            && self.host_regions[..self.host_regions_len.get()]
+                .iter()
+                .all(|region| protect(region.get()))
+    }
+}
+
+// Run `f` with all signals blocked, such that our recovery signal handler does
+// not observe the control page in an intermediate state:
+fn mprotect_rt_with_signals_blocked<R>(f: impl FnOnce() -> R) -> R {
+    let mut all: libc::sigset_t = unsafe { core::mem::zeroed() };
+    let mut prev: libc::sigset_t = unsafe { core::mem::zeroed() };
+    unsafe {
+        libc::sigfillset(&mut all);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &all, &mut prev);
+    }
+    let res = f();
+    unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &prev, core::ptr::null_mut()) };
+    res
+}
+
+// Revoke the host's access to its memory. On failure, access is left intact and
+// this returns `false`. Runs while foreign code may execute, and hence must only
+// access the control page:
+extern "C" fn mprotect_rt_revoke(control: &MprotectRtControl) -> bool {
+    mprotect_rt_with_signals_blocked(|| {
+        if control.revoked.get() {
+            return true;
+        }
+
+        if control.heap_start != 0 {
+            let brk = unsafe { libc::sbrk(0) } as usize;
+            control
+                .heap_end
+                .set(mprotect_rt_page_ceil(brk).max(control.heap_start));
+        }
+
+        let revoked = control.for_each_range(|start, end| unsafe {
+            libc::mprotect(start as *mut c_void, end - start, libc::PROT_NONE) == 0
+        });
+
+        if revoked {
+            control.revoked.set(true);
+        } else {
+            // Restore the ranges revoked so far:
+            control.for_each_range(|start, end| unsafe {
+                libc::mprotect(
+                    start as *mut c_void,
+                    end - start,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                );
+                true
+            });
+            control.failed.set(true);
+        }
+
+        revoked
+    })
+}
+
+// Restore the host's access to its memory, returning whether it was revoked:
+extern "C" fn mprotect_rt_restore(control: &MprotectRtControl) -> bool {
+    mprotect_rt_with_signals_blocked(|| {
+        if !control.revoked.get() {
+            return false;
+        }
+
+        // These ranges were readable and writable before, and are still mapped.
+        // We thus don't expect this to fail, and the host would fault anyways:
+        control.for_each_range(|start, end| unsafe {
+            libc::mprotect(
+                start as *mut c_void,
+                end - start,
+                libc::PROT_READ | libc::PROT_WRITE,
+            );
+            true
+        });
+        control.revoked.set(false);
+
+        true
+    })
+}
+
+unsafe extern "C" fn mprotect_rt_hook_restore(control: *const ()) -> bool {
+    mprotect_rt_restore(unsafe { &*(control as *const MprotectRtControl) })
+}
+
+unsafe extern "C" fn mprotect_rt_hook_revoke(control: *const ()) {
+    // We're about to return to foreign code, and have no means to report an
+    // error to the host. Never let it continue with access to host memory:
+    if !mprotect_rt_revoke(unsafe { &*(control as *const MprotectRtControl) }) {
+        unsafe { libc::abort() };
+    }
+}
+
+fn mprotect_rt_page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn mprotect_rt_page_floor(addr: usize) -> usize {
+    addr & !(mprotect_rt_page_size() - 1)
+}
+
+fn mprotect_rt_page_ceil(addr: usize) -> usize {
+    mprotect_rt_page_floor(addr + mprotect_rt_page_size() - 1)
+}
+
+// The current thread's stack and TLS block, see `MprotectRtControl`:
+#[derive(Clone, Copy)]
+struct MprotectRtThreadInfo {
+    stack: (usize, usize),
+    tls: (usize, usize),
+}
+
+std::thread_local! {
+    static MPROTECT_RT_THREAD_INFO: Cell<Option<MprotectRtThreadInfo>> =
+        const { Cell::new(None) };
+}
+
+impl MprotectRtThreadInfo {
+    fn current() -> Option<Self> {
+        if let Some(info) = MPROTECT_RT_THREAD_INFO.with(Cell::get) {
+            return Some(info);
+        }
+
+        let mut attr: libc::pthread_attr_t = unsafe { core::mem::zeroed() };
+        let mut stack_addr: *mut c_void = core::ptr::null_mut();
+        let mut stack_size = 0;
+        unsafe {
+            if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+                return None;
+            }
+            let res = libc::pthread_attr_getstack(&attr, &mut stack_addr, &mut stack_size);
+            libc::pthread_attr_destroy(&mut attr);
+            if res != 0 {
+                return None;
+            }
+        }
+
+        // On x86_64, the thread pointer points to the thread's control block,
+        // with its static TLS block right below. Both are at most as large as
+        // the static TLS size reported by the dynamic linker:
+        let thread_pointer: usize;
+        unsafe {
+            core::arch::asm!(
+                "mov {}, qword ptr fs:[0]",
+                out(reg) thread_pointer,
+                options(nostack, readonly),
+            )
+        };
+        let tls_size = mprotect_rt_static_tls_size()?;
+
+        let info = MprotectRtThreadInfo {
+            stack: (
+                stack_addr as usize,
+                mprotect_rt_page_floor(stack_addr as usize + stack_size),
+            ),
+            tls: (
+                mprotect_rt_page_floor(thread_pointer.saturating_sub(tls_size)),
+                mprotect_rt_page_ceil(thread_pointer + tls_size),
+            ),
+        };
+        MPROTECT_RT_THREAD_INFO.with(|cell| cell.set(Some(info)));
+        Some(info)
+    }
+}
+
+fn mprotect_rt_static_tls_size() -> Option<usize> {
+    let get_tls_static_info =
+        unsafe { libc::dlsym(libc::RTLD_DEFAULT, c"_dl_get_tls_static_info".as_ptr()) };
+    if get_tls_static_info.is_null() {
+        return None;
+    }
+
+    let get_tls_static_info: unsafe extern "C" fn(*mut usize, *mut usize) =
+        unsafe { core::mem::transmute(get_tls_static_info) };
+    let (mut size, mut align) = (0, 0);
+    unsafe { get_tls_static_info(&mut size, &mut align) };
+    Some(size)
+}
+
+// Start of the host's main heap, or of the program break if there is none yet:
+fn mprotect_rt_heap_start() -> usize {
+    std::fs::read_to_string("/proc/self/maps")
+        .ok()
+        .and_then(|maps| {
+            let line = maps.lines().find(|line| line.ends_with("[heap]"))?;
+            usize::from_str_radix(line.split('-').next()?, 16).ok()
+        })
+        .unwrap_or_else(|| mprotect_rt_page_ceil(unsafe { libc::sbrk(0) } as usize))
+}
+
+// A sandbox heap serves the allocations of the library loaded by a single
+// `MprotectRt`, such that no runtime can upgrade pointers into another one's
+// heap. It is a reservation of address space, which is only populated as it is
+// used. Blocks are preceded by a 16-byte header, holding their size and, for
+// free blocks, the offset of the next free block. Foreign code may corrupt
+// these headers, so all offsets are checked to lie within the heap before
+// following them.
+//
+// Each runtime claims one of a fixed number of slots, whose allocator functions
+// are interposed in place of the C library's for its library only.
+//
+// A heap's bookkeeping is only modified with its lock held. When foreign code
+// is abandoned while holding it, the lock is released again by the runtime
+// through `MprotectRtSandboxHeap::release_abandoned_lock`.
+struct MprotectRtSandboxHeap {
+    // Start of the heap's reservation, zero while its slot is unused, or
+    // `usize::MAX` while it is being claimed:
+    base: AtomicUsize,
+    // Zero when unlocked, or the ID of the thread holding the lock:
+    lock: AtomicUsize,
+    // Offset of the first unused byte, and of the first free block:
+    top: AtomicUsize,
+    free: AtomicUsize,
+}
+
+static MPROTECT_RT_SANDBOX_HEAPS: [MprotectRtSandboxHeap; MPROTECT_RT_MAX_INSTANCES] = [const {
+    MprotectRtSandboxHeap {
+        base: AtomicUsize::new(0),
+        lock: AtomicUsize::new(0),
+        top: AtomicUsize::new(0),
+        free: AtomicUsize::new(usize::MAX),
+    }
+};
+    MPROTECT_RT_MAX_INSTANCES];
+
+const MPROTECT_RT_SANDBOX_HEAP_HEADER: usize = 16;
+
+// Bound on the free list entries inspected per allocation, such that corrupted
+// (cyclic) free lists cannot stall it:
+const MPROTECT_RT_SANDBOX_HEAP_MAX_SCAN: usize = 1024;
+
+fn mprotect_rt_sandbox_heap_lock_owner() -> usize {
+    unsafe { libc::gettid() as usize }
+}
+
+impl MprotectRtSandboxHeap {
+    fn base(&self) -> usize {
+        self.base.load(Ordering::Relaxed)
+    }
+
+    fn with_lock<R>(&self, f: impl FnOnce(usize) -> R) -> R {
+        let owner = mprotect_rt_sandbox_heap_lock_owner();
+        while self
+            .lock
+            .compare_exchange_weak(0, owner, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::thread::yield_now();
+        }
+
+        let res = f(self.base());
+        self.lock.store(0, Ordering::Release);
+        res
+    }
+
+    // Release this heap's lock, if it is held by abandoned frames of this
+    // thread:
+    fn release_abandoned_lock(&self) {
+        let _ = self.lock.compare_exchange(
+            mprotect_rt_sandbox_heap_lock_owner(),
+            0,
+            Ordering::Release,
+            Ordering::Relaxed,
+        );
+    }
+
+    // The size of the block at `offset`, if it is a plausible block of this
+    // heap, starting at `base`. Must be called with the heap's lock held:
+    unsafe fn block_size(&self, base: usize, offset: usize) -> Option<usize> {
+        let top = self.top.load(Ordering::Relaxed);
+        if !offset.is_multiple_of(16) || offset.checked_add(MPROTECT_RT_SANDBOX_HEAP_HEADER)? > top
+        {
+            return None;
+        }
+
+        let size = unsafe { *((base + offset) as *const usize) };
+        (offset + MPROTECT_RT_SANDBOX_HEAP_HEADER)
+            .checked_add(size)
+            .filter(|end| *end <= top && size.is_multiple_of(16))
+            .map(|_| size)
+    }
+
+    // The offset of the block holding `ptr`, if it lies within this heap:
+    fn offset(&self, ptr: *mut c_void) -> Option<usize> {
+        (ptr as usize)
+            .checked_sub(self.base() + MPROTECT_RT_SANDBOX_HEAP_HEADER)
+            .filter(|offset| *offset < MPROTECT_RT_SANDBOX_HEAP_SIZE)
+    }
+
+    unsafe fn malloc(&self, size: usize) -> *mut c_void {
+        let Some(size) = size.max(16).checked_next_multiple_of(16) else {
+            return core::ptr::null_mut();
+        };
+
+        self.with_lock(|base| unsafe {
+            // Reuse the first free block that is large enough:
+            let mut link = self.free.as_ptr();
+            for _ in 0..MPROTECT_RT_SANDBOX_HEAP_MAX_SCAN {
+                let offset = *link;
+                if offset == usize::MAX {
+                    break;
+                }
+                let Some(block_size) = self.block_size(base, offset) else {
+                    // A corrupted free list, drop its remainder:
+                    *link = usize::MAX;
+                    break;
+                };
+
+                let next = (base + offset + 8) as *mut usize;
+                if block_size >= size {
+                    *link = *next;
+                    return (base + offset + MPROTECT_RT_SANDBOX_HEAP_HEADER) as *mut c_void;
+                }
+                link = next;
+            }
+
+            // Otherwise, allocate a new block at the top of the heap:
+            let offset = self.top.load(Ordering::Relaxed);
+            if MPROTECT_RT_SANDBOX_HEAP_SIZE - offset < MPROTECT_RT_SANDBOX_HEAP_HEADER + size {
+                return core::ptr::null_mut();
+            }
+            self.top.store(
+                offset + MPROTECT_RT_SANDBOX_HEAP_HEADER + size,
+                Ordering::Relaxed,
+            );
+            *((base + offset) as *mut usize) = size;
+            (base + offset + MPROTECT_RT_SANDBOX_HEAP_HEADER) as *mut c_void
+        })
+    }
+
+    unsafe fn calloc(&self, nmemb: usize, size: usize) -> *mut c_void {
+        let Some(size) = nmemb.checked_mul(size) else {
+            return core::ptr::null_mut();
+        };
+
+        // Blocks may be reused, and hence must be cleared:
+        let ptr = unsafe { self.malloc(size) };
+        if !ptr.is_null() {
+            unsafe { core::ptr::write_bytes(ptr as *mut u8, 0, size) };
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut c_void, size: usize) -> *mut c_void {
+        if ptr.is_null() {
+            return unsafe { self.malloc(size) };
+        }
+
+        let Some(offset) = self.offset(ptr) else {
+            // Blocks allocated before this library's allocations were
+            // interposed live on the host's heap:
+            return unsafe { libc::realloc(ptr, size) };
+        };
+
+        if size == 0 {
+            unsafe { self.free(ptr) };
+            return core::ptr::null_mut();
+        }
+
+        let Some(old_size) = self.with_lock(|base| unsafe { self.block_size(base, offset) }) else {
+            return core::ptr::null_mut();
+        };
+        if old_size >= size {
+            return ptr;
+        }
+
+        let new_ptr = unsafe { self.malloc(size) };
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8, old_size);
+                self.free(ptr);
+            }
+        }
+        new_ptr
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) {
+        if ptr.is_null() {
+            return;
+        }
+
+        let Some(offset) = self.offset(ptr) else {
+            return unsafe { libc::free(ptr) };
+        };
+
+        self.with_lock(|base| unsafe {
+            // Ignore frees of pointers that are not the start of a block:
+            if self.block_size(base, offset).is_some() {
+                *((base + offset + 8) as *mut usize) = self.free.load(Ordering::Relaxed);
+                self.free.store(offset, Ordering::Relaxed);
+            }
+        })
+    }
+}
+
+unsafe extern "C" fn mprotect_rt_sandbox_malloc<const HEAP: usize>(size: usize) -> *mut c_void {
+    unsafe { MPROTECT_RT_SANDBOX_HEAPS[HEAP].malloc(size) }
+}
+
+unsafe extern "C" fn mprotect_rt_sandbox_calloc<const HEAP: usize>(
+    nmemb: usize,
+    size: usize,
+) -> *mut c_void {
+    unsafe { MPROTECT_RT_SANDBOX_HEAPS[HEAP].calloc(nmemb, size) }
+}
+
+unsafe extern "C" fn mprotect_rt_sandbox_realloc<const HEAP: usize>(
+    ptr: *mut c_void,
+    size: usize,
+) -> *mut c_void {
+    unsafe { MPROTECT_RT_SANDBOX_HEAPS[HEAP].realloc(ptr, size) }
+}
+
+unsafe extern "C" fn mprotect_rt_sandbox_free<const HEAP: usize>(ptr: *mut c_void) {
+    unsafe { MPROTECT_RT_SANDBOX_HEAPS[HEAP].free(ptr) }
+}
+
+// The allocator functions serving from the sandbox heap in slot `heap`:
+fn mprotect_rt_sandbox_heap_functions(heap: usize) -> [(&'static CStr, *const ()); 4] {
+    seq_macro::seq!(N in 0..16 {
+        match heap {
+            #(
+                N => [
+                    (c"malloc", mprotect_rt_sandbox_malloc::<N> as *const ()),
+                    (c"calloc", mprotect_rt_sandbox_calloc::<N> as *const ()),
+                    (c"realloc", mprotect_rt_sandbox_realloc::<N> as *const ()),
+                    (c"free", mprotect_rt_sandbox_free::<N> as *const ()),
+                ],
+            )*
+            _ => panic!("Sandbox heap slot exceeds MPROTECT_RT_MAX_INSTANCES"),
+        }
+    })
+}
+
+// A claimed slot of `MPROTECT_RT_SANDBOX_HEAPS`, which is released once
+// dropped.
+//
+// References into a heap may outlive its runtime, so its reservation is never
+// unmapped. A slot claimed again is backed by a fresh reservation. Libraries
+// are never unloaded either, but their code only runs through their runtime.
+struct MprotectRtSandboxHeapSlot(usize);
+
+impl MprotectRtSandboxHeapSlot {
+    fn claim() -> Option<Self> {
+        let idx = MPROTECT_RT_SANDBOX_HEAPS.iter().position(|heap| {
+            heap.base
+                .compare_exchange(0, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        let heap = &MPROTECT_RT_SANDBOX_HEAPS[idx];
+
+        let mapping = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                MPROTECT_RT_SANDBOX_HEAP_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if mapping == libc::MAP_FAILED {
+            heap.base.store(0, Ordering::Release);
+            return None;
+        }
+
+        heap.top.store(0, Ordering::Relaxed);
+        heap.free.store(usize::MAX, Ordering::Relaxed);
+        heap.base.store(mapping as usize, Ordering::Release);
+        Some(MprotectRtSandboxHeapSlot(idx))
+    }
+
+    fn heap(&self) -> &'static MprotectRtSandboxHeap {
+        &MPROTECT_RT_SANDBOX_HEAPS[self.0]
+    }
+
+    // Serve `library`'s heap allocations from this sandbox heap. On failure,
+    // the library is left untouched:
+    unsafe fn interpose(&self, library: &DlLibrary) -> OGResult<()> {
+        let functions = mprotect_rt_sandbox_heap_functions(self.0);
+
+        let mut interpositions = Vec::with_capacity(functions.len());
+        for (symbol, replacement) in functions {
+            match unsafe { library.interpose(symbol, replacement) } {
+                Some(interposition) => interpositions.push(interposition),
+                None => {
+                    for interposition in interpositions.into_iter().rev() {
+                        unsafe { interposition.revert(library) };
+                    }
+                    return Err(OGError::LibraryLoadFailed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MprotectRtSandboxHeapSlot {
+    fn drop(&mut self) {
+        self.heap().base.store(0, Ordering::Release);
+    }
+}
+
+/// A runtime executing a foreign shared library in the host's process, with
+/// access to parts of the host's memory revoked.
+///
+/// This runtime does not isolate foreign code: it can still read and write most
+/// of the host's memory, and only faults on accesses to the regions listed in
+/// the [module documentation](self). It thus protects against accidental
+/// corruption of these regions, but not against malicious foreign code.
+#[repr(C)]
+pub struct MprotectRt<ID: OGID> {
+    // Must remain the first member, see `MprotectRtControl`:
+    control: *const MprotectRtControl,
+    // We reuse the MockRt's stacked allocation and callback infrastructure:
+    mock: MockRt<ID, ForeignStackAllocator>,
+    library: DlLibrary,
+    heap: MprotectRtSandboxHeapSlot,
+}
+
+pub struct MprotectRtSymbolTableState<
+    const SYMTAB_SIZE: usize,
+    const FIXED_OFFSET_SYMTAB_SIZE: usize,
+> {
+    symbols: [*const (); SYMTAB_SIZE],
+    fixed_offset_symbols: [Option<*const ()>; FIXED_OFFSET_SYMTAB_SIZE],
+}
+
+impl<ID: OGID> MprotectRt<ID> {
+    /// Load the shared object at `library_path` and create a runtime for it.
+    ///
+    /// Foreign code runs on a stack of at least `stack_size` bytes. With
+    /// `protect_heap`, foreign code also loses access to the host's main heap,
+    /// which affects all threads of this process (see the [module
+    /// documentation](self)).
+    ///
+    /// # Safety
+    ///
+    /// Loading the library runs its initializers without any isolation. No
+    /// other thread may use the host's main heap while foreign code executes
+    /// with `protect_heap`.
+    ///
+    /// Fails with [`OGError::LibraryLoadFailed`] if the library is loaded
+    /// already (e.g., by being linked to the host binary, or by another
+    /// runtime), and with [`OGError::AllocNoMem`] if
+    /// [`MPROTECT_RT_MAX_INSTANCES`] runtimes exist already.
+    pub unsafe fn new(
+        library_path: &CStr,
+        stack_size: usize,
+        protect_heap: bool,
+        branding: ID,
+    ) -> OGResult<(
+        Self,
+        AllocScope<'static, MockRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
+    )> {
+        mprotect_rt_static_tls_size().ok_or(OGError::InternalError)?;
+        let allocator = ForeignStackAllocator::new(stack_size).map_err(|_| OGError::AllocNoMem)?;
+        let heap = MprotectRtSandboxHeapSlot::claim().ok_or(OGError::AllocNoMem)?;
+
+        // A library loaded already would share its memory (and its heap
+        // allocator functions) with the host or another runtime:
+        if DlLibrary::is_loaded(library_path) {
+            return Err(OGError::LibraryLoadFailed);
+        }
+        let library = unsafe { DlLibrary::open(library_path) }.ok_or(OGError::LibraryLoadFailed)?;
+
+        // Upgrades are valid within this runtime's sandbox heap only:
+        let mut regions = std::vec![MockRtAllocation::new(
+            heap.heap().base() as *mut (),
+            MPROTECT_RT_SANDBOX_HEAP_SIZE,
+            true,
+        )];
+        if !library.segments(|segment| {
+            regions.push(MockRtAllocation::new(
+                segment.start as *mut (),
+                segment.len,
+                segment.writable,
+            ))
+        }) {
+            return Err(OGError::LibraryLoadFailed);
+        }
+
+        // The library has only just been loaded, no other thread can be
+        // calling into it yet:
+        unsafe { heap.interpose(&library) }?;
+
+        let control = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                mprotect_rt_page_size(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if control == libc::MAP_FAILED {
+            return Err(OGError::AllocNoMem);
+        }
+        let control = control as *mut MprotectRtControl;
+        unsafe {
+            control.write(MprotectRtControl {
+                target: Cell::new(core::ptr::null()),
+                ret: Cell::new([0; 2]),
+                revoked: Cell::new(false),
+                failed: Cell::new(false),
+                stack: Cell::new((0, 0)),
+                tls: Cell::new((0, 0)),
+                heap_start: if protect_heap {
+                    mprotect_rt_heap_start()
+                } else {
+                    0
+                },
+                heap_end: Cell::new(0),
+                host_regions: [const { Cell::new((0, 0)) }; MPROTECT_RT_MAX_HOST_REGIONS],
+                host_regions_len: Cell::new(0),
+            })
+        };
+
+        let id_imprint = branding.get_imprint();
+
+        // The MockRt's own scopes are discarded, we issue scopes that validate
+        // upgrades against the library's regions instead:
+        let (mock, _, _) = unsafe { MockRt::new(false, false, allocator, branding) };
+
+        Ok((
+            MprotectRt {
+                control,
+                mock,
+                library,
+                heap,
+            },
+            unsafe { AllocScope::new(MockRtAllocChain::Regions(Rc::from(regions)), id_imprint) },
+            unsafe { AccessScope::new(id_imprint) },
+        ))
+    }
+
+    /// Revoke foreign code's access to a further region of host memory.
+    ///
+    /// For the duration of every foreign function call, the pages of this
+    /// region are made inaccessible. They are restored to be readable and
+    /// writable whenever control returns to the host, including in callbacks.
+    /// At most [`MPROTECT_RT_MAX_HOST_REGIONS`] regions can be registered.
+    ///
+    /// # Safety
+    ///
+    /// The region must be page-aligned, readable and writable, and remain
+    /// mapped for the lifetime of this runtime. It must not contain any memory
+    /// accessed while foreign code executes, such as the foreign stack, the
+    /// current thread's TLS block, or the library's segments.
+    pub unsafe fn protect_host_region(&self, ptr: *mut u8, len: usize) -> OGResult<()> {
+        let start = ptr as usize;
+        if len == 0 || mprotect_rt_page_floor(start) != start {
+            return Err(OGError::AllocInvalidLayout);
+        }
+        let end = start.checked_add(len).ok_or(OGError::AllocInvalidLayout)?;
+
+        let control = self.control();
+        let idx = control.host_regions_len.get();
+        let region = control.host_regions.get(idx).ok_or(OGError::AllocNoMem)?;
+        region.set((start, mprotect_rt_page_ceil(end)));
+        control.host_regions_len.set(idx + 1);
+
+        Ok(())
+    }
+
+    /// Recover from faults raised by foreign code, including its accesses to
+    /// protected host memory.
+    ///
+    /// See [`MockRt::set_catch_faults`].
+    pub fn set_catch_faults(&mut self, catch: bool) {
+        self.mock.set_catch_faults(catch);
+    }
+
+    fn control(&self) -> &MprotectRtControl {
+        unsafe { &*self.control }
+    }
+
+    // Install this runtime's host access hooks for `execute`, which runs
+    // foreign code through one of the MockRt's `execute` functions:
+    fn with_host_access_hooks<R>(
+        &self,
+        execute: impl FnOnce(&MprotectRtThreadInfo) -> OGResult<OGResult<R>>,
+    ) -> OGResult<R> {
+        let thread = MprotectRtThreadInfo::current().ok_or(OGError::InternalError)?;
+        let hooks = MockRtHostAccessHooks {
+            data: self.control as *const (),
+            restore: mprotect_rt_hook_restore,
+            revoke: mprotect_rt_hook_revoke,
+        };
+
+        let res = mock_rt_with_host_access_hooks(hooks, || execute(&thread));
+
+        // Foreign code may have been abandoned, which our signal handler only
+        // does with access to host memory restored. It may have held the
+        // sandbox heap's lock:
+        if res.is_err() {
+            mprotect_rt_restore(self.control());
+            self.heap.heap().release_abandoned_lock();
+        }
+
+        res.and_then(|res| res)
+    }
+
+    // Run `f` on the foreign stack, within the MockRt's `execute`:
+    fn run_protected<R>(
+        &self,
+        target_symbol: *const (),
+        thread: &MprotectRtThreadInfo,
+        f: impl FnOnce() -> R,
+    ) -> OGResult<R> {
+        let control = self.control();
+
+        // Picked up by the `invoke` trampoline. Nested executions (from
+        // callbacks) restore the outer target once done:
+        let outer_target = control.target.replace(target_symbol);
+
+        // Foreign code runs on the foreign stack, which the MockRt has switched
+        // to. Revoke access to the host's frames on this thread's stack:
+        control.tls.set(thread.tls);
+        control.stack.set(
+            mock_rt_host_stack_pointer(thread.stack.0..thread.stack.1)
+                .map_or((0, 0), |sp| (mprotect_rt_page_floor(sp), thread.stack.1)),
+        );
+
+        let res = f();
+        control.target.set(outer_target);
+
+        // The trampoline does not call the foreign function when it failed to
+        // revoke access to host memory:
+        if control.failed.replace(false) {
+            Err(OGError::InternalError)
+        } else {
+            Ok(res)
+        }
+    }
+}
+
+impl<ID: OGID> Drop for MprotectRt<ID> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.control as *mut c_void, mprotect_rt_page_size()) };
+    }
+}
+
+pub struct MprotectRtInvokeRes<T>(PhantomData<T>);
+
+unsafe impl<ID: OGID, T> SysVAMD64InvokeRes<MprotectRt<ID>, T> for MprotectRtInvokeRes<T> {
+    fn new() -> Self {
+        MprotectRtInvokeRes(PhantomData)
+    }
+
+    fn into_result_registers(self, rt: &MprotectRt<ID>) -> OGResult<OGRet<T>> {
+        // Values larger than two registers must be returned on the stack:
+        OGRet::from_register_pair(rt.control().ret.get()).ok_or(OGError::InternalError)
+    }
+
+    unsafe fn into_result_stacked(
+        self,
+        _rt: &MprotectRt<ID>,
+        stacked_res: *mut T,
+    ) -> OGResult<OGRet<T>> {
+        Ok(OGRet::from_initialized_memory(unsafe {
+            core::ptr::read(stacked_res as *const MaybeValid<T>)
+        }))
+    }
+}
+
+impl<ID: OGID> SysVAMD64BaseRt for MprotectRt<ID> {
+    type InvokeRes<T> = MprotectRtInvokeRes<T>;
+}
+
+impl<ID: OGID, const STACK_SPILL: usize, RTLOC: ArgumentSlot> SysVAMD64Rt<STACK_SPILL, RTLOC>
+    for MprotectRt<ID>
+{
+    #[unsafe(naked)]
+    unsafe extern "C" fn invoke() {
+        core::arch::naked_asm!(
+            "
+            // Set up a stack frame, and save the callee-saved registers we use.
+            // This leaves the stack pointer 16-byte aligned:
+            push rbp
+            mov rbp, rsp
+            push rbx
+            push r12
+
+            // Load the runtime pointer from its argument slot:
+            .if {rtloc_stacked}
+            mov rbx, qword ptr [rbp + 16 + 8 * {rtloc_offset}]
+            .elseif {rtloc_reg} == 0
+            mov rbx, rdi
+            .elseif {rtloc_reg} == 1
+            mov rbx, rsi
+            .elseif {rtloc_reg} == 2
+            mov rbx, rdx
+            .elseif {rtloc_reg} == 3
+            mov rbx, rcx
+            .elseif {rtloc_reg} == 4
+            mov rbx, r8
+            .elseif {rtloc_reg} == 5
+            mov rbx, r9
+            .else
+            .error \"unsupported RTLOC argument slot\"
+            .endif
+
+            // The runtime itself may be inaccessible to foreign code, so keep
+            // a pointer to its control page (its first member) instead:
+            mov rbx, qword ptr [rbx]
+
+            // Save all argument registers (including `al` for variadic
+            // functions) while revoking access to host memory:
+            sub rsp, 192
+            mov qword ptr [rsp + 0], rdi
+            mov qword ptr [rsp + 8], rsi
+            mov qword ptr [rsp + 16], rdx
+            mov qword ptr [rsp + 24], rcx
+            mov qword ptr [rsp + 32], r8
+            mov qword ptr [rsp + 40], r9
+            mov qword ptr [rsp + 48], rax
+            movdqu xmmword ptr [rsp + 64], xmm0
+            movdqu xmmword ptr [rsp + 80], xmm1
+            movdqu xmmword ptr [rsp + 96], xmm2
+            movdqu xmmword ptr [rsp + 112], xmm3
+            movdqu xmmword ptr [rsp + 128], xmm4
+            movdqu xmmword ptr [rsp + 144], xmm5
+            movdqu xmmword ptr [rsp + 160], xmm6
+            movdqu xmmword ptr [rsp + 176], xmm7
+
+            mov rdi, rbx
+            call {revoke}
+            test al, al
+            jz 4f
+
+            mov rdi, qword ptr [rsp + 0]
+            mov rsi, qword ptr [rsp + 8]
+            mov rdx, qword ptr [rsp + 16]
+            mov rcx, qword ptr [rsp + 24]
+            mov r8, qword ptr [rsp + 32]
+            mov r9, qword ptr [rsp + 40]
+            mov rax, qword ptr [rsp + 48]
+            movdqu xmm0, xmmword ptr [rsp + 64]
+            movdqu xmm1, xmmword ptr [rsp + 80]
+            movdqu xmm2, xmmword ptr [rsp + 96]
+            movdqu xmm3, xmmword ptr [rsp + 112]
+            movdqu xmm4, xmmword ptr [rsp + 128]
+            movdqu xmm5, xmmword ptr [rsp + 144]
+            movdqu xmm6, xmmword ptr [rsp + 160]
+            movdqu xmm7, xmmword ptr [rsp + 176]
+
+            // Reserve space for the stack-spilled arguments, keeping the stack
+            // 16-byte aligned, and copy them from our caller's frame:
+            sub rsp, {stack_spill_bytes}
+            xor r12, r12
+        2:
+            cmp r12, {stack_spill}
+            jae 3f
+            mov r11, qword ptr [rbp + 8 * r12 + 16]
+            mov qword ptr [rsp + 8 * r12], r11
+            inc r12
+            jmp 2b
+        3:
+
+            call qword ptr [rbx + {target_offset}]
+
+            // Store its return registers in the control page, and preserve
+            // its floating-point return registers while restoring access:
+            mov qword ptr [rbx + {ret_offset}], rax
+            mov qword ptr [rbx + {ret_offset} + 8], rdx
+            lea rsp, [rbp - 208]
+            movdqu xmmword ptr [rsp + 64], xmm0
+            movdqu xmmword ptr [rsp + 80], xmm1
+
+            mov rdi, rbx
+            call {restore}
+
+            mov rax, qword ptr [rbx + {ret_offset}]
+            mov rdx, qword ptr [rbx + {ret_offset} + 8]
+            movdqu xmm0, xmmword ptr [rsp + 64]
+            movdqu xmm1, xmmword ptr [rsp + 80]
+            jmp 5f
+
+        4:
+            // Revoking access failed, which `mprotect_rt_revoke` recorded in
+            // the control page. Don't call the foreign function:
+            xor eax, eax
+            xor edx, edx
+            mov qword ptr [rbx + {ret_offset}], rax
+            mov qword ptr [rbx + {ret_offset} + 8], rdx
+
+        5:
+            // Restore the callee-saved registers and return:
+            lea rsp, [rbp - 16]
+            pop r12
+            pop rbx
+            pop rbp
+            ret
+            ",
+            rtloc_stacked = const RTLOC::IS_STACKED as usize,
+            rtloc_offset = const if RTLOC::IS_STACKED {
+                RTLOC::STACK_OFFSET_WORDS
+            } else {
+                0
+            },
+            rtloc_reg = const sysv_amd64_areg_index::<RTLOC>(),
+            stack_spill = const STACK_SPILL,
+            stack_spill_bytes = const (STACK_SPILL * 8 + 15) & !15,
+            target_offset = const core::mem::offset_of!(MprotectRtControl, target),
+            ret_offset = const core::mem::offset_of!(MprotectRtControl, ret),
+            revoke = sym mprotect_rt_revoke,
+            restore = sym mprotect_rt_restore,
+        );
+    }
+}
+
+unsafe impl<ID: OGID> OGRuntime for MprotectRt<ID> {
+    type ID = ID;
+    type AllocTracker<'a> = MockRtAllocChain<'a>;
+    type ABI = SysVAMD64ABI;
+    type CallbackTrampolineFn =
+        <MockRt<ID, ForeignStackAllocator> as OGRuntime>::CallbackTrampolineFn;
+    type CallbackContext = MockRtCallbackContext;
+    type CallbackReturn = MockRtCallbackReturn;
+
+    type SymbolTableState<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> =
+        MprotectRtSymbolTableState<SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>;
+
+    fn resolve_symbols<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        symbol_table: &'a [&'a CStr; SYMTAB_SIZE],
+        fixed_offset_symbol_table: &'a [Option<&'a CStr>; FIXED_OFFSET_SYMTAB_SIZE],
+    ) -> Result<
+        Self::SymbolTableState<'a, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+        Option<&'a core::ffi::CStr>,
+    > {
+        let mut symbols = [core::ptr::null(); SYMTAB_SIZE];
+        for (dst, name) in symbols.iter_mut().zip(symbol_table.iter()) {
+            *dst = self.library.symbol(name).ok_or(Some(*name))?;
+        }
+
+        let mut fixed_offset_symbols = [None; FIXED_OFFSET_SYMTAB_SIZE];
+        for (dst, name_opt) in fixed_offset_symbols
+            .iter_mut()
+            .zip(fixed_offset_symbol_table.iter())
+        {
+            if let Some(name) = name_opt {
+                *dst = Some(self.library.symbol(name).ok_or(Some(*name))?);
+            }
+        }
+
+        Ok(MprotectRtSymbolTableState {
+            symbols,
+            fixed_offset_symbols,
+        })
+    }
+
+    fn lookup_symbol<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        compact_symtab_index: usize,
+        fixed_offset_symtab_index: usize,
+        symtabstate: &Self::SymbolTableState<'_, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+    ) -> Option<*const ()> {
+        symtabstate
+            .symbols
+            .get(compact_symtab_index)
+            .copied()
+            .or_else(|| {
+                symtabstate
+                    .fixed_offset_symbols
+                    .get(fixed_offset_symtab_index)
+                    .copied()
+                    .flatten()
+            })
+    }
+
+    fn setup_callback<C, F, R>(
+        &self,
+        callback: &mut C,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &Self::CallbackContext,
+            &mut Self::CallbackReturn,
+            &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+            &mut AccessScope<Self::ID>,
+        ),
+        F: for<'b> FnOnce(
+            *const Self::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        ) -> R,
+    {
+        // The MockRt's callback trampolines restore access to host memory
+        // through our host access hooks:
+        self.mock.setup_callback(callback, alloc_scope, fun)
+    }
+
+    fn execute<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        f: F,
+    ) -> OGResult<R> {
+        self.with_host_access_hooks(|thread| {
+            self.mock
+                .execute(target_symbol, alloc_scope, access_scope, || {
+                    self.run_protected(target_symbol, thread, f)
+                })
+        })
+    }
+
+    const SUPPORTS_DEADLINE: bool =
+        <MockRt<ID, ForeignStackAllocator> as OGRuntime>::SUPPORTS_DEADLINE;
+
+    fn execute_with_deadline<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        timeout: core::time::Duration,
+        f: F,
+    ) -> OGResult<R> {
+        self.with_host_access_hooks(|thread| {
+            self.mock.execute_with_deadline(
+                target_symbol,
+                alloc_scope,
+                access_scope,
+                timeout,
+                || self.run_protected(target_symbol, thread, f),
+            )
+        })
+    }
+
+    fn allocate_stacked_untracked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: FnOnce(*mut ()) -> R,
+    {
+        self.mock.allocate_stacked_untracked_mut(layout, fun)
+    }
+
+    fn allocate_stacked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: for<'b> FnOnce(*mut (), &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>) -> R,
+    {
+        self.mock.allocate_stacked_mut(layout, alloc_scope, fun)
+    }
+}
+
+#[cfg(test)]
+const TEST_LIBRARY: &str = "
+    #include <stdarg.h>
+    #include <stdio.h>
+    #include <stdlib.h>
+
+    void write_to(unsigned long *ptr, unsigned long value) {
+        *ptr = value;
+    }
+
+    void *alloc(size_t size) {
+        return malloc(size);
+    }
+
+    void release(void *ptr) {
+        free(ptr);
+    }
+
+    unsigned long call_back(unsigned long (*callback)(unsigned long), unsigned long value) {
+        return callback(value) + 1;
+    }
+
+    int format(char *buf, size_t len, const char *fmt, ...) {
+        va_list args;
+        va_start(args, fmt);
+        int res = vsnprintf(buf, len, fmt, args);
+        va_end(args);
+        return res;
+    }
+";
+
+// Compile `TEST_LIBRARY` under the given name. Libraries can be loaded by a
+// single runtime only, so each test requires its own copy:
+#[cfg(test)]
+fn test_library(name: &str) -> std::ffi::CString {
+    crate::util::test_lib::compile(name, TEST_LIBRARY)
+}
+
+// Invoke the two-argument function `symbol` through this runtime's trampoline:
+#[cfg(test)]
+fn test_invoke<ID: OGID>(
+    rt: &MprotectRt<ID>,
+    symbol: *const (),
+    args: [usize; 2],
+    alloc_scope: &mut AllocScope<'_, MockRtAllocChain<'_>, ID>,
+    access_scope: &mut AccessScope<ID>,
+) -> OGResult<usize> {
+    use crate::abi::calling_convention::AREG2;
+
+    let invoke: unsafe extern "C" fn(usize, usize, *const MprotectRt<ID>) -> usize = unsafe {
+        core::mem::transmute(
+            <MprotectRt<ID> as SysVAMD64Rt<0, AREG2<SysVAMD64ABI>>>::invoke
+                as unsafe extern "C" fn(),
+        )
+    };
+    rt.execute(symbol, alloc_scope, access_scope, || unsafe {
+        invoke(args[0], args[1], rt)
+    })
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_host_stack_protected() {
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::{CallbackContext, CallbackReturn};
+
+    let path = test_library("mprotect_rt_host_stack_protected");
+    let (mut rt, mut alloc_scope, mut access_scope) =
+        unsafe { MprotectRt::new(&path, 64 * 1024, false, OGRuntimeBranding::new()) }.unwrap();
+    rt.set_catch_faults(true);
+
+    let symtab = rt
+        .resolve_symbols(&[c"write_to", c"call_back"], &[])
+        .unwrap();
+    let write_to = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let call_back = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+
+    // Foreign writes to the host's stack fault, and poison the domain. Frames
+    // at the top of this thread's stack may share a page with its TLS block,
+    // so write to a value further down:
+    let mut host_values = [1_u64; 1024];
+    let host_ptr = host_values.as_mut_ptr();
+    assert_eq!(
+        test_invoke(
+            &rt,
+            write_to,
+            [host_ptr as usize, 2],
+            &mut alloc_scope,
+            &mut access_scope
+        ),
+        Err(OGError::ForeignFault {
+            signal: libc::SIGSEGV,
+            address: host_ptr as usize,
+            symbol: write_to as usize,
+        })
+    );
+    assert!(access_scope.is_poisoned());
+    assert_eq!(unsafe { core::ptr::read_volatile(host_ptr) }, 1);
+    unsafe { access_scope.unpoison() };
+
+    // Stacked allocations are placed on the foreign stack, which remains
+    // accessible:
+    rt.allocate_stacked_mut(
+        core::alloc::Layout::new::<u64>(),
+        &mut alloc_scope,
+        |ptr, alloc_scope| {
+            test_invoke(
+                &rt,
+                write_to,
+                [ptr as usize, 3],
+                alloc_scope,
+                &mut access_scope,
+            )
+            .unwrap();
+            assert_eq!(unsafe { core::ptr::read_volatile(ptr as *const u64) }, 3);
+        },
+    )
+    .unwrap();
+
+    // Callbacks run with access to the host's stack restored, and revoke it
+    // again when returning to foreign code:
+    let mut callback = |ctx: &MockRtCallbackContext,
+                        ret: &mut MockRtCallbackReturn,
+                        _: &mut AllocScope<'_, MockRtAllocChain<'_>, OGRuntimeBranding>,
+                        _: &mut AccessScope<OGRuntimeBranding>| {
+        let value = unsafe { core::ptr::read_volatile(host_ptr) };
+        ret.set_return_register(0, ctx.get_argument_register(0).unwrap() + value as usize);
+    };
+    let res = rt
+        .setup_callback(
+            &mut callback,
+            &mut alloc_scope,
+            |trampoline, alloc_scope| {
+                test_invoke(
+                    &rt,
+                    call_back,
+                    [trampoline as usize, 10],
+                    alloc_scope,
+                    &mut access_scope,
+                )
+            },
+        )
+        .unwrap();
+    assert_eq!(res, Ok(12));
+
+    assert!(matches!(
+        test_invoke(
+            &rt,
+            write_to,
+            [host_ptr as usize, 2],
+            &mut alloc_scope,
+            &mut access_scope
+        ),
+        Err(OGError::ForeignFault { .. })
+    ));
+    unsafe { access_scope.unpoison() };
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_sandbox_heap_allocation() {
+    use crate::alloc_tracker::AllocTracker;
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let path = test_library("mprotect_rt_sandbox_heap_allocation");
+    let (rt, mut alloc_scope, mut access_scope) =
+        unsafe { MprotectRt::new(&path, 64 * 1024, false, OGRuntimeBranding::new()) }.unwrap();
+
+    let symtab = rt
+        .resolve_symbols(&[c"alloc", c"release", c"write_to"], &[])
+        .unwrap();
+    let alloc = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let release = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+    let write_to = rt.lookup_symbol(2, usize::MAX, &symtab).unwrap();
+
+    // The library's allocations are served from the sandbox heap, which
+    // foreign code can write to and which is valid for upgrades:
+    let ptr = test_invoke(&rt, alloc, [24, 0], &mut alloc_scope, &mut access_scope).unwrap();
+    let heap = rt.heap.heap();
+    assert!(heap.offset(ptr as *mut c_void).is_some());
+    assert!(alloc_scope.tracker().is_valid_mut(ptr as *mut (), 24));
+
+    test_invoke(&rt, write_to, [ptr, 7], &mut alloc_scope, &mut access_scope).unwrap();
+    assert_eq!(unsafe { core::ptr::read_volatile(ptr as *const u64) }, 7);
+
+    // Freed blocks are reused, and cleared by calloc:
+    test_invoke(&rt, release, [ptr, 0], &mut alloc_scope, &mut access_scope).unwrap();
+    let calloc_ptr = unsafe { heap.calloc(2, 8) };
+    assert!(!calloc_ptr.is_null());
+    assert_eq!(unsafe { *(calloc_ptr as *const [u64; 2]) }, [0, 0]);
+
+    // Growing a block moves its contents:
+    unsafe { (calloc_ptr as *mut u64).write(9) };
+    let grown = unsafe { heap.realloc(calloc_ptr, 4096) };
+    assert!(heap.offset(grown).is_some());
+    assert_eq!(unsafe { (grown as *const u64).read() }, 9);
+    unsafe { heap.free(grown) };
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_sandbox_heap_per_runtime() {
+    use crate::alloc_tracker::AllocTracker;
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let path_a = test_library("mprotect_rt_sandbox_heap_per_runtime_a");
+    let path_b = test_library("mprotect_rt_sandbox_heap_per_runtime_b");
+    let (rt_a, mut alloc_scope_a, mut access_scope_a) =
+        unsafe { MprotectRt::new(&path_a, 64 * 1024, false, OGRuntimeBranding::new()) }.unwrap();
+    let (rt_b, mut alloc_scope_b, mut access_scope_b) =
+        unsafe { MprotectRt::new(&path_b, 64 * 1024, false, OGRuntimeBranding::new()) }.unwrap();
+
+    // A library can only be loaded by a single runtime:
+    assert!(matches!(
+        unsafe { MprotectRt::new(&path_a, 64 * 1024, false, OGRuntimeBranding::new()) },
+        Err(OGError::LibraryLoadFailed)
+    ));
+
+    let symtab_a = rt_a.resolve_symbols(&[c"alloc"], &[]).unwrap();
+    let alloc_a = rt_a.lookup_symbol(0, usize::MAX, &symtab_a).unwrap();
+    let symtab_b = rt_b.resolve_symbols(&[c"alloc"], &[]).unwrap();
+    let alloc_b = rt_b.lookup_symbol(0, usize::MAX, &symtab_b).unwrap();
+
+    // Each runtime serves allocations from its own heap, and does not accept
+    // upgrades of blocks allocated by the other runtime's library:
+    let ptr_a = test_invoke(
+        &rt_a,
+        alloc_a,
+        [16, 0],
+        &mut alloc_scope_a,
+        &mut access_scope_a,
+    )
+    .unwrap() as *mut ();
+    let ptr_b = test_invoke(
+        &rt_b,
+        alloc_b,
+        [16, 0],
+        &mut alloc_scope_b,
+        &mut access_scope_b,
+    )
+    .unwrap() as *mut ();
+
+    assert!(rt_a.heap.heap().offset(ptr_a as *mut c_void).is_some());
+    assert!(rt_b.heap.heap().offset(ptr_b as *mut c_void).is_some());
+    assert!(alloc_scope_a.tracker().is_valid_mut(ptr_a, 16));
+    assert!(alloc_scope_b.tracker().is_valid_mut(ptr_b, 16));
+    assert!(!alloc_scope_a.tracker().is_valid_mut(ptr_b, 16));
+    assert!(!alloc_scope_b.tracker().is_valid_mut(ptr_a, 16));
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_host_heap_protected() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    // Protecting the host's heap affects all threads of this process, so
+    // observe it from a child process, running only this test. Its main thread
+    // merely waits for the test to complete:
+    const CHILD_ENV: &str = "OMNIGLOT_MPROTECT_RT_HEAP_TEST_CHILD";
+    if std::env::var_os(CHILD_ENV).is_none() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "rt::mprotect::test_host_heap_protected",
+                "--test-threads=1",
+                "--nocapture",
+            ])
+            .env(CHILD_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "child process failed with {}:\n{}{}",
+            output.status,
+            std::string::String::from_utf8_lossy(&output.stdout),
+            std::string::String::from_utf8_lossy(&output.stderr),
+        );
+        // Make sure the child actually ran this test:
+        assert!(
+            std::string::String::from_utf8_lossy(&output.stdout).contains("1 passed"),
+            "child process did not run the test"
+        );
+        return;
+    }
+
+    let path = test_library("mprotect_rt_host_heap_protected");
+    let (mut rt, mut alloc_scope, mut access_scope) =
+        unsafe { MprotectRt::new(&path, 64 * 1024, true, OGRuntimeBranding::new()) }.unwrap();
+    rt.set_catch_faults(true);
+
+    let symtab = rt.resolve_symbols(&[c"alloc", c"write_to"], &[]).unwrap();
+    let alloc = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let write_to = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+
+    // Memory below the program break, which this thread's allocator arena may
+    // not be serving from:
+    let heap_ptr = unsafe { libc::sbrk(4096) } as *mut u64;
+    unsafe { heap_ptr.write(1) };
+
+    assert!(matches!(
+        test_invoke(
+            &rt,
+            write_to,
+            [heap_ptr as usize, 2],
+            &mut alloc_scope,
+            &mut access_scope
+        ),
+        Err(OGError::ForeignFault { address, .. }) if address == heap_ptr as usize
+    ));
+    assert_eq!(unsafe { heap_ptr.read() }, 1);
+    unsafe { access_scope.unpoison() };
+
+    // The library's own allocations remain accessible:
+    let ptr = test_invoke(&rt, alloc, [32, 0], &mut alloc_scope, &mut access_scope).unwrap();
+    test_invoke(&rt, write_to, [ptr, 5], &mut alloc_scope, &mut access_scope).unwrap();
+    assert_eq!(unsafe { (ptr as *const u64).read() }, 5);
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_variadic_foreign_call() {
+    use core::ffi::{c_char, c_int};
+
+    use crate::abi::calling_convention::AREG5;
+    use crate::abi::sysv_amd64::{
+        SysVArgAllocator, SysVArgLocation, SysVLayout, SysVReg, SysVScalar, SysVScalarClass,
+        sysv_amd64_return_location,
+    };
+    use crate::id::runtime::OGRuntimeBranding;
+
+    const fn scalar(class: SysVScalarClass) -> [SysVScalar; 1] {
+        [SysVScalar {
+            offset: 0,
+            size: 8,
+            class,
+        }]
+    }
+    const INT: [SysVScalar; 1] = scalar(SysVScalarClass::Integer);
+    const DOUBLE: [SysVScalar; 1] = scalar(SysVScalarClass::Sse);
+    let layout = |fields| SysVLayout {
+        size: 8,
+        align: 8,
+        fields,
+    };
+
+    // int format(char *buf, size_t len, const char *fmt, ...), a wrapper around
+    // vsnprintf, called with a string, an int and a double as variadic
+    // arguments, followed by the runtime pointer for the trampoline:
+    let mut args = SysVArgAllocator::new(sysv_amd64_return_location(&layout(&INT)));
+    let locations =
+        [&INT, &INT, &INT, &INT, &INT, &DOUBLE, &INT].map(|arg| args.allocate(&layout(arg)));
+    let reg = |reg| SysVArgLocation::Registers([Some(reg), None]);
+    assert_eq!(
+        locations,
+        [
+            reg(SysVReg::Integer(0)),
+            reg(SysVReg::Integer(1)),
+            reg(SysVReg::Integer(2)),
+            reg(SysVReg::Integer(3)),
+            reg(SysVReg::Integer(4)),
+            reg(SysVReg::Sse(0)),
+            reg(SysVReg::Integer(5)),
+        ]
+    );
+    assert_eq!(args.stack_words(), 0);
+    assert_eq!(args.vector_registers(), 1);
+
+    let path = test_library("mprotect_rt_variadic_foreign_call");
+    let (rt, mut alloc_scope, mut access_scope) =
+        unsafe { MprotectRt::new(&path, 64 * 1024, false, OGRuntimeBranding::new()) }.unwrap();
+    let symtab = rt.resolve_symbols(&[c"format"], &[]).unwrap();
+    let format = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+    // As a variadic function pointer, the compiler sets al to the number of
+    // vector registers used, which the trampoline must preserve:
+    fn variadic_invoke<ID: OGID>(
+        _rt: &MprotectRt<ID>,
+    ) -> unsafe extern "C" fn(*mut c_char, usize, *const c_char, ...) -> c_int {
+        unsafe {
+            core::mem::transmute(
+                <MprotectRt<ID> as SysVAMD64Rt<0, AREG5<SysVAMD64ABI>>>::invoke
+                    as unsafe extern "C" fn(),
+            )
+        }
+    }
+    let invoke = variadic_invoke(&rt);
+
+    rt.allocate_stacked_mut(
+        core::alloc::Layout::new::<[u8; 64]>(),
+        &mut alloc_scope,
+        |buf, alloc_scope| {
+            let res = rt.execute(format, alloc_scope, &mut access_scope, || unsafe {
+                invoke(
+                    buf as *mut c_char,
+                    64,
+                    c"%s: %d, %.2f".as_ptr(),
+                    c"answer".as_ptr(),
+                    42 as c_int,
+                    1.5_f64,
+                    &rt as *const MprotectRt<_>,
+                )
+            });
+
+            let expected = c"answer: 42, 1.50";
+            assert_eq!(res, Ok(expected.count_bytes() as c_int));
+            assert_eq!(unsafe { CStr::from_ptr(buf as *const c_char) }, expected);
+        },
+    )
+    .unwrap();
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_fp_arguments() {
+    use core::ffi::c_long;
+
+    use crate::abi::calling_convention::{AREG1, FPREG0, FPREG1, FPREG7};
+    use crate::abi::sysv_amd64::{
+        SysVArgAllocator, SysVArgLocation, SysVLayout, SysVReg, SysVReturnLocation, SysVScalar,
+        SysVScalarClass, sysv_amd64_return_location,
+    };
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::sysv_amd64::sysv_amd64_fpreg_index;
+
+    const fn scalar(size: usize, class: SysVScalarClass) -> [SysVScalar; 1] {
+        [SysVScalar {
+            offset: 0,
+            size,
+            class,
+        }]
+    }
+    const DOUBLE: [SysVScalar; 1] = scalar(8, SysVScalarClass::Sse);
+    const FLOAT: [SysVScalar; 1] = scalar(4, SysVScalarClass::Sse);
+    const LONG: [SysVScalar; 1] = scalar(8, SysVScalarClass::Integer);
+    let layout = |fields: &'static [SysVScalar; 1]| SysVLayout {
+        size: fields[0].size,
+        align: fields[0].size,
+        fields,
+    };
+
+    // double fp_args(double, float, long, double, double, double, double,
+    //                double, double), followed by the runtime pointer for the
+    // trampoline. Its eight floating-point arguments occupy all FPREG slots:
+    let ret = sysv_amd64_return_location(&layout(&DOUBLE));
+    assert_eq!(
+        ret,
+        SysVReturnLocation::Registers([Some(SysVReg::Sse(0)), None])
+    );
+    let mut args = SysVArgAllocator::new(ret);
+    let locations = [
+        &DOUBLE, &FLOAT, &LONG, &DOUBLE, &DOUBLE, &DOUBLE, &DOUBLE, &DOUBLE, &DOUBLE, &LONG,
+    ]
+    .map(|arg| match args.allocate(&layout(arg)) {
+        SysVArgLocation::Registers([Some(reg), None]) => reg,
+        location => panic!("unexpected argument location {location:?}"),
+    });
+    assert_eq!(
+        locations,
+        [
+            SysVReg::Sse(0),
+            SysVReg::Sse(1),
+            SysVReg::Integer(0),
+            SysVReg::Sse(2),
+            SysVReg::Sse(3),
+            SysVReg::Sse(4),
+            SysVReg::Sse(5),
+            SysVReg::Sse(6),
+            SysVReg::Sse(7),
+            SysVReg::Integer(1),
+        ]
+    );
+    assert_eq!(args.stack_words(), 0);
+
+    // ... which correspond to the argument slots with the same register names:
+    const {
+        assert!(sysv_amd64_fpreg_index::<FPREG0<SysVAMD64ABI>>() == 0);
+        assert!(sysv_amd64_fpreg_index::<FPREG1<SysVAMD64ABI>>() == 1);
+        assert!(sysv_amd64_fpreg_index::<FPREG7<SysVAMD64ABI>>() == 7);
+    }
+    assert_eq!(
+        SysVReg::Sse(7).argument_register_name(),
+        <FPREG7<SysVAMD64ABI> as ArgumentSlot>::REG_NAME
+    );
+    assert_eq!(
+        SysVReg::Integer(1).argument_register_name(),
+        <AREG1<SysVAMD64ABI> as ArgumentSlot>::REG_NAME
+    );
+
+    let path = crate::util::test_lib::compile(
+        "mprotect_rt_fp_args",
+        "
+        double fp_args(double a, float b, long n, double c, double d, double e,
+                       double f, double g, double h) {
+            return a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f + 7 * g + 8 * h
+                + 100 * n;
+        }
+        ",
+    );
+    let (rt, mut alloc_scope, mut access_scope) =
+        unsafe { MprotectRt::new(&path, 64 * 1024, false, OGRuntimeBranding::new()) }.unwrap();
+    let symtab = rt.resolve_symbols(&[c"fp_args"], &[]).unwrap();
+    let fp_args = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+    type FpArgsFn<RT> =
+        unsafe extern "C" fn(f64, f32, c_long, f64, f64, f64, f64, f64, f64, *const RT) -> f64;
+    fn fp_args_invoke<ID: OGID>(_rt: &MprotectRt<ID>) -> FpArgsFn<MprotectRt<ID>> {
+        unsafe {
+            core::mem::transmute(
+                <MprotectRt<ID> as SysVAMD64Rt<0, AREG1<SysVAMD64ABI>>>::invoke
+                    as unsafe extern "C" fn(),
+            )
+        }
+    }
+    let invoke = fp_args_invoke(&rt);
+
+    // The trampoline forwards all FPREG slots, and the result in xmm0:
+    assert_eq!(
+        rt.execute(fp_args, &mut alloc_scope, &mut access_scope, || unsafe {
+            invoke(1.0, 2.0, 3, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, &rt)
+        }),
+        Ok(504.0)
+    );
+}
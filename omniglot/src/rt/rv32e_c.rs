@@ -0,0 +1,80 @@
+// -*- fill-column: 80; -*-
+
+use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
+use crate::foreign_memory::og_ret::OGRet;
+use crate::rt::OGRuntime;
+
+/// Retrieves the result of a function called through [`Rv32eCRt::invoke`].
+///
+/// # Safety
+///
+/// Implementations must only return `OGRet`s containing the values returned by
+/// the last invoked foreign function.
+pub unsafe trait Rv32eCInvokeRes<RT: Rv32eCBaseRt, T: Sized> {
+    fn new() -> Self;
+
+    /// Assemble the result from the `a0` and `a1` return registers.
+    fn into_result_registers(self, rt: &RT) -> OGResult<OGRet<T>>;
+
+    /// # Safety
+    ///
+    /// `stacked_res` must point to a stacked allocation of `T` that the last
+    /// invoked foreign function returned its result in.
+    unsafe fn into_result_stacked(self, rt: &RT, stacked_res: *mut T) -> OGResult<OGRet<T>>;
+}
+
+pub trait Rv32eCBaseRt: OGRuntime<ABI = crate::abi::rv32e_c::Rv32eCABI> + Sized {
+    type InvokeRes<T>: Rv32eCInvokeRes<Self, T>;
+}
+
+pub trait Rv32eCRt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
+    Rv32eCBaseRt
+{
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the ILP32E calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
+    unsafe extern "C" fn invoke();
+}
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn rv32e_c_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["a0", "a1", "a2", "a3", "a4", "a5"])
+}
+
+/// Byte offset of the stacked argument referred to by an `ArgumentSlot`,
+/// relative to the stack pointer on entry to the callee, or `usize::MAX` if it
+/// does not refer to a stacked argument. Stack slots are 4 bytes wide.
+pub const fn rv32e_c_stack_offset<S: crate::abi::calling_convention::ArgumentSlot>() -> usize {
+    if !S::IS_STACKED {
+        return usize::MAX;
+    }
+
+    S::STACK_OFFSET_WORDS * 4
+}
+
+/// Bytes of stack to reserve for `stack_spill` words of stacked arguments. The
+/// ILP32E ABI only requires a 4-byte aligned stack pointer, so this adds no
+/// padding.
+pub const fn rv32e_c_stack_spill_bytes(stack_spill: usize) -> usize {
+    stack_spill * 4
+}
+
+#[test]
+fn test_rv32e_c_argument_slots() {
+    use crate::abi::calling_convention::{AREG5, Stacked};
+    use crate::abi::rv32e_c::Rv32eCABI;
+
+    assert_eq!(rv32e_c_areg_index::<AREG5<Rv32eCABI>>(), 5);
+    assert_eq!(rv32e_c_stack_offset::<AREG5<Rv32eCABI>>(), usize::MAX);
+    assert_eq!(rv32e_c_areg_index::<Stacked<3, Rv32eCABI>>(), usize::MAX);
+    assert_eq!(rv32e_c_stack_offset::<Stacked<3, Rv32eCABI>>(), 12);
+    assert_eq!(rv32e_c_stack_spill_bytes(3), 12);
+}
@@ -0,0 +1,71 @@
+// -*- fill-column: 80; -*-
+
+use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
+use crate::foreign_memory::og_ret::OGRet;
+use crate::rt::OGRuntime;
+
+/// Retrieves the result of a function called through [`Rv32gCRt::invoke`].
+///
+/// # Safety
+///
+/// Implementations must only return `OGRet`s containing the values returned by
+/// the last invoked foreign function.
+pub unsafe trait Rv32gCInvokeRes<RT: Rv32gCBaseRt, T: Sized> {
+    fn new() -> Self;
+
+    /// Assemble the result from the integer (`a0`, `a1`) and floating-point
+    /// (`fa0`, `fa1`) return registers.
+    fn into_result_registers(self, rt: &RT) -> OGResult<OGRet<T>>;
+
+    /// # Safety
+    ///
+    /// `stacked_res` must point to a stacked allocation of `T` that the last
+    /// invoked foreign function returned its result in.
+    unsafe fn into_result_stacked(self, rt: &RT, stacked_res: *mut T) -> OGResult<OGRet<T>>;
+}
+
+pub trait Rv32gCBaseRt: OGRuntime<ABI = crate::abi::rv32g_c::Rv32gCABI> + Sized {
+    type InvokeRes<T>: Rv32gCInvokeRes<Self, T>;
+}
+
+pub trait Rv32gCRt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
+    Rv32gCBaseRt
+{
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the ILP32D calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
+    unsafe extern "C" fn invoke();
+}
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn rv32g_c_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"])
+}
+
+/// Index of the floating-point argument register referred to by an
+/// `ArgumentSlot`, or `usize::MAX` if it does not refer to one.
+pub const fn rv32g_c_fpreg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["fa0", "fa1", "fa2", "fa3", "fa4", "fa5", "fa6", "fa7"])
+}
+
+#[test]
+fn test_rv32g_c_argument_slots() {
+    use crate::abi::calling_convention::{AREG0, FPREG0, FPREG5, Stacked};
+    use crate::abi::rv32g_c::Rv32gCABI;
+
+    const {
+        assert!(rv32g_c_fpreg_index::<FPREG0<Rv32gCABI>>() == 0);
+        assert!(rv32g_c_fpreg_index::<FPREG5<Rv32gCABI>>() == 5);
+        assert!(rv32g_c_fpreg_index::<AREG0<Rv32gCABI>>() == usize::MAX);
+        assert!(rv32g_c_fpreg_index::<Stacked<1, Rv32gCABI>>() == usize::MAX);
+        assert!(rv32g_c_areg_index::<FPREG5<Rv32gCABI>>() == usize::MAX);
+    }
+}
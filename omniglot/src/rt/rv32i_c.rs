@@ -1,6 +1,7 @@
 // -*- fill-column: 80; -*-
 
 use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
 use crate::foreign_memory::og_ret::OGRet;
 use crate::rt::OGRuntime;
 
@@ -18,5 +19,28 @@ pub trait Rv32iCBaseRt: OGRuntime<ABI = crate::abi::rv32i_c::Rv32iCABI> + Sized
 pub trait Rv32iCRt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
     Rv32iCBaseRt
 {
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// Foreign functions may be variadic. Their arguments are placed as
+    /// assigned by
+    /// [`RiscVArgAllocator::ilp32`](crate::abi::riscv::RiscVArgAllocator::ilp32).
+    /// Variadic arguments only occupy integer argument registers and stack
+    /// words, so unlike on System V AMD64, no further state is passed.
+    /// Implementations must forward all argument registers and the
+    /// `STACK_SPILL` stacked words unmodified.
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the RV32I calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
     unsafe extern "C" fn invoke();
 }
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn rv32i_c_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"])
+}
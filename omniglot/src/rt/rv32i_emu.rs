@@ -0,0 +1,1908 @@
+// -*- fill-column: 80; -*-
+
+//! A runtime interpreting foreign libraries compiled for RV32I(MC).
+//!
+//! [`Rv32iEmuRt`] loads a statically linked, little-endian `rv32` ELF
+//! executable into an emulated memory region and interprets its code. This
+//! allows bindings targeting the [`Rv32iCABI`] (such as those used by Tock
+//! applications) to be exercised on any host, e.g., as part of `cargo test`.
+//!
+//! As foreign code can only access the emulated memory region, this region
+//! forms the foreign domain. It contains the executable's loaded segments and
+//! the foreign stack, which is placed at its top end.
+//!
+//! Unlike runtimes executing foreign code natively, foreign functions are not
+//! invoked through [`Rv32iCRt::invoke`](crate::rt::rv32i_c::Rv32iCRt::invoke).
+//! Instead, arguments are placed into their [`Rv32iCABI`] argument slots
+//! through [`Rv32iEmuRt::set_argument`], and the function is then called
+//! through [`Rv32iEmuRt::invoke`].
+
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::ffi::{CStr, c_void};
+use core::marker::PhantomData;
+
+use std::borrow::ToOwned;
+use std::boxed::Box;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::time::Instant;
+use std::vec;
+use std::vec::Vec;
+
+use crate::abi::calling_convention::ArgumentSlot;
+use crate::abi::rv32i_c::Rv32iCABI;
+use crate::alloc_tracker::AllocTracker;
+use crate::foreign_memory::og_ret::OGRet;
+use crate::id::OGID;
+use crate::markers::{AccessScope, AllocScope};
+use crate::maybe_valid::MaybeValid;
+use crate::rt::rv32i_c::{Rv32iCBaseRt, Rv32iCInvokeRes, rv32i_c_areg_index};
+use crate::rt::{CallbackContext, CallbackReturn, OGRuntime};
+use crate::{OGError, OGResult};
+
+const REG_RA: usize = 1;
+const REG_SP: usize = 2;
+const REG_GP: usize = 3;
+const REG_A0: usize = 10;
+
+// ---------- ELF loading ------------------------------------------------------
+
+const EM_RISCV: u16 = 243;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_NOTYPE: u8 = 0;
+const SHN_UNDEF: u16 = 0;
+
+struct Rv32iEmuElfSegment<'a> {
+    vaddr: u32,
+    data: &'a [u8],
+    memsz: u32,
+}
+
+fn elf_u16(elf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        elf.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+fn elf_u32(elf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        elf.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+fn elf_table(elf: &[u8], offset: u32, entsize: u16, num: u16) -> Option<Vec<usize>> {
+    (0..num as usize)
+        .map(|i| {
+            let entry = (offset as usize).checked_add(i * entsize as usize)?;
+            elf.get(entry..entry.checked_add(entsize as usize)?)?;
+            Some(entry)
+        })
+        .collect()
+}
+
+fn elf_segments(elf: &[u8]) -> Option<Vec<Rv32iEmuElfSegment<'_>>> {
+    // ELFCLASS32, ELFDATA2LSB, statically linked executable for RISC-V:
+    if elf.get(0..6)? != b"\x7fELF\x01\x01"
+        || elf_u16(elf, 16)? != ET_EXEC
+        || elf_u16(elf, 18)? != EM_RISCV
+    {
+        return None;
+    }
+
+    elf_table(elf, elf_u32(elf, 28)?, elf_u16(elf, 42)?, elf_u16(elf, 44)?)?
+        .into_iter()
+        .filter(|phdr| elf_u32(elf, *phdr) == Some(PT_LOAD))
+        .map(|phdr| {
+            let offset = elf_u32(elf, phdr + 4)? as usize;
+            let filesz = elf_u32(elf, phdr + 16)? as usize;
+            let memsz = elf_u32(elf, phdr + 20)?;
+            if filesz > memsz as usize {
+                return None;
+            }
+
+            Some(Rv32iEmuElfSegment {
+                vaddr: elf_u32(elf, phdr + 8)?,
+                data: elf.get(offset..offset.checked_add(filesz)?)?,
+                memsz,
+            })
+        })
+        .collect()
+}
+
+fn elf_symbols(elf: &[u8]) -> Option<BTreeMap<CString, u32>> {
+    let shdrs = elf_table(elf, elf_u32(elf, 32)?, elf_u16(elf, 46)?, elf_u16(elf, 48)?)?;
+    let mut symbols = BTreeMap::new();
+
+    for symtab in shdrs
+        .iter()
+        .filter(|shdr| elf_u32(elf, **shdr + 4) == Some(SHT_SYMTAB))
+    {
+        let strtab = *shdrs.get(elf_u32(elf, symtab + 24)? as usize)?;
+        let strtab_offset = elf_u32(elf, strtab + 16)? as usize;
+        let strtab_data = elf
+            .get(strtab_offset..strtab_offset.checked_add(elf_u32(elf, strtab + 20)? as usize)?)?;
+
+        let syms = elf_table(
+            elf,
+            elf_u32(elf, symtab + 16)?,
+            16,
+            (elf_u32(elf, symtab + 20)? / 16) as u16,
+        )?;
+
+        for sym in syms {
+            let info = *elf.get(sym + 12)?;
+            if elf_u16(elf, sym + 14)? == SHN_UNDEF
+                || !matches!(info & 0xf, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+            {
+                continue;
+            }
+
+            let name =
+                CStr::from_bytes_until_nul(strtab_data.get(elf_u32(elf, sym)? as usize..)?).ok()?;
+            if !name.is_empty() {
+                symbols.insert(name.to_owned(), elf_u32(elf, sym + 4)?);
+            }
+        }
+    }
+
+    Some(symbols)
+}
+
+// ---------- Instruction decoding ---------------------------------------------
+
+fn sext(value: u32, bits: u32) -> u32 {
+    (((value << (32 - bits)) as i32) >> (32 - bits)) as u32
+}
+
+fn enc_r(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn enc_i(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn enc_s(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    (((imm >> 5) & 0x7f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm & 0x1f) << 7)
+        | 0x23
+}
+
+fn enc_b(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    (((imm >> 12) & 1) << 31)
+        | (((imm >> 5) & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | (((imm >> 1) & 0xf) << 8)
+        | (((imm >> 11) & 1) << 7)
+        | 0x63
+}
+
+fn enc_j(imm: u32, rd: u32) -> u32 {
+    (((imm >> 20) & 1) << 31)
+        | (((imm >> 1) & 0x3ff) << 21)
+        | (((imm >> 11) & 1) << 20)
+        | (((imm >> 12) & 0xff) << 12)
+        | (rd << 7)
+        | 0x6f
+}
+
+// Expand a compressed (RVC) instruction into its 32-bit equivalent, or return
+// `None` if it is not a valid RV32C instruction:
+fn expand_compressed(inst: u32) -> Option<u32> {
+    let funct3 = (inst >> 13) & 7;
+    let rd = (inst >> 7) & 0x1f;
+    let rs2 = (inst >> 2) & 0x1f;
+    // Registers x8-x15, as encoded in 3-bit register fields:
+    let rd_p = 8 + ((inst >> 2) & 7);
+    let rs1_p = 8 + ((inst >> 7) & 7);
+    let imm6 = sext(((inst >> 7) & 0x20) | ((inst >> 2) & 0x1f), 6);
+
+    let cj_imm = sext(
+        ((inst >> 1) & 0x800)
+            | ((inst >> 7) & 0x10)
+            | ((inst >> 1) & 0x300)
+            | ((inst << 2) & 0x400)
+            | ((inst >> 1) & 0x40)
+            | ((inst << 1) & 0x80)
+            | ((inst >> 2) & 0xe)
+            | ((inst << 3) & 0x20),
+        12,
+    );
+    let cb_imm = sext(
+        ((inst >> 4) & 0x100)
+            | ((inst >> 7) & 0x18)
+            | ((inst << 1) & 0xc0)
+            | ((inst >> 2) & 0x6)
+            | ((inst << 3) & 0x20),
+        9,
+    );
+    let clw_imm = ((inst >> 7) & 0x38) | ((inst >> 4) & 0x4) | ((inst << 1) & 0x40);
+
+    match (inst & 3, funct3) {
+        // C.ADDI4SPN
+        (0, 0b000) => {
+            let imm = ((inst >> 7) & 0x30)
+                | ((inst >> 1) & 0x3c0)
+                | ((inst >> 4) & 0x4)
+                | ((inst >> 2) & 0x8);
+            (imm != 0).then(|| enc_i(imm, REG_SP as u32, 0b000, rd_p, 0x13))
+        }
+        // C.LW
+        (0, 0b010) => Some(enc_i(clw_imm, rs1_p, 0b010, rd_p, 0x03)),
+        // C.SW
+        (0, 0b110) => Some(enc_s(clw_imm, rd_p, rs1_p, 0b010)),
+
+        // C.ADDI, C.NOP
+        (1, 0b000) => Some(enc_i(imm6, rd, 0b000, rd, 0x13)),
+        // C.JAL
+        (1, 0b001) => Some(enc_j(cj_imm, REG_RA as u32)),
+        // C.LI
+        (1, 0b010) => Some(enc_i(imm6, 0, 0b000, rd, 0x13)),
+        // C.ADDI16SP
+        (1, 0b011) if rd == REG_SP as u32 => {
+            let imm = sext(
+                ((inst >> 3) & 0x200)
+                    | ((inst >> 2) & 0x10)
+                    | ((inst << 1) & 0x40)
+                    | ((inst << 4) & 0x180)
+                    | ((inst << 3) & 0x20),
+                10,
+            );
+            (imm != 0).then(|| enc_i(imm, REG_SP as u32, 0b000, REG_SP as u32, 0x13))
+        }
+        // C.LUI
+        (1, 0b011) => (imm6 != 0).then_some((imm6 << 12) | (rd << 7) | 0x37),
+        (1, 0b100) => match (inst >> 10) & 3 {
+            // C.SRLI, C.SRAI (shift amounts >= 32 are reserved on RV32)
+            0b00 if inst & 0x1000 == 0 => Some(enc_r(0, rs2, rs1_p, 0b101, rs1_p, 0x13)),
+            0b01 if inst & 0x1000 == 0 => Some(enc_r(0x20, rs2, rs1_p, 0b101, rs1_p, 0x13)),
+            // C.ANDI
+            0b10 => Some(enc_i(imm6, rs1_p, 0b111, rs1_p, 0x13)),
+            // C.SUB, C.XOR, C.OR, C.AND
+            0b11 if inst & 0x1000 == 0 => {
+                let (funct7, funct3) = match (inst >> 5) & 3 {
+                    0b00 => (0x20, 0b000),
+                    0b01 => (0, 0b100),
+                    0b10 => (0, 0b110),
+                    _ => (0, 0b111),
+                };
+                Some(enc_r(funct7, rd_p, rs1_p, funct3, rs1_p, 0x33))
+            }
+            _ => None,
+        },
+        // C.J
+        (1, 0b101) => Some(enc_j(cj_imm, 0)),
+        // C.BEQZ, C.BNEZ
+        (1, 0b110) => Some(enc_b(cb_imm, 0, rs1_p, 0b000)),
+        (1, 0b111) => Some(enc_b(cb_imm, 0, rs1_p, 0b001)),
+
+        // C.SLLI
+        (2, 0b000) if inst & 0x1000 == 0 => Some(enc_r(0, rs2, rd, 0b001, rd, 0x13)),
+        // C.LWSP
+        (2, 0b010) if rd != 0 => {
+            let imm = ((inst >> 7) & 0x20) | ((inst >> 2) & 0x1c) | ((inst << 4) & 0xc0);
+            Some(enc_i(imm, REG_SP as u32, 0b010, rd, 0x03))
+        }
+        (2, 0b100) => match (inst & 0x1000 != 0, rd, rs2) {
+            // C.JR
+            (false, 1.., 0) => Some(enc_i(0, rd, 0b000, 0, 0x67)),
+            // C.MV
+            (false, _, _) => Some(enc_r(0, rs2, 0, 0b000, rd, 0x33)),
+            // C.EBREAK
+            (true, 0, 0) => Some(0x00100073),
+            // C.JALR
+            (true, _, 0) => Some(enc_i(0, rd, 0b000, REG_RA as u32, 0x67)),
+            // C.ADD
+            (true, _, _) => Some(enc_r(0, rs2, rd, 0b000, rd, 0x33)),
+        },
+        // C.SWSP
+        (2, 0b110) => {
+            let imm = ((inst >> 7) & 0x3c) | ((inst >> 1) & 0xc0);
+            Some(enc_s(imm, rs2, REG_SP as u32, 0b010))
+        }
+
+        // Floating-point loads and stores, and reserved encodings:
+        _ => None,
+    }
+}
+
+// ---------- Runtime ----------------------------------------------------------
+
+#[derive(Debug, Clone, Copy)]
+struct Rv32iEmuCpu {
+    pc: u32,
+    x: [u32; 32],
+}
+
+#[derive(Debug, Default)]
+struct Rv32iEmuArgs {
+    regs: [u32; 8],
+    stack: Vec<u32>,
+}
+
+/// Pointers to this type are addresses of callback trampolines in the foreign
+/// address space. These addresses lie outside of the emulated memory region.
+pub enum Rv32iEmuRtCallbackTrampolineFn {}
+
+#[derive(Debug, Clone)]
+pub struct Rv32iEmuRtCallbackContext {
+    pub arg_regs: [usize; 8],
+    stack_pointer: *mut c_void,
+}
+
+impl CallbackContext for Rv32iEmuRtCallbackContext {
+    fn get_argument_register(&self, reg: usize) -> Option<usize> {
+        self.arg_regs.get(reg).copied()
+    }
+
+    fn get_stack_pointer(&self) -> *mut c_void {
+        self.stack_pointer
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rv32iEmuRtCallbackReturn {
+    pub return_regs: [usize; 2],
+}
+
+impl CallbackReturn for Rv32iEmuRtCallbackReturn {
+    fn set_return_register(&mut self, reg: usize, value: usize) -> bool {
+        if let Some(r) = self.return_regs.get_mut(reg) {
+            *r = value;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Rv32iEmuRtCallbackDescriptor<'a> {
+    wrapper: unsafe extern "C" fn(
+        *mut c_void,
+        &Rv32iEmuRtCallbackContext,
+        &mut Rv32iEmuRtCallbackReturn,
+        *mut (),
+        *mut (),
+    ),
+    context: *mut c_void,
+    _lt: PhantomData<&'a mut c_void>,
+}
+
+impl Rv32iEmuRtCallbackDescriptor<'_> {
+    unsafe fn invoke(
+        &self,
+        callback_ctx: &Rv32iEmuRtCallbackContext,
+        callback_ret: &mut Rv32iEmuRtCallbackReturn,
+        alloc_scope: *mut (),
+        access_scope: *mut (),
+    ) {
+        unsafe {
+            (self.wrapper)(
+                self.context,
+                callback_ctx,
+                callback_ret,
+                alloc_scope,
+                access_scope,
+            )
+        }
+    }
+}
+
+/// Allocation tracker of the [`Rv32iEmuRt`].
+///
+/// Foreign code can access the entire emulated memory region, so upgrades are
+/// validated against its bounds only. The remaining elements of this list
+/// track the callbacks currently available to foreign code.
+#[derive(Debug)]
+pub enum Rv32iEmuRtAllocChain<'a> {
+    Memory {
+        start: usize,
+        len: usize,
+    },
+    Callback(
+        usize,
+        Rv32iEmuRtCallbackDescriptor<'a>,
+        &'a Rv32iEmuRtAllocChain<'a>,
+    ),
+    Cons(&'a Rv32iEmuRtAllocChain<'a>),
+}
+
+struct Rv32iEmuRtAllocChainIter<'a>(Option<&'a Rv32iEmuRtAllocChain<'a>>);
+
+impl<'a> Iterator for Rv32iEmuRtAllocChainIter<'a> {
+    type Item = &'a Rv32iEmuRtAllocChain<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(cur) = self.0 {
+            self.0 = match cur {
+                Rv32iEmuRtAllocChain::Memory { .. } => None,
+                Rv32iEmuRtAllocChain::Callback(_, _, pred) => Some(pred),
+                Rv32iEmuRtAllocChain::Cons(pred) => Some(pred),
+            };
+
+            Some(cur)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Rv32iEmuRtAllocChain<'a> {
+    fn iter(&'a self) -> Rv32iEmuRtAllocChainIter<'a> {
+        Rv32iEmuRtAllocChainIter(Some(self))
+    }
+
+    fn is_valid_int(&self, ptr: usize, len: usize) -> bool {
+        self.iter().any(|elem| match elem {
+            Rv32iEmuRtAllocChain::Memory {
+                start,
+                len: memory_len,
+            } => {
+                ptr >= *start
+                    && ptr
+                        .checked_add(len)
+                        .is_some_and(|end| end <= start + memory_len)
+            }
+            Rv32iEmuRtAllocChain::Callback(_, _, _) => false,
+            Rv32iEmuRtAllocChain::Cons(_) => false,
+        })
+    }
+
+    fn next_callback_id(&self) -> usize {
+        self.iter()
+            .find_map(|elem| match elem {
+                Rv32iEmuRtAllocChain::Memory { .. } => None,
+                Rv32iEmuRtAllocChain::Callback(id, _, _) => Some(id + 1),
+                Rv32iEmuRtAllocChain::Cons(_) => None,
+            })
+            .unwrap_or(0)
+    }
+
+    fn find_callback_descriptor(&self, id: usize) -> Option<&Rv32iEmuRtCallbackDescriptor<'_>> {
+        self.iter().find_map(|elem| match elem {
+            Rv32iEmuRtAllocChain::Memory { .. } => None,
+            Rv32iEmuRtAllocChain::Callback(desc_id, desc, _) => {
+                if id == *desc_id {
+                    Some(desc)
+                } else {
+                    None
+                }
+            }
+            Rv32iEmuRtAllocChain::Cons(_) => None,
+        })
+    }
+}
+
+unsafe impl AllocTracker for Rv32iEmuRtAllocChain<'_> {
+    fn is_valid(&self, ptr: *const (), len: usize) -> bool {
+        self.is_valid_int(ptr as usize, len)
+    }
+
+    fn is_valid_mut(&self, ptr: *mut (), len: usize) -> bool {
+        // The emulator does not implement any memory protection, so all of its
+        // memory is mutable by foreign code:
+        self.is_valid_int(ptr as usize, len)
+    }
+}
+
+pub struct Rv32iEmuRtSymbolTableState<
+    const SYMTAB_SIZE: usize,
+    const FIXED_OFFSET_SYMTAB_SIZE: usize,
+> {
+    symbols: [*const (); SYMTAB_SIZE],
+    fixed_offset_symbols: [Option<*const ()>; FIXED_OFFSET_SYMTAB_SIZE],
+}
+
+pub struct Rv32iEmuRt<ID: OGID> {
+    id_imprint: ID::Imprint,
+    memory_base: *mut u8,
+    memory_len: usize,
+    // Foreign address of the start of the emulated memory region:
+    guest_base: u32,
+    // Lowest foreign address that the foreign stack may grow to:
+    stack_low: u32,
+    symbols: BTreeMap<CString, u32>,
+    cpu: UnsafeCell<Rv32iEmuCpu>,
+    args: RefCell<Rv32iEmuArgs>,
+    // State of the current `execute` call. These are nested by callbacks
+    // executing further foreign functions:
+    target: Cell<*const ()>,
+    active_alloc_chain: Cell<*const Rv32iEmuRtAllocChain<'static>>,
+    ret: Cell<[u32; 2]>,
+    // Deadline of the current `execute_with_deadline` call, if any, and whether
+    // the interpreter has been stopped as it expired:
+    deadline: Cell<Option<Instant>>,
+    timed_out: Cell<bool>,
+}
+
+impl<ID: OGID> Rv32iEmuRt<ID> {
+    /// Load the statically linked RV32I(MC) ELF executable `elf` into an
+    /// emulated memory region of `memory_size` bytes.
+    ///
+    /// The memory region starts at the page containing the executable's
+    /// lowest loaded segment. The top `stack_size` bytes of this region are
+    /// reserved for the foreign stack, and must not overlap with any of the
+    /// executable's segments.
+    pub fn new(
+        elf: &[u8],
+        memory_size: usize,
+        stack_size: usize,
+        branding: ID,
+    ) -> OGResult<(
+        Self,
+        AllocScope<'static, Rv32iEmuRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
+    )> {
+        let segments = elf_segments(elf).ok_or(OGError::LibraryLoadFailed)?;
+        let symbols = elf_symbols(elf).ok_or(OGError::LibraryLoadFailed)?;
+
+        let guest_base = segments
+            .iter()
+            .map(|segment| segment.vaddr & !0xfff)
+            .min()
+            .ok_or(OGError::LibraryLoadFailed)?;
+
+        // Callback trampolines are placed directly above the memory region, so
+        // keep some of the address space free for them:
+        let guest_end = u32::try_from(memory_size)
+            .ok()
+            .and_then(|size| guest_base.checked_add(size))
+            .filter(|end| *end <= u32::MAX - 0xffff)
+            .ok_or(OGError::AllocInvalidLayout)?;
+        let stack_top = guest_end & !0xf;
+        let stack_low = u32::try_from(stack_size)
+            .ok()
+            .and_then(|size| stack_top.checked_sub(size))
+            .filter(|low| *low >= guest_base)
+            .ok_or(OGError::AllocNoMem)?;
+
+        // Foreign libraries are never unloaded, as references into their
+        // memory may outlive the runtime. We thus leak this allocation:
+        let memory = Box::leak(vec![0_u8; memory_size].into_boxed_slice());
+
+        for segment in segments.iter() {
+            let start = (segment.vaddr - guest_base) as usize;
+            if start
+                .checked_add(segment.memsz as usize)
+                .is_none_or(|end| end > (stack_low - guest_base) as usize)
+            {
+                return Err(OGError::LibraryLoadFailed);
+            }
+
+            memory[start..start + segment.data.len()].copy_from_slice(segment.data);
+        }
+
+        let mut cpu = Rv32iEmuCpu { pc: 0, x: [0; 32] };
+        cpu.x[REG_SP] = stack_top;
+        if let Some(gp) = symbols.get(c"__global_pointer$") {
+            cpu.x[REG_GP] = *gp;
+        }
+
+        let memory_base = memory.as_mut_ptr();
+        let id_imprint = branding.get_imprint();
+
+        Ok((
+            Rv32iEmuRt {
+                id_imprint,
+                memory_base,
+                memory_len: memory_size,
+                guest_base,
+                stack_low,
+                symbols,
+                cpu: UnsafeCell::new(cpu),
+                args: RefCell::new(Rv32iEmuArgs::default()),
+                target: Cell::new(core::ptr::null()),
+                active_alloc_chain: Cell::new(core::ptr::null()),
+                ret: Cell::new([0; 2]),
+                deadline: Cell::new(None),
+                timed_out: Cell::new(false),
+            },
+            unsafe {
+                AllocScope::new(
+                    Rv32iEmuRtAllocChain::Memory {
+                        start: memory_base as usize,
+                        len: memory_size,
+                    },
+                    id_imprint,
+                )
+            },
+            unsafe { AccessScope::new(id_imprint) },
+        ))
+    }
+
+    /// Translate a host pointer into the foreign domain into a foreign
+    /// address.
+    pub fn host_to_foreign_ptr(&self, ptr: *const ()) -> Option<u32> {
+        (ptr as usize)
+            .checked_sub(self.memory_base as usize)
+            .filter(|offset| *offset <= self.memory_len)
+            .map(|offset| self.guest_base + offset as u32)
+    }
+
+    /// Translate a foreign address into a host pointer.
+    ///
+    /// The returned pointer is not guaranteed to be valid, and must be upgraded
+    /// before it can be dereferenced.
+    pub fn foreign_to_host_ptr(&self, addr: u32) -> *mut () {
+        self.memory_base
+            .wrapping_add(addr.wrapping_sub(self.guest_base) as usize) as *mut ()
+    }
+
+    /// Place `value` into the argument slot `S` for the next call to
+    /// [`Rv32iEmuRt::invoke`].
+    pub fn set_argument<S: ArgumentSlot>(&self, value: u32) -> OGResult<()> {
+        let mut args = self.args.borrow_mut();
+
+        if S::IS_REG {
+            *args
+                .regs
+                .get_mut(rv32i_c_areg_index::<S>())
+                .ok_or(OGError::InternalError)? = value;
+        } else if S::IS_STACKED {
+            if args.stack.len() <= S::STACK_OFFSET_WORDS {
+                args.stack.resize(S::STACK_OFFSET_WORDS + 1, 0);
+            }
+            args.stack[S::STACK_OFFSET_WORDS] = value;
+        } else {
+            return Err(OGError::InternalError);
+        }
+
+        Ok(())
+    }
+
+    /// Call the function passed as `target_symbol` to the enclosing
+    /// [`OGRuntime::execute`], with the arguments placed through
+    /// [`Rv32iEmuRt::set_argument`].
+    ///
+    /// Stacked arguments are passed at the stack pointer on entry to the
+    /// function. The contents of the return registers are retained in the
+    /// runtime, and can be retrieved through
+    /// [`Rv32iCInvokeRes::into_result_registers`].
+    ///
+    /// The function may be variadic, with its arguments placed as assigned by
+    /// [`RiscVArgAllocator::ilp32`](crate::abi::riscv::RiscVArgAllocator::ilp32).
+    pub fn invoke(&self) -> OGResult<()> {
+        let args = core::mem::take(&mut *self.args.borrow_mut());
+        let target = self
+            .host_to_foreign_ptr(self.target.get())
+            .ok_or(OGError::InternalError)?;
+
+        // Preserve the state of any foreign function that is currently
+        // calling back into the host:
+        let outer_cpu = unsafe { *self.cpu.get() };
+
+        let sp = u32::try_from(args.stack.len() * 4)
+            .ok()
+            .and_then(|size| outer_cpu.x[REG_SP].checked_sub(size))
+            .map(|sp| sp & !0xf)
+            .filter(|sp| *sp >= self.stack_low)
+            .ok_or(OGError::AllocNoMem)?;
+        for (i, word) in args.stack.iter().enumerate() {
+            self.store(sp + 4 * i as u32, &word.to_le_bytes())?;
+        }
+
+        {
+            let cpu = unsafe { &mut *self.cpu.get() };
+            cpu.x[REG_A0..REG_A0 + 8].copy_from_slice(&args.regs);
+            cpu.x[REG_SP] = sp;
+            cpu.x[REG_RA] = self.return_addr();
+            cpu.pc = target;
+        }
+
+        let res = self.run();
+
+        let cpu = unsafe { &mut *self.cpu.get() };
+        self.ret.set([cpu.x[REG_A0], cpu.x[REG_A0 + 1]]);
+        *cpu = outer_cpu;
+
+        res
+    }
+
+    // Returning to this address stops the interpreter:
+    fn return_addr(&self) -> u32 {
+        self.guest_base + self.memory_len as u32
+    }
+
+    fn callback_addr(&self, callback_id: usize) -> Option<u32> {
+        u32::try_from(callback_id)
+            .ok()
+            .and_then(|id| id.checked_add(1))
+            .and_then(|slot| slot.checked_mul(4))
+            .and_then(|offset| self.return_addr().checked_add(offset))
+    }
+
+    fn load<const N: usize>(&self, addr: u32) -> OGResult<[u8; N]> {
+        let offset = addr.wrapping_sub(self.guest_base) as usize;
+        if offset
+            .checked_add(N)
+            .is_none_or(|end| end > self.memory_len)
+        {
+            return Err(OGError::ForeignTrap);
+        }
+
+        Ok(unsafe { core::ptr::read_unaligned(self.memory_base.add(offset) as *const [u8; N]) })
+    }
+
+    fn store(&self, addr: u32, bytes: &[u8]) -> OGResult<()> {
+        let offset = addr.wrapping_sub(self.guest_base) as usize;
+        if offset
+            .checked_add(bytes.len())
+            .is_none_or(|end| end > self.memory_len)
+        {
+            return Err(OGError::ForeignTrap);
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                self.memory_base.add(offset),
+                bytes.len(),
+            )
+        };
+        Ok(())
+    }
+
+    fn run(&self) -> OGResult<()> {
+        let return_addr = self.return_addr();
+
+        let mut steps: usize = 0;
+        loop {
+            // Checking the clock is expensive, so only do so periodically:
+            steps = steps.wrapping_add(1);
+            if steps.is_multiple_of(4096)
+                && self
+                    .deadline
+                    .get()
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                self.timed_out.set(true);
+                return Err(OGError::Timeout);
+            }
+
+            let pc = unsafe { (*self.cpu.get()).pc };
+
+            if pc == return_addr {
+                return Ok(());
+            } else if pc > return_addr {
+                // Callbacks may nest further calls into foreign code, so don't
+                // hold a reference to the CPU state across them:
+                self.dispatch_callback(((pc - return_addr) / 4 - 1) as usize)?;
+            } else {
+                self.step()?;
+            }
+        }
+    }
+
+    fn step(&self) -> OGResult<()> {
+        let cpu = unsafe { &mut *self.cpu.get() };
+        let pc = cpu.pc;
+
+        let half = u16::from_le_bytes(self.load::<2>(pc)?) as u32;
+        let (inst, len) = if half & 3 == 3 {
+            (u32::from_le_bytes(self.load::<4>(pc)?), 4)
+        } else {
+            (expand_compressed(half).ok_or(OGError::ForeignTrap)?, 2)
+        };
+
+        let opcode = inst & 0x7f;
+        let rd = ((inst >> 7) & 0x1f) as usize;
+        let funct3 = (inst >> 12) & 7;
+        let rs1 = cpu.x[((inst >> 15) & 0x1f) as usize];
+        let rs2 = cpu.x[((inst >> 20) & 0x1f) as usize];
+        let funct7 = inst >> 25;
+
+        let imm_i = sext(inst >> 20, 12);
+        let imm_s = sext(((inst >> 20) & 0xfe0) | ((inst >> 7) & 0x1f), 12);
+        let imm_b = sext(
+            ((inst >> 19) & 0x1000)
+                | ((inst << 4) & 0x800)
+                | ((inst >> 20) & 0x7e0)
+                | ((inst >> 7) & 0x1e),
+            13,
+        );
+        let imm_j = sext(
+            ((inst >> 11) & 0x100000)
+                | (inst & 0xff000)
+                | ((inst >> 9) & 0x800)
+                | ((inst >> 20) & 0x7fe),
+            21,
+        );
+
+        let mut next_pc = pc.wrapping_add(len);
+        let result = match opcode {
+            // LUI, AUIPC
+            0x37 => Some(inst & 0xfffff000),
+            0x17 => Some(pc.wrapping_add(inst & 0xfffff000)),
+
+            // JAL, JALR
+            0x6f => {
+                next_pc = pc.wrapping_add(imm_j);
+                Some(pc.wrapping_add(len))
+            }
+            0x67 if funct3 == 0 => {
+                next_pc = rs1.wrapping_add(imm_i) & !1;
+                Some(pc.wrapping_add(len))
+            }
+
+            // BEQ, BNE, BLT, BGE, BLTU, BGEU
+            0x63 => {
+                let taken = match funct3 {
+                    0b000 => rs1 == rs2,
+                    0b001 => rs1 != rs2,
+                    0b100 => (rs1 as i32) < (rs2 as i32),
+                    0b101 => (rs1 as i32) >= (rs2 as i32),
+                    0b110 => rs1 < rs2,
+                    0b111 => rs1 >= rs2,
+                    _ => return Err(OGError::ForeignTrap),
+                };
+                if taken {
+                    next_pc = pc.wrapping_add(imm_b);
+                }
+                None
+            }
+
+            // LB, LH, LW, LBU, LHU
+            0x03 => {
+                let addr = rs1.wrapping_add(imm_i);
+                Some(match funct3 {
+                    0b000 => self.load::<1>(addr)?[0] as i8 as u32,
+                    0b001 => i16::from_le_bytes(self.load::<2>(addr)?) as u32,
+                    0b010 => u32::from_le_bytes(self.load::<4>(addr)?),
+                    0b100 => self.load::<1>(addr)?[0] as u32,
+                    0b101 => u16::from_le_bytes(self.load::<2>(addr)?) as u32,
+                    _ => return Err(OGError::ForeignTrap),
+                })
+            }
+
+            // SB, SH, SW
+            0x23 => {
+                let addr = rs1.wrapping_add(imm_s);
+                match funct3 {
+                    0b000 => self.store(addr, &rs2.to_le_bytes()[..1])?,
+                    0b001 => self.store(addr, &rs2.to_le_bytes()[..2])?,
+                    0b010 => self.store(addr, &rs2.to_le_bytes())?,
+                    _ => return Err(OGError::ForeignTrap),
+                }
+                None
+            }
+
+            // ADDI, SLTI, SLTIU, XORI, ORI, ANDI, SLLI, SRLI, SRAI
+            0x13 => Some(match (funct3, funct7) {
+                (0b000, _) => rs1.wrapping_add(imm_i),
+                (0b010, _) => ((rs1 as i32) < (imm_i as i32)) as u32,
+                (0b011, _) => (rs1 < imm_i) as u32,
+                (0b100, _) => rs1 ^ imm_i,
+                (0b110, _) => rs1 | imm_i,
+                (0b111, _) => rs1 & imm_i,
+                (0b001, 0x00) => rs1 << (imm_i & 0x1f),
+                (0b101, 0x00) => rs1 >> (imm_i & 0x1f),
+                (0b101, 0x20) => ((rs1 as i32) >> (imm_i & 0x1f)) as u32,
+                _ => return Err(OGError::ForeignTrap),
+            }),
+
+            // Integer register-register operations, including the M extension
+            0x33 => Some(match (funct7, funct3) {
+                (0x00, 0b000) => rs1.wrapping_add(rs2),
+                (0x20, 0b000) => rs1.wrapping_sub(rs2),
+                (0x00, 0b001) => rs1 << (rs2 & 0x1f),
+                (0x00, 0b010) => ((rs1 as i32) < (rs2 as i32)) as u32,
+                (0x00, 0b011) => (rs1 < rs2) as u32,
+                (0x00, 0b100) => rs1 ^ rs2,
+                (0x00, 0b101) => rs1 >> (rs2 & 0x1f),
+                (0x20, 0b101) => ((rs1 as i32) >> (rs2 & 0x1f)) as u32,
+                (0x00, 0b110) => rs1 | rs2,
+                (0x00, 0b111) => rs1 & rs2,
+
+                (0x01, 0b000) => rs1.wrapping_mul(rs2),
+                (0x01, 0b001) => ((rs1 as i32 as i64 * rs2 as i32 as i64) >> 32) as u32,
+                (0x01, 0b010) => ((rs1 as i32 as i64 * rs2 as i64) >> 32) as u32,
+                (0x01, 0b011) => ((rs1 as u64 * rs2 as u64) >> 32) as u32,
+                // Division by zero does not trap, but produces defined results:
+                (0x01, 0b100) if rs2 == 0 => u32::MAX,
+                (0x01, 0b100) => (rs1 as i32).wrapping_div(rs2 as i32) as u32,
+                (0x01, 0b101) => rs1.checked_div(rs2).unwrap_or(u32::MAX),
+                (0x01, 0b110) if rs2 == 0 => rs1,
+                (0x01, 0b110) => (rs1 as i32).wrapping_rem(rs2 as i32) as u32,
+                (0x01, 0b111) => rs1.checked_rem(rs2).unwrap_or(rs1),
+                _ => return Err(OGError::ForeignTrap),
+            }),
+
+            // FENCE, FENCE.I
+            0x0f => None,
+
+            // ECALL, EBREAK, CSR accesses and all other opcodes:
+            _ => return Err(OGError::ForeignTrap),
+        };
+
+        if let Some(value) = result
+            && rd != 0
+        {
+            cpu.x[rd] = value;
+        }
+        cpu.pc = next_pc;
+
+        Ok(())
+    }
+
+    fn setup_callback_int<'a, C, F, R>(
+        &self,
+        callback: &'a mut C,
+        alloc_scope: &mut AllocScope<
+            '_,
+            <Self as OGRuntime>::AllocTracker<'_>,
+            <Self as OGRuntime>::ID,
+        >,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &<Self as OGRuntime>::CallbackContext,
+            &mut <Self as OGRuntime>::CallbackReturn,
+            *mut (),
+            *mut (),
+        ),
+        F: for<'b> FnOnce(
+            *const <Self as OGRuntime>::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, <Self as OGRuntime>::ID>,
+        ) -> R,
+    {
+        self.id_imprint_check(Some(alloc_scope), None)?;
+
+        struct Context<'a, ClosureTy> {
+            closure: &'a mut ClosureTy,
+        }
+
+        unsafe extern "C" fn callback_wrapper<
+            'a,
+            ClosureTy: FnMut(&Rv32iEmuRtCallbackContext, &mut Rv32iEmuRtCallbackReturn, *mut (), *mut ()) + 'a,
+        >(
+            ctx_ptr: *mut c_void,
+            callback_ctx: &Rv32iEmuRtCallbackContext,
+            callback_ret: &mut Rv32iEmuRtCallbackReturn,
+            alloc_scope: *mut (),
+            access_scope: *mut (),
+        ) {
+            let ctx: &mut Context<'a, ClosureTy> =
+                unsafe { &mut *(ctx_ptr as *mut Context<'a, ClosureTy>) };
+
+            // For now, we assume that the function doesn't unwind:
+            (ctx.closure)(callback_ctx, callback_ret, alloc_scope, access_scope)
+        }
+
+        let callback_id = alloc_scope.tracker().next_callback_id();
+        let callback_addr = self
+            .callback_addr(callback_id)
+            .ok_or(OGError::SetupCallbackInsufficientSlots)?;
+
+        let mut ctx: Context<'a, C> = Context { closure: callback };
+
+        let mut inner_alloc_scope = unsafe {
+            AllocScope::new(
+                Rv32iEmuRtAllocChain::Callback(
+                    callback_id,
+                    Rv32iEmuRtCallbackDescriptor {
+                        wrapper: callback_wrapper::<C>,
+                        context: &mut ctx as *mut _ as *mut c_void,
+                        _lt: PhantomData::<&'a mut c_void>,
+                    },
+                    alloc_scope.tracker(),
+                ),
+                alloc_scope.id_imprint(),
+            )
+        };
+
+        Ok(fun(
+            callback_addr as usize as *const Rv32iEmuRtCallbackTrampolineFn,
+            &mut inner_alloc_scope,
+        ))
+    }
+
+    fn dispatch_callback(&self, callback_id: usize) -> OGResult<()> {
+        let alloc_chain =
+            unsafe { self.active_alloc_chain.get().as_ref() }.ok_or(OGError::ForeignTrap)?;
+        // Calls to trampolines of callbacks that are no longer in scope trap,
+        // just like calls to any other unmapped address:
+        let callback_desc = alloc_chain
+            .find_callback_descriptor(callback_id)
+            .ok_or(OGError::ForeignTrap)?;
+
+        let cpu = unsafe { *self.cpu.get() };
+        let mut callback_ctx = Rv32iEmuRtCallbackContext {
+            arg_regs: [0; 8],
+            stack_pointer: self.foreign_to_host_ptr(cpu.x[REG_SP]) as *mut c_void,
+        };
+        for (dst, src) in callback_ctx
+            .arg_regs
+            .iter_mut()
+            .zip(cpu.x[REG_A0..REG_A0 + 8].iter())
+        {
+            *dst = *src as usize;
+        }
+
+        let mut callback_ret = Rv32iEmuRtCallbackReturn {
+            return_regs: [0; 2],
+        };
+
+        let mut inner_alloc_scope: AllocScope<'_, Rv32iEmuRtAllocChain<'_>, ID> =
+            unsafe { AllocScope::new(Rv32iEmuRtAllocChain::Cons(alloc_chain), self.id_imprint) };
+
+        unsafe {
+            callback_desc.invoke(
+                &callback_ctx,
+                &mut callback_ret,
+                &mut inner_alloc_scope as *mut _ as *mut (),
+                // Safe, as the only existing AccessScope<ID> is already
+                // borrowed by the `execute` call that is running foreign code.
+                &mut AccessScope::<ID>::new(self.id_imprint) as *mut _ as *mut (),
+            )
+        };
+
+        // Return to the caller of the trampoline:
+        let cpu = unsafe { &mut *self.cpu.get() };
+        cpu.x[REG_A0] = callback_ret.return_regs[0] as u32;
+        cpu.x[REG_A0 + 1] = callback_ret.return_regs[1] as u32;
+        cpu.pc = cpu.x[REG_RA];
+
+        Ok(())
+    }
+
+    fn execute_int<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, ID>,
+        access_scope: &mut AccessScope<ID>,
+        timeout: Option<core::time::Duration>,
+        f: F,
+    ) -> OGResult<R> {
+        self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
+
+        // Picked up by `invoke` and the callback dispatcher. Callbacks may
+        // execute further foreign functions, so restore the previous values
+        // afterwards. Nested calls must also complete before the deadline of
+        // the outer call:
+        let outer_target = self.target.replace(target_symbol);
+        let outer_alloc_chain = self
+            .active_alloc_chain
+            .replace((alloc_scope.tracker() as *const Rv32iEmuRtAllocChain<'_>).cast());
+        let outer_deadline = self.deadline.get();
+        if let Some(timeout) = timeout {
+            let deadline = Instant::now() + timeout;
+            self.deadline.set(Some(
+                outer_deadline.map_or(deadline, |outer| outer.min(deadline)),
+            ));
+        }
+
+        let res = f();
+
+        self.target.set(outer_target);
+        self.active_alloc_chain.set(outer_alloc_chain);
+        self.deadline.set(outer_deadline);
+
+        // Once no deadline remains, further calls may run to completion:
+        let timed_out = self.timed_out.get();
+        if outer_deadline.is_none() {
+            self.timed_out.set(false);
+        }
+
+        if timed_out {
+            // The interpreter was stopped at an arbitrary point, which may have
+            // left the foreign memory in an inconsistent state:
+            access_scope.poison();
+            Err(OGError::Timeout)
+        } else {
+            Ok(res)
+        }
+    }
+
+    #[inline]
+    fn id_imprint_check(
+        &self,
+        alloc_scope: Option<
+            &mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, <Self as OGRuntime>::ID>,
+        >,
+        access_scope: Option<&mut AccessScope<<Self as OGRuntime>::ID>>,
+    ) -> OGResult<()> {
+        if alloc_scope.is_some_and(|s| self.id_imprint != s.id_imprint())
+            || access_scope.is_some_and(|s| self.id_imprint != s.id_imprint())
+        {
+            Err(OGError::IDMismatch)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub struct Rv32iEmuRtInvokeRes<T>(PhantomData<T>);
+
+unsafe impl<ID: OGID, T> Rv32iCInvokeRes<Rv32iEmuRt<ID>, T> for Rv32iEmuRtInvokeRes<T> {
+    fn new() -> Self {
+        Rv32iEmuRtInvokeRes(PhantomData)
+    }
+
+    fn into_result_registers(self, rt: &Rv32iEmuRt<ID>) -> OGResult<OGRet<T>> {
+        // Larger values (including 128-bit integers) must be returned through a
+        // stacked allocation:
+        OGRet::from_register_pair(rt.ret.get()).ok_or(OGError::InternalError)
+    }
+
+    unsafe fn into_result_stacked(
+        self,
+        _rt: &Rv32iEmuRt<ID>,
+        stacked_res: *mut T,
+    ) -> OGResult<OGRet<T>> {
+        Ok(OGRet::from_initialized_memory(unsafe {
+            core::ptr::read(stacked_res as *const MaybeValid<T>)
+        }))
+    }
+}
+
+impl<ID: OGID> Rv32iCBaseRt for Rv32iEmuRt<ID> {
+    type InvokeRes<T> = Rv32iEmuRtInvokeRes<T>;
+}
+
+unsafe impl<ID: OGID> OGRuntime for Rv32iEmuRt<ID> {
+    type ID = ID;
+    type AllocTracker<'a> = Rv32iEmuRtAllocChain<'a>;
+    type ABI = Rv32iCABI;
+    type CallbackTrampolineFn = Rv32iEmuRtCallbackTrampolineFn;
+    type CallbackContext = Rv32iEmuRtCallbackContext;
+    type CallbackReturn = Rv32iEmuRtCallbackReturn;
+
+    type SymbolTableState<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> =
+        Rv32iEmuRtSymbolTableState<SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>;
+
+    fn resolve_symbols<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        symbol_table: &'a [&'a CStr; SYMTAB_SIZE],
+        fixed_offset_symbol_table: &'a [Option<&'a CStr>; FIXED_OFFSET_SYMTAB_SIZE],
+    ) -> Result<
+        Self::SymbolTableState<'a, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+        Option<&'a core::ffi::CStr>,
+    > {
+        // Symbols are resolved to host pointers into the emulated memory, so
+        // that bindings can access foreign statics:
+        let resolve = |name: &'a CStr| {
+            self.symbols
+                .get(name)
+                .map(|addr| self.foreign_to_host_ptr(*addr) as *const ())
+                .ok_or(Some(name))
+        };
+
+        let mut symbols = [core::ptr::null(); SYMTAB_SIZE];
+        for (dst, name) in symbols.iter_mut().zip(symbol_table.iter()) {
+            *dst = resolve(name)?;
+        }
+
+        let mut fixed_offset_symbols = [None; FIXED_OFFSET_SYMTAB_SIZE];
+        for (dst, name_opt) in fixed_offset_symbols
+            .iter_mut()
+            .zip(fixed_offset_symbol_table.iter())
+        {
+            if let Some(name) = name_opt {
+                *dst = Some(resolve(name)?);
+            }
+        }
+
+        Ok(Rv32iEmuRtSymbolTableState {
+            symbols,
+            fixed_offset_symbols,
+        })
+    }
+
+    fn lookup_symbol<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        compact_symtab_index: usize,
+        fixed_offset_symtab_index: usize,
+        symtabstate: &Self::SymbolTableState<'_, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+    ) -> Option<*const ()> {
+        symtabstate
+            .symbols
+            .get(compact_symtab_index)
+            .copied()
+            .or_else(|| {
+                symtabstate
+                    .fixed_offset_symbols
+                    .get(fixed_offset_symtab_index)
+                    .copied()
+                    .flatten()
+            })
+    }
+
+    fn setup_callback<C, F, R>(
+        &self,
+        callback: &mut C,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &Self::CallbackContext,
+            &mut Self::CallbackReturn,
+            &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+            &mut AccessScope<Self::ID>,
+        ),
+        F: for<'b> FnOnce(
+            *const Self::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        ) -> R,
+    {
+        let typecast_callback =
+            &mut |callback_ctx: &Rv32iEmuRtCallbackContext,
+                  callback_ret: &mut Rv32iEmuRtCallbackReturn,
+                  alloc_scope_ptr: *mut (),
+                  access_scope_ptr: *mut ()| {
+                let alloc_scope = unsafe {
+                    &mut *(alloc_scope_ptr as *mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>)
+                };
+
+                let access_scope =
+                    unsafe { &mut *(access_scope_ptr as *mut AccessScope<Self::ID>) };
+
+                callback(callback_ctx, callback_ret, alloc_scope, access_scope);
+            };
+
+        // See `MockRt::setup_callback` on why we erase the scopes' types here:
+        self.setup_callback_int(typecast_callback, alloc_scope, fun)
+    }
+
+    fn execute<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        f: F,
+    ) -> OGResult<R> {
+        self.execute_int(target_symbol, alloc_scope, access_scope, None, f)
+    }
+
+    const SUPPORTS_DEADLINE: bool = true;
+
+    fn execute_with_deadline<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        timeout: core::time::Duration,
+        f: F,
+    ) -> OGResult<R> {
+        self.execute_int(target_symbol, alloc_scope, access_scope, Some(timeout), f)
+    }
+
+    fn allocate_stacked_untracked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: FnOnce(*mut ()) -> R,
+    {
+        if layout.size() == 0 {
+            return Err(OGError::AllocInvalidLayout);
+        }
+
+        // The foreign stack is kept 16-byte aligned:
+        let align = core::cmp::max(16, layout.align());
+
+        let sp = unsafe { (*self.cpu.get()).x[REG_SP] };
+        let alloc_sp = u32::try_from(layout.size())
+            .ok()
+            .and_then(|size| sp.checked_sub(size))
+            .and_then(|alloc_sp| u32::try_from(align).ok().map(|a| alloc_sp & !(a - 1)))
+            .filter(|alloc_sp| *alloc_sp >= self.stack_low)
+            .ok_or(OGError::AllocNoMem)?;
+
+        unsafe { (*self.cpu.get()).x[REG_SP] = alloc_sp };
+        let res = fun(self.foreign_to_host_ptr(alloc_sp));
+        unsafe { (*self.cpu.get()).x[REG_SP] = sp };
+
+        Ok(res)
+    }
+
+    fn allocate_stacked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: for<'b> FnOnce(*mut (), &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>) -> R,
+    {
+        self.id_imprint_check(Some(alloc_scope), None)?;
+
+        // Stacked allocations are placed in the emulated memory, and thus
+        // already valid for upgrades. There's no need to track them separately:
+        self.allocate_stacked_untracked_mut(layout, move |ptr| fun(ptr, alloc_scope))
+    }
+}
+
+#[cfg(all(test, feature = "runtime_id"))]
+mod tests {
+    use super::*;
+    use crate::abi::calling_convention::{
+        AREG0, AREG1, AREG2, AREG3, AREG4, AREG5, AREG6, AREG7, Stacked,
+    };
+    use crate::id::runtime::OGRuntimeBranding;
+
+    const TEXT_BASE: u32 = 0x10000;
+    const MEMORY_SIZE: usize = 0x10000;
+    const STACK_SIZE: usize = 0x1000;
+
+    type TestRt = Rv32iEmuRt<OGRuntimeBranding>;
+
+    const A0: usize = REG_A0;
+    const A1: usize = REG_A0 + 1;
+    const A2: usize = REG_A0 + 2;
+
+    fn put(elf: &mut [u8], offset: usize, bytes: &[u8]) {
+        elf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // Build a statically linked executable loading `text` at `TEXT_BASE`, with
+    // `symbols` defined at the given offsets into `text`:
+    fn elf(text: &[u8], symbols: &[(&str, u32)]) -> Vec<u8> {
+        let mut strtab = vec![0_u8];
+        let mut symtab = vec![0_u8; 16];
+        for (name, offset) in symbols {
+            let mut sym = [0_u8; 16];
+            put(&mut sym, 0, &(strtab.len() as u32).to_le_bytes());
+            put(&mut sym, 4, &(TEXT_BASE + offset).to_le_bytes());
+            sym[12] = STT_FUNC;
+            put(&mut sym, 14, &1_u16.to_le_bytes());
+            symtab.extend_from_slice(&sym);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+
+        // ELF header, program header, text, symbol and string tables, and
+        // section headers (null, .symtab and .strtab):
+        let text_offset = 52 + 32;
+        let symtab_offset = (text_offset + text.len()).next_multiple_of(4);
+        let strtab_offset = symtab_offset + symtab.len();
+        let shdrs_offset = (strtab_offset + strtab.len()).next_multiple_of(4);
+
+        let mut elf = vec![0_u8; shdrs_offset + 3 * 40];
+        put(&mut elf, 0, b"\x7fELF\x01\x01\x01");
+        put(&mut elf, 16, &ET_EXEC.to_le_bytes());
+        put(&mut elf, 18, &EM_RISCV.to_le_bytes());
+        put(&mut elf, 20, &1_u32.to_le_bytes());
+        put(&mut elf, 28, &52_u32.to_le_bytes());
+        put(&mut elf, 32, &(shdrs_offset as u32).to_le_bytes());
+        put(&mut elf, 40, &52_u16.to_le_bytes());
+        put(&mut elf, 42, &32_u16.to_le_bytes());
+        put(&mut elf, 44, &1_u16.to_le_bytes());
+        put(&mut elf, 46, &40_u16.to_le_bytes());
+        put(&mut elf, 48, &3_u16.to_le_bytes());
+
+        put(&mut elf, 52, &PT_LOAD.to_le_bytes());
+        put(&mut elf, 56, &(text_offset as u32).to_le_bytes());
+        put(&mut elf, 60, &TEXT_BASE.to_le_bytes());
+        put(&mut elf, 64, &TEXT_BASE.to_le_bytes());
+        put(&mut elf, 68, &(text.len() as u32).to_le_bytes());
+        put(&mut elf, 72, &(text.len() as u32).to_le_bytes());
+
+        put(&mut elf, text_offset, text);
+        put(&mut elf, symtab_offset, &symtab);
+        put(&mut elf, strtab_offset, &strtab);
+
+        let symtab_shdr = shdrs_offset + 40;
+        put(&mut elf, symtab_shdr + 4, &SHT_SYMTAB.to_le_bytes());
+        put(
+            &mut elf,
+            symtab_shdr + 16,
+            &(symtab_offset as u32).to_le_bytes(),
+        );
+        put(
+            &mut elf,
+            symtab_shdr + 20,
+            &(symtab.len() as u32).to_le_bytes(),
+        );
+        put(&mut elf, symtab_shdr + 24, &2_u32.to_le_bytes());
+        put(&mut elf, symtab_shdr + 36, &16_u32.to_le_bytes());
+
+        let strtab_shdr = shdrs_offset + 80;
+        put(&mut elf, strtab_shdr + 4, &3_u32.to_le_bytes());
+        put(
+            &mut elf,
+            strtab_shdr + 16,
+            &(strtab_offset as u32).to_le_bytes(),
+        );
+        put(
+            &mut elf,
+            strtab_shdr + 20,
+            &(strtab.len() as u32).to_le_bytes(),
+        );
+
+        elf
+    }
+
+    fn test_rt() -> TestRt {
+        Rv32iEmuRt::new(
+            &elf(&[], &[]),
+            MEMORY_SIZE,
+            STACK_SIZE,
+            OGRuntimeBranding::new(),
+        )
+        .unwrap()
+        .0
+    }
+
+    // Place `insts` at `TEXT_BASE` and step through them, starting with the
+    // registers `regs`, until control leaves them:
+    fn run(rt: &TestRt, insts: &[u32], regs: &[(usize, u32)]) -> OGResult<Rv32iEmuCpu> {
+        for (i, inst) in insts.iter().enumerate() {
+            rt.store(TEXT_BASE + 4 * i as u32, &inst.to_le_bytes())
+                .unwrap();
+        }
+
+        unsafe {
+            (*rt.cpu.get()).pc = TEXT_BASE;
+            for (reg, value) in regs {
+                (*rt.cpu.get()).x[*reg] = *value;
+            }
+        }
+
+        let text = TEXT_BASE..TEXT_BASE + 4 * insts.len() as u32;
+        while text.contains(&unsafe { (*rt.cpu.get()).pc }) {
+            rt.step()?;
+        }
+
+        Ok(unsafe { *rt.cpu.get() })
+    }
+
+    // Execute the instruction `inst` computing `a0` from `a1` and `a2`:
+    fn eval(inst: u32, a1: u32, a2: u32) -> OGResult<u32> {
+        run(&test_rt(), &[inst], &[(A1, a1), (A2, a2)]).map(|cpu| cpu.x[A0])
+    }
+
+    #[test]
+    fn test_immediates() {
+        let cpu = run(
+            &test_rt(),
+            &[
+                0xfff00513, // li a0, -1
+                0xfffff5b7, // lui a1, 0xfffff
+                0x00001617, // auipc a2, 1
+                0xfff03693, // sltiu a3, zero, -1
+                0xfff02713, // slti a4, zero, -1
+            ],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(cpu.x[A0], u32::MAX);
+        assert_eq!(cpu.x[A1], 0xfffff000);
+        assert_eq!(cpu.x[A2], TEXT_BASE + 8 + 0x1000);
+        assert_eq!(cpu.x[REG_A0 + 3], 1);
+        assert_eq!(cpu.x[REG_A0 + 4], 0);
+        assert_eq!(cpu.pc, TEXT_BASE + 20);
+    }
+
+    #[test]
+    fn test_register_operations() {
+        const SUB: u32 = 0x40c58533; // sub a0, a1, a2
+        const XOR: u32 = 0x00c5c533; // xor a0, a1, a2
+        const OR: u32 = 0x00c5e533; // or a0, a1, a2
+        const AND: u32 = 0x00c5f533; // and a0, a1, a2
+
+        assert_eq!(eval(SUB, 0, 1), Ok(u32::MAX));
+        assert_eq!(eval(XOR, 0xff00, 0x0ff0), Ok(0xf0f0));
+        assert_eq!(eval(OR, 0xff00, 0x0ff0), Ok(0xfff0));
+        assert_eq!(eval(AND, 0xff00, 0x0ff0), Ok(0x0f00));
+
+        // Writes to the zero register are discarded:
+        let cpu = run(
+            &test_rt(),
+            &[0x00c58033], // add zero, a1, a2
+            &[(A1, 1), (A2, 2)],
+        )
+        .unwrap();
+        assert_eq!(cpu.x[0], 0);
+    }
+
+    #[test]
+    fn test_shifts() {
+        const SLLI: u32 = 0x01f59513; // slli a0, a1, 31
+        const SRLI: u32 = 0x01f5d513; // srli a0, a1, 31
+        const SRAI: u32 = 0x41f5d513; // srai a0, a1, 31
+        const SLL: u32 = 0x00c59533; // sll a0, a1, a2
+        const SRL: u32 = 0x00c5d533; // srl a0, a1, a2
+        const SRA: u32 = 0x40c5d533; // sra a0, a1, a2
+
+        assert_eq!(eval(SLLI, 3, 0), Ok(0x80000000));
+        assert_eq!(eval(SRLI, 0x80000000, 0), Ok(1));
+        assert_eq!(eval(SRAI, 0x80000000, 0), Ok(u32::MAX));
+        assert_eq!(eval(SRAI, 0x7fffffff, 0), Ok(0));
+
+        // Shift amounts are taken from the low five bits of the register:
+        assert_eq!(eval(SLL, 1, 33), Ok(2));
+        assert_eq!(eval(SRL, 0x80000000, 32), Ok(0x80000000));
+        assert_eq!(eval(SRA, 0x80000000, 31), Ok(u32::MAX));
+        assert_eq!(eval(SRA, 0x80000000, 63), Ok(u32::MAX));
+    }
+
+    #[test]
+    fn test_multiplication_division() {
+        const MUL: u32 = 0x02c58533; // mul a0, a1, a2
+        const MULH: u32 = 0x02c59533; // mulh a0, a1, a2
+        const MULHSU: u32 = 0x02c5a533; // mulhsu a0, a1, a2
+        const MULHU: u32 = 0x02c5b533; // mulhu a0, a1, a2
+        const DIV: u32 = 0x02c5c533; // div a0, a1, a2
+        const DIVU: u32 = 0x02c5d533; // divu a0, a1, a2
+        const REM: u32 = 0x02c5e533; // rem a0, a1, a2
+        const REMU: u32 = 0x02c5f533; // remu a0, a1, a2
+
+        const MIN: u32 = i32::MIN as u32;
+        const NEG_1: u32 = -1_i32 as u32;
+        const NEG_7: u32 = -7_i32 as u32;
+
+        assert_eq!(eval(MUL, 0x10000, 0x10001), Ok(0x10000));
+        assert_eq!(eval(MULH, NEG_1, NEG_1), Ok(0));
+        assert_eq!(eval(MULH, MIN, MIN), Ok(0x40000000));
+        assert_eq!(eval(MULHSU, NEG_1, u32::MAX), Ok(u32::MAX));
+        assert_eq!(eval(MULHU, u32::MAX, u32::MAX), Ok(0xfffffffe));
+
+        // Signed division rounds towards zero:
+        assert_eq!(eval(DIV, NEG_7, 2), Ok(-3_i32 as u32));
+        assert_eq!(eval(REM, NEG_7, 2), Ok(NEG_1));
+        assert_eq!(eval(DIVU, NEG_7, 2), Ok(0x7ffffffc));
+        assert_eq!(eval(REMU, NEG_7, 2), Ok(1));
+
+        // Division by zero and overflow produce defined results:
+        assert_eq!(eval(DIV, 7, 0), Ok(u32::MAX));
+        assert_eq!(eval(DIVU, 7, 0), Ok(u32::MAX));
+        assert_eq!(eval(REM, 7, 0), Ok(7));
+        assert_eq!(eval(REMU, 7, 0), Ok(7));
+        assert_eq!(eval(DIV, MIN, NEG_1), Ok(MIN));
+        assert_eq!(eval(REM, MIN, NEG_1), Ok(0));
+    }
+
+    #[test]
+    fn test_loads_stores() {
+        const LB: u32 = 0x00058503; // lb a0, 0(a1)
+        const LH: u32 = 0x00059503; // lh a0, 0(a1)
+        const LW: u32 = 0x0015a503; // lw a0, 1(a1)
+        const LBU: u32 = 0x0005c503; // lbu a0, 0(a1)
+        const LHU: u32 = 0x0005d503; // lhu a0, 0(a1)
+
+        let rt = test_rt();
+        let data = TEXT_BASE + 0x800;
+        rt.store(data, &[0x80, 0xff, 0x34, 0x12, 0x78]).unwrap();
+        let load = |inst| run(&rt, &[inst], &[(A1, data)]).map(|cpu| cpu.x[A0]);
+
+        // Signed loads are sign-extended, unsigned loads are zero-extended:
+        assert_eq!(load(LB), Ok(0xffffff80));
+        assert_eq!(load(LH), Ok(0xffffff80));
+        assert_eq!(load(LBU), Ok(0x80));
+        assert_eq!(load(LHU), Ok(0xff80));
+
+        // Misaligned accesses are supported:
+        assert_eq!(load(LW), Ok(0x781234ff));
+
+        // Stores only write the accessed bytes:
+        run(
+            &rt,
+            &[
+                0x00c58023, // sb a2, 0(a1)
+                0x00c59123, // sh a2, 2(a1)
+                0xfec5ae23, // sw a2, -4(a1)
+            ],
+            &[(A1, data), (A2, 0x11223344)],
+        )
+        .unwrap();
+        assert_eq!(rt.load::<4>(data), Ok([0x44, 0xff, 0x44, 0x33]));
+        assert_eq!(rt.load::<4>(data - 4), Ok([0x44, 0x33, 0x22, 0x11]));
+    }
+
+    #[test]
+    fn test_out_of_bounds_accesses() {
+        const LW: u32 = 0x0015a503; // lw a0, 1(a1)
+        const SB: u32 = 0x00c58023; // sb a2, 0(a1)
+
+        let rt = test_rt();
+        let end = TEXT_BASE + MEMORY_SIZE as u32;
+
+        assert_eq!(
+            run(&rt, &[LW], &[(A1, end - 5)]).map(|cpu| cpu.x[A0]),
+            Ok(0)
+        );
+        assert_eq!(
+            run(&rt, &[LW], &[(A1, end - 4)]).map(|cpu| cpu.x[A0]),
+            Err(OGError::ForeignTrap)
+        );
+        assert!(matches!(
+            run(&rt, &[LW], &[(A1, 0)]),
+            Err(OGError::ForeignTrap)
+        ));
+        assert!(matches!(
+            run(&rt, &[SB], &[(A1, end)]),
+            Err(OGError::ForeignTrap)
+        ));
+        assert!(matches!(
+            run(&rt, &[SB], &[(A1, TEXT_BASE - 1)]),
+            Err(OGError::ForeignTrap)
+        ));
+    }
+
+    #[test]
+    fn test_branches_jumps() {
+        const BEQ: u32 = 0x00b50463; // beq a0, a1, 8
+        const BLT: u32 = 0x00b54463; // blt a0, a1, 8
+        const BLTU: u32 = 0x00b56463; // bltu a0, a1, 8
+
+        let rt = test_rt();
+        let branch = |inst, a0, a1| run(&rt, &[inst], &[(A0, a0), (A1, a1)]).map(|cpu| cpu.pc);
+
+        assert_eq!(branch(BEQ, 1, 1), Ok(TEXT_BASE + 8));
+        assert_eq!(branch(BEQ, 1, 2), Ok(TEXT_BASE + 4));
+        assert_eq!(branch(BLT, u32::MAX, 0), Ok(TEXT_BASE + 8));
+        assert_eq!(branch(BLTU, u32::MAX, 0), Ok(TEXT_BASE + 4));
+
+        // jal ra, 16
+        let cpu = run(&rt, &[0x010000ef], &[]).unwrap();
+        assert_eq!((cpu.pc, cpu.x[REG_RA]), (TEXT_BASE + 16, TEXT_BASE + 4));
+
+        // jalr ra, 3(a1) clears the lowest bit of the target address:
+        let cpu = run(&rt, &[0x003580e7], &[(A1, TEXT_BASE + 0x100)]).unwrap();
+        assert_eq!((cpu.pc, cpu.x[REG_RA]), (TEXT_BASE + 0x102, TEXT_BASE + 4));
+    }
+
+    #[test]
+    fn test_illegal_instructions() {
+        let rt = test_rt();
+        let illegal = |inst| matches!(run(&rt, &[inst], &[]), Err(OGError::ForeignTrap));
+
+        assert!(illegal(0x00000073)); // ecall
+        assert!(illegal(0x00100073)); // ebreak
+        assert!(illegal(0x00000000)); // c.unimp (all-zero encoding)
+        assert!(illegal(0xffffffff));
+        assert!(illegal(0x0005b503)); // ld a0, 0(a1)
+        assert!(illegal(0x003590e7)); // jalr with funct3 = 1
+        assert!(illegal(0x04c58533)); // add with funct7 = 2
+        assert!(illegal(0x03f59513)); // slli a0, a1, 63 (RV64 only)
+        assert!(illegal(0x00002008)); // c.fld fa0, 0(s0)
+
+        // fence is accepted, and has no effect on a single hart:
+        assert!(!illegal(0x0ff0000f));
+    }
+
+    #[test]
+    fn test_compressed_instructions() {
+        for (compressed, expanded) in [
+            (0x0505, 0x00150513), // addi a0, a0, 1
+            (0x557d, 0xfff00513), // li a0, -1
+            (0x75fd, 0xfffff5b7), // lui a1, 0xfffff
+            (0x847d, 0x41f45413), // srai s0, s0, 31
+            (0x40c8, 0x0044a503), // lw a0, 4(s1)
+            (0xc42a, 0x00a12423), // sw a0, 8(sp)
+            (0x713d, 0xfe010113), // addi sp, sp, -32
+            (0x852e, 0x00b00533), // mv a0, a1
+            (0x8082, 0x00008067), // ret
+            (0xdc75, 0xfe040ee3), // beqz s0, -4
+        ] {
+            assert_eq!(expand_compressed(compressed), Some(expanded));
+        }
+
+        // c.fld, and c.srai with a shift amount reserved on RV32:
+        assert_eq!(expand_compressed(0x2008), None);
+        assert_eq!(expand_compressed(0x947d), None);
+
+        // Compressed instructions advance the pc by two bytes, after which
+        // full-length instructions need only be aligned to two bytes:
+        let rt = test_rt();
+        rt.store(TEXT_BASE + 4, &[0x05, 0x05, 0x13, 0x05, 0x15, 0x00])
+            .unwrap();
+        let cpu = run(
+            &rt,
+            &[0x0040006f], // j 4
+            &[(A0, 1)],
+        )
+        .unwrap();
+        assert_eq!(cpu.pc, TEXT_BASE + 4);
+        rt.step().unwrap();
+        assert_eq!(unsafe { (*rt.cpu.get()).pc }, TEXT_BASE + 6);
+        rt.step().unwrap();
+        assert_eq!(unsafe { *rt.cpu.get() }.x[A0], 3);
+        assert_eq!(unsafe { (*rt.cpu.get()).pc }, TEXT_BASE + 10);
+    }
+
+    #[test]
+    fn test_result_registers() {
+        fn result<T: zerocopy::FromBytes + zerocopy::Immutable + zerocopy::KnownLayout>(
+            rt: &TestRt,
+        ) -> OGResult<T> {
+            <Rv32iEmuRtInvokeRes<T> as Rv32iCInvokeRes<TestRt, T>>::new()
+                .into_result_registers(rt)
+                .map(OGRet::valid)
+        }
+
+        let rt = test_rt();
+        rt.ret.set([0x89abcdef, 0x01234567]);
+
+        assert_eq!(result::<u64>(&rt), Ok(0x01234567_89abcdef));
+        assert_eq!(result::<u32>(&rt), Ok(0x89abcdef));
+        assert_eq!(result::<u16>(&rt), Ok(0xcdef));
+        assert_eq!(result::<i8>(&rt), Ok(-17));
+        assert_eq!(result::<[u32; 3]>(&rt), Err(OGError::InternalError));
+    }
+
+    #[test]
+    fn test_invoke() {
+        #[rustfmt::skip]
+        const TEXT: [u32; 15] = [
+            // sum: add all eight register and two stacked arguments, and set
+            // the upper half of the 64-bit result to 1
+            0x00b50533, // add a0, a0, a1
+            0x00c50533, // add a0, a0, a2
+            0x00d50533, // add a0, a0, a3
+            0x00e50533, // add a0, a0, a4
+            0x00f50533, // add a0, a0, a5
+            0x01050533, // add a0, a0, a6
+            0x01150533, // add a0, a0, a7
+            0x00012283, // lw t0, 0(sp)
+            0x00550533, // add a0, a0, t0
+            0x00412283, // lw t0, 4(sp)
+            0x00550533, // add a0, a0, t0
+            0x00100593, // li a1, 1
+            0x00008067, // ret
+            // fault: load from an address outside of the foreign memory
+            0x00002503, // lw a0, 0(zero)
+            0x00008067, // ret
+        ];
+
+        let text: Vec<u8> = TEXT.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+        let (rt, mut alloc_scope, mut access_scope) = Rv32iEmuRt::new(
+            &elf(&text, &[("sum", 0), ("fault", 13 * 4)]),
+            MEMORY_SIZE,
+            STACK_SIZE,
+            OGRuntimeBranding::new(),
+        )
+        .unwrap();
+
+        let symtab = rt.resolve_symbols(&[c"sum", c"fault"], &[]).unwrap();
+        let sum = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+        let fault = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+        let sp = unsafe { (*rt.cpu.get()).x[REG_SP] };
+
+        let invoke_sum = |alloc_scope: &mut _, access_scope: &mut _| {
+            rt.execute(sum, alloc_scope, access_scope, || {
+                rt.set_argument::<AREG0<Rv32iCABI>>(1)?;
+                rt.set_argument::<AREG1<Rv32iCABI>>(2)?;
+                rt.set_argument::<AREG2<Rv32iCABI>>(3)?;
+                rt.set_argument::<AREG3<Rv32iCABI>>(4)?;
+                rt.set_argument::<AREG4<Rv32iCABI>>(5)?;
+                rt.set_argument::<AREG5<Rv32iCABI>>(6)?;
+                rt.set_argument::<AREG6<Rv32iCABI>>(7)?;
+                rt.set_argument::<AREG7<Rv32iCABI>>(8)?;
+                rt.set_argument::<Stacked<0, Rv32iCABI>>(9)?;
+                rt.set_argument::<Stacked<1, Rv32iCABI>>(10)?;
+                rt.invoke()?;
+                <Rv32iEmuRtInvokeRes<u64> as Rv32iCInvokeRes<TestRt, u64>>::new()
+                    .into_result_registers(&rt)
+            })
+            .unwrap()
+            .map(OGRet::valid)
+        };
+
+        assert_eq!(
+            invoke_sum(&mut alloc_scope, &mut access_scope),
+            Ok(1 << 32 | 55)
+        );
+        assert_eq!(unsafe { (*rt.cpu.get()).x[REG_SP] }, sp);
+
+        // Traps are reported by `invoke`, and leave the runtime usable:
+        assert!(matches!(
+            rt.execute(fault, &mut alloc_scope, &mut access_scope, || rt.invoke()),
+            Ok(Err(OGError::ForeignTrap))
+        ));
+        assert_eq!(unsafe { (*rt.cpu.get()).x[REG_SP] }, sp);
+        assert_eq!(
+            invoke_sum(&mut alloc_scope, &mut access_scope),
+            Ok(1 << 32 | 55)
+        );
+    }
+
+    #[test]
+    fn test_variadic_invoke() {
+        use crate::abi::calling_convention::ArgumentKind::{Fixed, Variadic};
+        use crate::abi::riscv::{
+            RiscVArgAllocator, RiscVArgLocation, RiscVScalar, RiscVScalarClass,
+        };
+
+        #[rustfmt::skip]
+        const TEXT: [u32; 10] = [
+            // sum: int64_t sum(int a, ...), with an int, an int64_t, an int and
+            // two int64_t as variadic arguments. Add up the low and high words
+            // of all arguments separately
+            0x00b50533, // add a0, a0, a1
+            0x00c50533, // add a0, a0, a2
+            0x00e50533, // add a0, a0, a4
+            0x01050533, // add a0, a0, a6
+            0x00012283, // lw t0, 0(sp)
+            0x00550533, // add a0, a0, t0
+            0x011685b3, // add a1, a3, a7
+            0x00412283, // lw t0, 4(sp)
+            0x005585b3, // add a1, a1, t0
+            0x00008067, // ret
+        ];
+
+        let int = RiscVScalar {
+            size: 4,
+            class: RiscVScalarClass::Integer,
+        };
+        let long_long = RiscVScalar {
+            size: 8,
+            class: RiscVScalarClass::Integer,
+        };
+
+        // Variadic two-word arguments start at an even register, skipping a5,
+        // and spill onto the stack once all registers are occupied:
+        let mut args = RiscVArgAllocator::ilp32();
+        let locations = [
+            (int, Fixed),
+            (int, Variadic),
+            (long_long, Variadic),
+            (int, Variadic),
+            (long_long, Variadic),
+            (long_long, Variadic),
+        ]
+        .map(|(arg, kind)| args.allocate(arg, kind));
+        let regs = |first, count| RiscVArgLocation::Registers { first, count };
+        assert_eq!(
+            locations,
+            [
+                regs(0, 1),
+                regs(1, 1),
+                regs(2, 2),
+                regs(4, 1),
+                regs(6, 2),
+                RiscVArgLocation::Stacked {
+                    offset_words: 0,
+                    words: 2
+                },
+            ]
+        );
+        assert_eq!(args.stack_words(), 2);
+
+        let text: Vec<u8> = TEXT.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+        let (rt, mut alloc_scope, mut access_scope) = Rv32iEmuRt::new(
+            &elf(&text, &[("sum", 0)]),
+            MEMORY_SIZE,
+            STACK_SIZE,
+            OGRuntimeBranding::new(),
+        )
+        .unwrap();
+        let symtab = rt.resolve_symbols(&[c"sum"], &[]).unwrap();
+        let sum = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+        // sum(1, 2, 3 << 32 | 4, 5, 6 << 32 | 7, 8 << 32 | 9):
+        let res = rt
+            .execute(sum, &mut alloc_scope, &mut access_scope, || {
+                rt.set_argument::<AREG0<Rv32iCABI>>(1)?;
+                rt.set_argument::<AREG1<Rv32iCABI>>(2)?;
+                rt.set_argument::<AREG2<Rv32iCABI>>(4)?;
+                rt.set_argument::<AREG3<Rv32iCABI>>(3)?;
+                rt.set_argument::<AREG4<Rv32iCABI>>(5)?;
+                rt.set_argument::<AREG5<Rv32iCABI>>(0xdead)?;
+                rt.set_argument::<AREG6<Rv32iCABI>>(7)?;
+                rt.set_argument::<AREG7<Rv32iCABI>>(6)?;
+                rt.set_argument::<Stacked<0, Rv32iCABI>>(9)?;
+                rt.set_argument::<Stacked<1, Rv32iCABI>>(8)?;
+                rt.invoke()?;
+                <Rv32iEmuRtInvokeRes<u64> as Rv32iCInvokeRes<TestRt, u64>>::new()
+                    .into_result_registers(&rt)
+            })
+            .unwrap()
+            .map(OGRet::valid);
+        assert_eq!(res, Ok(17 << 32 | 28));
+    }
+}
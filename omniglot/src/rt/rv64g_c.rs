@@ -0,0 +1,93 @@
+// -*- fill-column: 80; -*-
+
+use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
+use crate::foreign_memory::og_ret::OGRet;
+use crate::rt::OGRuntime;
+
+/// Retrieves the result of a function called through [`Rv64gCRt::invoke`].
+///
+/// # Safety
+///
+/// Implementations must only return `OGRet`s containing the values returned by
+/// the last invoked foreign function.
+pub unsafe trait Rv64gCInvokeRes<RT: Rv64gCBaseRt, T: Sized> {
+    fn new() -> Self;
+
+    /// Assemble the result from the integer (`a0`, `a1`) and floating-point
+    /// (`fa0`, `fa1`) return registers.
+    fn into_result_registers(self, rt: &RT) -> OGResult<OGRet<T>>;
+
+    /// # Safety
+    ///
+    /// `stacked_res` must point to a stacked allocation of `T` that the last
+    /// invoked foreign function returned its result in.
+    unsafe fn into_result_stacked(self, rt: &RT, stacked_res: *mut T) -> OGResult<OGRet<T>>;
+}
+
+pub trait Rv64gCBaseRt: OGRuntime<ABI = crate::abi::rv64g_c::Rv64gCABI> + Sized {
+    type InvokeRes<T>: Rv64gCInvokeRes<Self, T>;
+}
+
+pub trait Rv64gCRt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
+    Rv64gCBaseRt
+{
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the LP64D calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
+    unsafe extern "C" fn invoke();
+}
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn rv64g_c_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"])
+}
+
+/// Index of the floating-point argument register referred to by an
+/// `ArgumentSlot`, or `usize::MAX` if it does not refer to one.
+pub const fn rv64g_c_fpreg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["fa0", "fa1", "fa2", "fa3", "fa4", "fa5", "fa6", "fa7"])
+}
+
+/// Byte offset of the stacked argument referred to by an `ArgumentSlot`,
+/// relative to the stack pointer on entry to the callee, or `usize::MAX` if it
+/// does not refer to a stacked argument. Stack slots are 8 bytes wide.
+pub const fn rv64g_c_stack_offset<S: crate::abi::calling_convention::ArgumentSlot>() -> usize {
+    if !S::IS_STACKED {
+        return usize::MAX;
+    }
+
+    S::STACK_OFFSET_WORDS * 8
+}
+
+/// Bytes of stack to reserve for `stack_spill` words of stacked arguments,
+/// keeping the stack pointer 16-byte aligned as required by the LP64D ABI.
+pub const fn rv64g_c_stack_spill_bytes(stack_spill: usize) -> usize {
+    (stack_spill * 8).next_multiple_of(16)
+}
+
+#[test]
+fn test_rv64g_c_argument_slots() {
+    use crate::abi::calling_convention::{AREG0, AREG7, FPREG0, FPREG7, Stacked};
+    use crate::abi::rv64g_c::Rv64gCABI;
+
+    const {
+        assert!(rv64g_c_areg_index::<AREG0<Rv64gCABI>>() == 0);
+        assert!(rv64g_c_areg_index::<AREG7<Rv64gCABI>>() == 7);
+        assert!(rv64g_c_fpreg_index::<FPREG0<Rv64gCABI>>() == 0);
+        assert!(rv64g_c_fpreg_index::<FPREG7<Rv64gCABI>>() == 7);
+        assert!(rv64g_c_fpreg_index::<AREG0<Rv64gCABI>>() == usize::MAX);
+        assert!(rv64g_c_areg_index::<FPREG0<Rv64gCABI>>() == usize::MAX);
+        assert!(rv64g_c_fpreg_index::<Stacked<1, Rv64gCABI>>() == usize::MAX);
+        assert!(rv64g_c_stack_offset::<Stacked<1, Rv64gCABI>>() == 8);
+        assert!(rv64g_c_stack_offset::<FPREG0<Rv64gCABI>>() == usize::MAX);
+        assert!(rv64g_c_stack_spill_bytes(1) == 16);
+    }
+}
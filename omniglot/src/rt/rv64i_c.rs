@@ -0,0 +1,81 @@
+// -*- fill-column: 80; -*-
+
+use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
+use crate::foreign_memory::og_ret::OGRet;
+use crate::rt::OGRuntime;
+
+/// Retrieves the result of a function called through [`Rv64iCRt::invoke`].
+///
+/// # Safety
+///
+/// Implementations must only return `OGRet`s containing the values returned by
+/// the last invoked foreign function.
+pub unsafe trait Rv64iCInvokeRes<RT: Rv64iCBaseRt, T: Sized> {
+    fn new() -> Self;
+
+    /// Assemble the result from the `a0` and `a1` return registers.
+    fn into_result_registers(self, rt: &RT) -> OGResult<OGRet<T>>;
+
+    /// # Safety
+    ///
+    /// `stacked_res` must point to a stacked allocation of `T` that the last
+    /// invoked foreign function returned its result in.
+    unsafe fn into_result_stacked(self, rt: &RT, stacked_res: *mut T) -> OGResult<OGRet<T>>;
+}
+
+pub trait Rv64iCBaseRt: OGRuntime<ABI = crate::abi::rv64i_c::Rv64iCABI> + Sized {
+    type InvokeRes<T>: Rv64iCInvokeRes<Self, T>;
+}
+
+pub trait Rv64iCRt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
+    Rv64iCBaseRt
+{
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the LP64 calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
+    unsafe extern "C" fn invoke();
+}
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn rv64i_c_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"])
+}
+
+/// Byte offset of the stacked argument referred to by an `ArgumentSlot`,
+/// relative to the stack pointer on entry to the callee, or `usize::MAX` if it
+/// does not refer to a stacked argument. Stack slots are 8 bytes wide.
+pub const fn rv64i_c_stack_offset<S: crate::abi::calling_convention::ArgumentSlot>() -> usize {
+    if !S::IS_STACKED {
+        return usize::MAX;
+    }
+
+    S::STACK_OFFSET_WORDS * 8
+}
+
+/// Bytes of stack to reserve for `stack_spill` words of stacked arguments,
+/// keeping the stack pointer 16-byte aligned as required by the LP64 ABI.
+pub const fn rv64i_c_stack_spill_bytes(stack_spill: usize) -> usize {
+    (stack_spill * 8).next_multiple_of(16)
+}
+
+#[test]
+fn test_rv64i_c_argument_slots() {
+    use crate::abi::calling_convention::{AREG0, AREG7, Stacked};
+    use crate::abi::rv64i_c::Rv64iCABI;
+
+    assert_eq!(rv64i_c_areg_index::<AREG0<Rv64iCABI>>(), 0);
+    assert_eq!(rv64i_c_areg_index::<AREG7<Rv64iCABI>>(), 7);
+    assert_eq!(rv64i_c_stack_offset::<AREG7<Rv64iCABI>>(), usize::MAX);
+    assert_eq!(rv64i_c_areg_index::<Stacked<3, Rv64iCABI>>(), usize::MAX);
+    assert_eq!(rv64i_c_stack_offset::<Stacked<3, Rv64iCABI>>(), 24);
+    assert_eq!(rv64i_c_stack_spill_bytes(3), 32);
+    assert_eq!(rv64i_c_stack_spill_bytes(4), 32);
+}
@@ -0,0 +1,2115 @@
+// -*- fill-column: 80; -*-
+
+//! A Linux runtime executing foreign code in a separate child process.
+//!
+//! [`SubprocessRt`] forks a template process, which loads the foreign library
+//! as a shared object. It then forks the child process from this template,
+//! which serves requests from the host over a socket. Foreign code thus never
+//! runs in the host's address space: a crashing or misbehaving library only
+//! takes down the child process, after which all further calls into the
+//! library fail with [`OGError::ForeignDomainTerminated`]. Likewise, when a
+//! call made through [`OGRuntime::execute_with_deadline`] exceeds its deadline,
+//! the runtime kills the child process and reports [`OGError::Timeout`].
+//!
+//! [`SubprocessRt::respawn`] replaces a terminated child process with a fresh
+//! one, forked from the template. As the template never calls into the library,
+//! the new child starts out with the library in its initial state, loaded at
+//! the same address.
+//!
+//! Before forking, the runtime maps a region of shared memory, which appears at
+//! the same address in both processes. This region forms the foreign domain:
+//! upgrades of foreign pointers are validated against it, and stacked
+//! allocations are placed within it. The library's own globals and heap reside
+//! in the child's private memory, and are not accessible to the host.
+//!
+//! The child process runs on a stack that is shared with the host as well, but
+//! not part of the foreign domain. This allows callbacks to access their
+//! stack-spilled arguments through [`CallbackContext::get_stack_pointer`].
+//!
+//! # Invocation protocol
+//!
+//! This runtime's [`SysVAMD64Rt::invoke`] trampoline is to be called in place
+//! of the foreign function, with all arguments set up according to the System V
+//! AMD64 calling convention, and `STACK_SPILL` words of arguments passed on the
+//! stack. The `RTLOC` argument slot must hold a pointer to the runtime. The
+//! trampoline forwards all integer and vector argument registers (the latter
+//! with their lower 64 bits, as used by `f32` and `f64` arguments), the number
+//! of vector registers used in `al`, and the stack-spilled arguments to the
+//! child. The child then calls the `target_symbol` passed to the enclosing
+//! [`OGRuntime::execute`], which may be variadic.
+//!
+//! Callback trampolines handed out by [`OGRuntime::setup_callback`] are only
+//! valid within the child process. When foreign code calls them, the child
+//! forwards the call to the host, which runs the callback while it is waiting
+//! for the foreign function to return. Callbacks must not unwind into foreign
+//! code: a panicking callback returns zero to the child, and its panic is
+//! resumed once the enclosing [`OGRuntime::execute`] returns.
+//!
+//! # Caveats
+//!
+//! As the child process is created through `fork`, it inherits a (copy-on-write)
+//! snapshot of the host's memory at the time the runtime is created, and only
+//! the thread that created it. Runtimes should thus be created while the host
+//! process is single-threaded, and before it holds any secrets that must not be
+//! readable by foreign code.
+
+use core::cell::Cell;
+use core::ffi::{CStr, c_char, c_int, c_void};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::time::Duration;
+use std::time::Instant;
+
+use crate::abi::calling_convention::ArgumentSlot;
+use crate::abi::sysv_amd64::SysVAMD64ABI;
+use crate::alloc_tracker::AllocTracker;
+use crate::foreign_memory::og_ret::OGRet;
+use crate::id::OGID;
+use crate::markers::{AccessScope, AllocScope};
+use crate::maybe_valid::MaybeValid;
+use crate::rt::sysv_amd64::{
+    SysVAMD64BaseRt, SysVAMD64InvokeRes, SysVAMD64Rt, sysv_amd64_areg_index,
+};
+use crate::rt::{CallbackContext, CallbackReturn, OGRuntime};
+use crate::util::dl::DlLibrary;
+use crate::{OGError, OGResult};
+
+// ---------- Messages exchanged between the host and child process -----------
+
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SubprocessRtMsgKind {
+    // child -> host: the library has been loaded
+    Ready = 0,
+    // child -> host: the library could not be loaded
+    LoadFailed = 1,
+    // host -> child: resolve the symbol with the name at `words[0]`
+    Resolve = 2,
+    // child -> host: the symbol's address in `words[0]`, or `0`
+    Resolved = 3,
+    // host -> child: call `words[0]` with the integer argument registers in
+    // `words[1..7]`, `words[8]` stack-spilled words at `words[7]`, the number
+    // of vector registers used in `words[9]`, and xmm0-xmm7 in `words[10..18]`
+    Invoke = 4,
+    // child -> host: the invoked function returned rax and rdx in
+    // `words[0..2]`, and xmm0 and xmm1 in `words[2..4]`
+    Return = 5,
+    // child -> host: call callback `words[0]` with the argument registers in
+    // `words[1..7]`, and the stack-spilled arguments starting at `words[7]`
+    Callback = 6,
+    // host -> child: the callback returned `words[0..2]`
+    CallbackReturn = 7,
+    // host -> template: fork a new child process
+    Spawn = 8,
+    // template -> host: forked the child process `words[0]`, whose socket is
+    // attached to this message
+    Spawned = 9,
+    // host -> template: kill and reap the child process `words[0]`
+    Kill = 10,
+}
+
+impl SubprocessRtMsgKind {
+    fn from_usize(kind: usize) -> Option<Self> {
+        match kind {
+            0 => Some(SubprocessRtMsgKind::Ready),
+            1 => Some(SubprocessRtMsgKind::LoadFailed),
+            2 => Some(SubprocessRtMsgKind::Resolve),
+            3 => Some(SubprocessRtMsgKind::Resolved),
+            4 => Some(SubprocessRtMsgKind::Invoke),
+            5 => Some(SubprocessRtMsgKind::Return),
+            6 => Some(SubprocessRtMsgKind::Callback),
+            7 => Some(SubprocessRtMsgKind::CallbackReturn),
+            8 => Some(SubprocessRtMsgKind::Spawn),
+            9 => Some(SubprocessRtMsgKind::Spawned),
+            10 => Some(SubprocessRtMsgKind::Kill),
+            _ => None,
+        }
+    }
+}
+
+const SUBPROCESS_RT_MSG_WORDS: usize = 18;
+
+type SubprocessRtMsgWords = [usize; SUBPROCESS_RT_MSG_WORDS];
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SubprocessRtMsg {
+    kind: usize,
+    words: SubprocessRtMsgWords,
+}
+
+fn subprocess_rt_send(
+    socket: c_int,
+    kind: SubprocessRtMsgKind,
+    words: SubprocessRtMsgWords,
+) -> bool {
+    subprocess_rt_send_fd(socket, kind, words, None)
+}
+
+// Send a message, optionally passing the file descriptor `fd` along with it:
+fn subprocess_rt_send_fd(
+    socket: c_int,
+    kind: SubprocessRtMsgKind,
+    words: SubprocessRtMsgWords,
+    fd: Option<c_int>,
+) -> bool {
+    let mut msg = SubprocessRtMsg {
+        kind: kind as usize,
+        words,
+    };
+    let mut iov = libc::iovec {
+        iov_base: &mut msg as *mut SubprocessRtMsg as *mut c_void,
+        iov_len: core::mem::size_of::<SubprocessRtMsg>(),
+    };
+    let mut cmsg_buf = SubprocessRtCmsgBuf([0; SUBPROCESS_RT_CMSG_LEN]);
+
+    let mut hdr: libc::msghdr = unsafe { core::mem::zeroed() };
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    if let Some(fd) = fd {
+        hdr.msg_control = cmsg_buf.0.as_mut_ptr() as *mut c_void;
+        hdr.msg_controllen = SUBPROCESS_RT_CMSG_LEN as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(core::mem::size_of::<c_int>() as u32) as _;
+            core::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut c_int, fd);
+        }
+    }
+
+    loop {
+        let res = unsafe { libc::sendmsg(socket, &hdr, libc::MSG_NOSIGNAL) };
+
+        if res == core::mem::size_of::<SubprocessRtMsg>() as isize {
+            return true;
+        } else if res < 0
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+        {
+            continue;
+        } else {
+            return false;
+        }
+    }
+}
+
+// Returns `None` when the socket has been closed, or when the peer has sent a
+// malformed message:
+fn subprocess_rt_recv(socket: c_int) -> Option<(SubprocessRtMsgKind, SubprocessRtMsgWords)> {
+    let (kind, words, fd) = subprocess_rt_recv_fd(socket)?;
+    if let Some(fd) = fd {
+        // We did not expect a file descriptor with this message:
+        unsafe { libc::close(fd) };
+    }
+    Some((kind, words))
+}
+
+// Space for the control message carrying a single file descriptor:
+const SUBPROCESS_RT_CMSG_LEN: usize =
+    unsafe { libc::CMSG_SPACE(core::mem::size_of::<c_int>() as u32) } as usize;
+
+#[repr(C, align(8))]
+struct SubprocessRtCmsgBuf([u8; SUBPROCESS_RT_CMSG_LEN]);
+
+// Receive a message, along with the file descriptor passed with it (if any):
+fn subprocess_rt_recv_fd(
+    socket: c_int,
+) -> Option<(SubprocessRtMsgKind, SubprocessRtMsgWords, Option<c_int>)> {
+    let mut msg = SubprocessRtMsg {
+        kind: 0,
+        words: [0; SUBPROCESS_RT_MSG_WORDS],
+    };
+    let mut iov = libc::iovec {
+        iov_base: &mut msg as *mut SubprocessRtMsg as *mut c_void,
+        iov_len: core::mem::size_of::<SubprocessRtMsg>(),
+    };
+    let mut cmsg_buf = SubprocessRtCmsgBuf([0; SUBPROCESS_RT_CMSG_LEN]);
+
+    let mut hdr: libc::msghdr = unsafe { core::mem::zeroed() };
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = cmsg_buf.0.as_mut_ptr() as *mut c_void;
+    hdr.msg_controllen = SUBPROCESS_RT_CMSG_LEN as _;
+
+    loop {
+        let res = unsafe { libc::recvmsg(socket, &mut hdr, libc::MSG_CMSG_CLOEXEC) };
+
+        if res == core::mem::size_of::<SubprocessRtMsg>() as isize {
+            let cmsg = unsafe { libc::CMSG_FIRSTHDR(&hdr) };
+            let fd = unsafe { cmsg.as_ref() }
+                .filter(|cmsg| {
+                    cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SCM_RIGHTS
+                })
+                .map(|cmsg| unsafe {
+                    core::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int)
+                });
+
+            return match SubprocessRtMsgKind::from_usize(msg.kind) {
+                Some(kind) => Some((kind, msg.words, fd)),
+                None => {
+                    if let Some(fd) = fd {
+                        unsafe { libc::close(fd) };
+                    }
+                    None
+                }
+            };
+        } else if res < 0
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+        {
+            continue;
+        } else {
+            return None;
+        }
+    }
+}
+
+// ---------- Child process ----------------------------------------------------
+
+struct SubprocessRtChild<'l> {
+    socket: c_int,
+    library: &'l DlLibrary,
+}
+
+// Only ever set within the child process, where it points to the child's state
+// on the stack of `subprocess_rt_child_main`, which never returns. Its library
+// is owned by `subprocess_rt_template_main`, which never returns either:
+static SUBPROCESS_RT_CHILD: AtomicPtr<SubprocessRtChild<'static>> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+// Make sure that we don't outlive our parent process:
+unsafe fn subprocess_rt_die_with_parent(parent: libc::pid_t) {
+    unsafe {
+        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+        if libc::getppid() != parent {
+            libc::_exit(1);
+        }
+    }
+}
+
+unsafe fn subprocess_rt_template_main(
+    socket: c_int,
+    library_path: &CStr,
+    stack_top: usize,
+    parent: libc::pid_t,
+) -> ! {
+    unsafe { subprocess_rt_die_with_parent(parent) };
+
+    let Some(library) = (unsafe { DlLibrary::open(library_path) }) else {
+        subprocess_rt_send(
+            socket,
+            SubprocessRtMsgKind::LoadFailed,
+            [0; SUBPROCESS_RT_MSG_WORDS],
+        );
+        unsafe { libc::_exit(1) };
+    };
+
+    if !subprocess_rt_send(
+        socket,
+        SubprocessRtMsgKind::Ready,
+        [0; SUBPROCESS_RT_MSG_WORDS],
+    ) {
+        unsafe { libc::_exit(1) };
+    }
+
+    // Child processes remain zombies until the host asks us to reap them, such
+    // that their PIDs cannot be reused in the meantime. We exit when the host
+    // closes its socket, which takes down all child processes with us:
+    loop {
+        let served = match subprocess_rt_recv(socket) {
+            Some((SubprocessRtMsgKind::Spawn, _)) => {
+                subprocess_rt_template_spawn(socket, &library, stack_top)
+            }
+            Some((SubprocessRtMsgKind::Kill, words)) => {
+                unsafe {
+                    libc::kill(words[0] as libc::pid_t, libc::SIGKILL);
+                    libc::waitpid(words[0] as libc::pid_t, core::ptr::null_mut(), 0);
+                }
+                true
+            }
+            Some(_) | None => false,
+        };
+
+        if !served {
+            unsafe { libc::_exit(0) };
+        }
+    }
+}
+
+// Fork a child process, and pass its socket to the host:
+fn subprocess_rt_template_spawn(
+    template_socket: c_int,
+    library: &DlLibrary,
+    stack_top: usize,
+) -> bool {
+    let mut sockets = [0 as c_int; 2];
+    if unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            sockets.as_mut_ptr(),
+        )
+    } != 0
+    {
+        // Report the failure without a socket:
+        return subprocess_rt_send(
+            template_socket,
+            SubprocessRtMsgKind::Spawned,
+            [0; SUBPROCESS_RT_MSG_WORDS],
+        );
+    }
+
+    let template = unsafe { libc::getpid() };
+    match unsafe { libc::fork() } {
+        0 => unsafe {
+            libc::close(template_socket);
+            libc::close(sockets[0]);
+            subprocess_rt_child_main(sockets[1], library, stack_top, template)
+        },
+        child => {
+            unsafe { libc::close(sockets[1]) };
+
+            let mut words = [0; SUBPROCESS_RT_MSG_WORDS];
+            words[0] = child as usize;
+            let fd = if child == -1 { None } else { Some(sockets[0]) };
+            let sent =
+                subprocess_rt_send_fd(template_socket, SubprocessRtMsgKind::Spawned, words, fd);
+
+            unsafe { libc::close(sockets[0]) };
+            sent
+        }
+    }
+}
+
+unsafe fn subprocess_rt_child_main(
+    socket: c_int,
+    library: &DlLibrary,
+    stack_top: usize,
+    parent: libc::pid_t,
+) -> ! {
+    unsafe { subprocess_rt_die_with_parent(parent) };
+
+    let child = SubprocessRtChild { socket, library };
+    SUBPROCESS_RT_CHILD.store(
+        (&child as *const SubprocessRtChild<'_>).cast_mut().cast(),
+        Ordering::Relaxed,
+    );
+
+    // Continue on the shared stack, such that all foreign frames reside there.
+    // We never return to the original stack, which keeps `child` alive:
+    unsafe {
+        core::arch::asm!(
+            "
+            mov rsp, {stack_top}
+            call {run}
+            ud2
+            ",
+            stack_top = in(reg) stack_top,
+            run = sym subprocess_rt_child_run,
+            in("rdi") &child,
+            options(noreturn),
+        )
+    }
+}
+
+extern "C" fn subprocess_rt_child_run(child: &SubprocessRtChild) -> ! {
+    if subprocess_rt_send(
+        child.socket,
+        SubprocessRtMsgKind::Ready,
+        [0; SUBPROCESS_RT_MSG_WORDS],
+    ) {
+        // This only returns when the host sends a stray `CallbackReturn`:
+        subprocess_rt_child_serve(child);
+    }
+
+    unsafe { libc::_exit(1) }
+}
+
+// Serve requests from the host, until it returns from a callback. The child
+// process exits when the host closes its socket:
+fn subprocess_rt_child_serve(child: &SubprocessRtChild) -> [usize; 2] {
+    loop {
+        let sent = match subprocess_rt_recv(child.socket) {
+            Some((SubprocessRtMsgKind::Resolve, words)) => {
+                let name = unsafe { CStr::from_ptr(words[0] as *const c_char) };
+                let addr = child.library.symbol(name).map_or(0, |ptr| ptr as usize);
+
+                let mut reply = [0; SUBPROCESS_RT_MSG_WORDS];
+                reply[0] = addr;
+                subprocess_rt_send(child.socket, SubprocessRtMsgKind::Resolved, reply)
+            }
+
+            Some((SubprocessRtMsgKind::Invoke, words)) => {
+                let ([rax, rdx], [xmm0, xmm1]) = unsafe {
+                    subprocess_rt_child_invoke(
+                        words[0],
+                        &words[1..7],
+                        words[7] as *const usize,
+                        words[8],
+                        words[9],
+                        &words[10..18],
+                    )
+                };
+
+                let mut reply = [0; SUBPROCESS_RT_MSG_WORDS];
+                reply[0] = rax;
+                reply[1] = rdx;
+                reply[2] = xmm0 as usize;
+                reply[3] = xmm1 as usize;
+                subprocess_rt_send(child.socket, SubprocessRtMsgKind::Return, reply)
+            }
+
+            Some((SubprocessRtMsgKind::CallbackReturn, words)) => {
+                return [words[0], words[1]];
+            }
+
+            Some(_) | None => false,
+        };
+
+        if !sent {
+            unsafe { libc::_exit(0) };
+        }
+    }
+}
+
+unsafe fn subprocess_rt_child_invoke(
+    target: usize,
+    regs: &[usize],
+    stack_args: *const usize,
+    stack_args_len: usize,
+    vector_count: usize,
+    vector_regs: &[usize],
+) -> ([usize; 2], [u64; 2]) {
+    let rax: usize;
+    let rdx: usize;
+    let xmm0: f64;
+    let xmm1: f64;
+
+    unsafe {
+        core::arch::asm!(
+            "
+            // Save the original stack pointer in a callee-saved register:
+            mov r12, rsp
+
+            // Reserve space for the stack-spilled arguments, keeping the stack
+            // 16-byte aligned, and copy them from the shared region:
+            and rsp, -16
+            lea r10, [r14 * 8 + 15]
+            and r10, -16
+            sub rsp, r10
+            xor r10, r10
+        2:
+            cmp r10, r14
+            jae 3f
+            mov r15, qword ptr [r13 + 8 * r10]
+            mov qword ptr [rsp + 8 * r10], r15
+            inc r10
+            jmp 2b
+        3:
+
+            // al holds the number of vector registers used, as the caller set
+            // it up for variadic functions:
+            call r11
+
+            // Restore the original stack pointer:
+            mov rsp, r12
+            ",
+            in("rdi") regs[0],
+            in("rsi") regs[1],
+            in("rdx") regs[2],
+            in("rcx") regs[3],
+            in("r8") regs[4],
+            in("r9") regs[5],
+            inout("xmm0") f64::from_bits(vector_regs[0] as u64) => xmm0,
+            inout("xmm1") f64::from_bits(vector_regs[1] as u64) => xmm1,
+            in("xmm2") f64::from_bits(vector_regs[2] as u64),
+            in("xmm3") f64::from_bits(vector_regs[3] as u64),
+            in("xmm4") f64::from_bits(vector_regs[4] as u64),
+            in("xmm5") f64::from_bits(vector_regs[5] as u64),
+            in("xmm6") f64::from_bits(vector_regs[6] as u64),
+            in("xmm7") f64::from_bits(vector_regs[7] as u64),
+            in("rax") vector_count,
+            in("r11") target,
+            inout("r13") stack_args => _,
+            inout("r14") stack_args_len => _,
+            out("r12") _,
+            out("r15") _,
+            lateout("rax") rax,
+            lateout("rdx") rdx,
+            clobber_abi("C"),
+        );
+    }
+
+    ([rax, rdx], [xmm0.to_bits(), xmm1.to_bits()])
+}
+
+// Use 6 arguments, as that's how many are passed in registers on x86.
+#[repr(C)]
+pub struct CallbackTrampolineFnReturn {
+    reg0: usize,
+    reg1: usize,
+}
+
+type CallbackTrampolineFn =
+    unsafe extern "C" fn(usize, usize, usize, usize, usize, usize) -> CallbackTrampolineFnReturn;
+
+// Only ever called by foreign code within the child process. Captures the
+// caller's stack pointer, such that callbacks can access stack-spilled
+// arguments:
+#[unsafe(naked)]
+unsafe extern "C" fn subprocess_rt_callback_trampoline<const CALLBACK_ID: usize>(
+    _a0: usize,
+    _a1: usize,
+    _a2: usize,
+    _a3: usize,
+    _a4: usize,
+    _a5: usize,
+) -> CallbackTrampolineFnReturn {
+    core::arch::naked_asm!(
+        "
+        // Set up a stack frame, and save all argument registers in an array on
+        // the stack. This leaves the stack pointer 16-byte aligned:
+        push rbp
+        mov rbp, rsp
+        push r9
+        push r8
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+
+        // The stack-spilled arguments start right above our return address.
+        // The return value is passed through in rax and rdx:
+        mov rdi, {callback_id}
+        mov rsi, rsp
+        lea rdx, [rbp + 16]
+        call {inner}
+
+        mov rsp, rbp
+        pop rbp
+        ret
+        ",
+        callback_id = const CALLBACK_ID,
+        inner = sym subprocess_rt_callback_trampoline_inner,
+    );
+}
+
+extern "C" fn subprocess_rt_callback_trampoline_inner(
+    callback_id: usize,
+    arg_regs: &[usize; 6],
+    stack_pointer: usize,
+) -> CallbackTrampolineFnReturn {
+    let child_ptr = SUBPROCESS_RT_CHILD.load(Ordering::Relaxed);
+    if child_ptr.is_null() {
+        // Called outside of a child process, there's no way to recover:
+        unsafe { libc::abort() };
+    }
+    let child = unsafe { &*child_ptr };
+
+    let mut words = [0; SUBPROCESS_RT_MSG_WORDS];
+    words[0] = callback_id;
+    words[1..7].copy_from_slice(arg_regs);
+    words[7] = stack_pointer;
+    if !subprocess_rt_send(child.socket, SubprocessRtMsgKind::Callback, words) {
+        unsafe { libc::_exit(0) };
+    }
+
+    // The host may call back into the library while running the callback:
+    let [reg0, reg1] = subprocess_rt_child_serve(child);
+
+    CallbackTrampolineFnReturn { reg0, reg1 }
+}
+
+const SUBPROCESS_RT_CALLBACKS: [CallbackTrampolineFn; 512] = seq_macro::seq!(N in 0..512 { [
+    #( subprocess_rt_callback_trampoline::<N>, )*
+] });
+
+// ---------- Host process -----------------------------------------------------
+
+// A panic raised by a host callback. `callback_wrapper` catches it and stores it
+// here, such that the child process receives a return value. It is resumed once
+// the enclosing `execute` call returns:
+std::thread_local! {
+    static SUBPROCESS_RT_CALLBACK_PANIC: Cell<Option<std::boxed::Box<dyn core::any::Any + Send>>> =
+        const { Cell::new(None) };
+}
+
+#[derive(Debug, Clone)]
+pub struct SubprocessRtCallbackContext {
+    pub arg_regs: [usize; 6],
+    // Points to the first stack-spilled argument on the shared stack, i.e., the
+    // caller's stack pointer at the time of the call:
+    stack_pointer: *mut c_void,
+}
+
+impl CallbackContext for SubprocessRtCallbackContext {
+    fn get_argument_register(&self, reg: usize) -> Option<usize> {
+        self.arg_regs.get(reg).copied()
+    }
+
+    fn get_stack_pointer(&self) -> *mut c_void {
+        self.stack_pointer
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubprocessRtCallbackReturn {
+    pub return_regs: [usize; 2],
+}
+
+impl CallbackReturn for SubprocessRtCallbackReturn {
+    fn set_return_register(&mut self, reg: usize, value: usize) -> bool {
+        if let Some(r) = self.return_regs.get_mut(reg) {
+            *r = value;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SubprocessRtCallbackDescriptor<'a> {
+    wrapper: unsafe extern "C" fn(
+        *mut c_void,
+        &SubprocessRtCallbackContext,
+        &mut SubprocessRtCallbackReturn,
+        *mut (),
+        *mut (),
+    ),
+    context: *mut c_void,
+    _lt: PhantomData<&'a mut c_void>,
+}
+
+impl SubprocessRtCallbackDescriptor<'_> {
+    unsafe fn invoke(
+        &self,
+        callback_ctx: &SubprocessRtCallbackContext,
+        callback_ret: &mut SubprocessRtCallbackReturn,
+        alloc_scope: *mut (),
+        access_scope: *mut (),
+    ) {
+        unsafe {
+            (self.wrapper)(
+                self.context,
+                callback_ctx,
+                callback_ret,
+                alloc_scope,
+                access_scope,
+            )
+        }
+    }
+}
+
+/// Allocation tracker of the [`SubprocessRt`].
+///
+/// All memory accessible to foreign code is contained in the shared region, so
+/// upgrades are validated against its bounds only. The remaining elements of
+/// this list track the callbacks currently available to foreign code.
+#[derive(Debug)]
+pub enum SubprocessRtAllocChain<'a> {
+    SharedRegion {
+        start: usize,
+        len: usize,
+    },
+    Callback(
+        usize,
+        SubprocessRtCallbackDescriptor<'a>,
+        &'a SubprocessRtAllocChain<'a>,
+    ),
+    Cons(&'a SubprocessRtAllocChain<'a>),
+}
+
+struct SubprocessRtAllocChainIter<'a>(Option<&'a SubprocessRtAllocChain<'a>>);
+
+impl<'a> Iterator for SubprocessRtAllocChainIter<'a> {
+    type Item = &'a SubprocessRtAllocChain<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(cur) = self.0 {
+            self.0 = match cur {
+                SubprocessRtAllocChain::SharedRegion { .. } => None,
+                SubprocessRtAllocChain::Callback(_, _, pred) => Some(pred),
+                SubprocessRtAllocChain::Cons(pred) => Some(pred),
+            };
+
+            Some(cur)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> SubprocessRtAllocChain<'a> {
+    fn iter(&'a self) -> SubprocessRtAllocChainIter<'a> {
+        SubprocessRtAllocChainIter(Some(self))
+    }
+
+    fn is_valid_int(&self, ptr: usize, len: usize) -> bool {
+        self.iter().any(|elem| match elem {
+            SubprocessRtAllocChain::SharedRegion {
+                start,
+                len: region_len,
+            } => {
+                ptr >= *start
+                    && ptr
+                        .checked_add(len)
+                        .is_some_and(|end| end <= start + region_len)
+            }
+            SubprocessRtAllocChain::Callback(_, _, _) => false,
+            SubprocessRtAllocChain::Cons(_) => false,
+        })
+    }
+
+    fn next_callback_id(&self) -> usize {
+        self.iter()
+            .find_map(|elem| match elem {
+                SubprocessRtAllocChain::SharedRegion { .. } => None,
+                SubprocessRtAllocChain::Callback(id, _, _) => Some(id + 1),
+                SubprocessRtAllocChain::Cons(_) => None,
+            })
+            .unwrap_or(0)
+    }
+
+    fn find_callback_descriptor(&self, id: usize) -> Option<&SubprocessRtCallbackDescriptor<'_>> {
+        self.iter().find_map(|elem| match elem {
+            SubprocessRtAllocChain::SharedRegion { .. } => None,
+            SubprocessRtAllocChain::Callback(desc_id, desc, _) => {
+                if id == *desc_id {
+                    Some(desc)
+                } else {
+                    None
+                }
+            }
+            SubprocessRtAllocChain::Cons(_) => None,
+        })
+    }
+}
+
+unsafe impl AllocTracker for SubprocessRtAllocChain<'_> {
+    fn is_valid(&self, ptr: *const (), len: usize) -> bool {
+        self.is_valid_int(ptr as usize, len)
+    }
+
+    fn is_valid_mut(&self, ptr: *mut (), len: usize) -> bool {
+        // The entire shared region is mutable by foreign code:
+        self.is_valid_int(ptr as usize, len)
+    }
+}
+
+pub struct SubprocessRtSymbolTableState<
+    const SYMTAB_SIZE: usize,
+    const FIXED_OFFSET_SYMTAB_SIZE: usize,
+> {
+    symbols: [*const (); SYMTAB_SIZE],
+    fixed_offset_symbols: [Option<*const ()>; FIXED_OFFSET_SYMTAB_SIZE],
+}
+
+pub struct SubprocessRt<ID: OGID> {
+    id_imprint: ID::Imprint,
+    template: libc::pid_t,
+    template_socket: c_int,
+    // The current child process and its socket, replaced by `respawn`:
+    child: Cell<libc::pid_t>,
+    socket: Cell<c_int>,
+    shared_region: *mut u8,
+    shared_region_len: usize,
+    // Offset of the first free byte in the shared region. Stacked allocations
+    // are placed in the shared region in LIFO order:
+    shared_region_top: Cell<usize>,
+    // The child's stack, including its guard page:
+    stack: *mut u8,
+    stack_len: usize,
+    terminated: Cell<bool>,
+    // Whether the child process was terminated as a call exceeded its deadline:
+    timed_out: Cell<bool>,
+    // Deadline of the current `execute_with_deadline` call, if any:
+    deadline: Cell<Option<Instant>>,
+    // State of the current `execute` call, for use by the `invoke` trampoline.
+    // These are nested by callbacks executing further foreign functions:
+    target: Cell<*const ()>,
+    active_alloc_chain: Cell<*const SubprocessRtAllocChain<'static>>,
+    ret: Cell<[usize; 2]>,
+}
+
+impl<ID: OGID> SubprocessRt<ID> {
+    /// Create a child process that loads the shared object at `library_path`,
+    /// with a shared region of `shared_region_size` bytes as its foreign
+    /// domain. The child runs on a shared stack of `stack_size` bytes.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called while the host process is single-threaded. Loading
+    /// the library runs its initializers in the child process.
+    pub unsafe fn new(
+        library_path: &CStr,
+        shared_region_size: usize,
+        stack_size: usize,
+        branding: ID,
+    ) -> OGResult<(
+        Self,
+        AllocScope<'static, SubprocessRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
+    )> {
+        if shared_region_size == 0 || stack_size == 0 {
+            return Err(OGError::AllocInvalidLayout);
+        }
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let stack_len = stack_size
+            .checked_next_multiple_of(page_size)
+            .and_then(|size| size.checked_add(page_size))
+            .ok_or(OGError::AllocInvalidLayout)?;
+
+        // The child's stack, with a guard page at its lower end:
+        let stack = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                stack_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS | libc::MAP_STACK,
+                -1,
+                0,
+            )
+        };
+        if stack == libc::MAP_FAILED {
+            return Err(OGError::AllocNoMem);
+        }
+        if unsafe { libc::mprotect(stack, page_size, libc::PROT_NONE) } != 0 {
+            unsafe { libc::munmap(stack, stack_len) };
+            return Err(OGError::InternalError);
+        }
+
+        let shared_region = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                shared_region_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if shared_region == libc::MAP_FAILED {
+            unsafe { libc::munmap(stack, stack_len) };
+            return Err(OGError::AllocNoMem);
+        }
+
+        let mut sockets = [0 as c_int; 2];
+        if unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                0,
+                sockets.as_mut_ptr(),
+            )
+        } != 0
+        {
+            unsafe {
+                libc::munmap(shared_region, shared_region_size);
+                libc::munmap(stack, stack_len);
+            }
+            return Err(OGError::InternalError);
+        }
+
+        let parent = unsafe { libc::getpid() };
+        let template = match unsafe { libc::fork() } {
+            -1 => {
+                unsafe {
+                    libc::close(sockets[0]);
+                    libc::close(sockets[1]);
+                    libc::munmap(shared_region, shared_region_size);
+                    libc::munmap(stack, stack_len);
+                }
+                return Err(OGError::InternalError);
+            }
+            0 => unsafe {
+                libc::close(sockets[0]);
+                let stack_top = stack as usize + stack_len;
+                subprocess_rt_template_main(sockets[1], library_path, stack_top, parent)
+            },
+            template => {
+                unsafe { libc::close(sockets[1]) };
+                template
+            }
+        };
+
+        let id_imprint = branding.get_imprint();
+
+        // From here on, dropping the runtime terminates the template and child
+        // processes:
+        let rt = SubprocessRt {
+            id_imprint,
+            template,
+            template_socket: sockets[0],
+            child: Cell::new(0),
+            socket: Cell::new(-1),
+            shared_region: shared_region as *mut u8,
+            shared_region_len: shared_region_size,
+            shared_region_top: Cell::new(0),
+            stack: stack as *mut u8,
+            stack_len,
+            // Until we have spawned the first child process:
+            terminated: Cell::new(true),
+            timed_out: Cell::new(false),
+            deadline: Cell::new(None),
+            target: Cell::new(core::ptr::null()),
+            active_alloc_chain: Cell::new(core::ptr::null()),
+            ret: Cell::new([0; 2]),
+        };
+
+        match subprocess_rt_recv(rt.template_socket) {
+            Some((SubprocessRtMsgKind::Ready, _)) => (),
+            _ => return Err(OGError::LibraryLoadFailed),
+        }
+
+        if !rt.spawn_child() {
+            return Err(OGError::InternalError);
+        }
+
+        Ok((
+            rt,
+            unsafe {
+                AllocScope::new(
+                    SubprocessRtAllocChain::SharedRegion {
+                        start: shared_region as usize,
+                        len: shared_region_size,
+                    },
+                    id_imprint,
+                )
+            },
+            unsafe { AccessScope::new(id_imprint) },
+        ))
+    }
+
+    /// Whether the child process has terminated, or has been terminated due to
+    /// a protocol violation or timeout.
+    pub fn is_terminated(&self) -> bool {
+        self.terminated.get()
+    }
+
+    /// Replace the child process with a new one, forked from the template
+    /// process, and unpoison `access_scope`.
+    ///
+    /// This terminates the current child process if it is still running. The
+    /// new child process starts out with the library in its initial state, as
+    /// left by its initializers. Symbols resolved previously remain valid, and
+    /// the contents of the shared region are retained.
+    ///
+    /// Use this to recover after the child process has crashed, or after a
+    /// call has exceeded its deadline.
+    pub fn respawn(&mut self, access_scope: &mut AccessScope<ID>) -> OGResult<()> {
+        self.id_imprint_check(None, Some(access_scope))?;
+
+        self.terminate();
+        if !self.spawn_child() {
+            return Err(OGError::ForeignDomainTerminated);
+        }
+
+        // While we hold `&mut self`, no call into the library can be in
+        // progress. Thus, the aborted call can no longer modify the shared
+        // region:
+        unsafe { access_scope.unpoison() };
+
+        Ok(())
+    }
+
+    // Fork a new child process from the template, replacing the current one
+    // (which must be terminated already). Returns `false` on failure:
+    fn spawn_child(&self) -> bool {
+        debug_assert!(self.terminated.get());
+
+        if !subprocess_rt_send(
+            self.template_socket,
+            SubprocessRtMsgKind::Spawn,
+            [0; SUBPROCESS_RT_MSG_WORDS],
+        ) {
+            return false;
+        }
+
+        let (child, socket) = match subprocess_rt_recv_fd(self.template_socket) {
+            Some((SubprocessRtMsgKind::Spawned, words, Some(socket))) => {
+                (words[0] as libc::pid_t, socket)
+            }
+            Some((_, _, Some(socket))) => {
+                unsafe { libc::close(socket) };
+                return false;
+            }
+            _ => return false,
+        };
+
+        self.child.set(child);
+        let prev_socket = self.socket.replace(socket);
+        if prev_socket >= 0 {
+            unsafe { libc::close(prev_socket) };
+        }
+
+        match subprocess_rt_recv(socket) {
+            Some((SubprocessRtMsgKind::Ready, _)) => (),
+            _ => {
+                self.kill_child();
+                return false;
+            }
+        }
+
+        self.terminated.set(false);
+        self.timed_out.set(false);
+        true
+    }
+
+    fn terminate(&self) {
+        if !self.terminated.replace(true) {
+            self.kill_child();
+        }
+    }
+
+    // Ask the template to kill and reap the current child process. It is only
+    // reaped by the template, so its PID remains valid until then:
+    fn kill_child(&self) {
+        let mut words = [0; SUBPROCESS_RT_MSG_WORDS];
+        words[0] = self.child.get() as usize;
+        subprocess_rt_send(self.template_socket, SubprocessRtMsgKind::Kill, words);
+    }
+
+    fn setup_callback_int<'a, C, F, R>(
+        &self,
+        callback: &'a mut C,
+        alloc_scope: &mut AllocScope<
+            '_,
+            <Self as OGRuntime>::AllocTracker<'_>,
+            <Self as OGRuntime>::ID,
+        >,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &<Self as OGRuntime>::CallbackContext,
+            &mut <Self as OGRuntime>::CallbackReturn,
+            *mut (),
+            *mut (),
+        ),
+        F: for<'b> FnOnce(
+            *const <Self as OGRuntime>::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, <Self as OGRuntime>::ID>,
+        ) -> R,
+    {
+        self.id_imprint_check(Some(alloc_scope), None)?;
+
+        struct Context<'a, ClosureTy> {
+            closure: &'a mut ClosureTy,
+        }
+
+        unsafe extern "C" fn callback_wrapper<
+            'a,
+            ClosureTy: FnMut(
+                    &SubprocessRtCallbackContext,
+                    &mut SubprocessRtCallbackReturn,
+                    *mut (),
+                    *mut (),
+                ) + 'a,
+        >(
+            ctx_ptr: *mut c_void,
+            callback_ctx: &SubprocessRtCallbackContext,
+            callback_ret: &mut SubprocessRtCallbackReturn,
+            alloc_scope: *mut (),
+            access_scope: *mut (),
+        ) {
+            let ctx: &mut Context<'a, ClosureTy> =
+                unsafe { &mut *(ctx_ptr as *mut Context<'a, ClosureTy>) };
+
+            // The child waits for this callback to return, so we must not
+            // unwind into the request loop:
+            let res = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+                (ctx.closure)(callback_ctx, callback_ret, alloc_scope, access_scope)
+            }));
+
+            if let Err(payload) = res {
+                // Foreign code may invoke further callbacks before returning to
+                // the host. Only the first panic is resumed:
+                SUBPROCESS_RT_CALLBACK_PANIC.with(|cell| {
+                    let pending = cell.take();
+                    cell.set(Some(pending.unwrap_or(payload)));
+                });
+            }
+        }
+
+        let callback_id = alloc_scope.tracker().next_callback_id();
+        let callback_trampoline = SUBPROCESS_RT_CALLBACKS
+            .get(callback_id)
+            .copied()
+            .ok_or(OGError::SetupCallbackInsufficientSlots)?;
+
+        let mut ctx: Context<'a, C> = Context { closure: callback };
+
+        let mut inner_alloc_scope = unsafe {
+            AllocScope::new(
+                SubprocessRtAllocChain::Callback(
+                    callback_id,
+                    SubprocessRtCallbackDescriptor {
+                        wrapper: callback_wrapper::<C>,
+                        context: &mut ctx as *mut _ as *mut c_void,
+                        _lt: PhantomData::<&'a mut c_void>,
+                    },
+                    alloc_scope.tracker(),
+                ),
+                alloc_scope.id_imprint(),
+            )
+        };
+
+        // Unlike with the `MockRt`, callbacks are only dispatched while the
+        // host is waiting on the result of an `execute` call. It makes the
+        // `AllocScope` passed to `execute` available to the dispatcher, so
+        // there's no global state to maintain here:
+        Ok(fun(
+            callback_trampoline as *const CallbackTrampolineFn,
+            &mut inner_alloc_scope,
+        ))
+    }
+
+    // Dispatch a callback requested by the child process. Returns `None` if
+    // the callback is not available in the active `AllocScope`:
+    fn dispatch_callback(&self, words: &SubprocessRtMsgWords) -> Option<[usize; 2]> {
+        let alloc_chain = unsafe { self.active_alloc_chain.get().as_ref() }?;
+        let callback_desc = alloc_chain.find_callback_descriptor(words[0])?;
+
+        let callback_ctx = SubprocessRtCallbackContext {
+            arg_regs: [words[1], words[2], words[3], words[4], words[5], words[6]],
+            stack_pointer: words[7] as *mut c_void,
+        };
+        let mut callback_ret = SubprocessRtCallbackReturn {
+            return_regs: [0; 2],
+        };
+
+        let mut inner_alloc_scope: AllocScope<'_, SubprocessRtAllocChain<'_>, ID> =
+            unsafe { AllocScope::new(SubprocessRtAllocChain::Cons(alloc_chain), self.id_imprint) };
+
+        unsafe {
+            callback_desc.invoke(
+                &callback_ctx,
+                &mut callback_ret,
+                &mut inner_alloc_scope as *mut _ as *mut (),
+                // Safe, as the only existing AccessScope<ID> is already
+                // borrowed by the `execute` call that is waiting for the
+                // foreign function to return.
+                &mut AccessScope::<ID>::new(self.id_imprint) as *mut _ as *mut (),
+            )
+        };
+
+        Some(callback_ret.return_regs)
+    }
+
+    // Forward a call of the foreign function to the child, and wait for it to
+    // return, serving any callbacks in the meantime. Returns `None` if the
+    // child process has terminated or violated the protocol.
+    fn invoke_rpc(
+        &self,
+        regs: &mut SubprocessRtInvokeRegs,
+        stack_args: &[usize],
+    ) -> Option<[usize; 2]> {
+        if self.terminated.get() {
+            return None;
+        }
+
+        let mut rpc = |stack_args_ptr: *mut ()| {
+            let mut words = [0; SUBPROCESS_RT_MSG_WORDS];
+            words[0] = self.target.get() as usize;
+            words[1..7].copy_from_slice(&regs.int_regs);
+            words[7] = stack_args_ptr as usize;
+            words[8] = stack_args.len();
+            words[9] = regs.vector_count;
+            words[10..18].copy_from_slice(&regs.vector_regs);
+            if !subprocess_rt_send(self.socket.get(), SubprocessRtMsgKind::Invoke, words) {
+                return None;
+            }
+
+            loop {
+                if !self.wait_for_child() {
+                    self.timed_out.set(true);
+                    return None;
+                }
+
+                match subprocess_rt_recv(self.socket.get())? {
+                    (SubprocessRtMsgKind::Return, words) => {
+                        regs.vector_regs[..2].copy_from_slice(&words[2..4]);
+                        return Some([words[0], words[1]]);
+                    }
+
+                    (SubprocessRtMsgKind::Callback, words) => {
+                        let [reg0, reg1] = self.dispatch_callback(&words)?;
+                        let mut reply = [0; SUBPROCESS_RT_MSG_WORDS];
+                        reply[0] = reg0;
+                        reply[1] = reg1;
+                        if !subprocess_rt_send(
+                            self.socket.get(),
+                            SubprocessRtMsgKind::CallbackReturn,
+                            reply,
+                        ) {
+                            return None;
+                        }
+                    }
+
+                    _ => return None,
+                }
+            }
+        };
+
+        // Copy the stack-spilled arguments into the shared region, where they
+        // remain for the duration of the call:
+        let res = if stack_args.is_empty() {
+            rpc(core::ptr::null_mut())
+        } else {
+            self.allocate_stacked_untracked_mut(
+                core::alloc::Layout::for_value(stack_args),
+                |stack_args_ptr| {
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            stack_args.as_ptr(),
+                            stack_args_ptr as *mut usize,
+                            stack_args.len(),
+                        )
+                    };
+                    rpc(stack_args_ptr)
+                },
+            )
+            .ok()
+            .flatten()
+        };
+
+        if res.is_none() {
+            self.terminate();
+        }
+
+        res
+    }
+
+    // Wait for the child to send a message, until the deadline of the current
+    // call (if any). Returns `false` once this deadline has expired:
+    fn wait_for_child(&self) -> bool {
+        let Some(deadline) = self.deadline.get() else {
+            return true;
+        };
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = remaining
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(c_int::MAX as u128);
+
+            let mut pollfd = libc::pollfd {
+                fd: self.socket.get(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms as c_int) } {
+                0 if remaining.is_zero() => return false,
+                // Woken up early, or interrupted by a signal:
+                0 => continue,
+                res if res < 0
+                    && std::io::Error::last_os_error().kind()
+                        == std::io::ErrorKind::Interrupted =>
+                {
+                    continue;
+                }
+                // The socket is readable, or in an error state that the
+                // subsequent `recv` will report:
+                _ => return true,
+            }
+        }
+    }
+
+    fn execute_int<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, ID>,
+        access_scope: &mut AccessScope<ID>,
+        timeout: Option<Duration>,
+        f: F,
+    ) -> OGResult<R> {
+        self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
+
+        if self.terminated.get() {
+            return Err(OGError::ForeignDomainTerminated);
+        }
+
+        // Picked up by the `invoke` trampoline. Callbacks may execute further
+        // foreign functions, so restore the previous values afterwards. Nested
+        // calls must also complete before the deadline of the outer call:
+        let outer_target = self.target.replace(target_symbol);
+        let outer_alloc_chain = self
+            .active_alloc_chain
+            .replace((alloc_scope.tracker() as *const SubprocessRtAllocChain<'_>).cast());
+        let outer_deadline = self.deadline.get();
+        if let Some(timeout) = timeout {
+            let deadline = Instant::now() + timeout;
+            self.deadline.set(Some(
+                outer_deadline.map_or(deadline, |outer| outer.min(deadline)),
+            ));
+        }
+
+        let res = f();
+
+        self.target.set(outer_target);
+        self.active_alloc_chain.set(outer_alloc_chain);
+        self.deadline.set(outer_deadline);
+
+        let timed_out = self.timed_out.get();
+        if timed_out {
+            // The foreign call was aborted at an arbitrary point. While the
+            // child's memory is gone, the shared region may be inconsistent:
+            access_scope.poison();
+        }
+
+        // Re-raise any panic of a callback invoked within `f`, now that we're
+        // back on the host side:
+        if let Some(payload) = SUBPROCESS_RT_CALLBACK_PANIC.with(Cell::take) {
+            std::panic::resume_unwind(payload);
+        }
+
+        if timed_out {
+            Err(OGError::Timeout)
+        } else if self.terminated.get() {
+            Err(OGError::ForeignDomainTerminated)
+        } else {
+            Ok(res)
+        }
+    }
+
+    #[inline]
+    fn id_imprint_check(
+        &self,
+        alloc_scope: Option<
+            &mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, <Self as OGRuntime>::ID>,
+        >,
+        access_scope: Option<&mut AccessScope<<Self as OGRuntime>::ID>>,
+    ) -> OGResult<()> {
+        if alloc_scope.is_some_and(|s| self.id_imprint != s.id_imprint())
+            || access_scope.is_some_and(|s| self.id_imprint != s.id_imprint())
+        {
+            Err(OGError::IDMismatch)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<ID: OGID> Drop for SubprocessRt<ID> {
+    fn drop(&mut self) {
+        self.terminate();
+
+        // The template exits once we close its socket, which takes down the
+        // child process along with it. We don't unmap the shared region, as
+        // references into it may outlive this runtime. No references into the
+        // stack remain once the last `execute` call has returned:
+        unsafe {
+            if self.socket.get() >= 0 {
+                libc::close(self.socket.get());
+            }
+            libc::close(self.template_socket);
+            libc::waitpid(self.template, core::ptr::null_mut(), 0);
+            libc::munmap(self.stack as *mut c_void, self.stack_len);
+        }
+    }
+}
+
+#[repr(C)]
+struct SubprocessRtInvokeRet {
+    rax: usize,
+    rdx: usize,
+}
+
+// The argument registers, as saved by the `invoke` trampoline:
+#[repr(C)]
+struct SubprocessRtInvokeRegs {
+    int_regs: [usize; 6],
+    // The number of vector registers used, passed in al:
+    vector_count: usize,
+    // The lower 64 bits of xmm0-xmm7. Once the call returns, the first two
+    // hold the returned xmm0 and xmm1:
+    vector_regs: [usize; 8],
+}
+
+unsafe extern "C" fn subprocess_rt_invoke_rpc<ID: OGID>(
+    rt: &SubprocessRt<ID>,
+    regs: &mut SubprocessRtInvokeRegs,
+    stack_args: *const usize,
+    stack_spill: usize,
+) -> SubprocessRtInvokeRet {
+    let stack_args = unsafe { core::slice::from_raw_parts(stack_args, stack_spill) };
+
+    let [rax, rdx] = rt.invoke_rpc(regs, stack_args).unwrap_or_else(|| {
+        regs.vector_regs[..2].fill(0);
+        [0; 2]
+    });
+    rt.ret.set([rax, rdx]);
+
+    SubprocessRtInvokeRet { rax, rdx }
+}
+
+pub struct SubprocessRtInvokeRes<T>(PhantomData<T>);
+
+unsafe impl<ID: OGID, T> SysVAMD64InvokeRes<SubprocessRt<ID>, T> for SubprocessRtInvokeRes<T> {
+    fn new() -> Self {
+        SubprocessRtInvokeRes(PhantomData)
+    }
+
+    fn into_result_registers(self, rt: &SubprocessRt<ID>) -> OGResult<OGRet<T>> {
+        if rt.terminated.get() {
+            return Err(OGError::ForeignDomainTerminated);
+        }
+
+        // Values larger than two registers must be returned on the stack:
+        OGRet::from_register_pair(rt.ret.get()).ok_or(OGError::InternalError)
+    }
+
+    unsafe fn into_result_stacked(
+        self,
+        rt: &SubprocessRt<ID>,
+        stacked_res: *mut T,
+    ) -> OGResult<OGRet<T>> {
+        if rt.terminated.get() {
+            return Err(OGError::ForeignDomainTerminated);
+        }
+
+        Ok(OGRet::from_initialized_memory(unsafe {
+            core::ptr::read(stacked_res as *const MaybeValid<T>)
+        }))
+    }
+}
+
+impl<ID: OGID> SysVAMD64BaseRt for SubprocessRt<ID> {
+    type InvokeRes<T> = SubprocessRtInvokeRes<T>;
+}
+
+impl<ID: OGID, const STACK_SPILL: usize, RTLOC: ArgumentSlot> SysVAMD64Rt<STACK_SPILL, RTLOC>
+    for SubprocessRt<ID>
+{
+    #[unsafe(naked)]
+    unsafe extern "C" fn invoke() {
+        core::arch::naked_asm!(
+            "
+            // Set up a stack frame, and save all argument registers in a
+            // `SubprocessRtInvokeRegs` on the stack. This leaves the stack
+            // pointer 16-byte aligned:
+            push rbp
+            mov rbp, rsp
+            sub rsp, 128
+            mov qword ptr [rsp], rdi
+            mov qword ptr [rsp + 8], rsi
+            mov qword ptr [rsp + 16], rdx
+            mov qword ptr [rsp + 24], rcx
+            mov qword ptr [rsp + 32], r8
+            mov qword ptr [rsp + 40], r9
+            movzx eax, al
+            mov qword ptr [rsp + 48], rax
+            movq qword ptr [rsp + 56], xmm0
+            movq qword ptr [rsp + 64], xmm1
+            movq qword ptr [rsp + 72], xmm2
+            movq qword ptr [rsp + 80], xmm3
+            movq qword ptr [rsp + 88], xmm4
+            movq qword ptr [rsp + 96], xmm5
+            movq qword ptr [rsp + 104], xmm6
+            movq qword ptr [rsp + 112], xmm7
+
+            // Load the runtime pointer from its argument slot:
+            .if {rtloc_stacked}
+            mov rdi, qword ptr [rbp + 16 + 8 * {rtloc_offset}]
+            .elseif {rtloc_reg_valid}
+            mov rdi, qword ptr [rsp + 8 * {rtloc_reg}]
+            .else
+            .error \"unsupported RTLOC argument slot\"
+            .endif
+
+            // Forward the call to the child process. The stack-spilled
+            // arguments start right above our return address:
+            mov rsi, rsp
+            lea rdx, [rbp + 16]
+            mov rcx, {stack_spill}
+            call {invoke_rpc}
+
+            // The integer return registers are already in rax and rdx:
+            movq xmm0, qword ptr [rsp + 56]
+            movq xmm1, qword ptr [rsp + 64]
+            mov rsp, rbp
+            pop rbp
+            ret
+            ",
+            rtloc_stacked = const RTLOC::IS_STACKED as usize,
+            rtloc_offset = const if RTLOC::IS_STACKED {
+                RTLOC::STACK_OFFSET_WORDS
+            } else {
+                0
+            },
+            rtloc_reg_valid = const (sysv_amd64_areg_index::<RTLOC>() != usize::MAX) as usize,
+            rtloc_reg = const if sysv_amd64_areg_index::<RTLOC>() != usize::MAX {
+                sysv_amd64_areg_index::<RTLOC>()
+            } else {
+                0
+            },
+            stack_spill = const STACK_SPILL,
+            invoke_rpc = sym subprocess_rt_invoke_rpc::<ID>,
+        );
+    }
+}
+
+unsafe impl<ID: OGID> OGRuntime for SubprocessRt<ID> {
+    type ID = ID;
+    type AllocTracker<'a> = SubprocessRtAllocChain<'a>;
+    type ABI = SysVAMD64ABI;
+    type CallbackTrampolineFn = CallbackTrampolineFn;
+    type CallbackContext = SubprocessRtCallbackContext;
+    type CallbackReturn = SubprocessRtCallbackReturn;
+
+    type SymbolTableState<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> =
+        SubprocessRtSymbolTableState<SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>;
+
+    fn resolve_symbols<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        symbol_table: &'a [&'a CStr; SYMTAB_SIZE],
+        fixed_offset_symbol_table: &'a [Option<&'a CStr>; FIXED_OFFSET_SYMTAB_SIZE],
+    ) -> Result<
+        Self::SymbolTableState<'a, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+        Option<&'a core::ffi::CStr>,
+    > {
+        // Symbol names are passed to the child through the shared region. The
+        // resolved addresses are only meaningful within the child process:
+        let resolve = |name: &'a CStr| -> Result<*const (), Option<&'a CStr>> {
+            let name_bytes = name.to_bytes_with_nul();
+            let addr = self
+                .allocate_stacked_untracked_mut(
+                    core::alloc::Layout::for_value(name_bytes),
+                    |name_ptr| {
+                        unsafe {
+                            core::ptr::copy_nonoverlapping(
+                                name_bytes.as_ptr(),
+                                name_ptr as *mut u8,
+                                name_bytes.len(),
+                            )
+                        };
+
+                        let mut words = [0; SUBPROCESS_RT_MSG_WORDS];
+                        words[0] = name_ptr as usize;
+                        if !subprocess_rt_send(
+                            self.socket.get(),
+                            SubprocessRtMsgKind::Resolve,
+                            words,
+                        ) {
+                            return None;
+                        }
+
+                        match subprocess_rt_recv(self.socket.get()) {
+                            Some((SubprocessRtMsgKind::Resolved, words)) => Some(words[0]),
+                            _ => None,
+                        }
+                    },
+                )
+                .map_err(|_| None)?
+                .ok_or_else(|| {
+                    self.terminate();
+                    None
+                })?;
+
+            if addr == 0 {
+                Err(Some(name))
+            } else {
+                Ok(addr as *const ())
+            }
+        };
+
+        if self.terminated.get() {
+            return Err(None);
+        }
+
+        let mut symbols = [core::ptr::null(); SYMTAB_SIZE];
+        for (dst, name) in symbols.iter_mut().zip(symbol_table.iter()) {
+            *dst = resolve(name)?;
+        }
+
+        let mut fixed_offset_symbols = [None; FIXED_OFFSET_SYMTAB_SIZE];
+        for (dst, name_opt) in fixed_offset_symbols
+            .iter_mut()
+            .zip(fixed_offset_symbol_table.iter())
+        {
+            if let Some(name) = name_opt {
+                *dst = Some(resolve(name)?);
+            }
+        }
+
+        Ok(SubprocessRtSymbolTableState {
+            symbols,
+            fixed_offset_symbols,
+        })
+    }
+
+    fn lookup_symbol<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        compact_symtab_index: usize,
+        fixed_offset_symtab_index: usize,
+        symtabstate: &Self::SymbolTableState<'_, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+    ) -> Option<*const ()> {
+        symtabstate
+            .symbols
+            .get(compact_symtab_index)
+            .copied()
+            .or_else(|| {
+                symtabstate
+                    .fixed_offset_symbols
+                    .get(fixed_offset_symtab_index)
+                    .copied()
+                    .flatten()
+            })
+    }
+
+    fn setup_callback<C, F, R>(
+        &self,
+        callback: &mut C,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &Self::CallbackContext,
+            &mut Self::CallbackReturn,
+            &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+            &mut AccessScope<Self::ID>,
+        ),
+        F: for<'b> FnOnce(
+            *const Self::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        ) -> R,
+    {
+        let typecast_callback =
+            &mut |callback_ctx: &SubprocessRtCallbackContext,
+                  callback_ret: &mut SubprocessRtCallbackReturn,
+                  alloc_scope_ptr: *mut (),
+                  access_scope_ptr: *mut ()| {
+                let alloc_scope = unsafe {
+                    &mut *(alloc_scope_ptr as *mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>)
+                };
+
+                let access_scope =
+                    unsafe { &mut *(access_scope_ptr as *mut AccessScope<Self::ID>) };
+
+                callback(callback_ctx, callback_ret, alloc_scope, access_scope);
+            };
+
+        // See `MockRt::setup_callback` on why we erase the scopes' types here:
+        self.setup_callback_int(typecast_callback, alloc_scope, fun)
+    }
+
+    fn execute<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        f: F,
+    ) -> OGResult<R> {
+        self.execute_int(target_symbol, alloc_scope, access_scope, None, f)
+    }
+
+    const SUPPORTS_DEADLINE: bool = true;
+
+    fn execute_with_deadline<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        timeout: Duration,
+        f: F,
+    ) -> OGResult<R> {
+        self.execute_int(target_symbol, alloc_scope, access_scope, Some(timeout), f)
+    }
+
+    fn allocate_stacked_untracked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: FnOnce(*mut ()) -> R,
+    {
+        if layout.size() == 0 {
+            return Err(OGError::AllocInvalidLayout);
+        }
+
+        let region_start = self.shared_region as usize;
+        let region_end = region_start + self.shared_region_len;
+
+        let top = self.shared_region_top.get();
+        let alloc_start = (region_start + top)
+            .checked_next_multiple_of(layout.align())
+            .ok_or(OGError::AllocNoMem)?;
+        let alloc_end = alloc_start
+            .checked_add(layout.size())
+            .filter(|end| *end <= region_end)
+            .ok_or(OGError::AllocNoMem)?;
+
+        self.shared_region_top.set(alloc_end - region_start);
+        let res = fun(alloc_start as *mut ());
+        self.shared_region_top.set(top);
+
+        Ok(res)
+    }
+
+    fn allocate_stacked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: for<'b> FnOnce(*mut (), &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>) -> R,
+    {
+        self.id_imprint_check(Some(alloc_scope), None)?;
+
+        // Stacked allocations are placed in the shared region, and thus already
+        // valid for upgrades. There's no need to track them separately:
+        self.allocate_stacked_untracked_mut(layout, move |ptr| fun(ptr, alloc_scope))
+    }
+}
+
+// Call the two-argument function `symbol` through the `invoke` trampoline:
+#[cfg(all(test, feature = "runtime_id"))]
+fn test_invoke<ID: OGID>(
+    rt: &SubprocessRt<ID>,
+    symbol: *const (),
+    args: [usize; 2],
+    alloc_scope: &mut AllocScope<'_, SubprocessRtAllocChain<'_>, ID>,
+    access_scope: &mut AccessScope<ID>,
+    timeout: Option<Duration>,
+) -> OGResult<usize> {
+    use crate::abi::calling_convention::AREG2;
+
+    let invoke: unsafe extern "C" fn(usize, usize, *const SubprocessRt<ID>) -> usize = unsafe {
+        core::mem::transmute(
+            <SubprocessRt<ID> as SysVAMD64Rt<0, AREG2<SysVAMD64ABI>>>::invoke
+                as unsafe extern "C" fn(),
+        )
+    };
+    rt.execute_int(symbol, alloc_scope, access_scope, timeout, || unsafe {
+        invoke(args[0], args[1], rt)
+    })
+}
+
+#[cfg(all(feature = "runtime_id", target_env = "gnu"))]
+#[test]
+fn test_respawn_after_timeout() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let library = crate::util::test_lib::compile(
+        "subprocess_respawn",
+        "
+        static unsigned long counter;
+
+        unsigned long increment(unsigned long by) {
+            counter += by;
+            return counter;
+        }
+
+        unsigned long spin(unsigned long arg) {
+            for (;;) {
+                __asm__ volatile (\"\" ::: \"memory\");
+            }
+        }
+        ",
+    );
+
+    let (mut rt, mut alloc_scope, mut access_scope) =
+        unsafe { SubprocessRt::new(&library, 4096, 256 * 1024, OGRuntimeBranding::new()) }.unwrap();
+    let symtab = rt.resolve_symbols(&[c"increment", c"spin"], &[]).unwrap();
+    let increment = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let spin = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+
+    let mut call = |rt: &SubprocessRt<_>, symbol, arg, access_scope: &mut _, timeout| {
+        test_invoke(
+            rt,
+            symbol,
+            [arg, 0],
+            &mut alloc_scope,
+            access_scope,
+            timeout,
+        )
+    };
+
+    assert_eq!(call(&rt, increment, 1, &mut access_scope, None), Ok(1));
+    assert_eq!(call(&rt, increment, 2, &mut access_scope, None), Ok(3));
+
+    // Exceeding the deadline kills the child process:
+    assert_eq!(
+        call(
+            &rt,
+            spin,
+            0,
+            &mut access_scope,
+            Some(Duration::from_millis(50))
+        ),
+        Err(OGError::Timeout)
+    );
+    assert!(rt.is_terminated());
+    assert_eq!(
+        call(&rt, increment, 1, &mut access_scope, None),
+        Err(OGError::ForeignDomainTerminated)
+    );
+
+    // A respawned child starts out with the library in its initial state, and
+    // previously resolved symbols remain valid:
+    rt.respawn(&mut access_scope).unwrap();
+    assert!(!rt.is_terminated());
+    assert_eq!(call(&rt, increment, 5, &mut access_scope, None), Ok(5));
+
+    // Calls that complete before their deadline are unaffected by it:
+    assert_eq!(
+        call(
+            &rt,
+            increment,
+            1,
+            &mut access_scope,
+            Some(Duration::from_secs(10))
+        ),
+        Ok(6)
+    );
+
+    // Respawning a running child process replaces it as well:
+    rt.respawn(&mut access_scope).unwrap();
+    assert_eq!(call(&rt, increment, 1, &mut access_scope, None), Ok(1));
+}
+
+#[cfg(all(feature = "runtime_id", target_env = "gnu"))]
+#[test]
+fn test_variadic_invoke() {
+    use crate::abi::calling_convention::AREG1;
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let library = crate::util::test_lib::compile(
+        "subprocess_variadic",
+        "
+        #include <stdarg.h>
+
+        double sum(int count, ...) {
+            va_list args;
+            double sum = 0;
+
+            va_start(args, count);
+            for (int i = 0; i < count; i++) {
+                sum += va_arg(args, double);
+            }
+            va_end(args);
+
+            return sum;
+        }
+        ",
+    );
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        unsafe { SubprocessRt::new(&library, 4096, 256 * 1024, OGRuntimeBranding::new()) }.unwrap();
+    let symtab = rt.resolve_symbols(&[c"sum"], &[]).unwrap();
+    let sum = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+    // As a variadic function pointer, the compiler sets al to the number of
+    // vector registers used. The runtime pointer follows the doubles in rsi:
+    fn variadic_invoke<ID: OGID>(
+        _rt: &SubprocessRt<ID>,
+    ) -> unsafe extern "C" fn(c_int, ...) -> f64 {
+        unsafe {
+            core::mem::transmute(
+                <SubprocessRt<ID> as SysVAMD64Rt<0, AREG1<SysVAMD64ABI>>>::invoke
+                    as unsafe extern "C" fn(),
+            )
+        }
+    }
+    let invoke = variadic_invoke(&rt);
+
+    // The child only reads the vector argument registers that al covers, and
+    // returns the result in xmm0:
+    assert_eq!(
+        rt.execute(sum, &mut alloc_scope, &mut access_scope, || unsafe {
+            invoke(3, 1.5_f64, 2.25_f64, 4.0_f64, &rt as *const SubprocessRt<_>)
+        }),
+        Ok(7.75)
+    );
+    assert_eq!(
+        rt.execute(sum, &mut alloc_scope, &mut access_scope, || unsafe {
+            invoke(0, &rt as *const SubprocessRt<_>)
+        }),
+        Ok(0.0)
+    );
+}
+
+#[cfg(all(feature = "runtime_id", target_env = "gnu"))]
+#[test]
+fn test_fp_arguments() {
+    use core::ffi::c_long;
+
+    use crate::abi::calling_convention::AREG1;
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let library = crate::util::test_lib::compile(
+        "subprocess_fp_args",
+        "
+        double fp_args(double a, float b, long n, double c, double d, double e,
+                       double f, double g, double h) {
+            return a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f + 7 * g + 8 * h
+                + 100 * n;
+        }
+        ",
+    );
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        unsafe { SubprocessRt::new(&library, 4096, 256 * 1024, OGRuntimeBranding::new()) }.unwrap();
+    let symtab = rt.resolve_symbols(&[c"fp_args"], &[]).unwrap();
+    let fp_args = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+    // The floating-point arguments occupy all FPREG slots (xmm0-xmm7), the
+    // integer argument and runtime pointer the AREG0 and AREG1 slots:
+    type FpArgsFn<RT> =
+        unsafe extern "C" fn(f64, f32, c_long, f64, f64, f64, f64, f64, f64, *const RT) -> f64;
+    fn fp_args_invoke<ID: OGID>(_rt: &SubprocessRt<ID>) -> FpArgsFn<SubprocessRt<ID>> {
+        unsafe {
+            core::mem::transmute(
+                <SubprocessRt<ID> as SysVAMD64Rt<0, AREG1<SysVAMD64ABI>>>::invoke
+                    as unsafe extern "C" fn(),
+            )
+        }
+    }
+    let invoke = fp_args_invoke(&rt);
+
+    // Calls to functions with a fixed parameter list leave al undefined, so
+    // all vector argument registers must be forwarded to the child regardless:
+    assert_eq!(
+        rt.execute(fp_args, &mut alloc_scope, &mut access_scope, || unsafe {
+            invoke(1.0, 2.0, 3, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, &rt)
+        }),
+        Ok(504.0)
+    );
+}
+
+#[cfg(all(feature = "runtime_id", target_env = "gnu"))]
+#[test]
+fn test_callback_round_trip() {
+    use crate::id::runtime::OGRuntimeBranding;
+    use crate::rt::CallbackReturn;
+
+    let library = crate::util::test_lib::compile(
+        "subprocess_callback",
+        "
+        typedef unsigned long ul;
+
+        ul call_back(ul (*callback)(ul, ul, ul, ul, ul, ul, ul, ul), ul value) {
+            return callback(value, 1, 2, 3, 4, 5, 6, 7) + 1;
+        }
+
+        ul add_one(ul value) {
+            return value + 1;
+        }
+
+        ul crash(ul arg) {
+            *(volatile ul *) arg = 0;
+            return 0;
+        }
+        ",
+    );
+
+    let (mut rt, mut alloc_scope, mut access_scope) =
+        unsafe { SubprocessRt::new(&library, 4096, 256 * 1024, OGRuntimeBranding::new()) }.unwrap();
+    let symtab = rt
+        .resolve_symbols(&[c"call_back", c"add_one", c"crash"], &[])
+        .unwrap();
+    let call_back = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let add_one = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+    let crash = rt.lookup_symbol(2, usize::MAX, &symtab).unwrap();
+
+    // Callbacks receive their register and stack-spilled arguments, and may
+    // call back into the library:
+    let mut callback = |ctx: &SubprocessRtCallbackContext,
+                        ret: &mut SubprocessRtCallbackReturn,
+                        alloc_scope: &mut AllocScope<'_, SubprocessRtAllocChain<'_>, _>,
+                        access_scope: &mut AccessScope<_>| {
+        let value = ctx.get_argument_register(0).unwrap();
+        if value == 0 {
+            panic!("callback panic");
+        }
+
+        let stack_args = ctx.get_stack_pointer() as *const usize;
+        let mut sum = (1..6)
+            .map(|reg| ctx.get_argument_register(reg).unwrap())
+            .sum::<usize>();
+        sum += unsafe { stack_args.read() + stack_args.add(1).read() };
+
+        let value = test_invoke(&rt, add_one, [value, 0], alloc_scope, access_scope, None);
+        ret.set_return_register(0, value.unwrap() * 100 + sum);
+    };
+
+    rt.setup_callback(
+        &mut callback,
+        &mut alloc_scope,
+        |trampoline, alloc_scope| {
+            assert_eq!(
+                test_invoke(
+                    &rt,
+                    call_back,
+                    [trampoline as usize, 41],
+                    alloc_scope,
+                    &mut access_scope,
+                    None
+                ),
+                Ok(42 * 100 + 28 + 1)
+            );
+
+            // Panics of callbacks are resumed on the host side, while the child
+            // process continues running:
+            let panic = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+                test_invoke(
+                    &rt,
+                    call_back,
+                    [trampoline as usize, 0],
+                    alloc_scope,
+                    &mut access_scope,
+                    None,
+                )
+            }))
+            .unwrap_err();
+            assert_eq!(panic.downcast_ref::<&str>(), Some(&"callback panic"));
+            assert!(!rt.is_terminated());
+        },
+    )
+    .unwrap();
+
+    // A crashing child process terminates the foreign domain, until respawned:
+    assert_eq!(
+        test_invoke(
+            &rt,
+            crash,
+            [8, 0],
+            &mut alloc_scope,
+            &mut access_scope,
+            None
+        ),
+        Err(OGError::ForeignDomainTerminated)
+    );
+    assert!(rt.is_terminated());
+    assert_eq!(
+        test_invoke(
+            &rt,
+            add_one,
+            [1, 0],
+            &mut alloc_scope,
+            &mut access_scope,
+            None
+        ),
+        Err(OGError::ForeignDomainTerminated)
+    );
+
+    rt.respawn(&mut access_scope).unwrap();
+    assert_eq!(
+        test_invoke(
+            &rt,
+            add_one,
+            [1, 0],
+            &mut alloc_scope,
+            &mut access_scope,
+            None
+        ),
+        Ok(2)
+    );
+}
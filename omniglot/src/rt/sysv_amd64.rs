@@ -1,6 +1,7 @@
 // -*- fill-column: 80; -*-
 
 use crate::OGResult;
+use crate::abi::calling_convention::{ArgumentSlot, arg_reg_index};
 use crate::foreign_memory::og_ret::OGRet;
 use crate::rt::OGRuntime;
 
@@ -18,5 +19,54 @@ pub trait SysVAMD64BaseRt: OGRuntime<ABI = crate::abi::sysv_amd64::SysVAMD64ABI>
 pub trait SysVAMD64Rt<const STACK_SPILL: usize, RTLOC: crate::abi::calling_convention::ArgumentSlot>:
     SysVAMD64BaseRt
 {
+    /// Trampoline to be called in place of the foreign function, which then
+    /// calls the `target_symbol` passed to the enclosing
+    /// [`OGRuntime::execute`].
+    ///
+    /// Foreign functions may be variadic. Their arguments are placed as
+    /// assigned by a
+    /// [`SysVArgAllocator`](crate::abi::sysv_amd64::SysVArgAllocator), which
+    /// also provides the number of vector registers to pass in `al`.
+    /// Implementations which forward vector registers to the foreign function
+    /// must forward `al` along with them.
+    ///
+    /// `f32` and `f64` arguments are passed in the `FPREG0`-`FPREG7` slots
+    /// (`xmm0`-`xmm7`). Implementations must forward all of these slots
+    /// unmodified, irrespective of `al`, and return `f32` and `f64` results
+    /// in `xmm0` and `xmm1`.
+    ///
+    /// # Safety
+    ///
+    /// Must be called according to the System V AMD64 calling convention, with
+    /// `STACK_SPILL` words of arguments passed on the stack, and the `RTLOC`
+    /// argument slot holding a pointer to the runtime.
     unsafe extern "C" fn invoke();
 }
+
+/// Index of the argument register referred to by an `ArgumentSlot`, or
+/// `usize::MAX` if it does not refer to an argument register.
+pub const fn sysv_amd64_areg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&["rdi", "rsi", "rdx", "rcx", "r8", "r9"])
+}
+
+/// Index of the floating-point argument register referred to by an
+/// `ArgumentSlot`, or `usize::MAX` if it does not refer to one.
+pub const fn sysv_amd64_fpreg_index<S: ArgumentSlot>() -> usize {
+    arg_reg_index::<S>(&[
+        "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7",
+    ])
+}
+
+#[test]
+fn test_sysv_amd64_argument_slots() {
+    use crate::abi::calling_convention::{AREG2, FPREG0, FPREG7, Stacked};
+    use crate::abi::sysv_amd64::SysVAMD64ABI;
+
+    const {
+        assert!(sysv_amd64_fpreg_index::<FPREG0<SysVAMD64ABI>>() == 0);
+        assert!(sysv_amd64_fpreg_index::<FPREG7<SysVAMD64ABI>>() == 7);
+        assert!(sysv_amd64_fpreg_index::<AREG2<SysVAMD64ABI>>() == usize::MAX);
+        assert!(sysv_amd64_fpreg_index::<Stacked<0, SysVAMD64ABI>>() == usize::MAX);
+        assert!(sysv_amd64_areg_index::<FPREG0<SysVAMD64ABI>>() == usize::MAX);
+    }
+}
@@ -0,0 +1,1279 @@
+// -*- fill-column: 80; -*-
+
+//! A runtime executing foreign libraries compiled to WebAssembly.
+//!
+//! [`WasmRt`] instantiates a `wasm32` module in the embedded `wasmi`
+//! interpreter. The module's linear memory forms the foreign domain: upgrades of
+//! foreign pointers are bounds-checked against it, and stacked allocations are
+//! carved from the module's shadow stack. As foreign code can only ever access
+//! its own linear memory, this provides a portable sandbox that does not rely
+//! on any hardware memory protection mechanisms.
+//!
+//! # Module requirements
+//!
+//! The module must not have any imports (such as WASI functions), as the runtime
+//! does not provide any. [`WasmRt::new`] rejects such modules with
+//! [`OGError::LibraryUnsupportedImport`]. Host functions are only available
+//! to foreign code as callbacks, through function pointers (see below). The
+//! module must further export:
+//!
+//! - its linear memory as `memory`,
+//! - its shadow stack pointer global as `__stack_pointer`, and
+//! - (to use callbacks) its function table as `__indirect_function_table`.
+//!
+//! When linking with `wasm-ld`, these can be exported through the
+//! `--export=__stack_pointer` and `--export-table` flags. If the module further
+//! exports a `__stack_low` or `__data_end` global, stacked allocations are
+//! prevented from growing the shadow stack beyond it.
+//!
+//! To be able to hand out stable host pointers into linear memory, the runtime
+//! grows the module's memory to a fixed size on creation, and prevents it from
+//! growing any further: `memory.grow` instructions fail, returning `-1`.
+//!
+//! # Traps
+//!
+//! When foreign code traps, its execution is aborted and the enclosing
+//! [`OGRuntime::execute`] call returns [`OGError::ForeignTrap`]. Foreign code may
+//! have left linear memory in an inconsistent state, so the runtime poisons the
+//! foreign domain (see [`AccessScope::poison`]). The shadow stack pointer is
+//! reset to its value at the start of the aborted call.
+//!
+//! # Callbacks
+//!
+//! Function pointers are indices into the module's function table, and calls
+//! through them are type-checked against the function's signature. Callbacks
+//! are therefore placed in the table as host functions of an explicit
+//! signature, which must be provided through [`WasmRt::with_callback_type`].
+
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::ffi::{CStr, c_void};
+use core::marker::PhantomData;
+
+use std::vec::Vec;
+
+use wasmi::core::{F32, F64, ValType};
+use wasmi::{
+    AsContextMut, Caller, Engine, Func, FuncRef, FuncType, Global, Instance, Linker, Memory,
+    Module, Store, StoreContextMut, StoreLimits, StoreLimitsBuilder, Table, Val,
+};
+
+use crate::abi::wasm32::Wasm32ABI;
+use crate::alloc_tracker::AllocTracker;
+use crate::foreign_memory::og_ret::OGRet;
+use crate::id::OGID;
+use crate::markers::{AccessScope, AllocScope};
+use crate::maybe_valid::MaybeValid;
+use crate::rt::wasm32::{Wasm32InvokeRes, Wasm32Rt, Wasm32Val};
+use crate::rt::{CallbackContext, CallbackReturn, OGRuntime};
+use crate::{OGError, OGResult};
+
+const WASM_PAGE_SIZE: usize = 65536;
+
+/// Pointers to this type are indices into the module's function table, which
+/// is how function pointers are represented in WebAssembly.
+pub enum WasmRtCallbackTrampolineFn {}
+
+type WasmRtDispatchFn = unsafe fn(
+    *const (),
+    &mut Caller<'_, WasmRtStoreData>,
+    usize,
+    &[Val],
+    &mut [Val],
+) -> Result<(), wasmi::Error>;
+
+pub struct WasmRtStoreData {
+    limits: StoreLimits,
+    // Set for the duration of an `execute` call, to dispatch callbacks to the
+    // `WasmRt` (which may have moved since its creation):
+    dispatch: Option<(*const (), WasmRtDispatchFn)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WasmRtCallbackContext {
+    // Parameters of the callback, in order. Floating-point values are
+    // represented by their bit patterns:
+    pub arg_regs: [usize; 8],
+    stack_pointer: *mut c_void,
+}
+
+impl CallbackContext for WasmRtCallbackContext {
+    fn get_argument_register(&self, reg: usize) -> Option<usize> {
+        self.arg_regs.get(reg).copied()
+    }
+
+    fn get_fp_argument_register(&self, reg: usize) -> Option<u64> {
+        // WebAssembly does not distinguish register files, floating-point
+        // parameters are stored in `arg_regs` by their position:
+        self.arg_regs.get(reg).map(|v| *v as u64)
+    }
+
+    fn get_stack_pointer(&self) -> *mut c_void {
+        self.stack_pointer
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WasmRtCallbackReturn {
+    pub return_regs: [usize; 2],
+}
+
+impl CallbackReturn for WasmRtCallbackReturn {
+    fn set_return_register(&mut self, reg: usize, value: usize) -> bool {
+        if let Some(r) = self.return_regs.get_mut(reg) {
+            *r = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_fp_return_register(&mut self, reg: usize, value: u64) -> bool {
+        self.set_return_register(reg, value as usize)
+    }
+}
+
+#[derive(Debug)]
+pub struct WasmRtCallbackDescriptor<'a> {
+    wrapper: unsafe extern "C" fn(
+        *mut c_void,
+        &WasmRtCallbackContext,
+        &mut WasmRtCallbackReturn,
+        *mut (),
+        *mut (),
+    ),
+    context: *mut c_void,
+    _lt: PhantomData<&'a mut c_void>,
+}
+
+impl WasmRtCallbackDescriptor<'_> {
+    unsafe fn invoke(
+        &self,
+        callback_ctx: &WasmRtCallbackContext,
+        callback_ret: &mut WasmRtCallbackReturn,
+        alloc_scope: *mut (),
+        access_scope: *mut (),
+    ) {
+        unsafe {
+            (self.wrapper)(
+                self.context,
+                callback_ctx,
+                callback_ret,
+                alloc_scope,
+                access_scope,
+            )
+        }
+    }
+}
+
+/// Allocation tracker of the [`WasmRt`].
+///
+/// Foreign code can access its entire linear memory, so upgrades are validated
+/// against its bounds only. The remaining elements of this list track the
+/// callbacks currently available to foreign code.
+#[derive(Debug)]
+pub enum WasmRtAllocChain<'a> {
+    LinearMemory {
+        start: usize,
+        len: usize,
+    },
+    Callback(
+        usize,
+        WasmRtCallbackDescriptor<'a>,
+        &'a WasmRtAllocChain<'a>,
+    ),
+    Cons(&'a WasmRtAllocChain<'a>),
+}
+
+struct WasmRtAllocChainIter<'a>(Option<&'a WasmRtAllocChain<'a>>);
+
+impl<'a> Iterator for WasmRtAllocChainIter<'a> {
+    type Item = &'a WasmRtAllocChain<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(cur) = self.0 {
+            self.0 = match cur {
+                WasmRtAllocChain::LinearMemory { .. } => None,
+                WasmRtAllocChain::Callback(_, _, pred) => Some(pred),
+                WasmRtAllocChain::Cons(pred) => Some(pred),
+            };
+
+            Some(cur)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> WasmRtAllocChain<'a> {
+    fn iter(&'a self) -> WasmRtAllocChainIter<'a> {
+        WasmRtAllocChainIter(Some(self))
+    }
+
+    fn is_valid_int(&self, ptr: usize, len: usize) -> bool {
+        self.iter().any(|elem| match elem {
+            WasmRtAllocChain::LinearMemory {
+                start,
+                len: memory_len,
+            } => {
+                ptr >= *start
+                    && ptr
+                        .checked_add(len)
+                        .is_some_and(|end| end <= start + memory_len)
+            }
+            WasmRtAllocChain::Callback(_, _, _) => false,
+            WasmRtAllocChain::Cons(_) => false,
+        })
+    }
+
+    fn next_callback_id(&self) -> usize {
+        self.iter()
+            .find_map(|elem| match elem {
+                WasmRtAllocChain::LinearMemory { .. } => None,
+                WasmRtAllocChain::Callback(id, _, _) => Some(id + 1),
+                WasmRtAllocChain::Cons(_) => None,
+            })
+            .unwrap_or(0)
+    }
+
+    fn find_callback_descriptor(&self, id: usize) -> Option<&WasmRtCallbackDescriptor<'_>> {
+        self.iter().find_map(|elem| match elem {
+            WasmRtAllocChain::LinearMemory { .. } => None,
+            WasmRtAllocChain::Callback(desc_id, desc, _) => {
+                if id == *desc_id {
+                    Some(desc)
+                } else {
+                    None
+                }
+            }
+            WasmRtAllocChain::Cons(_) => None,
+        })
+    }
+}
+
+unsafe impl AllocTracker for WasmRtAllocChain<'_> {
+    fn is_valid(&self, ptr: *const (), len: usize) -> bool {
+        self.is_valid_int(ptr as usize, len)
+    }
+
+    fn is_valid_mut(&self, ptr: *mut (), len: usize) -> bool {
+        // All of linear memory is mutable by foreign code:
+        self.is_valid_int(ptr as usize, len)
+    }
+}
+
+pub struct WasmRtSymbolTableState<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> {
+    symbols: [*const (); SYMTAB_SIZE],
+    fixed_offset_symbols: [Option<*const ()>; FIXED_OFFSET_SYMTAB_SIZE],
+}
+
+fn wasm_rt_val_to_usize(val: &Val) -> Option<usize> {
+    match val {
+        Val::I32(v) => Some(*v as u32 as usize),
+        Val::I64(v) => Some(*v as u64 as usize),
+        Val::F32(v) => Some(v.to_bits() as usize),
+        Val::F64(v) => Some(v.to_bits() as usize),
+        Val::FuncRef(_) | Val::ExternRef(_) => None,
+    }
+}
+
+fn wasm_rt_usize_to_val(ty: ValType, value: usize) -> Option<Val> {
+    match ty {
+        ValType::I32 => Some(Val::I32(value as u32 as i32)),
+        ValType::I64 => Some(Val::I64(value as u64 as i64)),
+        ValType::F32 => Some(Val::F32(F32::from_bits(value as u32))),
+        ValType::F64 => Some(Val::F64(F64::from_bits(value as u64))),
+        ValType::FuncRef | ValType::ExternRef => None,
+    }
+}
+
+fn wasm_rt_val_from_wasm32(val: Wasm32Val) -> Val {
+    match val {
+        Wasm32Val::I32(v) => Val::I32(v as i32),
+        Wasm32Val::I64(v) => Val::I64(v as i64),
+        Wasm32Val::F32(v) => Val::F32(F32::from(v)),
+        Wasm32Val::F64(v) => Val::F64(F64::from(v)),
+    }
+}
+
+fn wasm_rt_val_into_wasm32(val: &Val) -> Option<Wasm32Val> {
+    match val {
+        Val::I32(v) => Some(Wasm32Val::I32(*v as u32)),
+        Val::I64(v) => Some(Wasm32Val::I64(*v as u64)),
+        Val::F32(v) => Some(Wasm32Val::F32(f32::from(*v))),
+        Val::F64(v) => Some(Wasm32Val::F64(f64::from(*v))),
+        Val::FuncRef(_) | Val::ExternRef(_) => None,
+    }
+}
+
+// This is deliberately not generic over the runtime's `ID`, as host functions
+// must be `'static`. The runtime is instead located through the store's data:
+fn wasm_rt_callback_func(
+    ctx: impl AsContextMut<Data = WasmRtStoreData>,
+    ty: FuncType,
+    callback_id: usize,
+) -> Func {
+    Func::new(
+        ctx,
+        ty,
+        move |mut caller: Caller<'_, WasmRtStoreData>, params: &[Val], results: &mut [Val]| {
+            let (rt, dispatch) = caller
+                .data()
+                .dispatch
+                .ok_or_else(|| wasmi::Error::new("callback invoked outside of execute"))?;
+
+            unsafe { dispatch(rt, &mut caller, callback_id, params, results) }
+        },
+    )
+}
+
+unsafe fn wasm_rt_dispatch_callback<ID: OGID>(
+    rt: *const (),
+    caller: &mut Caller<'_, WasmRtStoreData>,
+    callback_id: usize,
+    params: &[Val],
+    results: &mut [Val],
+) -> Result<(), wasmi::Error> {
+    let rt = unsafe { &*(rt as *const WasmRt<ID>) };
+    rt.dispatch_callback(caller, callback_id, params, results)
+}
+
+pub struct WasmRt<ID: OGID> {
+    id_imprint: ID::Imprint,
+    store: UnsafeCell<Store<WasmRtStoreData>>,
+    instance: Instance,
+    memory_base: *mut u8,
+    memory_len: usize,
+    stack_pointer: Global,
+    stack_low: u32,
+    table: Option<Table>,
+    // Index of the first table slot used for callbacks:
+    callback_table_base: u32,
+    callback_funcs: RefCell<Vec<(FuncType, Func)>>,
+    callback_type: RefCell<FuncType>,
+    // Resolved exported functions. Symbols are indices into this vector, plus
+    // one:
+    funcs: RefCell<Vec<Func>>,
+    // State of the current `execute` call. These are nested by callbacks
+    // executing further foreign functions:
+    target: Cell<*const ()>,
+    active_alloc_chain: Cell<*const WasmRtAllocChain<'static>>,
+    active_caller: Cell<*mut Caller<'static, WasmRtStoreData>>,
+    ret: Cell<Option<Wasm32Val>>,
+    // Whether foreign code trapped within the current `execute` call:
+    trapped: Cell<bool>,
+}
+
+impl<ID: OGID> WasmRt<ID> {
+    /// Instantiate the WebAssembly module `wasm`, with a linear memory of
+    /// `memory_size` bytes.
+    ///
+    /// `memory_size` must be a multiple of the WebAssembly page size (64 KiB),
+    /// and at least as large as the module's initial memory size.
+    pub fn new(
+        wasm: &[u8],
+        memory_size: usize,
+        branding: ID,
+    ) -> OGResult<(
+        Self,
+        AllocScope<'static, WasmRtAllocChain<'static>, ID>,
+        AccessScope<ID>,
+    )> {
+        if memory_size == 0 || !memory_size.is_multiple_of(WASM_PAGE_SIZE) {
+            return Err(OGError::AllocInvalidLayout);
+        }
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).map_err(|_| OGError::LibraryLoadFailed)?;
+        if module.imports().len() != 0 {
+            return Err(OGError::LibraryUnsupportedImport);
+        }
+
+        let mut store = Store::new(
+            &engine,
+            WasmRtStoreData {
+                limits: StoreLimitsBuilder::new().memory_size(memory_size).build(),
+                dispatch: None,
+            },
+        );
+        store.limiter(|data| &mut data.limits);
+
+        let instance = Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|_| OGError::LibraryLoadFailed)?;
+
+        let memory: Memory = instance
+            .get_memory(&store, "memory")
+            .ok_or(OGError::LibraryLoadFailed)?;
+        let stack_pointer = instance
+            .get_global(&store, "__stack_pointer")
+            .ok_or(OGError::LibraryLoadFailed)?;
+        let stack_low = ["__stack_low", "__data_end"]
+            .iter()
+            .find_map(|name| instance.get_global(&store, name))
+            .and_then(|global| match global.get(&store) {
+                Val::I32(v) => Some(v as u32),
+                _ => None,
+            })
+            .unwrap_or(0);
+
+        // Grow the linear memory to its final size. Any further growth exceeds
+        // the store's limits, so its backing buffer will never be reallocated:
+        let current_len = memory.data(&store).len();
+        let additional_pages = memory_size
+            .checked_sub(current_len)
+            .map(|additional_len| additional_len / WASM_PAGE_SIZE)
+            .and_then(|pages| u32::try_from(pages).ok())
+            .and_then(wasmi::core::Pages::new)
+            .ok_or(OGError::AllocInvalidLayout)?;
+        if u32::from(additional_pages) > 0 {
+            memory
+                .grow(&mut store, additional_pages)
+                .map_err(|_| OGError::AllocNoMem)?;
+        }
+
+        let memory_data = memory.data_mut(&mut store);
+        let memory_base = memory_data.as_mut_ptr();
+        let memory_len = memory_data.len();
+
+        let table = instance.get_table(&store, "__indirect_function_table");
+        let callback_table_base = table.map_or(0, |table| table.size(&store));
+
+        let id_imprint = branding.get_imprint();
+
+        Ok((
+            WasmRt {
+                id_imprint,
+                store: UnsafeCell::new(store),
+                instance,
+                memory_base,
+                memory_len,
+                stack_pointer,
+                stack_low,
+                table,
+                callback_table_base,
+                callback_funcs: RefCell::new(Vec::new()),
+                callback_type: RefCell::new(FuncType::new([], [])),
+                funcs: RefCell::new(Vec::new()),
+                target: Cell::new(core::ptr::null()),
+                active_alloc_chain: Cell::new(core::ptr::null()),
+                active_caller: Cell::new(core::ptr::null_mut()),
+                ret: Cell::new(None),
+                trapped: Cell::new(false),
+            },
+            unsafe {
+                AllocScope::new(
+                    WasmRtAllocChain::LinearMemory {
+                        start: memory_base as usize,
+                        len: memory_len,
+                    },
+                    id_imprint,
+                )
+            },
+            unsafe { AccessScope::new(id_imprint) },
+        ))
+    }
+
+    /// Run `f`, with all callbacks set up in it having the signature `ty`.
+    ///
+    /// Callbacks are placed into the module's function table, and foreign code
+    /// can only call them through a matching function type.
+    pub fn with_callback_type<R>(&self, ty: FuncType, f: impl FnOnce() -> R) -> R {
+        let outer_ty = self.callback_type.replace(ty);
+        let res = f();
+        self.callback_type.replace(outer_ty);
+        res
+    }
+
+    // Run `f` on the store. While foreign code is calling back into the host,
+    // the store is exclusively borrowed by the interpreter, and we must access
+    // it through the callback's `Caller` instead:
+    fn with_context<R>(&self, f: impl FnOnce(StoreContextMut<'_, WasmRtStoreData>) -> R) -> R {
+        let caller = self.active_caller.get();
+        if caller.is_null() {
+            f(unsafe { &mut *self.store.get() }.as_context_mut())
+        } else {
+            f(unsafe { &mut *caller }.as_context_mut())
+        }
+    }
+
+    fn resolve_symbol(&self, name: &CStr) -> Option<*const ()> {
+        let name = name.to_str().ok()?;
+        let func = self.with_context(|ctx| self.instance.get_func(&ctx, name))?;
+
+        let mut funcs = self.funcs.borrow_mut();
+        funcs.push(func);
+        Some(funcs.len() as *const ())
+    }
+
+    // Place a host function for callback slot `callback_id` into the function
+    // table, and return its index:
+    fn callback_table_index(&self, callback_id: usize) -> OGResult<u32> {
+        let table = self.table.ok_or(OGError::SetupCallbackInsufficientSlots)?;
+        let table_index = u32::try_from(callback_id)
+            .ok()
+            .and_then(|id| self.callback_table_base.checked_add(id))
+            .ok_or(OGError::SetupCallbackInsufficientSlots)?;
+
+        let ty = self.callback_type.borrow().clone();
+        let mut callback_funcs = self.callback_funcs.borrow_mut();
+
+        // Host functions are never deallocated from the store, so reuse them
+        // across callbacks of the same slot and signature:
+        if let Some((slot_ty, _)) = callback_funcs.get(callback_id)
+            && *slot_ty == ty
+        {
+            return Ok(table_index);
+        }
+
+        self.with_context(|mut ctx| {
+            let table_size = table.size(&ctx);
+            if table_size <= table_index {
+                table
+                    .grow(
+                        &mut ctx,
+                        table_index + 1 - table_size,
+                        Val::FuncRef(FuncRef::null()),
+                    )
+                    .map_err(|_| OGError::SetupCallbackInsufficientSlots)?;
+            }
+
+            let func = wasm_rt_callback_func(&mut ctx, ty.clone(), callback_id);
+            table
+                .set(&mut ctx, table_index, Val::FuncRef(FuncRef::new(func)))
+                .map_err(|_| OGError::InternalError)?;
+
+            // Callback IDs are allocated in LIFO order, so this never leaves
+            // any gaps:
+            callback_funcs.truncate(callback_id);
+            callback_funcs.push((ty, func));
+
+            Ok(table_index)
+        })
+    }
+
+    fn setup_callback_int<'a, C, F, R>(
+        &self,
+        callback: &'a mut C,
+        alloc_scope: &mut AllocScope<
+            '_,
+            <Self as OGRuntime>::AllocTracker<'_>,
+            <Self as OGRuntime>::ID,
+        >,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &<Self as OGRuntime>::CallbackContext,
+            &mut <Self as OGRuntime>::CallbackReturn,
+            *mut (),
+            *mut (),
+        ),
+        F: for<'b> FnOnce(
+            *const <Self as OGRuntime>::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, <Self as OGRuntime>::ID>,
+        ) -> R,
+    {
+        self.id_imprint_check(Some(alloc_scope), None)?;
+
+        struct Context<'a, ClosureTy> {
+            closure: &'a mut ClosureTy,
+        }
+
+        unsafe extern "C" fn callback_wrapper<
+            'a,
+            ClosureTy: FnMut(&WasmRtCallbackContext, &mut WasmRtCallbackReturn, *mut (), *mut ()) + 'a,
+        >(
+            ctx_ptr: *mut c_void,
+            callback_ctx: &WasmRtCallbackContext,
+            callback_ret: &mut WasmRtCallbackReturn,
+            alloc_scope: *mut (),
+            access_scope: *mut (),
+        ) {
+            let ctx: &mut Context<'a, ClosureTy> =
+                unsafe { &mut *(ctx_ptr as *mut Context<'a, ClosureTy>) };
+
+            // For now, we assume that the function doesn't unwind:
+            (ctx.closure)(callback_ctx, callback_ret, alloc_scope, access_scope)
+        }
+
+        let callback_id = alloc_scope.tracker().next_callback_id();
+        let table_index = self.callback_table_index(callback_id)?;
+
+        let mut ctx: Context<'a, C> = Context { closure: callback };
+
+        let mut inner_alloc_scope = unsafe {
+            AllocScope::new(
+                WasmRtAllocChain::Callback(
+                    callback_id,
+                    WasmRtCallbackDescriptor {
+                        wrapper: callback_wrapper::<C>,
+                        context: &mut ctx as *mut _ as *mut c_void,
+                        _lt: PhantomData::<&'a mut c_void>,
+                    },
+                    alloc_scope.tracker(),
+                ),
+                alloc_scope.id_imprint(),
+            )
+        };
+
+        // Callbacks are only dispatched while the host is executing foreign
+        // code, which makes the `AllocScope` passed to `execute` available to
+        // the dispatcher:
+        Ok(fun(
+            table_index as usize as *const WasmRtCallbackTrampolineFn,
+            &mut inner_alloc_scope,
+        ))
+    }
+
+    fn dispatch_callback(
+        &self,
+        caller: &mut Caller<'_, WasmRtStoreData>,
+        callback_id: usize,
+        params: &[Val],
+        results: &mut [Val],
+    ) -> Result<(), wasmi::Error> {
+        let alloc_chain = unsafe { self.active_alloc_chain.get().as_ref() }
+            .ok_or_else(|| wasmi::Error::new("callback invoked outside of execute"))?;
+        let callback_desc = alloc_chain
+            .find_callback_descriptor(callback_id)
+            .ok_or_else(|| wasmi::Error::new("callback not found"))?;
+
+        let mut callback_ctx = WasmRtCallbackContext {
+            arg_regs: [0; 8],
+            stack_pointer: match self.stack_pointer.get(&*caller) {
+                Val::I32(sp) => self.foreign_to_host_ptr(sp as u32) as *mut c_void,
+                _ => core::ptr::null_mut(),
+            },
+        };
+        if params.len() > callback_ctx.arg_regs.len() {
+            return Err(wasmi::Error::new("too many callback parameters"));
+        }
+        for (reg, param) in callback_ctx.arg_regs.iter_mut().zip(params.iter()) {
+            *reg = wasm_rt_val_to_usize(param)
+                .ok_or_else(|| wasmi::Error::new("unsupported callback parameter type"))?;
+        }
+
+        let mut callback_ret = WasmRtCallbackReturn {
+            return_regs: [0; 2],
+        };
+
+        let mut inner_alloc_scope: AllocScope<'_, WasmRtAllocChain<'_>, ID> =
+            unsafe { AllocScope::new(WasmRtAllocChain::Cons(alloc_chain), self.id_imprint) };
+
+        // Nested calls into foreign code must go through the caller:
+        let outer_caller = self
+            .active_caller
+            .replace((caller as *mut Caller<'_, WasmRtStoreData>).cast());
+
+        unsafe {
+            callback_desc.invoke(
+                &callback_ctx,
+                &mut callback_ret,
+                &mut inner_alloc_scope as *mut _ as *mut (),
+                // Safe, as the only existing AccessScope<ID> is already
+                // borrowed by the `execute` call that is running foreign code.
+                &mut AccessScope::<ID>::new(self.id_imprint) as *mut _ as *mut (),
+            )
+        };
+
+        self.active_caller.set(outer_caller);
+
+        if results.len() > callback_ret.return_regs.len() {
+            return Err(wasmi::Error::new("too many callback results"));
+        }
+        for (result, reg) in results.iter_mut().zip(callback_ret.return_regs.iter()) {
+            *result = wasm_rt_usize_to_val(result.ty(), *reg)
+                .ok_or_else(|| wasmi::Error::new("unsupported callback result type"))?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn id_imprint_check(
+        &self,
+        alloc_scope: Option<
+            &mut AllocScope<'_, <Self as OGRuntime>::AllocTracker<'_>, <Self as OGRuntime>::ID>,
+        >,
+        access_scope: Option<&mut AccessScope<<Self as OGRuntime>::ID>>,
+    ) -> OGResult<()> {
+        if alloc_scope.is_some_and(|s| self.id_imprint != s.id_imprint())
+            || access_scope.is_some_and(|s| self.id_imprint != s.id_imprint())
+        {
+            Err(OGError::IDMismatch)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub struct WasmRtInvokeRes<T>(PhantomData<T>);
+
+unsafe impl<ID: OGID, T> Wasm32InvokeRes<WasmRt<ID>, T> for WasmRtInvokeRes<T> {
+    fn new() -> Self {
+        WasmRtInvokeRes(PhantomData)
+    }
+
+    fn into_result_value(self, rt: &WasmRt<ID>) -> OGResult<OGRet<T>> {
+        let mut bytes = [0_u8; 8];
+        let len = match rt.ret.get() {
+            None => 0,
+            Some(Wasm32Val::I32(v)) => {
+                bytes[..4].copy_from_slice(&v.to_ne_bytes());
+                4
+            }
+            Some(Wasm32Val::I64(v)) => {
+                bytes.copy_from_slice(&v.to_ne_bytes());
+                8
+            }
+            Some(Wasm32Val::F32(v)) => {
+                bytes[..4].copy_from_slice(&v.to_ne_bytes());
+                4
+            }
+            Some(Wasm32Val::F64(v)) => {
+                bytes.copy_from_slice(&v.to_ne_bytes());
+                8
+            }
+        };
+
+        // Larger values must be returned through a stacked allocation:
+        let size = core::mem::size_of::<T>();
+        if size > len {
+            return Err(OGError::InternalError);
+        }
+
+        Ok(OGRet::from_initialized_memory(MaybeValid::from_bytes(
+            &bytes[..size],
+        )))
+    }
+
+    unsafe fn into_result_stacked(
+        self,
+        _rt: &WasmRt<ID>,
+        stacked_res: *mut T,
+    ) -> OGResult<OGRet<T>> {
+        Ok(OGRet::from_initialized_memory(unsafe {
+            core::ptr::read(stacked_res as *const MaybeValid<T>)
+        }))
+    }
+}
+
+impl<ID: OGID> Wasm32Rt for WasmRt<ID> {
+    type InvokeRes<T> = WasmRtInvokeRes<T>;
+
+    fn invoke(&self, args: &[Wasm32Val]) -> OGResult<()> {
+        // Release the borrow before calling into foreign code, which may call
+        // back into the host and resolve further symbols:
+        let func = self
+            .funcs
+            .borrow()
+            .get((self.target.get() as usize).wrapping_sub(1))
+            .copied()
+            .ok_or(OGError::InternalError)?;
+
+        let inputs: Vec<Val> = args.iter().copied().map(wasm_rt_val_from_wasm32).collect();
+
+        self.with_context(|mut ctx| {
+            let ty = func.ty(&ctx);
+            if ty.params().len() != args.len()
+                || ty
+                    .params()
+                    .iter()
+                    .zip(inputs.iter())
+                    .any(|(param_ty, input)| *param_ty != input.ty())
+                || ty.results().len() > 1
+            {
+                return Err(OGError::InternalError);
+            }
+
+            let mut outputs: Vec<Val> = ty.results().iter().copied().map(Val::default).collect();
+            if func.call(&mut ctx, &inputs, &mut outputs).is_err() {
+                self.trapped.set(true);
+                return Err(OGError::ForeignTrap);
+            }
+
+            self.ret.set(
+                outputs
+                    .first()
+                    .map(|output| wasm_rt_val_into_wasm32(output).ok_or(OGError::InternalError))
+                    .transpose()?,
+            );
+
+            Ok(())
+        })
+    }
+
+    fn host_to_foreign_ptr(&self, ptr: *const ()) -> Option<u32> {
+        (ptr as usize)
+            .checked_sub(self.memory_base as usize)
+            .filter(|offset| *offset <= self.memory_len)
+            .and_then(|offset| u32::try_from(offset).ok())
+    }
+
+    fn foreign_to_host_ptr(&self, addr: u32) -> *mut () {
+        self.memory_base.wrapping_add(addr as usize) as *mut ()
+    }
+}
+
+unsafe impl<ID: OGID> OGRuntime for WasmRt<ID> {
+    type ID = ID;
+    type AllocTracker<'a> = WasmRtAllocChain<'a>;
+    type ABI = Wasm32ABI;
+    type CallbackTrampolineFn = WasmRtCallbackTrampolineFn;
+    type CallbackContext = WasmRtCallbackContext;
+    type CallbackReturn = WasmRtCallbackReturn;
+
+    type SymbolTableState<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize> =
+        WasmRtSymbolTableState<SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>;
+
+    fn resolve_symbols<'a, const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        symbol_table: &'a [&'a CStr; SYMTAB_SIZE],
+        fixed_offset_symbol_table: &'a [Option<&'a CStr>; FIXED_OFFSET_SYMTAB_SIZE],
+    ) -> Result<
+        Self::SymbolTableState<'a, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+        Option<&'a core::ffi::CStr>,
+    > {
+        let mut symbols = [core::ptr::null(); SYMTAB_SIZE];
+        for (dst, name) in symbols.iter_mut().zip(symbol_table.iter()) {
+            *dst = self.resolve_symbol(name).ok_or(Some(*name))?;
+        }
+
+        let mut fixed_offset_symbols = [None; FIXED_OFFSET_SYMTAB_SIZE];
+        for (dst, name_opt) in fixed_offset_symbols
+            .iter_mut()
+            .zip(fixed_offset_symbol_table.iter())
+        {
+            if let Some(name) = name_opt {
+                *dst = Some(self.resolve_symbol(name).ok_or(Some(*name))?);
+            }
+        }
+
+        Ok(WasmRtSymbolTableState {
+            symbols,
+            fixed_offset_symbols,
+        })
+    }
+
+    fn lookup_symbol<const SYMTAB_SIZE: usize, const FIXED_OFFSET_SYMTAB_SIZE: usize>(
+        &self,
+        compact_symtab_index: usize,
+        fixed_offset_symtab_index: usize,
+        symtabstate: &Self::SymbolTableState<'_, SYMTAB_SIZE, FIXED_OFFSET_SYMTAB_SIZE>,
+    ) -> Option<*const ()> {
+        symtabstate
+            .symbols
+            .get(compact_symtab_index)
+            .copied()
+            .or_else(|| {
+                symtabstate
+                    .fixed_offset_symbols
+                    .get(fixed_offset_symtab_index)
+                    .copied()
+                    .flatten()
+            })
+    }
+
+    fn setup_callback<C, F, R>(
+        &self,
+        callback: &mut C,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        C: FnMut(
+            &Self::CallbackContext,
+            &mut Self::CallbackReturn,
+            &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+            &mut AccessScope<Self::ID>,
+        ),
+        F: for<'b> FnOnce(
+            *const Self::CallbackTrampolineFn,
+            &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        ) -> R,
+    {
+        let typecast_callback =
+            &mut |callback_ctx: &WasmRtCallbackContext,
+                  callback_ret: &mut WasmRtCallbackReturn,
+                  alloc_scope_ptr: *mut (),
+                  access_scope_ptr: *mut ()| {
+                let alloc_scope = unsafe {
+                    &mut *(alloc_scope_ptr as *mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>)
+                };
+
+                let access_scope =
+                    unsafe { &mut *(access_scope_ptr as *mut AccessScope<Self::ID>) };
+
+                callback(callback_ctx, callback_ret, alloc_scope, access_scope);
+            };
+
+        // See `MockRt::setup_callback` on why we erase the scopes' types here:
+        self.setup_callback_int(typecast_callback, alloc_scope, fun)
+    }
+
+    fn execute<R, F: FnOnce() -> R>(
+        &self,
+        target_symbol: *const (),
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        access_scope: &mut AccessScope<Self::ID>,
+        f: F,
+    ) -> OGResult<R> {
+        self.id_imprint_check(Some(alloc_scope), Some(access_scope))?;
+
+        // Picked up by `invoke` and the callback dispatcher. Callbacks may
+        // execute further foreign functions, so restore the previous values
+        // afterwards:
+        let outer_target = self.target.replace(target_symbol);
+        let outer_alloc_chain = self
+            .active_alloc_chain
+            .replace((alloc_scope.tracker() as *const WasmRtAllocChain<'_>).cast());
+        let outer_dispatch = self.with_context(|mut ctx| {
+            ctx.data_mut().dispatch.replace((
+                self as *const Self as *const (),
+                wasm_rt_dispatch_callback::<ID> as WasmRtDispatchFn,
+            ))
+        });
+        let outer_trapped = self.trapped.replace(false);
+        let stack_pointer = self.with_context(|ctx| self.stack_pointer.get(&ctx));
+
+        let res = f();
+
+        self.with_context(|mut ctx| ctx.data_mut().dispatch = outer_dispatch);
+        self.target.set(outer_target);
+        self.active_alloc_chain.set(outer_alloc_chain);
+
+        // A trap leaves the calls enclosing this one (if any) running, but
+        // linear memory may be inconsistent for them as well:
+        let trapped = self.trapped.replace(outer_trapped || self.trapped.get());
+        if trapped {
+            // Foreign code was aborted at an arbitrary point, without
+            // restoring its shadow stack pointer:
+            self.with_context(|ctx| self.stack_pointer.set(ctx, stack_pointer))
+                .map_err(|_| OGError::InternalError)?;
+            access_scope.poison();
+            Err(OGError::ForeignTrap)
+        } else {
+            Ok(res)
+        }
+    }
+
+    fn allocate_stacked_untracked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: FnOnce(*mut ()) -> R,
+    {
+        if layout.size() == 0 {
+            return Err(OGError::AllocInvalidLayout);
+        }
+
+        // The shadow stack is kept 16-byte aligned:
+        let align = core::cmp::max(16, layout.align());
+
+        let sp = match self.with_context(|ctx| self.stack_pointer.get(&ctx)) {
+            Val::I32(sp) => sp as u32,
+            _ => return Err(OGError::InternalError),
+        };
+
+        let alloc_sp = u32::try_from(layout.size())
+            .ok()
+            .and_then(|size| sp.checked_sub(size))
+            .and_then(|alloc_sp| u32::try_from(align).ok().map(|a| alloc_sp & !(a - 1)))
+            .filter(|alloc_sp| *alloc_sp >= self.stack_low)
+            .ok_or(OGError::AllocNoMem)?;
+
+        self.with_context(|ctx| self.stack_pointer.set(ctx, Val::I32(alloc_sp as i32)))
+            .map_err(|_| OGError::InternalError)?;
+
+        let res = fun(self.foreign_to_host_ptr(alloc_sp));
+
+        self.with_context(|ctx| self.stack_pointer.set(ctx, Val::I32(sp as i32)))
+            .map_err(|_| OGError::InternalError)?;
+
+        Ok(res)
+    }
+
+    fn allocate_stacked_mut<F, R>(
+        &self,
+        layout: core::alloc::Layout,
+        alloc_scope: &mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>,
+        fun: F,
+    ) -> OGResult<R>
+    where
+        F: for<'b> FnOnce(*mut (), &'b mut AllocScope<'_, Self::AllocTracker<'_>, Self::ID>) -> R,
+    {
+        self.id_imprint_check(Some(alloc_scope), None)?;
+
+        // Stacked allocations are placed in linear memory, and thus already
+        // valid for upgrades. There's no need to track them separately:
+        self.allocate_stacked_untracked_mut(layout, move |ptr| fun(ptr, alloc_scope))
+    }
+}
+
+// A module exporting its memory (of one page), shadow stack pointer and
+// function table, along with the following functions:
+//
+//   (type $unary (func (param i32) (result i32)))
+//   (func $add_one (type $unary) (i32.add (local.get 0) (i32.const 1)))
+//   (func $trap (type $unary)
+//     (global.set $__stack_pointer
+//       (i32.sub (global.get $__stack_pointer) (i32.const 16)))
+//     unreachable)
+//   (func $call_back (param i32 i32) (result i32)
+//     (i32.add
+//       (call_indirect (type $unary) (local.get 1) (local.get 0))
+//       (i32.const 1)))
+//   (func $grow (type $unary) (memory.grow (local.get 0)))
+//   (func $load (type $unary) (i32.load (local.get 0)))
+#[cfg(test)]
+#[rustfmt::skip]
+const TEST_MODULE: &[u8] = &[
+    // magic, version
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    // type section: (i32) -> i32, (i32, i32) -> i32
+    0x01, 0x0c, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x02, 0x7f, 0x7f, 0x01,
+    0x7f,
+    // function section
+    0x03, 0x06, 0x05, 0x00, 0x00, 0x01, 0x00, 0x00,
+    // table section: funcref, min 1
+    0x04, 0x04, 0x01, 0x70, 0x00, 0x01,
+    // memory section: min 1 page
+    0x05, 0x03, 0x01, 0x00, 0x01,
+    // global section: (mut i32) initialized to 65536
+    0x06, 0x08, 0x01, 0x7f, 0x01, 0x41, 0x80, 0x80, 0x04, 0x0b,
+    // export section
+    0x07, 0x63, 0x08,
+    0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+    0x0f, b'_', b'_', b's', b't', b'a', b'c', b'k', b'_', b'p', b'o', b'i', b'n',
+    b't', b'e', b'r', 0x03, 0x00,
+    0x19, b'_', b'_', b'i', b'n', b'd', b'i', b'r', b'e', b'c', b't', b'_', b'f',
+    b'u', b'n', b'c', b't', b'i', b'o', b'n', b'_', b't', b'a', b'b', b'l', b'e',
+    0x01, 0x00,
+    0x07, b'a', b'd', b'd', b'_', b'o', b'n', b'e', 0x00, 0x00,
+    0x04, b't', b'r', b'a', b'p', 0x00, 0x01,
+    0x09, b'c', b'a', b'l', b'l', b'_', b'b', b'a', b'c', b'k', 0x00, 0x02,
+    0x04, b'g', b'r', b'o', b'w', 0x00, 0x03,
+    0x04, b'l', b'o', b'a', b'd', 0x00, 0x04,
+    // code section
+    0x0a, 0x30, 0x05,
+    0x07, 0x00, 0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b,
+    0x0a, 0x00, 0x23, 0x00, 0x41, 0x10, 0x6b, 0x24, 0x00, 0x00, 0x0b,
+    0x0c, 0x00, 0x20, 0x01, 0x20, 0x00, 0x11, 0x00, 0x00, 0x41, 0x01, 0x6a, 0x0b,
+    0x06, 0x00, 0x20, 0x00, 0x40, 0x00, 0x0b,
+    0x07, 0x00, 0x20, 0x00, 0x28, 0x02, 0x00, 0x0b,
+];
+
+// Call `symbol` through `Wasm32Rt::invoke`, and return its result:
+#[cfg(test)]
+fn test_invoke<ID: OGID>(
+    rt: &WasmRt<ID>,
+    symbol: *const (),
+    args: &[Wasm32Val],
+    alloc_scope: &mut AllocScope<'_, WasmRtAllocChain<'_>, ID>,
+    access_scope: &mut AccessScope<ID>,
+) -> OGResult<Option<Wasm32Val>> {
+    rt.execute(symbol, alloc_scope, access_scope, || rt.invoke(args))??;
+    Ok(rt.ret.get())
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_memory_grow() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        WasmRt::new(TEST_MODULE, 2 * WASM_PAGE_SIZE, OGRuntimeBranding::new()).unwrap();
+    let symtab = rt.resolve_symbols(&[c"grow"], &[]).unwrap();
+    let grow = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+
+    // The runtime grows linear memory to its final size:
+    let end = rt.foreign_to_host_ptr(2 * WASM_PAGE_SIZE as u32 - 8);
+    assert!(alloc_scope.tracker().is_valid_mut(end, 8));
+    assert!(!alloc_scope.tracker().is_valid_mut(end, 9));
+
+    // ... after which foreign code cannot grow it any further:
+    assert_eq!(
+        test_invoke(
+            &rt,
+            grow,
+            &[Wasm32Val::I32(1)],
+            &mut alloc_scope,
+            &mut access_scope
+        ),
+        Ok(Some(Wasm32Val::I32(u32::MAX)))
+    );
+
+    // Memory sizes beyond the wasm32 address space are rejected:
+    #[cfg(target_pointer_width = "64")]
+    for pages in [65538, 1 << 32, (1 << 32) + 2] {
+        assert!(matches!(
+            WasmRt::new(
+                TEST_MODULE,
+                pages * WASM_PAGE_SIZE,
+                OGRuntimeBranding::new()
+            ),
+            Err(OGError::AllocInvalidLayout)
+        ));
+    }
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_trap_poisons_domain() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        WasmRt::new(TEST_MODULE, WASM_PAGE_SIZE, OGRuntimeBranding::new()).unwrap();
+    let symtab = rt
+        .resolve_symbols(&[c"add_one", c"trap", c"load"], &[])
+        .unwrap();
+    let add_one = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let trap = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+    let load = rt.lookup_symbol(2, usize::MAX, &symtab).unwrap();
+    let stack_pointer =
+        |rt: &WasmRt<_>| rt.with_context(|ctx| wasm_rt_val_to_usize(&rt.stack_pointer.get(&ctx)));
+    let initial_stack_pointer = stack_pointer(&rt);
+
+    let mut call = |symbol, arg, access_scope: &mut _| {
+        test_invoke(
+            &rt,
+            symbol,
+            &[Wasm32Val::I32(arg)],
+            &mut alloc_scope,
+            access_scope,
+        )
+    };
+
+    // A trap poisons the domain, and resets the shadow stack pointer:
+    assert_eq!(call(trap, 0, &mut access_scope), Err(OGError::ForeignTrap));
+    assert!(access_scope.is_poisoned());
+    assert_eq!(stack_pointer(&rt), initial_stack_pointer);
+    unsafe { access_scope.unpoison() };
+
+    // Out-of-bounds accesses trap as well:
+    assert_eq!(
+        call(load, WASM_PAGE_SIZE as u32 - 2, &mut access_scope),
+        Err(OGError::ForeignTrap)
+    );
+    assert!(access_scope.is_poisoned());
+    unsafe { access_scope.unpoison() };
+
+    assert_eq!(
+        call(add_one, 41, &mut access_scope),
+        Ok(Some(Wasm32Val::I32(42)))
+    );
+    assert!(!access_scope.is_poisoned());
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_deadline_unsupported() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        WasmRt::new(TEST_MODULE, WASM_PAGE_SIZE, OGRuntimeBranding::new()).unwrap();
+    let symtab = rt.resolve_symbols(&[c"add_one"], &[]).unwrap();
+    let add_one = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    const { assert!(!<WasmRt<OGRuntimeBranding> as OGRuntime>::SUPPORTS_DEADLINE) };
+
+    // Deadlines are rejected instead of silently being ignored, without
+    // running the foreign code:
+    assert_eq!(
+        rt.execute_with_deadline(
+            add_one,
+            &mut alloc_scope,
+            &mut access_scope,
+            core::time::Duration::from_millis(10),
+            || -> () { unreachable!() },
+        ),
+        Err(OGError::DeadlineUnsupported)
+    );
+    assert!(!access_scope.is_poisoned());
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_callback() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    let (rt, mut alloc_scope, mut access_scope) =
+        WasmRt::new(TEST_MODULE, WASM_PAGE_SIZE, OGRuntimeBranding::new()).unwrap();
+    let symtab = rt
+        .resolve_symbols(&[c"call_back", c"add_one", c"trap"], &[])
+        .unwrap();
+    let call_back = rt.lookup_symbol(0, usize::MAX, &symtab).unwrap();
+    let add_one = rt.lookup_symbol(1, usize::MAX, &symtab).unwrap();
+    let trap = rt.lookup_symbol(2, usize::MAX, &symtab).unwrap();
+
+    // Callbacks receive their parameters in order, and may call back into the
+    // module. A trap in such a nested call poisons the enclosing call as well:
+    let mut callback = |ctx: &WasmRtCallbackContext,
+                        ret: &mut WasmRtCallbackReturn,
+                        alloc_scope: &mut AllocScope<'_, WasmRtAllocChain<'_>, _>,
+                        access_scope: &mut AccessScope<_>| {
+        let arg = ctx.get_argument_register(0).unwrap() as u32;
+        let symbol = if arg == 0 { trap } else { add_one };
+        let res = test_invoke(
+            &rt,
+            symbol,
+            &[Wasm32Val::I32(arg)],
+            alloc_scope,
+            access_scope,
+        );
+        let value = match res {
+            Ok(Some(Wasm32Val::I32(value))) => value * 10,
+            _ => 0,
+        };
+        ret.set_return_register(0, value as usize);
+    };
+
+    let unary = FuncType::new([ValType::I32], [ValType::I32]);
+    rt.with_callback_type(unary, || {
+        rt.setup_callback(
+            &mut callback,
+            &mut alloc_scope,
+            |trampoline, alloc_scope| {
+                let mut call = |arg, access_scope: &mut _| {
+                    test_invoke(
+                        &rt,
+                        call_back,
+                        &[Wasm32Val::I32(trampoline as u32), Wasm32Val::I32(arg)],
+                        alloc_scope,
+                        access_scope,
+                    )
+                };
+
+                assert_eq!(call(41, &mut access_scope), Ok(Some(Wasm32Val::I32(421))));
+
+                assert_eq!(call(0, &mut access_scope), Err(OGError::ForeignTrap));
+                assert!(access_scope.is_poisoned());
+            },
+        )
+    })
+    .unwrap();
+}
+
+#[cfg(feature = "runtime_id")]
+#[test]
+fn test_imports_rejected() {
+    use crate::id::runtime::OGRuntimeBranding;
+
+    // (module (import "wasi_snapshot_preview1" "proc_exit" (func (param i32))))
+    #[rustfmt::skip]
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x01, 0x7f, 0x00,
+        0x02, 0x24, 0x01,
+        0x16, b'w', b'a', b's', b'i', b'_', b's', b'n', b'a', b'p', b's', b'h', b'o',
+        b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w', b'1',
+        0x09, b'p', b'r', b'o', b'c', b'_', b'e', b'x', b'i', b't', 0x00, 0x00,
+    ];
+
+    assert!(matches!(
+        WasmRt::new(MODULE, WASM_PAGE_SIZE, OGRuntimeBranding::new()),
+        Err(OGError::LibraryUnsupportedImport)
+    ));
+}
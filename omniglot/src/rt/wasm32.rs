@@ -0,0 +1,57 @@
+// -*- fill-column: 80; -*-
+
+use crate::OGResult;
+use crate::foreign_memory::og_ret::OGRet;
+use crate::rt::OGRuntime;
+
+/// A parameter or result value of a WebAssembly function.
+///
+/// Pointers into the foreign domain are passed as `I32` linear memory
+/// addresses, which can be translated using
+/// [`Wasm32Rt::host_to_foreign_ptr`] and [`Wasm32Rt::foreign_to_host_ptr`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wasm32Val {
+    I32(u32),
+    I64(u64),
+    F32(f32),
+    F64(f64),
+}
+
+/// Retrieves the result of a function called through [`Wasm32Rt::invoke`].
+///
+/// # Safety
+///
+/// Implementations must only return `OGRet`s containing the bytes returned by
+/// the last invoked foreign function.
+pub unsafe trait Wasm32InvokeRes<RT: Wasm32Rt, T: Sized> {
+    fn new() -> Self;
+
+    fn into_result_value(self, rt: &RT) -> OGResult<OGRet<T>>;
+
+    /// # Safety
+    ///
+    /// `stacked_res` must point to a stacked allocation of `T` that the last
+    /// invoked foreign function returned its result in.
+    unsafe fn into_result_stacked(self, rt: &RT, stacked_res: *mut T) -> OGResult<OGRet<T>>;
+}
+
+pub trait Wasm32Rt: OGRuntime<ABI = crate::abi::wasm32::Wasm32ABI> + Sized {
+    type InvokeRes<T>: Wasm32InvokeRes<Self, T>;
+
+    /// Call the function passed as `target_symbol` to the enclosing
+    /// [`OGRuntime::execute`] with the given parameters.
+    ///
+    /// Its result (if any) is retained in the runtime, and can be retrieved
+    /// through [`Wasm32InvokeRes::into_result_value`].
+    fn invoke(&self, args: &[Wasm32Val]) -> OGResult<()>;
+
+    /// Translate a host pointer into the foreign domain into a linear memory
+    /// address.
+    fn host_to_foreign_ptr(&self, ptr: *const ()) -> Option<u32>;
+
+    /// Translate a linear memory address into a host pointer.
+    ///
+    /// The returned pointer is not guaranteed to be valid, and must be upgraded
+    /// before it can be dereferenced.
+    fn foreign_to_host_ptr(&self, addr: u32) -> *mut ();
+}
@@ -0,0 +1,418 @@
+// -*- fill-column: 80; -*-
+
+//! Thin wrappers around the host C library's dynamic linker interface
+//! (`dlopen`, `dlsym`, `dl_iterate_phdr`), for runtimes that load foreign
+//! libraries as shared objects into the current process.
+
+use core::ffi::{CStr, c_int, c_void};
+use std::vec::Vec;
+
+// Not (yet) part of the `libc` crate. We only rely on the publicly documented
+// leading members of glibc's `struct link_map` (see `<link.h>`):
+#[repr(C)]
+struct LinkMap {
+    l_addr: usize,
+    _l_name: *const core::ffi::c_char,
+    l_ld: *const c_void,
+}
+
+/// `p_type` of the `PT_GNU_RELRO` program header, which marks a prefix of a
+/// writable segment as read-only after relocation.
+const PT_GNU_RELRO: u32 = 0x6474e552;
+
+// ELF dynamic section tags and relocation types used to locate the GOT slots
+// of imported symbols:
+const DT_NULL: i64 = 0;
+const DT_PLTRELSZ: i64 = 2;
+const DT_STRTAB: i64 = 5;
+const DT_SYMTAB: i64 = 6;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_JMPREL: i64 = 23;
+
+#[cfg(target_arch = "x86_64")]
+const R_GLOB_DAT_JUMP_SLOT: Option<[u32; 2]> = Some([6, 7]);
+#[cfg(target_arch = "aarch64")]
+const R_GLOB_DAT_JUMP_SLOT: Option<[u32; 2]> = Some([1025, 1026]);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const R_GLOB_DAT_JUMP_SLOT: Option<[u32; 2]> = None;
+
+#[repr(C)]
+struct Elf64Dyn {
+    d_tag: i64,
+    d_val: u64,
+}
+
+#[repr(C)]
+struct Elf64Rela {
+    r_offset: u64,
+    r_info: u64,
+    _r_addend: i64,
+}
+
+#[repr(C)]
+struct Elf64Sym {
+    st_name: u32,
+    _st_info: u8,
+    _st_other: u8,
+    _st_shndx: u16,
+    _st_value: u64,
+    _st_size: u64,
+}
+
+/// A loaded memory segment of a shared object.
+#[derive(Debug, Clone, Copy)]
+pub struct DlSegment {
+    pub start: usize,
+    pub len: usize,
+    pub writable: bool,
+}
+
+/// The GOT slots rewritten by [`DlLibrary::interpose`], along with their
+/// previous values.
+#[derive(Debug)]
+pub struct DlInterposition {
+    slots: Vec<(*mut *const (), *const ())>,
+}
+
+impl DlInterposition {
+    /// The number of rewritten GOT slots.
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Restore all rewritten GOT slots to their previous values.
+    ///
+    /// Returns `false` if a slot could not be restored.
+    ///
+    /// # Safety
+    ///
+    /// `library` must be the library that this interposition was applied to.
+    /// No thread may concurrently call into this library.
+    pub unsafe fn revert(self, library: &DlLibrary) -> bool {
+        // Restore slots in reverse order, such that the original value of a
+        // slot that was rewritten twice wins:
+        let mut restored = true;
+        for (slot, prev) in self.slots.iter().rev() {
+            restored &= unsafe { library.write_got_slot(*slot, *prev) };
+        }
+        restored
+    }
+}
+
+/// A handle to a shared object loaded through `dlopen`.
+///
+/// Libraries are never unloaded, as references into their memory may outlive
+/// the runtime that loaded them.
+#[derive(Debug)]
+pub struct DlLibrary {
+    handle: *mut c_void,
+}
+
+impl DlLibrary {
+    /// Load the shared object at `path`, resolving all of its symbols
+    /// immediately.
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initializers, without any isolation.
+    pub unsafe fn open(path: &CStr) -> Option<Self> {
+        let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            None
+        } else {
+            Some(DlLibrary { handle })
+        }
+    }
+
+    /// Whether the shared object at `path` is loaded into this process
+    /// already.
+    pub fn is_loaded(path: &CStr) -> bool {
+        let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_NOLOAD) };
+        if handle.is_null() {
+            false
+        } else {
+            // Drop the reference taken by the above `dlopen`:
+            unsafe { libc::dlclose(handle) };
+            true
+        }
+    }
+
+    /// Look up the address of a symbol exported by this library.
+    pub fn symbol(&self, name: &CStr) -> Option<*const ()> {
+        let ptr = unsafe { libc::dlsym(self.handle, name.as_ptr()) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(ptr as *const ())
+        }
+    }
+
+    fn link_map(&self) -> Option<&LinkMap> {
+        let mut link_map: *const LinkMap = core::ptr::null();
+        if unsafe {
+            libc::dlinfo(
+                self.handle,
+                libc::RTLD_DI_LINKMAP,
+                &mut link_map as *mut *const LinkMap as *mut c_void,
+            )
+        } != 0
+        {
+            return None;
+        }
+
+        unsafe { link_map.as_ref() }
+    }
+
+    /// Invoke `f` for every readable `PT_LOAD` segment of this library.
+    ///
+    /// Segments covered by a `PT_GNU_RELRO` header are reported as
+    /// non-writable, as the dynamic linker revokes write access to them after
+    /// relocation. Returns `false` if the library could not be located in the
+    /// list of loaded objects.
+    pub fn segments<F: FnMut(DlSegment)>(&self, f: F) -> bool {
+        let Some(link_map) = self.link_map() else {
+            return false;
+        };
+
+        struct Ctx<F> {
+            l_addr: usize,
+            l_ld: usize,
+            found: bool,
+            f: F,
+        }
+
+        unsafe extern "C" fn iter_cb<F: FnMut(DlSegment)>(
+            info: *mut libc::dl_phdr_info,
+            _size: libc::size_t,
+            data: *mut c_void,
+        ) -> c_int {
+            let ctx = unsafe { &mut *(data as *mut Ctx<F>) };
+            let info = unsafe { &*info };
+            let phdrs =
+                unsafe { core::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
+
+            // Multiple objects may share a load address of 0 (such as the main
+            // executable and the vDSO), so identify the library by the address
+            // of its dynamic section instead:
+            let base = info.dlpi_addr as usize;
+            if base != ctx.l_addr
+                || !phdrs.iter().any(|phdr| {
+                    phdr.p_type == libc::PT_DYNAMIC && base + phdr.p_vaddr as usize == ctx.l_ld
+                })
+            {
+                // Continue iterating:
+                return 0;
+            }
+
+            let relro = phdrs
+                .iter()
+                .find(|phdr| phdr.p_type == PT_GNU_RELRO)
+                .map(|phdr| {
+                    let start = base + phdr.p_vaddr as usize;
+                    (start, start + phdr.p_memsz as usize)
+                });
+
+            for phdr in phdrs.iter() {
+                if phdr.p_type != libc::PT_LOAD || phdr.p_flags & libc::PF_R == 0 {
+                    continue;
+                }
+
+                let mut start = base + phdr.p_vaddr as usize;
+                let end = start + phdr.p_memsz as usize;
+                let writable = phdr.p_flags & libc::PF_W != 0;
+
+                // Split off the part of this segment that is read-only after
+                // relocation. It is always a prefix of the segment:
+                if let Some((relro_start, relro_end)) = relro
+                    && writable
+                    && relro_start <= start
+                    && relro_end > start
+                {
+                    let relro_end = core::cmp::min(relro_end, end);
+                    (ctx.f)(DlSegment {
+                        start,
+                        len: relro_end - start,
+                        writable: false,
+                    });
+                    start = relro_end;
+                }
+
+                if end > start {
+                    (ctx.f)(DlSegment {
+                        start,
+                        len: end - start,
+                        writable,
+                    });
+                }
+            }
+
+            ctx.found = true;
+
+            // Stop iterating:
+            1
+        }
+
+        let mut ctx = Ctx {
+            l_addr: link_map.l_addr,
+            l_ld: link_map.l_ld as usize,
+            found: false,
+            f,
+        };
+
+        unsafe {
+            libc::dl_iterate_phdr(Some(iter_cb::<F>), &mut ctx as *mut Ctx<F> as *mut c_void);
+        }
+
+        ctx.found
+    }
+
+    /// Redirect all of this library's references to the imported function
+    /// `symbol` to `replacement`, by rewriting its global offset table.
+    ///
+    /// This only affects calls made by this library itself, and not calls
+    /// made on its behalf by other libraries. Returns the rewritten GOT slots,
+    /// which can be restored through [`DlInterposition::revert`]. Returns
+    /// `None`, leaving all GOT slots untouched, if the library's dynamic
+    /// section could not be parsed, a slot could not be rewritten, or
+    /// interposition is not supported on this architecture.
+    ///
+    /// # Safety
+    ///
+    /// `replacement` must be a function compatible with the signature of
+    /// `symbol`. No thread may concurrently call into this library.
+    pub unsafe fn interpose(
+        &self,
+        symbol: &CStr,
+        replacement: *const (),
+    ) -> Option<DlInterposition> {
+        let reloc_types = R_GLOB_DAT_JUMP_SLOT?;
+
+        let link_map = self.link_map()?;
+        let l_addr = link_map.l_addr;
+
+        // Depending on the architecture, the dynamic linker may have already
+        // relocated the addresses in the dynamic section:
+        let dyn_ptr = |val: u64| {
+            let val = val as usize;
+            if val < l_addr { val + l_addr } else { val }
+        };
+
+        let (mut strtab, mut symtab) = (0, 0);
+        let mut rela_tables = [(0, 0); 2];
+        let mut dyn_entry = link_map.l_ld as *const Elf64Dyn;
+        loop {
+            let entry = unsafe { &*dyn_entry };
+            match entry.d_tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = dyn_ptr(entry.d_val),
+                DT_SYMTAB => symtab = dyn_ptr(entry.d_val),
+                DT_RELA => rela_tables[0].0 = dyn_ptr(entry.d_val),
+                DT_RELASZ => rela_tables[0].1 = entry.d_val as usize,
+                DT_JMPREL => rela_tables[1].0 = dyn_ptr(entry.d_val),
+                DT_PLTRELSZ => rela_tables[1].1 = entry.d_val as usize,
+                _ => (),
+            }
+            dyn_entry = unsafe { dyn_entry.add(1) };
+        }
+        if strtab == 0 || symtab == 0 {
+            return None;
+        }
+
+        let mut interposition = DlInterposition { slots: Vec::new() };
+        for (table, size) in rela_tables {
+            if table == 0 {
+                continue;
+            }
+
+            let relas = unsafe {
+                core::slice::from_raw_parts(
+                    table as *const Elf64Rela,
+                    size / core::mem::size_of::<Elf64Rela>(),
+                )
+            };
+
+            for rela in relas {
+                let sym_idx = (rela.r_info >> 32) as usize;
+                if sym_idx == 0 || !reloc_types.contains(&(rela.r_info as u32)) {
+                    continue;
+                }
+
+                let sym = unsafe { &*(symtab as *const Elf64Sym).add(sym_idx) };
+                let name = unsafe { CStr::from_ptr((strtab + sym.st_name as usize) as *const _) };
+                if name != symbol {
+                    continue;
+                }
+
+                let slot = (l_addr + rela.r_offset as usize) as *mut *const ();
+                let prev = unsafe { slot.read() };
+                if !unsafe { self.write_got_slot(slot, replacement) } {
+                    unsafe { interposition.revert(self) };
+                    return None;
+                }
+                interposition.slots.push((slot, prev));
+            }
+        }
+
+        Some(interposition)
+    }
+
+    // Write a GOT slot, which may reside in a region that has been made
+    // read-only after relocation:
+    unsafe fn write_got_slot(&self, slot: *mut *const (), value: *const ()) -> bool {
+        let mut writable = true;
+        self.segments(|segment| {
+            if (segment.start..segment.start + segment.len).contains(&(slot as usize)) {
+                writable = segment.writable;
+            }
+        });
+
+        if writable {
+            unsafe { slot.write(value) };
+            return true;
+        }
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let page = (slot as usize & !(page_size - 1)) as *mut c_void;
+        if unsafe { libc::mprotect(page, page_size, libc::PROT_READ | libc::PROT_WRITE) } != 0 {
+            return false;
+        }
+        unsafe { slot.write(value) };
+        unsafe { libc::mprotect(page, page_size, libc::PROT_READ) == 0 }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_interpose() {
+    unsafe extern "C" fn fake_getpid() -> libc::pid_t {
+        42
+    }
+
+    let path = crate::util::test_lib::compile(
+        "dl_interpose",
+        "
+        #include <unistd.h>
+
+        int call_getpid(void) {
+            return getpid();
+        }
+        ",
+    );
+    let library = unsafe { DlLibrary::open(&path) }.unwrap();
+    let call_getpid: unsafe extern "C" fn() -> libc::pid_t =
+        unsafe { core::mem::transmute(library.symbol(c"call_getpid").unwrap()) };
+    let pid = std::process::id() as libc::pid_t;
+    assert_eq!(unsafe { call_getpid() }, pid);
+
+    let interposition = unsafe { library.interpose(c"getpid", fake_getpid as *const ()) }.unwrap();
+    assert_eq!(interposition.slots(), 1);
+    assert_eq!(unsafe { call_getpid() }, 42);
+
+    // Functions that the library does not import are left alone:
+    let unused = unsafe { library.interpose(c"getppid", fake_getpid as *const ()) }.unwrap();
+    assert_eq!(unused.slots(), 0);
+
+    assert!(unsafe { interposition.revert(&library) });
+    assert_eq!(unsafe { call_getpid() }, pid);
+}
@@ -2,3 +2,9 @@
 
 pub mod as_ref_unchecked;
 pub mod maybe_uninit_as_bytes;
+
+#[cfg(all(feature = "std", target_os = "linux", target_env = "gnu"))]
+pub mod dl;
+
+#[cfg(all(test, feature = "std", target_os = "linux", target_env = "gnu"))]
+pub(crate) mod test_lib;
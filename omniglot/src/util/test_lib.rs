@@ -0,0 +1,53 @@
+// -*- fill-column: 80; -*-
+
+//! Shared objects compiled from C sources, for tests of runtimes that load
+//! foreign libraries.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStringExt;
+use std::sync::Mutex;
+
+/// Compile `source` into a shared object named `lib<name>.so`, and return its
+/// path.
+///
+/// Shared objects are placed next to the test executable, and only rebuilt
+/// when their source changes. Requires a C compiler, `cc` unless overridden
+/// through the `CC` environment variable.
+pub(crate) fn compile(name: &str, source: &str) -> CString {
+    // Tests run concurrently, and may request the same library:
+    static COMPILE: Mutex<()> = Mutex::new(());
+    let _compile = COMPILE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let dir = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .join("omniglot-test-libs");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let source_path = dir.join(std::format!("{name}.c"));
+    let library_path = dir.join(std::format!("lib{name}.so"));
+    let up_to_date = library_path.exists()
+        && std::fs::read_to_string(&source_path).is_ok_and(|prev| prev == source);
+
+    if !up_to_date {
+        // Don't leave a stale library behind, should compilation fail:
+        let _ = std::fs::remove_file(&library_path);
+        std::fs::write(&source_path, source).unwrap();
+        let status = std::process::Command::new(std::env::var_os("CC").unwrap_or("cc".into()))
+            .args(["-shared", "-fPIC", "-O1", "-o"])
+            .arg(&library_path)
+            .arg(&source_path)
+            .status()
+            .expect("failed to run the C compiler");
+        assert!(
+            status.success(),
+            "failed to compile {}",
+            source_path.display()
+        );
+    }
+
+    CString::new(library_path.into_os_string().into_vec()).unwrap()
+}